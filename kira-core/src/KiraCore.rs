@@ -1,60 +1,84 @@
-use crate::device::{
-    DeviceInfo, RebootMode, get_build_info, get_max_refresh_rate, get_storage, parse_battery,
-    reboot, shell_cmd,
-};
+use crate::config::{KiraConfig, adb_config};
+use crate::device::connection::ensure_server_at;
+use crate::device::{DeviceInfo, RebootMode, collect_device_info, reboot};
+use crate::error::KiraError;
 use adb_client::server::ADBServer;
 use adb_client::server_device::ADBServerDevice;
-use anyhow::Result;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::collections::HashMap;
 
 pub struct KiraCore {
+    config: KiraConfig,
     server: ADBServer,
+    devices: HashMap<String, ADBServerDevice>,
 }
 
 impl KiraCore {
-    pub fn new() -> Result<Self> {
-        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
-        let server = ADBServer::new(addr);
-        Ok(Self { server })
+    /// Uses the process wide [`adb_config`].
+    pub fn new() -> Result<Self, KiraError> {
+        Self::with_config(adb_config())
     }
 
-    pub fn refresh_device(&mut self, serial: &str) -> Result<DeviceInfo> {
-        let devices = self.server.devices()?;
-        let _ = devices
-            .iter()
-            .find(|d| d.identifier == serial)
-            .ok_or(anyhow::anyhow!("Device {} not found", serial))?;
-
-        let mut device = ADBServerDevice::new(serial.to_string(), None);
-
-        let info = DeviceInfo {
-            serial: serial.to_string(),
-            model: shell_cmd(&mut device, "getprop ro.product.model"),
-            manufacturer: shell_cmd(&mut device, "getprop ro.product.manufacturer"),
-            android_version: shell_cmd(&mut device, "getprop ro.build.version.release"),
-            abi: shell_cmd(&mut device, "getprop ro.product.cpu.abi"),
-            slot: shell_cmd(&mut device, "getprop ro.boot.slot_suffix"),
-            battery: parse_battery(
-                &shell_cmd(&mut device, "dumpsys battery | grep level").unwrap_or_default(),
-            ),
-            storage: get_storage(&mut device),
-            screen_resolution: shell_cmd(&mut device, "wm size"),
-            refresh_rate: get_max_refresh_rate(&mut device),
-            build: get_build_info(&mut device),
-        };
+    pub fn with_config(config: KiraConfig) -> Result<Self, KiraError> {
+        Ok(Self {
+            server: config.server(),
+            config,
+            devices: HashMap::new(),
+        })
+    }
+
+    /// Cached handle for `serial`, created on first use.
+    pub fn device(&mut self, serial: &str) -> &mut ADBServerDevice {
+        self.devices
+            .entry(serial.to_string())
+            .or_insert_with(|| self.config.device(serial.to_string()))
+    }
+
+    /// Drops the cached handle so the next call to `device` starts from scratch.
+    pub fn invalidate(&mut self, serial: &str) {
+        self.devices.remove(serial);
+    }
+
+    /// Runs `f` on the cached handle for `serial`. The handle is dropped if `f` fails to
+    /// reach the device, so a device that disconnected and came back gets a fresh
+    /// connection next time. Any other error keeps it.
+    pub fn with_device<T>(
+        &mut self,
+        serial: &str,
+        f: impl FnOnce(&mut ADBServerDevice) -> Result<T, KiraError>,
+    ) -> Result<T, KiraError> {
+        let result = f(self.device(serial));
+        if result.as_ref().is_err_and(KiraError::is_connection_error) {
+            self.invalidate(serial);
+        }
+        result
+    }
+
+    pub fn refresh_device(&mut self, serial: &str) -> Result<DeviceInfo, KiraError> {
+        self.ensure_listed(serial)?;
+
+        let info = collect_device_info(self.device(serial), serial);
 
         println!("KIRA: {:?}", info);
         Ok(info)
     }
 
-    pub fn reboot(&mut self, serial: &str, mode: RebootMode) -> Result<()> {
-        let devices = self.server.devices()?;
-        let _ = devices
-            .iter()
-            .find(|d| d.identifier == serial)
-            .ok_or(anyhow::anyhow!("Device {} not found", serial))?;
+    pub fn reboot(&mut self, serial: &str, mode: RebootMode) -> Result<(), KiraError> {
+        self.ensure_listed(serial)?;
 
-        let mut device = ADBServerDevice::new(serial.to_string(), None);
-        reboot(&mut device, mode)
+        let result = reboot(self.device(serial), mode);
+        // The connection doesn't survive the reboot either way
+        self.invalidate(serial);
+        Ok(result?)
+    }
+
+    fn ensure_listed(&mut self, serial: &str) -> Result<(), KiraError> {
+        ensure_server_at(self.config.server_addr())?;
+        let devices = self.server.devices()?;
+        if devices.iter().any(|d| d.identifier == serial) {
+            Ok(())
+        } else {
+            self.invalidate(serial);
+            Err(KiraError::DeviceNotFound(serial.to_string()))
+        }
     }
 }
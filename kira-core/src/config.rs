@@ -0,0 +1,199 @@
+use adb_client::server::ADBServer;
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+pub const DEFAULT_ADB_PORT: u16 = 5037;
+pub const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+
+/// Where the ADB server runs and which `adb` binary to use. Defaults to `localhost:5037`
+/// like the adb client does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KiraConfig {
+    pub adb_host: Ipv4Addr,
+    pub adb_port: u16,
+    /// Tries per shell command when the device can't be reached, 1 disables retrying
+    pub retry_attempts: u32,
+    /// The host `adb` binary, looked up when unset, see [`KiraConfig::adb_executable`]
+    pub adb_path: Option<PathBuf>,
+}
+
+impl Default for KiraConfig {
+    fn default() -> Self {
+        Self {
+            adb_host: Ipv4Addr::LOCALHOST,
+            adb_port: DEFAULT_ADB_PORT,
+            retry_attempts: DEFAULT_RETRY_ATTEMPTS,
+            adb_path: None,
+        }
+    }
+}
+
+impl KiraConfig {
+    /// Reads `ANDROID_ADB_SERVER_ADDRESS` and `ANDROID_ADB_SERVER_PORT`, the variables the
+    /// adb client itself honours. Unset or unusable values keep the default.
+    pub fn from_env() -> Self {
+        Self::from_vars(
+            std::env::var("ANDROID_ADB_SERVER_ADDRESS").ok().as_deref(),
+            std::env::var("ANDROID_ADB_SERVER_PORT").ok().as_deref(),
+        )
+    }
+
+    fn from_vars(address: Option<&str>, port: Option<&str>) -> Self {
+        let default = Self::default();
+        let adb_port = port
+            .and_then(|p| p.trim().parse().ok())
+            .filter(|p| *p != 0)
+            .unwrap_or(default.adb_port);
+        let adb_host = address
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .and_then(resolve_ipv4)
+            .unwrap_or(default.adb_host);
+
+        Self {
+            adb_host,
+            adb_port,
+            ..default
+        }
+    }
+
+    pub fn server_addr(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.adb_host, self.adb_port)
+    }
+
+    pub fn server(&self) -> ADBServer {
+        ADBServer::new(self.server_addr())
+    }
+
+    pub fn device(&self, serial: String) -> ADBServerDevice {
+        ADBServerDevice::new(serial, Some(self.server_addr()))
+    }
+
+    /// The host `adb` binary used for the commands that have no equivalent over the server
+    /// protocol. Without [`KiraConfig::adb_path`] that is `platform-tools/adb` of the SDK in
+    /// `ANDROID_HOME` or `ANDROID_SDK_ROOT` when it exists, otherwise `adb` from `PATH`.
+    pub fn adb_executable(&self) -> PathBuf {
+        if let Some(path) = &self.adb_path {
+            return path.clone();
+        }
+
+        ["ANDROID_HOME", "ANDROID_SDK_ROOT"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .find_map(|sdk| sdk_adb(Path::new(&sdk)))
+            .unwrap_or_else(|| PathBuf::from("adb"))
+    }
+
+    /// `-H`/`-P` options pointing the host `adb` binary at this server.
+    pub fn host_args(&self) -> [String; 4] {
+        [
+            "-H".to_string(),
+            self.adb_host.to_string(),
+            "-P".to_string(),
+            self.adb_port.to_string(),
+        ]
+    }
+}
+
+fn resolve_ipv4(host: &str) -> Option<Ipv4Addr> {
+    if let Ok(ip) = host.parse() {
+        return Some(ip);
+    }
+    (host, 0)
+        .to_socket_addrs()
+        .ok()?
+        .find_map(|addr| match addr {
+            SocketAddr::V4(v4) => Some(*v4.ip()),
+            SocketAddr::V6(_) => None,
+        })
+}
+
+static ADB_CONFIG: Mutex<Option<KiraConfig>> = Mutex::new(None);
+
+/// The config used by every function that talks to the ADB server, read from the
+/// environment on first use unless [`set_adb_config`] was called before.
+pub fn adb_config() -> KiraConfig {
+    let mut config = ADB_CONFIG.lock().unwrap_or_else(|e| e.into_inner());
+    config.get_or_insert_with(KiraConfig::from_env).clone()
+}
+
+/// Replaces the config for connections made from now on. Handles that already exist,
+/// like the ones cached by `KiraCore`, keep their address.
+pub fn set_adb_config(config: KiraConfig) {
+    *ADB_CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(config);
+}
+
+/// ADB server client for the current [`adb_config`].
+pub fn adb_server() -> ADBServer {
+    adb_config().server()
+}
+
+/// Device handle for `serial` on the ADB server of the current [`adb_config`].
+pub fn adb_device(serial: String) -> ADBServerDevice {
+    adb_config().device(serial)
+}
+
+/// The `adb` binary of the current [`adb_config`].
+pub fn adb_path() -> PathBuf {
+    adb_config().adb_executable()
+}
+
+/// Uses `path` as the `adb` binary from now on, `None` goes back to looking it up. The
+/// rest of the config is kept.
+pub fn set_adb_path(path: Option<PathBuf>) {
+    let mut config = ADB_CONFIG.lock().unwrap_or_else(|e| e.into_inner());
+    config.get_or_insert_with(KiraConfig::from_env).adb_path = path;
+}
+
+/// A `Command` for the [`adb_path`] binary, without any arguments yet.
+pub fn host_adb() -> Command {
+    Command::new(adb_path())
+}
+
+fn sdk_adb(sdk: &Path) -> Option<PathBuf> {
+    let name = if cfg!(windows) { "adb.exe" } else { "adb" };
+    let path = sdk.join("platform-tools").join(name);
+    path.is_file().then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_vars() {
+        assert_eq!(KiraConfig::from_vars(None, None), KiraConfig::default());
+        assert_eq!(
+            KiraConfig::from_vars(Some("192.168.1.20"), Some("5038")).server_addr(),
+            SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), 5038)
+        );
+        // Bad values fall back one by one
+        assert_eq!(
+            KiraConfig::from_vars(Some("192.168.1.20"), Some("adb")).server_addr(),
+            SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), DEFAULT_ADB_PORT)
+        );
+        assert_eq!(
+            KiraConfig::from_vars(Some(""), Some("0")),
+            KiraConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_sdk_adb() {
+        let sdk = std::env::temp_dir().join(format!("kira_test_sdk_{}", std::process::id()));
+        assert_eq!(sdk_adb(&sdk), None);
+
+        let tools = sdk.join("platform-tools");
+        std::fs::create_dir_all(&tools).unwrap();
+        let name = if cfg!(windows) { "adb.exe" } else { "adb" };
+        std::fs::write(tools.join(name), b"").unwrap();
+
+        let found = sdk_adb(&sdk);
+        let _ = std::fs::remove_dir_all(&sdk);
+        assert_eq!(found, Some(tools.join(name)));
+    }
+}
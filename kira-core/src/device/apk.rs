@@ -0,0 +1,665 @@
+use crate::device::app_manager::{AppInfo, AppManagerError, get_app_info};
+use adb_client::server_device::ADBServerDevice;
+use flate2::read::DeflateDecoder;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+/// What `AndroidManifest.xml` of an APK on the host says about it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApkManifest {
+    pub package_name: String,
+    pub version_code: Option<i64>,
+    pub version_name: Option<String>,
+    pub min_sdk: Option<u32>,
+    pub target_sdk: Option<u32>,
+}
+
+/// An APK compared against the version of the same package on the device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallPreview {
+    pub manifest: ApkManifest,
+    pub installed_version_code: Option<i64>,
+    pub installed_version_name: Option<String>,
+    /// Set when installing would replace a newer version
+    pub warning: Option<String>,
+}
+
+/// Reads the package name, version and SDK levels from the APK at `local_path` without
+/// touching any device.
+pub fn inspect_apk(local_path: &str) -> Result<ApkManifest, AppManagerError> {
+    let mut file = File::open(local_path)
+        .map_err(|e| AppManagerError::ParseError(format!("{}: {}", local_path, e)))?;
+    let manifest = read_zip_entry(&mut file, MANIFEST_ENTRY)
+        .map_err(|e| AppManagerError::ParseError(format!("{}: {}", local_path, e)))?;
+    parse_binary_manifest(&manifest)
+        .map_err(|e| AppManagerError::ParseError(format!("{}: {}", local_path, e)))
+}
+
+/// Inspects the APK at `local_path` and looks up the installed version of its package, so
+/// the UI can show what an install would change before running it.
+pub fn preview_install(
+    device: &mut ADBServerDevice,
+    local_path: &str,
+) -> Result<InstallPreview, AppManagerError> {
+    let manifest = inspect_apk(local_path)?;
+    // `pm dump` of a package that isn't installed has no versionCode line
+    let installed = get_app_info(device, &manifest.package_name)
+        .ok()
+        .filter(|info| info.version_code.is_some());
+
+    Ok(InstallPreview {
+        warning: installed
+            .as_ref()
+            .and_then(|info| downgrade_warning(info, &manifest)),
+        installed_version_code: installed.as_ref().and_then(|info| info.version_code),
+        installed_version_name: installed.and_then(|info| info.version_name),
+        manifest,
+    })
+}
+
+pub fn downgrade_warning(installed: &AppInfo, apk: &ApkManifest) -> Option<String> {
+    let installed_code = installed.version_code?;
+    let apk_code = apk.version_code?;
+    (apk_code < installed_code).then(|| {
+        format!(
+            "This will downgrade {} from {} to {}",
+            apk.package_name, installed_code, apk_code
+        )
+    })
+}
+
+const MANIFEST_ENTRY: &str = "AndroidManifest.xml";
+
+const ZIP_EOCD_SIG: u32 = 0x0605_4b50;
+const ZIP_CENTRAL_SIG: u32 = 0x0201_4b50;
+const ZIP_LOCAL_SIG: u32 = 0x0403_4b50;
+const ZIP_EOCD_LEN: u64 = 22;
+// Largest comment a ZIP can have after the end of central directory record
+const ZIP_MAX_COMMENT: u64 = 0xffff;
+// Nothing in a real manifest comes close, this just bounds the allocation
+const MAX_MANIFEST_LEN: u32 = 16 * 1024 * 1024;
+
+fn read_zip_entry<R: Read + Seek>(zip: &mut R, entry: &str) -> Result<Vec<u8>, String> {
+    let found = find_zip_entry(zip, entry)?.ok_or_else(|| format!("{} not found", entry))?;
+    if found.size > MAX_MANIFEST_LEN || found.compressed > MAX_MANIFEST_LEN {
+        return Err(format!("{} is too large", entry));
+    }
+    read_local_entry(
+        zip,
+        found.local_offset as u64,
+        found.method,
+        found.compressed,
+        found.size,
+    )
+}
+
+/// Whether the ZIP archive at `path` has an entry named `entry`, going by the central
+/// directory only.
+pub(crate) fn zip_has_entry(path: &str, entry: &str) -> Result<bool, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    Ok(find_zip_entry(&mut file, entry)?.is_some())
+}
+
+struct ZipEntry {
+    method: u16,
+    compressed: u32,
+    size: u32,
+    local_offset: u32,
+}
+
+fn find_zip_entry<R: Read + Seek>(zip: &mut R, entry: &str) -> Result<Option<ZipEntry>, String> {
+    let len = zip.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+    let tail_len = len.min(ZIP_EOCD_LEN + ZIP_MAX_COMMENT);
+    let tail = read_at(zip, len - tail_len, tail_len as usize)?;
+
+    let eocd = (0..=tail.len().saturating_sub(ZIP_EOCD_LEN as usize))
+        .rev()
+        .find(|&i| le_u32(&tail, i) == Some(ZIP_EOCD_SIG))
+        .ok_or("not a ZIP archive")?;
+    let entries = le_u16(&tail, eocd + 10).ok_or("truncated ZIP")?;
+    let central_len = le_u32(&tail, eocd + 12).ok_or("truncated ZIP")?;
+    let central_offset = le_u32(&tail, eocd + 16).ok_or("truncated ZIP")?;
+
+    let central = read_at(zip, central_offset as u64, central_len as usize)?;
+    let mut pos = 0;
+    for _ in 0..entries {
+        if le_u32(&central, pos) != Some(ZIP_CENTRAL_SIG) {
+            return Err("corrupt ZIP central directory".to_string());
+        }
+        let field = |offset| le_u16(&central, pos + offset).ok_or("truncated ZIP");
+        let method = field(10)?;
+        let name_len = field(28)? as usize;
+        let extra_len = field(30)? as usize;
+        let comment_len = field(32)? as usize;
+        let name = central
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or("truncated ZIP")?;
+
+        if name == entry.as_bytes() {
+            return Ok(Some(ZipEntry {
+                method,
+                compressed: le_u32(&central, pos + 20).ok_or("truncated ZIP")?,
+                size: le_u32(&central, pos + 24).ok_or("truncated ZIP")?,
+                local_offset: le_u32(&central, pos + 42).ok_or("truncated ZIP")?,
+            }));
+        }
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+
+    Ok(None)
+}
+
+fn read_local_entry<R: Read + Seek>(
+    zip: &mut R,
+    offset: u64,
+    method: u16,
+    compressed: u32,
+    size: u32,
+) -> Result<Vec<u8>, String> {
+    let header = read_at(zip, offset, 30)?;
+    if le_u32(&header, 0) != Some(ZIP_LOCAL_SIG) {
+        return Err("corrupt ZIP local header".to_string());
+    }
+    // The local header repeats the name and has its own extra field length
+    let name_len = le_u16(&header, 26).ok_or("truncated ZIP")? as u64;
+    let extra_len = le_u16(&header, 28).ok_or("truncated ZIP")? as u64;
+    let data = read_at(zip, offset + 30 + name_len + extra_len, compressed as usize)?;
+
+    match method {
+        0 => Ok(data),
+        8 => {
+            let mut out = Vec::new();
+            DeflateDecoder::new(data.as_slice())
+                .take(size as u64)
+                .read_to_end(&mut out)
+                .map_err(|e| e.to_string())?;
+            Ok(out)
+        }
+        other => Err(format!("unsupported ZIP compression method {}", other)),
+    }
+}
+
+/// Reads `len` bytes at `offset`. Offsets and lengths come straight from the ZIP headers,
+/// so they are checked against the file length before anything is allocated.
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+    let file_len = reader.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+    if offset
+        .checked_add(len as u64)
+        .is_none_or(|end| end > file_len)
+    {
+        return Err("truncated ZIP".to_string());
+    }
+    let mut buf = vec![0u8; len];
+    reader
+        .seek(SeekFrom::Start(offset))
+        .and_then(|_| reader.read_exact(&mut buf))
+        .map_err(|_| "truncated ZIP".to_string())?;
+    Ok(buf)
+}
+
+const AXML_STRING_POOL: u16 = 0x0001;
+const AXML_XML: u16 = 0x0003;
+const AXML_RESOURCE_MAP: u16 = 0x0180;
+const AXML_START_ELEMENT: u16 = 0x0102;
+const AXML_UTF8_FLAG: u32 = 1 << 8;
+const AXML_NO_INDEX: u32 = 0xffff_ffff;
+
+const TYPE_STRING: u8 = 0x03;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+
+// android:* attribute resource ids, obfuscated APKs can strip the attribute names
+const ATTR_VERSION_CODE: u32 = 0x0101_021b;
+const ATTR_VERSION_NAME: u32 = 0x0101_021c;
+const ATTR_MIN_SDK: u32 = 0x0101_020c;
+const ATTR_TARGET_SDK: u32 = 0x0101_0270;
+
+enum AttrValue {
+    String(String),
+    Int(u32),
+    Other,
+}
+
+struct Attribute {
+    resource_id: Option<u32>,
+    name: Option<String>,
+    value: AttrValue,
+}
+
+/// Parses the compiled (binary XML) `AndroidManifest.xml` found inside APKs.
+fn parse_binary_manifest(data: &[u8]) -> Result<ApkManifest, String> {
+    if le_u16(data, 0) != Some(AXML_XML) {
+        return Err("AndroidManifest.xml is not binary XML".to_string());
+    }
+    let header_len = le_u16(data, 2).ok_or("truncated manifest")? as usize;
+
+    let mut strings = Vec::new();
+    let mut resource_ids = Vec::new();
+    let mut manifest: Option<ApkManifest> = None;
+
+    let mut pos = header_len;
+    while pos + 8 <= data.len() {
+        let chunk_type = le_u16(data, pos).ok_or("truncated manifest")?;
+        let chunk_len = le_u32(data, pos + 4).ok_or("truncated manifest")? as usize;
+        let chunk = data
+            .get(pos..pos + chunk_len)
+            .filter(|_| chunk_len >= 8)
+            .ok_or("truncated manifest")?;
+
+        match chunk_type {
+            AXML_STRING_POOL => strings = parse_string_pool(chunk)?,
+            AXML_RESOURCE_MAP => {
+                resource_ids = (8..chunk.len())
+                    .step_by(4)
+                    .filter_map(|i| le_u32(chunk, i))
+                    .collect();
+            }
+            AXML_START_ELEMENT => {
+                let name = string_at(&strings, le_u32(chunk, 20).ok_or("truncated manifest")?);
+                let attrs = parse_attributes(chunk, &strings, &resource_ids)?;
+                let find = |id: u32, name: &str| {
+                    attrs
+                        .iter()
+                        .find(|attr| {
+                            attr.resource_id == Some(id) || attr.name.as_deref() == Some(name)
+                        })
+                        .map(|attr| &attr.value)
+                };
+
+                match name.as_deref() {
+                    Some("manifest") => {
+                        let package = attrs.iter().find_map(|attr| {
+                            match (attr.name.as_deref(), &attr.value) {
+                                (Some("package"), AttrValue::String(s)) => Some(s.clone()),
+                                _ => None,
+                            }
+                        });
+                        manifest = Some(ApkManifest {
+                            package_name: package.ok_or("manifest has no package")?,
+                            version_code: match find(ATTR_VERSION_CODE, "versionCode") {
+                                Some(AttrValue::Int(code)) => Some(*code as i64),
+                                Some(AttrValue::String(code)) => code.parse().ok(),
+                                _ => None,
+                            },
+                            version_name: match find(ATTR_VERSION_NAME, "versionName") {
+                                Some(AttrValue::String(name)) => Some(name.clone()),
+                                Some(AttrValue::Int(name)) => Some(name.to_string()),
+                                _ => None,
+                            },
+                            min_sdk: None,
+                            target_sdk: None,
+                        });
+                    }
+                    Some("uses-sdk") => {
+                        if let Some(manifest) = manifest.as_mut() {
+                            manifest.min_sdk = sdk_level(find(ATTR_MIN_SDK, "minSdkVersion"));
+                            manifest.target_sdk =
+                                sdk_level(find(ATTR_TARGET_SDK, "targetSdkVersion"));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+
+        pos += chunk_len;
+    }
+
+    manifest.ok_or_else(|| "no <manifest> element".to_string())
+}
+
+fn sdk_level(value: Option<&AttrValue>) -> Option<u32> {
+    match value? {
+        AttrValue::Int(level) => Some(*level),
+        // Preview SDKs use a codename here
+        AttrValue::String(level) => level.parse().ok(),
+        AttrValue::Other => None,
+    }
+}
+
+fn parse_attributes(
+    chunk: &[u8],
+    strings: &[String],
+    resource_ids: &[u32],
+) -> Result<Vec<Attribute>, String> {
+    let header_len = le_u16(chunk, 2).ok_or("truncated manifest")? as usize;
+    let attr_start = le_u16(chunk, header_len + 8).ok_or("truncated manifest")? as usize;
+    let attr_size = le_u16(chunk, header_len + 10).ok_or("truncated manifest")? as usize;
+    let attr_count = le_u16(chunk, header_len + 12).ok_or("truncated manifest")? as usize;
+    if attr_size < 20 {
+        return Err("corrupt manifest attributes".to_string());
+    }
+
+    (0..attr_count)
+        .map(|i| {
+            let at = header_len + attr_start + i * attr_size;
+            let name_index = le_u32(chunk, at + 4).ok_or("truncated manifest")?;
+            let raw_value = le_u32(chunk, at + 8).ok_or("truncated manifest")?;
+            let data_type = *chunk.get(at + 15).ok_or("truncated manifest")?;
+            let data = le_u32(chunk, at + 16).ok_or("truncated manifest")?;
+
+            let value = match data_type {
+                TYPE_STRING => string_at(strings, raw_value)
+                    .or_else(|| string_at(strings, data))
+                    .map(AttrValue::String)
+                    .unwrap_or(AttrValue::Other),
+                TYPE_INT_DEC | TYPE_INT_HEX => AttrValue::Int(data),
+                _ => AttrValue::Other,
+            };
+            Ok(Attribute {
+                resource_id: resource_ids.get(name_index as usize).copied(),
+                name: string_at(strings, name_index).filter(|name| !name.is_empty()),
+                value,
+            })
+        })
+        .collect()
+}
+
+fn string_at(strings: &[String], index: u32) -> Option<String> {
+    if index == AXML_NO_INDEX {
+        return None;
+    }
+    strings.get(index as usize).cloned()
+}
+
+fn parse_string_pool(chunk: &[u8]) -> Result<Vec<String>, String> {
+    let header_len = le_u16(chunk, 2).ok_or("truncated string pool")? as usize;
+    let count = le_u32(chunk, 8).ok_or("truncated string pool")? as usize;
+    let flags = le_u32(chunk, 16).ok_or("truncated string pool")?;
+    let strings_start = le_u32(chunk, 20).ok_or("truncated string pool")? as usize;
+    let utf8 = flags & AXML_UTF8_FLAG != 0;
+
+    (0..count)
+        .map(|i| {
+            let offset = le_u32(chunk, header_len + i * 4).ok_or("truncated string pool")?;
+            let at = strings_start + offset as usize;
+            let s = if utf8 {
+                read_utf8_string(chunk, at)
+            } else {
+                read_utf16_string(chunk, at)
+            };
+            s.ok_or_else(|| "corrupt string pool".to_string())
+        })
+        .collect()
+}
+
+fn read_utf16_string(data: &[u8], at: usize) -> Option<String> {
+    let mut len = le_u16(data, at)? as usize;
+    let mut start = at + 2;
+    if len & 0x8000 != 0 {
+        len = ((len & 0x7fff) << 16) | le_u16(data, at + 2)? as usize;
+        start += 2;
+    }
+    let units: Vec<u16> = (0..len)
+        .map(|i| le_u16(data, start + i * 2))
+        .collect::<Option<_>>()?;
+    Some(String::from_utf16_lossy(&units))
+}
+
+fn read_utf8_string(data: &[u8], at: usize) -> Option<String> {
+    // Length in UTF-16 units first, then in bytes, each one or two bytes long
+    let (_, at) = read_utf8_len(data, at)?;
+    let (len, at) = read_utf8_len(data, at)?;
+    let bytes = data.get(at..at + len)?;
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn read_utf8_len(data: &[u8], at: usize) -> Option<(usize, usize)> {
+    let first = *data.get(at)? as usize;
+    if first & 0x80 != 0 {
+        let second = *data.get(at + 1)? as usize;
+        Some((((first & 0x7f) << 8) | second, at + 2))
+    } else {
+        Some((first, at + 1))
+    }
+}
+
+fn le_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::app_manager::InstallLocation;
+    use std::io::Cursor;
+
+    /// Compiled manifest with a UTF-16 string pool, like aapt2 writes it.
+    fn binary_manifest() -> Vec<u8> {
+        let strings = [
+            "versionCode",
+            "versionName",
+            "minSdkVersion",
+            "targetSdkVersion",
+            "package",
+            "manifest",
+            "uses-sdk",
+            "com.example.app",
+            "1.2.3",
+        ];
+
+        let mut pool_data = Vec::new();
+        let mut offsets = Vec::new();
+        for s in strings {
+            offsets.push(pool_data.len() as u32);
+            let units: Vec<u16> = s.encode_utf16().collect();
+            pool_data.extend_from_slice(&(units.len() as u16).to_le_bytes());
+            for unit in units {
+                pool_data.extend_from_slice(&unit.to_le_bytes());
+            }
+            pool_data.extend_from_slice(&[0, 0]);
+        }
+        let pool_header = 28;
+        let strings_start = pool_header + offsets.len() * 4;
+        let mut pool = chunk_header(
+            AXML_STRING_POOL,
+            pool_header,
+            strings_start + pool_data.len(),
+        );
+        for value in [strings.len() as u32, 0, 0, strings_start as u32, 0] {
+            pool.extend_from_slice(&value.to_le_bytes());
+        }
+        for offset in offsets {
+            pool.extend_from_slice(&offset.to_le_bytes());
+        }
+        pool.extend_from_slice(&pool_data);
+
+        let ids = [
+            ATTR_VERSION_CODE,
+            ATTR_VERSION_NAME,
+            ATTR_MIN_SDK,
+            ATTR_TARGET_SDK,
+        ];
+        let mut map = chunk_header(AXML_RESOURCE_MAP, 8, 8 + ids.len() * 4);
+        for id in ids {
+            map.extend_from_slice(&id.to_le_bytes());
+        }
+
+        let manifest = start_element(
+            5,
+            &[
+                (0, AXML_NO_INDEX, TYPE_INT_HEX, 42),
+                (1, 8, TYPE_STRING, 8),
+                (4, 7, TYPE_STRING, 7),
+            ],
+        );
+        let uses_sdk = start_element(
+            6,
+            &[
+                (2, AXML_NO_INDEX, TYPE_INT_DEC, 24),
+                (3, AXML_NO_INDEX, TYPE_INT_DEC, 34),
+            ],
+        );
+
+        let body_len = pool.len() + map.len() + manifest.len() + uses_sdk.len();
+        let mut out = chunk_header(AXML_XML, 8, 8 + body_len);
+        out.extend_from_slice(&pool);
+        out.extend_from_slice(&map);
+        out.extend_from_slice(&manifest);
+        out.extend_from_slice(&uses_sdk);
+        out
+    }
+
+    fn chunk_header(chunk_type: u16, header_len: usize, len: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&chunk_type.to_le_bytes());
+        out.extend_from_slice(&(header_len as u16).to_le_bytes());
+        out.extend_from_slice(&(len as u32).to_le_bytes());
+        out
+    }
+
+    /// `attrs` are `(name, raw_value, data_type, data)`.
+    fn start_element(name: u32, attrs: &[(u32, u32, u8, u32)]) -> Vec<u8> {
+        let mut out = chunk_header(AXML_START_ELEMENT, 16, 16 + 20 + attrs.len() * 20);
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&AXML_NO_INDEX.to_le_bytes());
+        out.extend_from_slice(&AXML_NO_INDEX.to_le_bytes());
+        out.extend_from_slice(&name.to_le_bytes());
+        for value in [20u16, 20, attrs.len() as u16, 0, 0, 0] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        for &(name, raw_value, data_type, data) in attrs {
+            out.extend_from_slice(&AXML_NO_INDEX.to_le_bytes());
+            out.extend_from_slice(&name.to_le_bytes());
+            out.extend_from_slice(&raw_value.to_le_bytes());
+            out.extend_from_slice(&8u16.to_le_bytes());
+            out.push(0);
+            out.push(data_type);
+            out.extend_from_slice(&data.to_le_bytes());
+        }
+        out
+    }
+
+    /// ZIP with `entries` stored uncompressed.
+    fn stored_zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut central = Vec::new();
+        for (name, data) in entries {
+            let offset = out.len() as u32;
+            out.extend_from_slice(&ZIP_LOCAL_SIG.to_le_bytes());
+            out.extend_from_slice(&[0; 22]);
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&0u16.to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(data);
+
+            central.extend_from_slice(&ZIP_CENTRAL_SIG.to_le_bytes());
+            central.extend_from_slice(&[0; 6]);
+            central.extend_from_slice(&0u16.to_le_bytes());
+            central.extend_from_slice(&[0; 8]);
+            central.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            central.extend_from_slice(&[0; 12]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+
+        let central_offset = out.len() as u32;
+        out.extend_from_slice(&central);
+        out.extend_from_slice(&ZIP_EOCD_SIG.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        out.extend_from_slice(&central_offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn test_parse_binary_manifest() {
+        let manifest = parse_binary_manifest(&binary_manifest()).unwrap();
+        assert_eq!(
+            manifest,
+            ApkManifest {
+                package_name: "com.example.app".to_string(),
+                version_code: Some(42),
+                version_name: Some("1.2.3".to_string()),
+                min_sdk: Some(24),
+                target_sdk: Some(34),
+            }
+        );
+
+        assert!(parse_binary_manifest(b"<manifest/>").is_err());
+    }
+
+    #[test]
+    fn test_read_zip_entry() {
+        let manifest = binary_manifest();
+        let zip = stored_zip(&[("classes.dex", b"dex\n035"), (MANIFEST_ENTRY, &manifest)]);
+
+        let entry = read_zip_entry(&mut Cursor::new(&zip), MANIFEST_ENTRY).unwrap();
+        assert_eq!(entry, manifest);
+        assert!(read_zip_entry(&mut Cursor::new(&zip), "resources.arsc").is_err());
+        assert!(read_zip_entry(&mut Cursor::new(b"not a zip"), MANIFEST_ENTRY).is_err());
+
+        // A central directory length past the end of the file is rejected, not allocated
+        let mut corrupt = zip.clone();
+        let central_len = corrupt.len() - 10;
+        corrupt[central_len..central_len + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            read_zip_entry(&mut Cursor::new(&corrupt), MANIFEST_ENTRY),
+            Err("truncated ZIP".to_string())
+        );
+    }
+
+    #[test]
+    fn test_inspect_apk() {
+        let path = std::env::temp_dir().join(format!("kira_test_{}.apk", std::process::id()));
+        std::fs::write(&path, stored_zip(&[(MANIFEST_ENTRY, &binary_manifest())])).unwrap();
+
+        let manifest = inspect_apk(&path.to_string_lossy());
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(manifest.unwrap().package_name, "com.example.app");
+
+        assert!(matches!(
+            inspect_apk("/nonexistent/app.apk"),
+            Err(AppManagerError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_downgrade_warning() {
+        let apk = ApkManifest {
+            package_name: "com.x".to_string(),
+            version_code: Some(3),
+            version_name: None,
+            min_sdk: None,
+            target_sdk: None,
+        };
+        let mut installed = AppInfo {
+            package_name: "com.x".to_string(),
+            version_name: None,
+            version_code: Some(5),
+            min_sdk: None,
+            target_sdk: None,
+            label: None,
+            install_location: InstallLocation::Auto,
+            flags: Vec::new(),
+            first_install_time: None,
+            last_update_time: None,
+            apk_path: None,
+            split_apks: Vec::new(),
+            data_dir: None,
+            is_system_app: false,
+            is_enabled: true,
+            app_size_bytes: None,
+            data_size_bytes: None,
+        };
+
+        assert_eq!(
+            downgrade_warning(&installed, &apk).as_deref(),
+            Some("This will downgrade com.x from 5 to 3")
+        );
+
+        installed.version_code = Some(3);
+        assert_eq!(downgrade_warning(&installed, &apk), None);
+    }
+}
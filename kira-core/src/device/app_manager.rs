@@ -1,21 +1,40 @@
-use adb_client::ADBDeviceExt;
+use crate::config::{adb_config, host_adb};
+use crate::device::cancel::CancelToken;
+use crate::device::process::{ProcessInfo, find_process_by_package};
+use crate::device::shell::{shell_output, shell_quote};
+use crate::error::KiraError;
+use crate::format::format_bytes;
 use adb_client::server_device::ADBServerDevice;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::process::Stdio;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppInfo {
     pub package_name: String,
     pub version_name: Option<String>,
     pub version_code: Option<i64>,
+    #[serde(default)]
+    pub min_sdk: Option<u32>,
+    #[serde(default)]
+    pub target_sdk: Option<u32>,
     pub label: Option<String>,
     pub install_location: InstallLocation,
     pub flags: Vec<String>,
     pub first_install_time: Option<String>,
     pub last_update_time: Option<String>,
+    /// Install directory, or the APK itself on old builds
     pub apk_path: Option<String>,
+    /// Every APK of the app, base first. More than one means it was installed as a bundle.
+    #[serde(default)]
+    pub split_apks: Vec<String>,
     pub data_dir: Option<String>,
     pub is_system_app: bool,
     pub is_enabled: bool,
+    pub app_size_bytes: Option<u64>,
+    pub data_size_bytes: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +43,27 @@ pub struct TopPackage {
     pub pid: Option<u32>,
 }
 
+/// The window with input focus, which may be a dialog or popup over the top activity.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FocusedWindow {
+    /// Window title as WindowManager knows it, e.g. `com.example/com.example.MainActivity`
+    /// or `PopupWindow:4f2a1b`
+    pub window: String,
+    /// Owner of the window when its title names it
+    pub package: Option<String>,
+    /// Package of the activity WindowManager considers focused, set even while a system
+    /// window holds focus
+    pub focused_app: Option<String>,
+    pub keyboard_shown: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AppRuntimeState {
+    Foreground,
+    Background,
+    Stopped,
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum InstallLocation {
     Auto,
@@ -33,6 +73,7 @@ pub enum InstallLocation {
 }
 
 impl InstallLocation {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s {
             "auto" => InstallLocation::Auto,
@@ -64,6 +105,7 @@ pub enum PermissionStatus {
 }
 
 impl PermissionStatus {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s {
             "granted" => PermissionStatus::Granted,
@@ -79,156 +121,745 @@ pub struct InstallResult {
     pub success: bool,
     pub message: String,
     pub package_name: Option<String>,
+    #[serde(default)]
+    pub failure_reason: Option<InstallFailureReason>,
+    /// `INSTALL_FAILED_*` code from the brackets of the `Failure [...]` line
+    #[serde(default)]
+    pub error_code: Option<String>,
+}
+
+/// Flags passed to `pm install`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct InstallOptions {
+    /// `-g`, grant all runtime permissions
+    pub grant_permissions: bool,
+    /// `-d`, allow a lower version code than the installed one
+    pub allow_downgrade: bool,
+    /// `-r`, reinstall keeping data
+    pub replace_existing: bool,
+    /// `-t`, allow APKs with `android:testOnly`
+    pub allow_test: bool,
+}
+
+impl InstallOptions {
+    fn to_args(&self) -> Vec<&'static str> {
+        let mut args = Vec::new();
+        if self.grant_permissions {
+            args.push("-g");
+        }
+        if self.allow_downgrade {
+            args.push("-d");
+        }
+        if self.replace_existing {
+            args.push("-r");
+        }
+        if self.allow_test {
+            args.push("-t");
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum InstallFailureReason {
+    InsufficientStorage,
+    VersionDowngrade,
+    UpdateIncompatible,
+    Other(String),
+}
+
+impl InstallFailureReason {
+    /// Reads the `INSTALL_FAILED_*` code out of a `pm install` failure.
+    pub fn from_output(output: &str) -> Option<Self> {
+        let start = output.find("INSTALL_")?;
+        let code: String = output[start..]
+            .chars()
+            .take_while(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || *c == '_')
+            .collect();
+
+        Some(match code.as_str() {
+            "INSTALL_FAILED_INSUFFICIENT_STORAGE" => InstallFailureReason::InsufficientStorage,
+            "INSTALL_FAILED_VERSION_DOWNGRADE" => InstallFailureReason::VersionDowngrade,
+            "INSTALL_FAILED_UPDATE_INCOMPATIBLE" => InstallFailureReason::UpdateIncompatible,
+            _ => InstallFailureReason::Other(code),
+        })
+    }
+
+    /// What the user can do about it, for the UI to show next to the error.
+    pub fn suggestion(&self) -> Option<&'static str> {
+        match self {
+            InstallFailureReason::InsufficientStorage => {
+                Some("Free up space on the device and try again")
+            }
+            InstallFailureReason::VersionDowngrade => {
+                Some("Allow downgrade (-d) to install an older version")
+            }
+            InstallFailureReason::UpdateIncompatible => {
+                Some("The installed app is signed with a different key, uninstall it first")
+            }
+            InstallFailureReason::Other(_) => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UninstallResult {
     pub success: bool,
     pub message: String,
+    /// `DELETE_FAILED_*` code from the brackets of the `Failure [...]` line
+    #[serde(default)]
+    pub error_code: Option<String>,
 }
 
 pub fn list_installed_packages(
     device: &mut ADBServerDevice,
     filter: PackageFilter,
 ) -> Result<Vec<String>, AppManagerError> {
-    let mut args = vec!["pm", "list"];
+    let command = pm_list_packages_command(filter, None);
+    let output = run_shell_command(device, &command)?;
+
+    Ok(parse_package_list(&output))
+}
+
+/// The names of the packages matching `filter`, for comparing what is installed before and
+/// after a change.
+pub fn installed_packages_set(
+    device: &mut ADBServerDevice,
+    filter: PackageFilter,
+) -> Result<HashSet<String>, AppManagerError> {
+    Ok(list_installed_packages(device, filter)?
+        .into_iter()
+        .collect())
+}
+
+/// Lists packages whose name contains `query`, letting `pm` do the filtering on the device.
+pub fn search_packages(
+    device: &mut ADBServerDevice,
+    filter: PackageFilter,
+    query: &str,
+) -> Result<Vec<String>, AppManagerError> {
+    let query = query.trim();
+    if query.contains(|c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '_') {
+        // package names never contain anything else, so nothing can match
+        return Ok(Vec::new());
+    }
+
+    let command = pm_list_packages_command(filter, Some(query).filter(|q| !q.is_empty()));
+    let output = run_shell_command(device, &command)?;
+
+    Ok(parse_package_list(&output))
+}
+
+fn pm_list_packages_command(filter: PackageFilter, query: Option<&str>) -> String {
+    let mut args = vec!["pm", "list", "packages"];
 
     match filter {
-        PackageFilter::All => args.extend(["packages", ""]),
-        PackageFilter::System => args.extend(["packages", "-s"]),
-        PackageFilter::ThirdParty => args.extend(["packages", "-3"]),
-        PackageFilter::Enabled => args.extend(["packages", "-e"]),
-        PackageFilter::Disabled => args.extend(["packages", "-d"]),
+        PackageFilter::All => {}
+        PackageFilter::System => args.push("-s"),
+        PackageFilter::ThirdParty => args.push("-3"),
+        PackageFilter::Enabled => args.push("-e"),
+        PackageFilter::Disabled => args.push("-d"),
     };
 
-    let command = args.join(" ");
-    let output = run_shell_command(device, &command)?;
+    if let Some(query) = query {
+        args.push(query);
+    }
+
+    args.join(" ")
+}
 
-    let packages: Vec<String> = output
+fn parse_package_list(output: &str) -> Vec<String> {
+    output
         .lines()
-        .filter_map(|line| line.strip_prefix("package:"))
+        .filter_map(|line| line.trim().strip_prefix("package:"))
         .map(|s| s.to_string())
-        .collect();
-
-    Ok(packages)
+        .collect()
 }
 
 pub fn get_app_info(
     device: &mut ADBServerDevice,
     package_name: &str,
 ) -> Result<AppInfo, AppManagerError> {
-    let command = format!("pm dump {}", package_name);
+    let command = format!("pm dump {}", shell_quote(package_name));
     let output = run_shell_command(device, &command)?;
+    let mut info = parse_package_dump(package_name, &output);
+
+    if info.label.is_none() {
+        info.label = output
+            .lines()
+            .find_map(extract_label)
+            .or_else(|| resolve_app_label(device, package_name));
+    }
+    info.split_apks = get_app_splits(device, package_name).unwrap_or_default();
+
+    let (app_size_bytes, data_size_bytes) = run_shell_command(device, "dumpsys diskstats")
+        .map(|out| parse_diskstats(&out, package_name))
+        .unwrap_or((None, None));
+    info.app_size_bytes = app_size_bytes.or_else(|| {
+        info.apk_path
+            .as_deref()
+            .and_then(|path| disk_usage(device, path))
+    });
+    info.data_size_bytes = data_size_bytes.or_else(|| {
+        info.data_dir
+            .as_deref()
+            .and_then(|path| disk_usage(device, path))
+    });
+
+    Ok(info)
+}
+
+/// Paths of the base APK and every split APK of `package_name`, base first.
+pub fn get_app_splits(
+    device: &mut ADBServerDevice,
+    package_name: &str,
+) -> Result<Vec<String>, AppManagerError> {
+    let output = run_shell_command(device, &format!("pm path {}", shell_quote(package_name)))?;
+    let paths = parse_pm_path(&output);
+    if paths.is_empty() {
+        return Err(AppManagerError::PackageNotFound(package_name.to_string()));
+    }
+    Ok(paths)
+}
+
+// `package:/data/app/.../base.apk` per APK. The base usually comes first but not always.
+fn parse_pm_path(output: &str) -> Vec<String> {
+    let mut paths: Vec<String> = output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("package:"))
+        .map(String::from)
+        .collect();
+    if let Some(base) = paths.iter().position(|p| p.ends_with("/base.apk")) {
+        paths[..=base].rotate_right(1);
+    }
+    paths
+}
 
+/// The package section of `pm dump`. Only the first value of each field is kept, an
+/// updated system app lists its factory version again under "Hidden system packages".
+fn parse_package_dump(package_name: &str, output: &str) -> AppInfo {
     let mut version_name = None;
     let mut version_code = None;
+    let mut min_sdk = None;
+    let mut target_sdk = None;
     let mut label = None;
-    let mut install_location = InstallLocation::Unknown;
-    let mut flags = Vec::new();
+    let mut install_location = None;
+    let mut flags: Option<Vec<String>> = None;
     let mut first_install_time = None;
     let mut last_update_time = None;
     let mut apk_path = None;
     let mut data_dir = None;
-    let mut is_system_app = false;
-    let mut is_enabled = true;
+    let mut is_enabled = None;
 
     for line in output.lines() {
         let line = line.trim();
+        let value = |key: &str| line.strip_prefix(key).map(str::to_string);
 
         if line.starts_with("versionName=") {
-            version_name = Some(line.trim_start_matches("versionName=").to_string());
+            version_name = version_name.or(value("versionName="));
         } else if line.starts_with("versionCode=") {
-            if let Some(code) = line.trim_start_matches("versionCode=").split(' ').next() {
-                version_code = code.parse().ok();
-            }
+            // `versionCode=12 minSdk=21 targetSdk=33`, older builds have no minSdk
+            version_code =
+                version_code.or(dump_field(line, "versionCode").and_then(|v| v.parse().ok()));
+            min_sdk = min_sdk.or(dump_field(line, "minSdk").and_then(|v| v.parse().ok()));
+            target_sdk = target_sdk.or(dump_field(line, "targetSdk").and_then(|v| v.parse().ok()));
         } else if line.starts_with("pkgFlags=") {
             let flag_str = line.trim_start_matches("pkgFlags=");
-            flags = flag_str.split(' ').map(|s| s.to_string()).collect();
-            is_system_app = flags.contains(&"SYSTEM".to_string());
+            flags = flags.or(Some(flag_str.split(' ').map(|s| s.to_string()).collect()));
         } else if line.starts_with("installLocation=") {
-            let loc = line.trim_start_matches("installLocation=");
-            install_location = InstallLocation::from_str(loc);
+            install_location = install_location.or(Some(InstallLocation::from_str(
+                line.trim_start_matches("installLocation="),
+            )));
         } else if line.starts_with("firstInstallTime=") {
-            first_install_time = Some(line.trim_start_matches("firstInstallTime=").to_string());
+            first_install_time = first_install_time.or(value("firstInstallTime="));
         } else if line.starts_with("lastUpdateTime=") {
-            last_update_time = Some(line.trim_start_matches("lastUpdateTime=").to_string());
+            last_update_time = last_update_time.or(value("lastUpdateTime="));
         } else if line.starts_with("codePath=") {
-            apk_path = Some(line.trim_start_matches("codePath=").to_string());
+            apk_path = apk_path.or(value("codePath="));
         } else if line.starts_with("dataDir=") {
-            data_dir = Some(line.trim_start_matches("dataDir=").to_string());
+            data_dir = data_dir.or(value("dataDir="));
         } else if line.starts_with("label=") {
-            label = Some(line.trim_start_matches("label=").to_string());
+            label = label.or(extract_label(line));
         } else if line.starts_with("enabled=") {
-            is_enabled = line.contains("true");
+            is_enabled = is_enabled.or(Some(line.contains("true")));
         }
     }
 
-    Ok(AppInfo {
+    let flags = flags.unwrap_or_default();
+    AppInfo {
         package_name: package_name.to_string(),
         version_name,
         version_code,
+        min_sdk,
+        target_sdk,
         label,
-        install_location,
+        install_location: install_location.unwrap_or(InstallLocation::Unknown),
+        is_system_app: flags.iter().any(|f| f == "SYSTEM"),
         flags,
         first_install_time,
         last_update_time,
         apk_path,
+        split_apks: Vec::new(),
         data_dir,
-        is_system_app,
-        is_enabled,
-    })
+        is_enabled: is_enabled.unwrap_or(true),
+        app_size_bytes: None,
+        data_size_bytes: None,
+    }
+}
+
+/// `value` of a `key=value` token on a `pm dump` line.
+fn dump_field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.split_whitespace()
+        .find_map(|token| token.strip_prefix(key)?.strip_prefix('='))
+}
+
+/// App and data sizes for `package_name` from `dumpsys diskstats`, which lists all
+/// packages in one array and their sizes in parallel arrays.
+fn parse_diskstats(output: &str, package_name: &str) -> (Option<u64>, Option<u64>) {
+    let array = |prefix: &str| -> Option<Vec<String>> {
+        let line = output.lines().find(|l| l.trim().starts_with(prefix))?;
+        let inner = line.split_once('[')?.1.rsplit_once(']')?.0;
+        Some(
+            inner
+                .split(',')
+                .map(|v| v.trim().trim_matches('"').to_string())
+                .collect(),
+        )
+    };
+
+    let Some(index) = array("Package Names:")
+        .and_then(|names| names.iter().position(|name| name == package_name))
+    else {
+        return (None, None);
+    };
+    let size_at = |prefix: &str| array(prefix)?.get(index)?.parse::<u64>().ok();
+
+    (size_at("App Sizes:"), size_at("App Data Sizes:"))
+}
+
+// Fallback when diskstats is restricted. toybox du may not know -b, so retry in KiB.
+fn disk_usage(device: &mut ADBServerDevice, path: &str) -> Option<u64> {
+    let first_field = |out: String| out.split_whitespace().next()?.parse::<u64>().ok();
+
+    run_shell_command(device, &format!("du -sb {} 2>/dev/null", shell_quote(path)))
+        .ok()
+        .and_then(first_field)
+        .or_else(|| {
+            run_shell_command(device, &format!("du -sk {} 2>/dev/null", shell_quote(path)))
+                .ok()
+                .and_then(first_field)
+                .map(|kb| kb * 1024)
+        })
+}
+
+/// Last resort for the label when the `pm dump` has none. Labels defined as a plain string
+/// in the manifest show up as `nonLocalizedLabel` in the resolve-activity dump, resource
+/// based labels can't be read without aapt.
+fn resolve_app_label(device: &mut ADBServerDevice, package_name: &str) -> Option<String> {
+    let command = format!(
+        "cmd package resolve-activity -c android.intent.category.LAUNCHER {}",
+        shell_quote(package_name)
+    );
+    let output = run_shell_command(device, &command).ok()?;
+    output.lines().find_map(extract_label)
+}
+
+fn extract_label(line: &str) -> Option<String> {
+    let rest = ["nonLocalizedLabel=", "label="].iter().find_map(|key| {
+        let pos = line.find(key)?;
+        // don't match the `label=` inside `nonLocalizedLabel=`
+        let preceded_by_word = line[..pos]
+            .chars()
+            .last()
+            .is_some_and(|c| c.is_alphanumeric());
+        (!preceded_by_word).then(|| &line[pos + key.len()..])
+    })?;
+
+    // Dumps put several `key=value` pairs on one line, the label ends where the next
+    // key starts
+    let mut end = rest.len();
+    for (i, _) in rest.match_indices(' ') {
+        let next = &rest[i + 1..];
+        let key_len = next
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(next.len());
+        if key_len > 0 && next[key_len..].starts_with('=') {
+            end = i;
+            break;
+        }
+    }
+
+    let label = rest[..end].trim();
+    if label.is_empty() || label == "null" {
+        None
+    } else {
+        Some(label.to_string())
+    }
 }
 
+const INSTALL_TMP_DIR: &str = "/data/local/tmp";
+// Android uid = user id * 100000 + app id
+pub(crate) const PER_USER_RANGE: u32 = 100_000;
+
+/// Pushes the APK at `apk_path` on the host to a temp file and installs it with
+/// `pm install`. The temp file is removed afterwards, including when the push or the
+/// install fails halfway.
 pub fn install_app(
     device: &mut ADBServerDevice,
     apk_path: &str,
     grant_permissions: bool,
 ) -> Result<InstallResult, AppManagerError> {
-    let mut args = vec!["install"];
+    let options = InstallOptions {
+        grant_permissions,
+        ..Default::default()
+    };
+    install_app_with_options(device, apk_path, &options)
+}
+
+pub fn install_app_with_options(
+    device: &mut ADBServerDevice,
+    apk_path: &str,
+    options: &InstallOptions,
+) -> Result<InstallResult, AppManagerError> {
+    install_app_with_progress(device, apk_path, options, |_| {}, &CancelToken::new())
+}
+
+/// Where [`install_app_with_progress`] is at.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum InstallStage {
+    /// Copying the APK to the device
+    Uploading { bytes: u64, total: u64 },
+    /// `pm install` is running, this is the last stage
+    Committing,
+}
 
-    if grant_permissions {
-        args.push("-g");
+/// [`install_app_with_options`] reporting each stage to `progress` as it goes.
+///
+/// `cancel` is checked while the APK uploads, a cancelled install stops with `Cancelled`
+/// before `pm install` runs.
+pub fn install_app_with_progress(
+    device: &mut ADBServerDevice,
+    apk_path: &str,
+    options: &InstallOptions,
+    mut progress: impl FnMut(InstallStage),
+    cancel: &CancelToken,
+) -> Result<InstallResult, AppManagerError> {
+    let file_name = std::path::Path::new(apk_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or_else(|| AppManagerError::InstallFailed(format!("not a file: {}", apk_path)))?;
+    let remote_path = format!("{}/kira_{}", INSTALL_TMP_DIR, file_name);
+
+    let apk = std::fs::File::open(apk_path)
+        .map_err(|e| AppManagerError::InstallFailed(format!("{}: {}", apk_path, e)))?;
+    let apk_size = apk.metadata().map(|m| m.len()).ok();
+    let mut upload = ProgressReader {
+        inner: apk,
+        read: 0,
+        total: apk_size.unwrap_or_default(),
+        progress: &mut progress,
+        cancel,
+    };
+    let pushed = device.push(&mut upload, &remote_path);
+    if cancel.is_cancelled() {
+        remove_remote_file(device, &remote_path);
+        return Err(AppManagerError::Cancelled);
     }
+    if let Err(e) = pushed {
+        remove_remote_file(device, &remote_path);
+        return Err(AppManagerError::InstallFailed(e.to_string()));
+    }
+
+    let mut args = vec!["pm", "install"];
+    args.extend(options.to_args());
 
-    args.push(apk_path);
+    let remote_path_arg = shell_quote(&remote_path);
+    args.push(&remote_path_arg);
 
+    let before_packages = installed_packages_set(device, PackageFilter::ThirdParty).ok();
+    let before_paths = installed_package_paths(device).ok();
+
+    progress(InstallStage::Committing);
     let command = args.join(" ");
-    let output = run_shell_command(device, &command)?;
+    let output = run_shell_command(device, &command);
+    remove_remote_file(device, &remote_path);
+    let output = output?;
 
     if output.contains("Success") {
-        let package_name = extract_package_name_from_apk(device, apk_path)?;
+        let added = before_packages.and_then(|before| {
+            let after = installed_packages_set(device, PackageFilter::ThirdParty).ok()?;
+            added_package(&before, &after)
+        });
+        // A reinstall or update adds no package name, only a new path
+        let package_name = added.or_else(|| {
+            let after = installed_package_paths(device).ok()?;
+            newly_installed_package(&before_paths?, &after)
+        });
         Ok(InstallResult {
             success: true,
             message: "App installed successfully".to_string(),
-            package_name: Some(package_name),
+            package_name,
+            failure_reason: None,
+            error_code: None,
         })
     } else {
-        let error_msg = extract_error_message(&output);
+        let failure_reason = InstallFailureReason::from_output(&output);
+        let mut error_msg = extract_error_message(&output);
+        if failure_reason == Some(InstallFailureReason::InsufficientStorage)
+            && let Some(size) = apk_size
+        {
+            error_msg = format!("{} (the APK is {})", error_msg, format_bytes(size));
+        }
         Ok(InstallResult {
             success: false,
             message: error_msg,
             package_name: None,
+            failure_reason,
+            error_code: extract_error_code(&output),
         })
     }
 }
 
+/// Reports [`InstallStage::Uploading`] as the push reads the APK, and fails the read once
+/// `cancel` is set.
+struct ProgressReader<'a, R> {
+    inner: R,
+    read: u64,
+    total: u64,
+    progress: &'a mut dyn FnMut(InstallStage),
+    cancel: &'a CancelToken,
+}
+
+impl<R: std::io::Read> std::io::Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.cancel.is_cancelled() {
+            return Err(std::io::Error::other("cancelled"));
+        }
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        (self.progress)(InstallStage::Uploading {
+            bytes: self.read,
+            total: self.total,
+        });
+        Ok(n)
+    }
+}
+
+/// Downloads the APK at `url` to a temporary file on the host and installs it with
+/// [`install_app_with_options`]. The temporary file is removed afterwards.
+///
+/// Download problems come back as `DownloadFailed`, a file that isn't a ZIP as
+/// `InstallFailed`. The download blocks, so call this from [`run_blocking`] in async code.
+///
+/// [`run_blocking`]: crate::device::shell::run_blocking
+pub fn install_from_url(
+    device: &mut ADBServerDevice,
+    url: &str,
+    options: &InstallOptions,
+) -> Result<InstallResult, AppManagerError> {
+    let local_path = std::env::temp_dir().join(format!(
+        "kira_{}_{}",
+        std::process::id(),
+        apk_file_name_from_url(url)
+    ));
+
+    let result = download_apk(url, &local_path)
+        .and_then(|()| install_app_with_options(device, &local_path.to_string_lossy(), options));
+    let _ = std::fs::remove_file(&local_path);
+    result
+}
+
+fn download_apk(url: &str, local_path: &std::path::Path) -> Result<(), AppManagerError> {
+    let mut response = reqwest::blocking::get(url)
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| AppManagerError::DownloadFailed(e.to_string()))?;
+
+    let mut file = std::fs::File::create(local_path)
+        .map_err(|e| AppManagerError::InstallFailed(format!("{}: {}", local_path.display(), e)))?;
+    response
+        .copy_to(&mut file)
+        .map_err(|e| AppManagerError::DownloadFailed(e.to_string()))?;
+
+    let mut magic = [0u8; 4];
+    let is_zip = std::fs::File::open(local_path)
+        .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut magic))
+        .is_ok()
+        && is_zip_magic(&magic);
+    if !is_zip {
+        return Err(AppManagerError::InstallFailed(format!(
+            "{} is not an APK",
+            url
+        )));
+    }
+
+    Ok(())
+}
+
+/// APKs are ZIP archives, which start with a local file header.
+fn is_zip_magic(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"PK\x03\x04")
+}
+
+/// Last path segment of `url` when it names an APK, so the device side temp file is
+/// recognisable, otherwise a generic name.
+fn apk_file_name_from_url(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let name = path.rsplit('/').next().unwrap_or_default();
+
+    let safe = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if safe && name.len() > 4 && name.to_ascii_lowercase().ends_with(".apk") {
+        name.to_string()
+    } else {
+        "download.apk".to_string()
+    }
+}
+
+fn remove_remote_file(device: &mut ADBServerDevice, remote_path: &str) {
+    let _ = run_shell_command(device, &format!("rm -f {}", shell_quote(remote_path)));
+}
+
+/// How long `adb backup` may wait for the user to allow the backup on the device.
+const BACKUP_CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+/// `adb restore` gives no sign of progress, so this covers confirming and restoring.
+const RESTORE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const BACKUP_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const BACKUP_MAGIC: &[u8] = b"ANDROID BACKUP\n";
+
+/// Backs up the APK and data of `package` to `dest_path` on the host with `adb backup`
+/// and returns the size of the backup file.
+///
+/// The device shows a confirmation screen, nothing is written until the user taps "Back up
+/// my data" there. If that doesn't happen within a minute the backup is abandoned with
+/// `Timeout`. `adb backup` is deprecated and apps can opt out of it, in which case the
+/// backup comes back without their data.
+pub fn backup_app(
+    device: &mut ADBServerDevice,
+    package: &str,
+    dest_path: &str,
+) -> Result<u64, AppManagerError> {
+    let mut command = adb_command(device);
+    command.args(["backup", "-f", dest_path, "-apk", package]);
+    let mut child = command
+        .spawn()
+        .map_err(|e| AppManagerError::CommandFailed(e.to_string()))?;
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .map_err(|e| AppManagerError::CommandFailed(e.to_string()))?
+        {
+            break status;
+        }
+        // adb only writes to the file once the user has allowed the backup
+        let written = std::fs::metadata(dest_path).map(|m| m.len()).unwrap_or(0);
+        if written == 0 && started.elapsed() > BACKUP_CONFIRM_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            let _ = std::fs::remove_file(dest_path);
+            return Err(AppManagerError::Timeout(
+                "backup was not confirmed on the device".to_string(),
+            ));
+        }
+        thread::sleep(BACKUP_POLL_INTERVAL);
+    };
+
+    let mut header = [0u8; BACKUP_MAGIC.len()];
+    let valid = std::fs::File::open(dest_path)
+        .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut header))
+        .is_ok()
+        && header == BACKUP_MAGIC;
+    if !status.success() || !valid {
+        return Err(AppManagerError::CommandFailed(format!(
+            "adb backup of {} did not produce a backup file",
+            package
+        )));
+    }
+
+    std::fs::metadata(dest_path)
+        .map(|m| m.len())
+        .map_err(|e| AppManagerError::CommandFailed(format!("{}: {}", dest_path, e)))
+}
+
+/// Restores a backup made by [`backup_app`] with `adb restore`. Like the backup this needs
+/// confirming on the device, and fails with `Timeout` if the whole restore takes longer
+/// than ten minutes.
+pub fn restore_backup(device: &mut ADBServerDevice, ab_path: &str) -> Result<(), AppManagerError> {
+    if !std::path::Path::new(ab_path).is_file() {
+        return Err(AppManagerError::CommandFailed(format!(
+            "backup file not found: {}",
+            ab_path
+        )));
+    }
+
+    let mut command = adb_command(device);
+    command.args(["restore", ab_path]);
+    let mut child = command
+        .spawn()
+        .map_err(|e| AppManagerError::CommandFailed(e.to_string()))?;
+
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .map_err(|e| AppManagerError::CommandFailed(e.to_string()))?
+        {
+            return if status.success() {
+                Ok(())
+            } else {
+                Err(AppManagerError::CommandFailed(format!(
+                    "adb restore exited with {}",
+                    status
+                )))
+            };
+        }
+        if started.elapsed() > RESTORE_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(AppManagerError::Timeout(
+                "restore was not confirmed or did not finish".to_string(),
+            ));
+        }
+        thread::sleep(BACKUP_POLL_INTERVAL);
+    }
+}
+
+/// Host side `adb` for commands that have no equivalent over the server protocol.
+fn adb_command(device: &ADBServerDevice) -> std::process::Command {
+    let mut command = host_adb();
+    command.args(adb_config().host_args());
+    if let Some(serial) = &device.identifier {
+        command.args(["-s", serial]);
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command
+}
+
 pub fn uninstall_app(
     device: &mut ADBServerDevice,
     package_name: &str,
 ) -> Result<UninstallResult, AppManagerError> {
-    let command = format!("pm uninstall {}", package_name);
+    let command = format!("pm uninstall {}", shell_quote(package_name));
     let output = run_shell_command(device, &command)?;
 
     if output.contains("Success") {
         Ok(UninstallResult {
             success: true,
             message: "App uninstalled successfully".to_string(),
+            error_code: None,
         })
     } else {
         let error_msg = extract_error_message(&output);
         Ok(UninstallResult {
             success: false,
             message: error_msg,
+            error_code: extract_error_code(&output),
         })
     }
 }
@@ -237,19 +868,21 @@ pub fn uninstall_app_with_keep_data(
     device: &mut ADBServerDevice,
     package_name: &str,
 ) -> Result<UninstallResult, AppManagerError> {
-    let command = format!("pm uninstall -k {}", package_name);
+    let command = format!("pm uninstall -k {}", shell_quote(package_name));
     let output = run_shell_command(device, &command)?;
 
     if output.contains("Success") {
         Ok(UninstallResult {
             success: true,
             message: "App uninstalled (data kept)".to_string(),
+            error_code: None,
         })
     } else {
         let error_msg = extract_error_message(&output);
         Ok(UninstallResult {
             success: false,
             message: error_msg,
+            error_code: extract_error_code(&output),
         })
     }
 }
@@ -258,24 +891,24 @@ pub fn get_app_permissions(
     device: &mut ADBServerDevice,
     package_name: &str,
 ) -> Result<AppPermissions, AppManagerError> {
-    let command = format!("pm dump {}", package_name);
+    let command = format!("pm dump {}", shell_quote(package_name));
     let output = run_shell_command(device, &command)?;
 
     let mut permissions = Vec::new();
 
     for line in output.lines() {
-        if line.contains("granted=true") || line.contains("granted=false") {
-            if let Some(name_start) = line.find("name=") {
-                let name_line = &line[name_start..];
-                if let Some(name_end) = name_line.find(']') {
-                    let name = name_line[5..name_end].to_string();
-                    let status = if line.contains("granted=true") {
-                        PermissionStatus::Granted
-                    } else {
-                        PermissionStatus::Denied
-                    };
-                    permissions.push(PermissionInfo { name, status });
-                }
+        if (line.contains("granted=true") || line.contains("granted=false"))
+            && let Some(name_start) = line.find("name=")
+        {
+            let name_line = &line[name_start..];
+            if let Some(name_end) = name_line.find(']') {
+                let name = name_line[5..name_end].to_string();
+                let status = if line.contains("granted=true") {
+                    PermissionStatus::Granted
+                } else {
+                    PermissionStatus::Denied
+                };
+                permissions.push(PermissionInfo { name, status });
             }
         }
     }
@@ -290,7 +923,7 @@ pub fn clear_app_data(
     device: &mut ADBServerDevice,
     package_name: &str,
 ) -> Result<(), AppManagerError> {
-    let command = format!("pm clear {}", package_name);
+    let command = format!("pm clear {}", shell_quote(package_name));
     run_shell_command(device, &command)?;
     Ok(())
 }
@@ -299,7 +932,7 @@ pub fn force_stop_app(
     device: &mut ADBServerDevice,
     package_name: &str,
 ) -> Result<(), AppManagerError> {
-    let command = format!("am force-stop {}", package_name);
+    let command = format!("am force-stop {}", shell_quote(package_name));
     run_shell_command(device, &command)?;
     Ok(())
 }
@@ -308,17 +941,54 @@ pub fn disable_app(
     device: &mut ADBServerDevice,
     package_name: &str,
 ) -> Result<(), AppManagerError> {
-    let command = format!("pm disable-user {}", package_name);
-    run_shell_command(device, &command)?;
+    set_app_enabled(device, package_name, false)?;
     Ok(())
 }
 
 pub fn enable_app(device: &mut ADBServerDevice, package_name: &str) -> Result<(), AppManagerError> {
-    let command = format!("pm enable {}", package_name);
-    run_shell_command(device, &command)?;
+    set_app_enabled(device, package_name, true)?;
     Ok(())
 }
 
+/// Enables or disables `package_name` for the current user and returns the state `pm`
+/// reports afterwards.
+pub fn set_app_enabled(
+    device: &mut ADBServerDevice,
+    package_name: &str,
+    enabled: bool,
+) -> Result<bool, AppManagerError> {
+    let action = if enabled { "enable" } else { "disable-user" };
+    let command = format!("pm {} {}", action, shell_quote(package_name));
+    let output = run_shell_command(device, &command)?;
+    parse_new_state(&output, package_name)
+}
+
+// `Package com.example new state: disabled-user`
+fn parse_new_state(output: &str, package_name: &str) -> Result<bool, AppManagerError> {
+    if let Some(state) = output
+        .lines()
+        .find_map(|line| line.split_once("new state:").map(|(_, s)| s.trim()))
+    {
+        return match state {
+            "enabled" | "default" => Ok(true),
+            s if s.starts_with("disabled") => Ok(false),
+            s => Err(AppManagerError::ParseError(format!("unknown state: {}", s))),
+        };
+    }
+
+    if output.contains("Unknown package") {
+        Err(AppManagerError::PackageNotFound(package_name.to_string()))
+    } else if output.contains("SecurityException") || output.contains("protected package") {
+        Err(AppManagerError::PermissionDenied(extract_error_message(
+            output,
+        )))
+    } else {
+        Err(AppManagerError::CommandFailed(extract_error_message(
+            output,
+        )))
+    }
+}
+
 pub fn get_launcher_activity(
     device: &mut ADBServerDevice,
     package_name: &str,
@@ -342,7 +1012,7 @@ pub fn start_app(device: &mut ADBServerDevice, package_name: &str) -> Result<(),
 
     match activity {
         Some(act) => {
-            let command = format!("am start -n {}", act);
+            let command = format!("am start -n {}", shell_quote(&act));
             run_shell_command(device, &command)?;
             Ok(())
         }
@@ -354,135 +1024,742 @@ pub fn start_app_with_activity(
     device: &mut ADBServerDevice,
     activity: &str,
 ) -> Result<(), AppManagerError> {
-    let command = format!("am start -n {}", activity);
+    let command = format!("am start -n {}", shell_quote(activity));
     run_shell_command(device, &command)?;
     Ok(())
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-pub enum PackageFilter {
-    All,
-    System,
-    ThirdParty,
-    Enabled,
-    Disabled,
+/// Typed value for an intent extra, passed as `--es`, `--ei` or `--ez`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum IntentExtra {
+    String(String),
+    Int(i32),
+    Bool(bool),
 }
 
-fn run_shell_command(
+fn extra_args(extras: &[(String, IntentExtra)]) -> String {
+    extras
+        .iter()
+        .map(|(key, value)| {
+            let key = shell_quote(key);
+            match value {
+                IntentExtra::String(v) => format!(" --es {} {}", key, shell_quote(v)),
+                IntentExtra::Int(v) => format!(" --ei {} {}", key, v),
+                IntentExtra::Bool(v) => format!(" --ez {} {}", key, v),
+            }
+        })
+        .collect()
+}
+
+/// Sends `action` with `am broadcast` and returns the `Broadcast completed: result=0`
+/// line.
+pub fn send_broadcast(
     device: &mut ADBServerDevice,
-    command: &str,
+    action: &str,
+    extras: &[(String, IntentExtra)],
 ) -> Result<String, AppManagerError> {
-    let mut output = Vec::new();
-    device
-        .shell_command(&command, Some(&mut output), None)
-        .map_err(|e| AppManagerError::CommandFailed(e.to_string()))?;
+    let command = broadcast_command(action, extras);
+    let output = run_shell_command(device, &command)?;
 
-    String::from_utf8(output)
-        .map_err(|e| AppManagerError::ParseError(e.to_string()))
-        .map(|s| s.trim().to_string())
+    if output.contains("SecurityException") {
+        return Err(AppManagerError::PermissionDenied(extract_error_message(
+            &output,
+        )));
+    }
+    output
+        .lines()
+        .find(|l| l.starts_with("Broadcast completed"))
+        .map(|l| l.trim().to_string())
+        .ok_or_else(|| AppManagerError::CommandFailed(extract_error_message(&output)))
 }
 
-fn extract_package_name_from_apk(
-    _device: &mut ADBServerDevice,
-    apk_path: &str,
-) -> Result<String, AppManagerError> {
-    let name = std::path::Path::new(apk_path)
-        .file_stem()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_default();
+fn broadcast_command(action: &str, extras: &[(String, IntentExtra)]) -> String {
+    format!(
+        "am broadcast -a {}{}",
+        shell_quote(action),
+        extra_args(extras)
+    )
+}
 
-    Ok(name)
+/// Everything `am start` can be given to build an intent. Unset parts are left out.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct IntentSpec {
+    /// `-a`, e.g. `android.intent.action.VIEW`
+    pub action: Option<String>,
+    /// `-d`, the `&` and `?` deep links usually carry are quoted for the shell
+    pub data_uri: Option<String>,
+    /// `-t`
+    pub mime_type: Option<String>,
+    /// `-c`
+    pub category: Option<String>,
+    /// `-n`, `com.example/.MainActivity`
+    pub component: Option<String>,
+    pub extras: Vec<(String, IntentExtra)>,
 }
 
-fn extract_error_message(output: &str) -> String {
-    for line in output.lines() {
-        if line.contains("Failure") || line.contains("Error") || line.contains("error") {
-            return line.trim().to_string();
+/// Starts an activity from a full intent, for deep links like
+/// `am start -a android.intent.action.VIEW -d https://example.com/item?id=1`.
+pub fn start_intent(
+    device: &mut ADBServerDevice,
+    intent: &IntentSpec,
+) -> Result<(), AppManagerError> {
+    let command = start_intent_command(intent);
+    let output = run_shell_command(device, &command)?;
+
+    // `Error: Activity not started, unable to resolve Intent { ... }`
+    if output.lines().any(|l| l.starts_with("Error")) {
+        return Err(AppManagerError::CommandFailed(extract_error_message(
+            &output,
+        )));
+    }
+    if output.contains("SecurityException") {
+        return Err(AppManagerError::PermissionDenied(extract_error_message(
+            &output,
+        )));
+    }
+    Ok(())
+}
+
+fn start_intent_command(intent: &IntentSpec) -> String {
+    let mut command = "am start".to_string();
+    let options = [
+        ("-a", &intent.action),
+        ("-d", &intent.data_uri),
+        ("-t", &intent.mime_type),
+        ("-c", &intent.category),
+        ("-n", &intent.component),
+    ];
+    for (flag, value) in options {
+        if let Some(value) = value {
+            command.push_str(&format!(" {} {}", flag, shell_quote(value)));
         }
     }
-    output.lines().last().unwrap_or("Unknown error").to_string()
+    command.push_str(&extra_args(&intent.extras));
+    command
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub enum AppManagerError {
-    PackageNotFound(String),
-    ActivityNotFound(String),
-    InstallFailed(String),
-    UninstallFailed(String),
-    CommandFailed(String),
-    ParseError(String),
-    PermissionDenied(String),
+pub struct AppComponent {
+    /// `com.example/.MainActivity`, ready for `am start -n`
+    pub name: String,
+    /// Declares an intent filter, which makes it reachable from other apps unless the
+    /// manifest sets `android:exported="false"`. Always false for providers.
+    pub exported: bool,
 }
 
-impl std::fmt::Display for AppManagerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            AppManagerError::PackageNotFound(pkg) => write!(f, "Package not found: {}", pkg),
-            AppManagerError::ActivityNotFound(pkg) => {
-                write!(f, "Launcher activity not found: {}", pkg)
-            }
-            AppManagerError::InstallFailed(msg) => write!(f, "Install failed: {}", msg),
-            AppManagerError::UninstallFailed(msg) => write!(f, "Uninstall failed: {}", msg),
-            AppManagerError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
-            AppManagerError::ParseError(msg) => write!(f, "Parse error: {}", msg),
-            AppManagerError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
-        }
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AppComponents {
+    pub activities: Vec<AppComponent>,
+    pub services: Vec<AppComponent>,
+    pub receivers: Vec<AppComponent>,
+    pub providers: Vec<AppComponent>,
+}
+
+/// Components of `package_name` from the resolver tables of `dumpsys package`. Only
+/// components with an intent filter show up there, plus every registered provider.
+pub fn get_app_components(
+    device: &mut ADBServerDevice,
+    package_name: &str,
+) -> Result<AppComponents, AppManagerError> {
+    let command = format!("dumpsys package {}", shell_quote(package_name));
+    let output = run_shell_command(device, &command)?;
+
+    if output.contains("Unable to find package") {
+        return Err(AppManagerError::PackageNotFound(package_name.to_string()));
     }
+
+    Ok(parse_app_components(&output, package_name))
 }
 
-impl std::error::Error for AppManagerError {}
+fn parse_app_components(output: &str, package_name: &str) -> AppComponents {
+    let mut components = AppComponents::default();
+    let mut section: Option<&mut Vec<AppComponent>> = None;
+    let own = format!("{}/", package_name);
 
-pub fn parse_top_package(output: &str) -> TopPackage {
-    let mut top_line = "";
     for line in output.lines() {
-        if line.contains("top-activity") {
-            top_line = line.trim();
-            break;
+        if !line.starts_with(char::is_whitespace) {
+            section = match line.trim() {
+                "Activity Resolver Table:" => Some(&mut components.activities),
+                "Service Resolver Table:" => Some(&mut components.services),
+                "Receiver Resolver Table:" => Some(&mut components.receivers),
+                "Registered ContentProviders:" => Some(&mut components.providers),
+                _ => None,
+            };
+            continue;
         }
-    }
-
-    if top_line.is_empty() {
-        return TopPackage {
-            name: "".to_string(),
-            pid: None,
+        let Some(list) = section.as_deref_mut() else {
+            continue;
         };
-    }
 
-    let parts: Vec<&str> = top_line.split_whitespace().collect();
-    if parts.len() >= 2 {
-        let pkg_part = parts[parts.len() - 2];
-        let subparts: Vec<&str> = pkg_part.split(':').collect();
-        if subparts.len() == 2 {
-            let pid = subparts[0].parse::<u32>().ok();
-            let mut name = subparts[1].to_string();
-            if let Some(slash_idx) = name.find('/') {
-                name = name[..slash_idx].to_string();
-            }
-            return TopPackage { name, pid };
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        // `5c9a0b1 com.example/.MainActivity filter 8b1d2e3` in the resolver tables,
+        // `com.example/.DataProvider:` in the provider list
+        let (name, exported) = match parts.as_slice() {
+            [_, name, "filter", ..] => (*name, true),
+            [name] if name.ends_with(':') => (name.trim_end_matches(':'), false),
+            _ => continue,
+        };
+        if !name.starts_with(&own) || list.iter().any(|c| c.name == name) {
+            continue;
         }
-    }
 
-    TopPackage {
-        name: "".to_string(),
-        pid: None,
+        list.push(AppComponent {
+            name: name.to_string(),
+            exported,
+        });
     }
+
+    components
 }
 
-pub fn get_top_package(device: &mut ADBServerDevice) -> Result<TopPackage, AppManagerError> {
-    let command = "dumpsys activity";
-    let mut output = Vec::new();
-    device
-        .shell_command(&command, Some(&mut output), None)
-        .map_err(|e| AppManagerError::CommandFailed(e.to_string()))?;
+/// Enables or disables a single activity, service, receiver or provider of `package`, e.g.
+/// a push receiver, and returns whether it is enabled now. `component` can be
+/// `.PushReceiver`, `com.example.PushReceiver` or `com.example/.PushReceiver`.
+///
+/// The component has to be declared by `package` according to `pm dump`. The shell user
+/// may only change components of apps it owns, so this retries with `su` and ends in
+/// `PermissionDenied` without root.
+pub fn set_component_enabled(
+    device: &mut ADBServerDevice,
+    package: &str,
+    component: &str,
+    enabled: bool,
+) -> Result<bool, AppManagerError> {
+    let class = component_class(package, component);
+    // Not `get_app_components`, components without an intent filter are missing there
+    let output = run_shell_command(device, &format!("pm dump {}", shell_quote(package)))?;
+    if output.contains("Unable to find package") {
+        return Err(AppManagerError::PackageNotFound(package.to_string()));
+    }
+    if !dump_has_component(&output, package, &class) {
+        return Err(AppManagerError::ComponentNotFound(format!(
+            "{}/{}",
+            package, class
+        )));
+    }
+
+    let action = if enabled { "enable" } else { "disable" };
+    let command = format!(
+        "pm {} {}",
+        action,
+        shell_quote(&format!("{}/{}", package, class))
+    );
+    let output = run_shell_command(device, &command)?;
+    match parse_new_state(&output, package) {
+        Err(AppManagerError::PermissionDenied(_)) => {}
+        result => return result,
+    }
+
+    let output = run_shell_command(device, &format!("su -c {}", shell_quote(&command)))?;
+    if is_su_unavailable(&output) {
+        return Err(AppManagerError::PermissionDenied(format!(
+            "changing components of {} needs root",
+            package
+        )));
+    }
+    parse_new_state(&output, package)
+}
+
+/// Whether `pm dump` output names `class` as a component of `package`, either as
+/// `package/class` or bare in the enabled and disabled component lists.
+fn dump_has_component(output: &str, package: &str, class: &str) -> bool {
+    let own = format!("{}/", package);
+    output
+        .split_whitespace()
+        .map(|token| token.trim_end_matches(':'))
+        .filter(|token| token.starts_with(&own) || !token.contains('/'))
+        .any(|token| component_class(package, token) == class)
+}
+
+/// Fully qualified class name of `component`, which may be relative to `package` and may
+/// carry a `package/` prefix.
+fn component_class(package: &str, component: &str) -> String {
+    let component = component.trim();
+    let class = component
+        .split_once('/')
+        .map_or(component, |(_, class)| class);
+    match class.strip_prefix('.') {
+        Some(_) => format!("{}{}", package, class),
+        None => class.to_string(),
+    }
+}
+
+/// Contents of `shared_prefs/<prefs_name>.xml` in the data directory of `package`.
+///
+/// Goes through `run-as` first, which works for debuggable apps without root, then `su`.
+pub fn read_app_prefs(
+    device: &mut ADBServerDevice,
+    package: &str,
+    prefs_name: &str,
+) -> Result<String, AppManagerError> {
+    let name = prefs_name.strip_suffix(".xml").unwrap_or(prefs_name);
+    if name.is_empty() || name.contains('/') {
+        return Err(AppManagerError::CommandFailed(format!(
+            "Invalid shared preferences name '{}'",
+            prefs_name
+        )));
+    }
+
+    let path = format!("shared_prefs/{}.xml", name);
+    let output = run_in_app_data(device, package, &format!("cat {}", shell_quote(&path)))?;
+    if output.starts_with("cat:") {
+        return Err(AppManagerError::CommandFailed(output));
+    }
+    Ok(output)
+}
+
+/// Every file and directory under the data directory of `package`, relative to it and
+/// sorted, e.g. `databases/app.db`. Uses `run-as` or `su` like [`read_app_prefs`].
+pub fn list_app_files(
+    device: &mut ADBServerDevice,
+    package: &str,
+) -> Result<Vec<String>, AppManagerError> {
+    let output = run_in_app_data(device, package, "find .")?;
+    Ok(parse_app_files(&output))
+}
+
+fn parse_app_files(output: &str) -> Vec<String> {
+    let mut files: Vec<String> = output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("./"))
+        .filter(|path| !path.is_empty())
+        .map(|path| path.to_string())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Runs `command` from the data directory of `package`: with `run-as` for debuggable apps,
+/// otherwise with `su`. Fails with `PermissionDenied` when neither is possible.
+fn run_in_app_data(
+    device: &mut ADBServerDevice,
+    package: &str,
+    command: &str,
+) -> Result<String, AppManagerError> {
+    match run_as(device, package, command) {
+        Err(AppManagerError::PermissionDenied(_)) => {}
+        result => return result,
+    }
+
+    let script = format!("cd /data/data/{} && {}", shell_quote(package), command);
+    let output = run_shell_command(device, &format!("su -c {}", shell_quote(&script)))?;
+    if is_su_unavailable(&output) {
+        return Err(AppManagerError::PermissionDenied(format!(
+            "{} is not debuggable and root is not available",
+            package
+        )));
+    }
+    Ok(output)
+}
+
+/// Runs `command` as the app `package` with `run-as`, starting in its data directory. This
+/// is the no-root way into the private files of a debuggable app.
+///
+/// Fails with `PermissionDenied` when the app isn't debuggable and `PackageNotFound` when
+/// it isn't installed.
+pub fn run_as(
+    device: &mut ADBServerDevice,
+    package: &str,
+    command: &str,
+) -> Result<String, AppManagerError> {
+    let output = run_shell_command(
+        device,
+        &format!("run-as {} {}", shell_quote(package), command),
+    )?;
+    check_run_as_output(package, output)
+}
+
+/// `run-as` reports its own failures on a `run-as:` line before running anything.
+fn check_run_as_output(package: &str, output: String) -> Result<String, AppManagerError> {
+    let Some(message) = output
+        .lines()
+        .next()
+        .and_then(|l| l.strip_prefix("run-as:"))
+    else {
+        return Ok(output);
+    };
+
+    let message = message.trim();
+    let lower = message.to_lowercase();
+    if lower.contains("unknown package") || lower.contains("is unknown") {
+        Err(AppManagerError::PackageNotFound(package.to_string()))
+    } else {
+        Err(AppManagerError::PermissionDenied(message.to_string()))
+    }
+}
+
+pub(crate) fn is_su_unavailable(output: &str) -> bool {
+    let first = output.lines().next().unwrap_or("").to_lowercase();
+    first.contains("su: not found")
+        || first.contains("su: inaccessible or not found")
+        || first.contains("permission denied")
+        || first.contains("not allowed")
+}
+
+/// `pm list packages -U` as an app id to package map, so uids of secondary users match
+/// too. Lines read `package:com.example uid:10123`, or `uid:10123,1010123` for an app
+/// installed for several users. Shared uids like `system` belong to many packages and
+/// are left out.
+pub(crate) fn parse_package_uids(output: &str) -> HashMap<u32, String> {
+    let mut packages: HashMap<u32, Option<String>> = HashMap::new();
+    for line in output.lines() {
+        let Some((package, uid)) = line.trim().strip_prefix("package:").and_then(|rest| {
+            let (package, uids) = rest.split_once(" uid:")?;
+            Some((package, uids.split(',').next()?.trim().parse::<u32>().ok()?))
+        }) else {
+            continue;
+        };
+        packages
+            .entry(uid % PER_USER_RANGE)
+            .and_modify(|p| *p = None)
+            .or_insert_with(|| Some(package.to_string()));
+    }
+    packages
+        .into_iter()
+        .filter_map(|(uid, package)| Some((uid, package?)))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PackageFilter {
+    All,
+    System,
+    ThirdParty,
+    Enabled,
+    Disabled,
+}
+
+fn run_shell_command(
+    device: &mut ADBServerDevice,
+    command: &str,
+) -> Result<String, AppManagerError> {
+    let output =
+        shell_output(device, command).map_err(|e| AppManagerError::CommandFailed(e.to_string()))?;
+
+    String::from_utf8(output)
+        .map_err(|e| AppManagerError::ParseError(e.to_string()))
+        .map(|s| s.trim().to_string())
+}
+
+// Every install, including a reinstall, lands in a fresh /data/app directory, so the
+// `path=package` entries that weren't there before belong to the package just installed
+fn installed_package_paths(
+    device: &mut ADBServerDevice,
+) -> Result<HashSet<String>, AppManagerError> {
+    let output = run_shell_command(device, "pm list packages -f")?;
+    Ok(output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("package:"))
+        .map(String::from)
+        .collect())
+}
+
+/// The one package in `after` that isn't in `before`, `None` if there are none or several.
+fn added_package(before: &HashSet<String>, after: &HashSet<String>) -> Option<String> {
+    let mut added = after.difference(before);
+    match (added.next(), added.next()) {
+        (Some(package), None) => Some(package.clone()),
+        _ => None,
+    }
+}
+
+fn newly_installed_package(before: &HashSet<String>, after: &HashSet<String>) -> Option<String> {
+    let mut packages: Vec<&str> = after
+        .difference(before)
+        .filter_map(|entry| entry.rsplit_once('=').map(|(_, pkg)| pkg))
+        .collect();
+    packages.sort_unstable();
+    packages.dedup();
+
+    // Anything else would be a guess, e.g. another install finishing at the same time
+    match packages.as_slice() {
+        [package] => Some(package.to_string()),
+        _ => None,
+    }
+}
+
+/// The code in `Failure [INSTALL_FAILED_INSUFFICIENT_STORAGE]` or
+/// `Failure [INSTALL_PARSE_FAILED_NO_CERTIFICATES: Failed to collect certificates]`.
+/// Messages like `Failure [not installed for 0]` carry no code.
+fn extract_error_code(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (_, rest) = line.split_once('[')?;
+        let code: String = rest
+            .chars()
+            .take_while(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || *c == '_')
+            .collect();
+        let known = code.starts_with("INSTALL_") || code.starts_with("DELETE_");
+        known.then_some(code)
+    })
+}
+
+fn extract_error_message(output: &str) -> String {
+    for line in output.lines() {
+        if line.contains("Failure") || line.contains("Error") || line.contains("error") {
+            return line.trim().to_string();
+        }
+    }
+    output.lines().last().unwrap_or("Unknown error").to_string()
+}
 
-    let out_str = String::from_utf8(output).unwrap_or_default();
-    Ok(parse_top_package(&out_str))
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AppManagerError {
+    PackageNotFound(String),
+    ActivityNotFound(String),
+    ComponentNotFound(String),
+    InstallFailed(String),
+    UninstallFailed(String),
+    CommandFailed(String),
+    ParseError(String),
+    PermissionDenied(String),
+    DownloadFailed(String),
+    Timeout(String),
+    Cancelled,
+}
+
+impl std::fmt::Display for AppManagerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppManagerError::PackageNotFound(pkg) => write!(f, "Package not found: {}", pkg),
+            AppManagerError::ActivityNotFound(pkg) => {
+                write!(f, "Launcher activity not found: {}", pkg)
+            }
+            AppManagerError::ComponentNotFound(name) => {
+                write!(f, "Component not found: {}", name)
+            }
+            AppManagerError::InstallFailed(msg) => write!(f, "Install failed: {}", msg),
+            AppManagerError::UninstallFailed(msg) => write!(f, "Uninstall failed: {}", msg),
+            AppManagerError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+            AppManagerError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+            AppManagerError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+            AppManagerError::DownloadFailed(msg) => write!(f, "Download failed: {}", msg),
+            AppManagerError::Timeout(msg) => write!(f, "Timed out: {}", msg),
+            AppManagerError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for AppManagerError {}
+
+/// Finds the foreground app in `dumpsys activity` output. The `top-activity` process line
+/// carries the pid, the `ResumedActivity`/`mResumedActivity`/`topResumedActivity` lines of
+/// the activities section only the package.
+pub fn parse_top_package(output: &str) -> TopPackage {
+    let lines = || output.lines().map(str::trim);
+
+    if let Some(top) = lines()
+        .filter(|line| line.contains("top-activity"))
+        .find_map(parse_top_activity_line)
+    {
+        return top;
+    }
+
+    let resumed = lines()
+        .filter(|line| {
+            ["mResumedActivity", "ResumedActivity", "topResumedActivity"]
+                .iter()
+                .any(|key| line.starts_with(key))
+        })
+        .find_map(parse_activity_record);
+    TopPackage {
+        name: resumed.unwrap_or_default(),
+        pid: None,
+    }
+}
+
+/// `Proc # 0: fg T/A/TOP LCM t: 0 12345:com.example/u0a123 (top-activity)`
+fn parse_top_activity_line(line: &str) -> Option<TopPackage> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let pkg_part = parts.get(parts.len().checked_sub(2)?)?;
+    let (pid, name) = pkg_part.split_once(':')?;
+    let name = name.split('/').next().unwrap_or(name);
+    Some(TopPackage {
+        name: name.to_string(),
+        pid: pid.parse().ok(),
+    })
+}
+
+/// Package of `... ActivityRecord{f00ba12 u0 com.example/.MainActivity t123}`.
+fn parse_activity_record(line: &str) -> Option<String> {
+    let (_, record) = line.split_once("ActivityRecord{")?;
+    record
+        .split_whitespace()
+        .find_map(|token| token.split_once('/'))
+        .map(|(package, _)| package.to_string())
+        .filter(|package| !package.is_empty())
+}
+
+/// The app in the foreground. Reads the process list and activities sections of `dumpsys
+/// activity`, each a fraction of the full dump, and only falls back to the full dump when
+/// neither names a top app.
+pub fn get_top_package(device: &mut ADBServerDevice) -> Result<TopPackage, AppManagerError> {
+    for command in ["dumpsys activity processes", "dumpsys activity activities"] {
+        let top = parse_top_package(&run_shell_command(device, command)?);
+        if !top.name.is_empty() {
+            return Ok(top);
+        }
+    }
+
+    Ok(parse_top_package(&run_shell_command(
+        device,
+        "dumpsys activity",
+    )?))
+}
+
+/// The window that has input focus, more precise than [`get_top_package`] when a dialog
+/// or the keyboard is up. `None` when nothing is focused, e.g. while the screen is off.
+pub fn get_focused_window(
+    device: &mut ADBServerDevice,
+) -> Result<Option<FocusedWindow>, AppManagerError> {
+    let windows = run_shell_command(device, "dumpsys window windows")?;
+    let Some(mut focused) = parse_focused_window(&windows) else {
+        return Ok(None);
+    };
+
+    // Not fatal, some builds restrict the input method dump
+    if let Ok(ime) = run_shell_command(device, "dumpsys input_method") {
+        focused.keyboard_shown = ime.lines().any(|line| {
+            line.split_whitespace()
+                .any(|field| field == "mInputShown=true")
+        });
+    }
+    Ok(Some(focused))
+}
+
+/// `mCurrentFocus=Window{a1b2c3 u0 com.example/com.example.MainActivity}` and
+/// `mFocusedApp=ActivityRecord{...}` from `dumpsys window`.
+fn parse_focused_window(output: &str) -> Option<FocusedWindow> {
+    let value = |key: &str| {
+        output
+            .lines()
+            .map(str::trim)
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+    };
+
+    // The title may contain spaces, `Application Error: com.example`
+    let window = value("mCurrentFocus")?
+        .strip_prefix("Window{")?
+        .strip_suffix('}')?
+        .splitn(3, ' ')
+        .nth(2)?
+        .to_string();
+    let package = match window.split_once('/') {
+        Some((package, _)) => Some(package.to_string()),
+        None => is_package_name(&window).then(|| window.clone()),
+    };
+
+    Some(FocusedWindow {
+        package,
+        focused_app: value("mFocusedApp").and_then(parse_activity_record),
+        window,
+        keyboard_shown: false,
+    })
+}
+
+fn is_package_name(name: &str) -> bool {
+    name.contains('.')
+        && name.split('.').all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+/// Whether the app is on screen, alive in the background (including apps where only a
+/// `:service` style process is running) or not running at all.
+pub fn get_app_runtime_state(
+    device: &mut ADBServerDevice,
+    package_name: &str,
+) -> Result<AppRuntimeState, KiraError> {
+    // Nothing may be focused, e.g. with the screen off, which just means not foreground
+    let top = get_top_package(device).ok();
+    let processes = find_process_by_package(device, package_name)?;
+
+    Ok(runtime_state_from(top.as_ref(), &processes, package_name))
+}
+
+fn runtime_state_from(
+    top: Option<&TopPackage>,
+    processes: &[ProcessInfo],
+    package_name: &str,
+) -> AppRuntimeState {
+    if top.is_some_and(|t| t.name == package_name) {
+        return AppRuntimeState::Foreground;
+    }
+
+    // find_process_by_package matches substrings, com.foo must not count for com.foobar
+    let belongs_to_package = |p: &ProcessInfo| {
+        p.name == package_name
+            || p.name
+                .strip_prefix(package_name)
+                .is_some_and(|rest| rest.starts_with(':'))
+    };
+
+    if processes.iter().any(belongs_to_package) {
+        AppRuntimeState::Background
+    } else {
+        AppRuntimeState::Stopped
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_progress_reader() {
+        use std::io::Read;
+
+        let mut stages = Vec::new();
+        let cancel = CancelToken::new();
+        {
+            let mut record = |stage: InstallStage| stages.push(stage);
+            let mut reader = ProgressReader {
+                inner: std::io::Cursor::new(vec![0u8; 10]),
+                read: 0,
+                total: 10,
+                progress: &mut record,
+                cancel: &cancel,
+            };
+            let mut buf = [0u8; 4];
+            while reader.read(&mut buf).unwrap() > 0 {}
+
+            cancel.cancel();
+            assert!(reader.read(&mut buf).is_err());
+        }
+
+        let uploaded: Vec<u64> = stages
+            .iter()
+            .map(|stage| match stage {
+                InstallStage::Uploading { bytes, total: 10 } => *bytes,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(uploaded, vec![4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn test_apk_file_name_from_url() {
+        assert_eq!(
+            apk_file_name_from_url("https://example.com/files/app-release.apk?token=abc"),
+            "app-release.apk"
+        );
+        assert_eq!(
+            apk_file_name_from_url("https://example.com/download?id=42"),
+            "download.apk"
+        );
+        assert_eq!(
+            apk_file_name_from_url("https://example.com/my%20app.apk"),
+            "download.apk"
+        );
+    }
+
+    #[test]
+    fn test_is_zip_magic() {
+        assert!(is_zip_magic(b"PK\x03\x04rest"));
+        assert!(!is_zip_magic(b"<!DOCTYPE html>"));
+        assert!(!is_zip_magic(b"PK"));
+    }
+
     #[test]
     fn test_install_location_from_str() {
         assert_eq!(InstallLocation::from_str("auto"), InstallLocation::Auto);
@@ -527,21 +1804,90 @@ mod tests {
         assert_eq!(PackageFilter::ThirdParty, PackageFilter::ThirdParty);
     }
 
+    #[test]
+    fn test_parse_pm_path() {
+        let output = "package:/data/app/~~a==/com.example-b==/split_config.arm64_v8a.apk\n\
+                      package:/data/app/~~a==/com.example-b==/base.apk\n\
+                      package:/data/app/~~a==/com.example-b==/split_config.xxhdpi.apk\n";
+
+        assert_eq!(
+            parse_pm_path(output),
+            vec![
+                "/data/app/~~a==/com.example-b==/base.apk",
+                "/data/app/~~a==/com.example-b==/split_config.arm64_v8a.apk",
+                "/data/app/~~a==/com.example-b==/split_config.xxhdpi.apk",
+            ]
+        );
+        assert_eq!(
+            parse_pm_path("package:/system/app/Calculator.apk"),
+            vec!["/system/app/Calculator.apk"]
+        );
+        assert!(parse_pm_path("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_package_dump() {
+        let output = "Packages:
+  Package [com.google.android.youtube] (8c3c1a2):
+    userId=10154
+    pkg=Package{3fb7e1d com.google.android.youtube}
+    codePath=/data/app/~~Qe8w==/com.google.android.youtube-Zx1Q==
+    versionCode=1545134528 minSdk=26 targetSdk=34
+    minExtensionVersions=[]
+    versionName=19.05.36
+    dataDir=/data/user/0/com.google.android.youtube
+    pkgFlags=[ SYSTEM HAS_CODE ALLOW_CLEAR_USER_DATA ]
+    firstInstallTime=2008-12-31 16:00:00
+    lastUpdateTime=2024-02-10 08:12:45
+
+Hidden system packages:
+  Package [com.google.android.youtube] (a1b2c3d):
+    codePath=/product/app/YouTube
+    versionCode=1541152192 minSdk=26 targetSdk=33
+    versionName=18.45.43
+";
+
+        let info = parse_package_dump("com.google.android.youtube", output);
+
+        assert_eq!(info.version_code, Some(1545134528));
+        assert_eq!(info.min_sdk, Some(26));
+        assert_eq!(info.target_sdk, Some(34));
+        assert_eq!(info.version_name.as_deref(), Some("19.05.36"));
+        assert_eq!(
+            info.apk_path.as_deref(),
+            Some("/data/app/~~Qe8w==/com.google.android.youtube-Zx1Q==")
+        );
+        assert!(info.is_system_app);
+        assert!(info.is_enabled);
+
+        // Builds before minSdk was printed
+        let info = parse_package_dump("com.x", "versionCode=12 targetSdk=28\n");
+        assert_eq!(info.version_code, Some(12));
+        assert_eq!(info.min_sdk, None);
+        assert_eq!(info.target_sdk, Some(28));
+        assert_eq!(dump_field("versionCode=12 minSdk=21", "minSdk"), Some("21"));
+    }
+
     #[test]
     fn test_app_info_creation() {
         let info = AppInfo {
             package_name: "com.example.app".to_string(),
             version_name: Some("1.0.0".to_string()),
             version_code: Some(1),
+            min_sdk: None,
+            target_sdk: None,
             label: Some("Example App".to_string()),
             install_location: InstallLocation::Auto,
             flags: vec!["HAS_CODE".to_string()],
             first_install_time: Some("2024-01-01".to_string()),
             last_update_time: Some("2024-01-15".to_string()),
             apk_path: Some("/data/app/example.apk".to_string()),
+            split_apks: Vec::new(),
             data_dir: Some("/data/data/com.example.app".to_string()),
             is_system_app: false,
             is_enabled: true,
+            app_size_bytes: None,
+            data_size_bytes: None,
         };
 
         assert_eq!(info.package_name, "com.example.app");
@@ -554,6 +1900,8 @@ mod tests {
             success: true,
             message: "App installed successfully".to_string(),
             package_name: Some("com.example.app".to_string()),
+            failure_reason: None,
+            error_code: None,
         };
 
         assert!(result.success);
@@ -566,17 +1914,53 @@ mod tests {
             success: false,
             message: "INSTALL_FAILED_INSUFFICIENT_STORAGE".to_string(),
             package_name: None,
+            failure_reason: Some(InstallFailureReason::InsufficientStorage),
+            error_code: Some("INSTALL_FAILED_INSUFFICIENT_STORAGE".to_string()),
         };
 
         assert!(!result.success);
         assert!(result.package_name.is_none());
     }
 
+    #[test]
+    fn test_install_failure_reason_from_output() {
+        assert_eq!(
+            InstallFailureReason::from_output(
+                "Failure [INSTALL_FAILED_VERSION_DOWNGRADE: Downgrade detected: Update version code 1 is older than current 2]"
+            ),
+            Some(InstallFailureReason::VersionDowngrade)
+        );
+        assert_eq!(
+            InstallFailureReason::from_output("Failure [INSTALL_FAILED_INSUFFICIENT_STORAGE]"),
+            Some(InstallFailureReason::InsufficientStorage)
+        );
+        assert_eq!(
+            InstallFailureReason::from_output(
+                "Failure [INSTALL_FAILED_UPDATE_INCOMPATIBLE: Existing package com.example signatures do not match newer version; ignoring!]"
+            ),
+            Some(InstallFailureReason::UpdateIncompatible)
+        );
+        assert_eq!(
+            InstallFailureReason::from_output("Failure [INSTALL_PARSE_FAILED_NO_CERTIFICATES]"),
+            Some(InstallFailureReason::Other(
+                "INSTALL_PARSE_FAILED_NO_CERTIFICATES".to_string()
+            ))
+        );
+        assert_eq!(InstallFailureReason::from_output("Success"), None);
+        assert!(
+            InstallFailureReason::VersionDowngrade
+                .suggestion()
+                .unwrap()
+                .contains("-d")
+        );
+    }
+
     #[test]
     fn test_uninstall_result_success() {
         let result = UninstallResult {
             success: true,
             message: "App uninstalled successfully".to_string(),
+            error_code: None,
         };
 
         assert!(result.success);
@@ -587,6 +1971,7 @@ mod tests {
         let result = UninstallResult {
             success: false,
             message: "DELETE_FAILED_INTERNAL_ERROR".to_string(),
+            error_code: Some("DELETE_FAILED_INTERNAL_ERROR".to_string()),
         };
 
         assert!(!result.success);
@@ -649,15 +2034,20 @@ mod tests {
             package_name: "com.android.system".to_string(),
             version_name: Some("1.0".to_string()),
             version_code: Some(1),
+            min_sdk: None,
+            target_sdk: None,
             label: Some("System".to_string()),
             install_location: InstallLocation::InternalOnly,
             flags: vec!["SYSTEM".to_string()],
             first_install_time: None,
             last_update_time: None,
             apk_path: None,
+            split_apks: Vec::new(),
             data_dir: None,
             is_system_app: true,
             is_enabled: true,
+            app_size_bytes: None,
+            data_size_bytes: None,
         };
 
         assert!(info.is_system_app);
@@ -669,15 +2059,20 @@ mod tests {
             package_name: "com.example.disabled".to_string(),
             version_name: None,
             version_code: None,
+            min_sdk: None,
+            target_sdk: None,
             label: None,
             install_location: InstallLocation::Unknown,
             flags: vec![],
             first_install_time: None,
             last_update_time: None,
             apk_path: None,
+            split_apks: Vec::new(),
             data_dir: None,
             is_system_app: false,
             is_enabled: false,
+            app_size_bytes: None,
+            data_size_bytes: None,
         };
 
         assert!(!info.is_enabled);
@@ -694,6 +2089,27 @@ mod tests {
         assert!(msg2.contains("Error"));
     }
 
+    #[test]
+    fn test_extract_error_code() {
+        assert_eq!(
+            extract_error_code("Failure [INSTALL_FAILED_INSUFFICIENT_STORAGE]"),
+            Some("INSTALL_FAILED_INSUFFICIENT_STORAGE".to_string())
+        );
+        assert_eq!(
+            extract_error_code(
+                "Performing Streamed Install\n\
+                 Failure [INSTALL_PARSE_FAILED_NO_CERTIFICATES: Failed to collect certificates]"
+            ),
+            Some("INSTALL_PARSE_FAILED_NO_CERTIFICATES".to_string())
+        );
+        assert_eq!(
+            extract_error_code("Failure [DELETE_FAILED_INTERNAL_ERROR]"),
+            Some("DELETE_FAILED_INTERNAL_ERROR".to_string())
+        );
+        assert_eq!(extract_error_code("Failure [not installed for 0]"), None);
+        assert_eq!(extract_error_code("Success"), None);
+    }
+
     #[test]
     fn test_pm_list_packages_command_format() {
         let command = "pm list packages -3".to_string();
@@ -703,11 +2119,72 @@ mod tests {
 
     #[test]
     fn test_pm_install_command_format() {
-        let command = format!("pm install -g /sdcard/Download/app.apk");
+        let command = "pm install -g /sdcard/Download/app.apk".to_string();
         assert!(command.contains("install"));
         assert!(command.contains("-g"));
     }
 
+    #[test]
+    fn test_added_package() {
+        let set = |names: &[&str]| -> HashSet<String> {
+            names.iter().map(|name| name.to_string()).collect()
+        };
+        let before = set(&["com.example.app"]);
+
+        assert_eq!(
+            added_package(&before, &set(&["com.example.app", "org.other"])),
+            Some("org.other".to_string())
+        );
+        // Reinstall, left to the path comparison
+        assert_eq!(added_package(&before, &before), None);
+        assert_eq!(
+            added_package(&before, &set(&["com.example.app", "org.a", "org.b"])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_newly_installed_package() {
+        let entries =
+            |lines: &[&str]| -> HashSet<String> { lines.iter().map(|l| l.to_string()).collect() };
+        let before = entries(&[
+            "/system/app/Calculator/Calculator.apk=com.android.calculator2",
+            "/data/app/~~Ab12==/com.example.app-Xy==/base.apk=com.example.app",
+        ]);
+
+        // Reinstall moves the package to a new directory
+        let reinstalled = entries(&[
+            "/system/app/Calculator/Calculator.apk=com.android.calculator2",
+            "/data/app/~~Cd34==/com.example.app-Zw==/base.apk=com.example.app",
+        ]);
+        assert_eq!(
+            newly_installed_package(&before, &reinstalled),
+            Some("com.example.app".to_string())
+        );
+
+        let mut fresh = before.clone();
+        fresh.insert("/data/app/~~Ef56==/org.other-Qq==/base.apk=org.other".to_string());
+        assert_eq!(
+            newly_installed_package(&before, &fresh),
+            Some("org.other".to_string())
+        );
+
+        assert_eq!(newly_installed_package(&before, &before), None);
+    }
+
+    #[test]
+    fn test_install_options_to_args() {
+        assert!(InstallOptions::default().to_args().is_empty());
+
+        let options = InstallOptions {
+            grant_permissions: true,
+            allow_downgrade: true,
+            replace_existing: true,
+            allow_test: true,
+        };
+        assert_eq!(options.to_args(), vec!["-g", "-d", "-r", "-t"]);
+    }
+
     #[test]
     fn test_pm_uninstall_command_format() {
         let package = "com.brave.browser";
@@ -729,6 +2206,123 @@ mod tests {
         assert_eq!(command, "am force-stop com.example.app");
     }
 
+    #[test]
+    fn test_broadcast_command() {
+        assert_eq!(
+            broadcast_command("android.intent.action.BOOT_COMPLETED", &[]),
+            "am broadcast -a android.intent.action.BOOT_COMPLETED"
+        );
+        let extras = vec![
+            (
+                "message".to_string(),
+                IntentExtra::String("it's on".to_string()),
+            ),
+            ("count".to_string(), IntentExtra::Int(-3)),
+            ("enabled".to_string(), IntentExtra::Bool(true)),
+        ];
+        assert_eq!(
+            broadcast_command("com.example.DEBUG", &extras),
+            "am broadcast -a com.example.DEBUG --es message 'it'\\''s on' --ei count -3 \
+             --ez enabled true"
+        );
+    }
+
+    #[test]
+    fn test_start_intent_command() {
+        let intent = IntentSpec {
+            action: Some("android.intent.action.VIEW".to_string()),
+            data_uri: Some("https://example.com/item?id=1&ref=kira".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            start_intent_command(&intent),
+            "am start -a android.intent.action.VIEW -d 'https://example.com/item?id=1&ref=kira'"
+        );
+
+        let intent = IntentSpec {
+            mime_type: Some("text/plain".to_string()),
+            category: Some("android.intent.category.DEFAULT".to_string()),
+            component: Some("com.example/.ShareActivity".to_string()),
+            extras: vec![("fast".to_string(), IntentExtra::Bool(false))],
+            ..Default::default()
+        };
+        assert_eq!(
+            start_intent_command(&intent),
+            "am start -t text/plain -c android.intent.category.DEFAULT \
+             -n com.example/.ShareActivity --ez fast false"
+        );
+    }
+
+    #[test]
+    fn test_parse_app_components() {
+        let output = "\
+Activity Resolver Table:
+  Full MIME Types:
+      text/plain:
+        a1b2c3d com.example/.ShareActivity filter e4f5a6b
+          Action: \"android.intent.action.SEND\"
+  Non-Data Actions:
+      android.intent.action.MAIN:
+        5c9a0b1 com.example/.MainActivity filter 8b1d2e3
+          Action: \"android.intent.action.MAIN\"
+          Category: \"android.intent.category.LAUNCHER\"
+      android.intent.action.SEND:
+        a1b2c3d com.example/.ShareActivity filter 9f8e7d6
+
+Receiver Resolver Table:
+  Non-Data Actions:
+      android.intent.action.BOOT_COMPLETED:
+        1f2e3d4 com.example/.BootReceiver filter 4a5b6c7
+
+Service Resolver Table:
+  Non-Data Actions:
+      com.example.SYNC:
+        9a8b7c6 com.example/com.example.sync.SyncService filter 1c2d3e4
+
+Registered ContentProviders:
+  com.example/.DataProvider:
+    Provider{7d8e9f0 com.example/.DataProvider}
+
+ContentProvider Authorities:
+  [com.example.provider]:
+    Provider{7d8e9f0 com.example/.DataProvider}
+
+Packages:
+  Package [com.example] (2c4e6a8):
+    userId=10219
+";
+        let components = parse_app_components(output, "com.example");
+        let names = |list: &[AppComponent]| -> Vec<String> {
+            list.iter().map(|c| c.name.clone()).collect()
+        };
+
+        assert_eq!(
+            names(&components.activities),
+            vec!["com.example/.ShareActivity", "com.example/.MainActivity"]
+        );
+        assert!(components.activities.iter().all(|a| a.exported));
+        assert_eq!(
+            names(&components.receivers),
+            vec!["com.example/.BootReceiver"]
+        );
+        assert_eq!(
+            names(&components.services),
+            vec!["com.example/com.example.sync.SyncService"]
+        );
+        assert_eq!(
+            components.providers,
+            vec![AppComponent {
+                name: "com.example/.DataProvider".to_string(),
+                exported: false,
+            }]
+        );
+
+        assert_eq!(
+            parse_app_components("", "com.example"),
+            AppComponents::default()
+        );
+    }
+
     #[test]
     fn test_am_start_command_format() {
         let activity = "com.example.app/.MainActivity";
@@ -736,6 +2330,185 @@ mod tests {
         assert_eq!(command, "am start -n com.example.app/.MainActivity");
     }
 
+    #[test]
+    fn test_parse_new_state() {
+        assert_eq!(
+            parse_new_state(
+                "Package com.example new state: disabled-user",
+                "com.example"
+            ),
+            Ok(false)
+        );
+        assert_eq!(
+            parse_new_state("Package com.example new state: enabled", "com.example"),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_component_class() {
+        let class = |component| component_class("com.example", component);
+
+        assert_eq!(class(".push.Receiver"), "com.example.push.Receiver");
+        assert_eq!(
+            class("com.example/.push.Receiver"),
+            "com.example.push.Receiver"
+        );
+        assert_eq!(
+            class("com.example/com.google.firebase.iid.FirebaseInstanceIdReceiver"),
+            "com.google.firebase.iid.FirebaseInstanceIdReceiver"
+        );
+        assert_eq!(class("org.lib.Service"), "org.lib.Service");
+    }
+
+    #[test]
+    fn test_dump_has_component() {
+        let output = "Activity Resolver Table:
+  Non-Data Actions:
+      android.intent.action.MAIN:
+        5c9a0b1 com.example/.MainActivity filter 8b1d2e3
+        7d2e1f0 com.other/.SyncService filter 1a2b3c4
+Packages:
+  Package [com.example] (3f2a1b0):
+    User 0: ceDataInode=1234 installed=true
+      disabledComponents:
+        com.example.push.PushReceiver
+";
+        let has = |class| dump_has_component(output, "com.example", class);
+
+        assert!(has("com.example.MainActivity"));
+        assert!(has("com.example.push.PushReceiver"));
+        assert!(!has("com.other.SyncService"));
+        assert!(!has("com.example.Missing"));
+    }
+
+    #[test]
+    fn test_parse_new_state_errors() {
+        let protected = "Exception occurred while executing 'disable-user':\n\
+            java.lang.IllegalArgumentException: Cannot disable a protected package: com.android.settings";
+        assert!(matches!(
+            parse_new_state(protected, "com.android.settings"),
+            Err(AppManagerError::PermissionDenied(_))
+        ));
+
+        let security = "Error: java.lang.SecurityException: Shell cannot change component state for com.android.phone/null to 3";
+        assert!(matches!(
+            parse_new_state(security, "com.android.phone"),
+            Err(AppManagerError::PermissionDenied(_))
+        ));
+
+        assert_eq!(
+            parse_new_state(
+                "Error: java.lang.IllegalArgumentException: Unknown package: com.nope",
+                "com.nope"
+            ),
+            Err(AppManagerError::PackageNotFound("com.nope".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_diskstats() {
+        let output = "Latency: 1ms [512B Data Write]\n\
+                      Package Names: [\"com.android.settings\",\"com.example.app\"]\n\
+                      App Sizes: [1048576,204800]\n\
+                      App Data Sizes: [40960,8192]\n\
+                      Cache Sizes: [0,4096]";
+        assert_eq!(
+            parse_diskstats(output, "com.example.app"),
+            (Some(204800), Some(8192))
+        );
+        assert_eq!(parse_diskstats(output, "com.missing"), (None, None));
+        assert_eq!(parse_diskstats("", "com.example.app"), (None, None));
+    }
+
+    #[test]
+    fn test_pm_list_packages_command_with_query() {
+        assert_eq!(
+            pm_list_packages_command(PackageFilter::All, None),
+            "pm list packages"
+        );
+        assert_eq!(
+            pm_list_packages_command(PackageFilter::ThirdParty, Some("whatsapp")),
+            "pm list packages -3 whatsapp"
+        );
+    }
+
+    #[test]
+    fn test_parse_package_list() {
+        let output = "package:com.whatsapp\npackage:com.whatsapp.w4b\n";
+        assert_eq!(
+            parse_package_list(output),
+            vec!["com.whatsapp", "com.whatsapp.w4b"]
+        );
+        assert!(parse_package_list("").is_empty());
+    }
+
+    fn process(name: &str) -> ProcessInfo {
+        ProcessInfo {
+            pid: 1000,
+            name: name.to_string(),
+            user: "u0_a100".to_string(),
+            cpu: "0.0".to_string(),
+            mem: "1M".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_runtime_state_foreground() {
+        let top = TopPackage {
+            name: "com.example.app".to_string(),
+            pid: Some(1000),
+        };
+        let processes = vec![process("com.example.app")];
+        assert_eq!(
+            runtime_state_from(Some(&top), &processes, "com.example.app"),
+            AppRuntimeState::Foreground
+        );
+    }
+
+    #[test]
+    fn test_runtime_state_service_only() {
+        let top = TopPackage {
+            name: "com.android.launcher3".to_string(),
+            pid: Some(900),
+        };
+        let processes = vec![process("com.example.app:sync")];
+        assert_eq!(
+            runtime_state_from(Some(&top), &processes, "com.example.app"),
+            AppRuntimeState::Background
+        );
+    }
+
+    #[test]
+    fn test_runtime_state_stopped() {
+        let processes = vec![process("com.example.appx")];
+        assert_eq!(
+            runtime_state_from(None, &processes, "com.example.app"),
+            AppRuntimeState::Stopped
+        );
+        assert_eq!(
+            runtime_state_from(None, &[], "com.example.app"),
+            AppRuntimeState::Stopped
+        );
+    }
+
+    #[test]
+    fn test_extract_label() {
+        assert_eq!(
+            extract_label("label=Settings"),
+            Some("Settings".to_string())
+        );
+        assert_eq!(
+            extract_label("labelRes=0x7f120000 nonLocalizedLabel=My App icon=0x7f080001"),
+            Some("My App".to_string())
+        );
+        assert_eq!(
+            extract_label("labelRes=0x7f120000 nonLocalizedLabel=null icon=0x7f080001"),
+            None
+        );
+        assert_eq!(extract_label("versionName=1.0"), None);
+    }
+
     #[test]
     fn test_parse_top_package_valid() {
         let sample_output = "
@@ -748,6 +2521,55 @@ mod tests {
         assert_eq!(top.pid, Some(4567));
     }
 
+    #[test]
+    fn test_parse_top_package_processes_dump() {
+        let sample_output = "\
+ACTIVITY MANAGER RUNNING PROCESSES (dumpsys activity processes)
+  All known processes:
+  *APP* UID 10234 ProcessRecord{9a1c2f3 12345:com.example.app/u0a234}
+    user #0 uid=10234 gids={50234, 20234, 9997}
+    mRequiredAbi=arm64-v8a instructionSet=null
+  Isolated process list (sorted by uid):
+  Process LRU list (sorted by oom_adj, 52 total, non-act at 3, non-svc at 3):
+    Proc #51: fg     T/A/TOP  LCM  t: 0 12345:com.example.app/u0a234 (top-activity)
+    Proc #50: psvc   F/ /PER  LCM  t: 0 1523:com.android.systemui/u0a167 (fixed)
+    Proc #49: vis    F/ /BFGS ---  t: 0 2288:com.google.android.inputmethod.latin/u0a188 (service)
+  PID mappings:
+    PID #1523: ProcessRecord{5b3e0a1 1523:com.android.systemui/u0a167}
+";
+        let top = parse_top_package(sample_output);
+        assert_eq!(top.name, "com.example.app");
+        assert_eq!(top.pid, Some(12345));
+    }
+
+    #[test]
+    fn test_parse_top_package_resumed_activity() {
+        // Android 10+
+        let sample_output = "\
+ACTIVITY MANAGER ACTIVITIES (dumpsys activity activities)
+Display #0 (activities from top to bottom):
+  * Task{3f2a1b0 #412 type=standard A=10234:com.example.app U=0 visible=true}
+    * Hist  #0: ActivityRecord{8c1d2e3 u0 com.example.app/.ui.MainActivity t412}
+  ResumedActivity: ActivityRecord{8c1d2e3 u0 com.example.app/.ui.MainActivity t412}
+  mFocusedApp=ActivityRecord{8c1d2e3 u0 com.example.app/.ui.MainActivity t412}
+";
+        let top = parse_top_package(sample_output);
+        assert_eq!(top.name, "com.example.app");
+        assert_eq!(top.pid, None);
+
+        // Android 9 and older
+        let top = parse_top_package(
+            "    mResumedActivity: ActivityRecord{5d4e3f2 u0 com.android.settings/.Settings t27}",
+        );
+        assert_eq!(top.name, "com.android.settings");
+
+        // Android 12+ activity starter section
+        let top = parse_top_package(
+            "  topResumedActivity=ActivityRecord{a1b2c3d u0 org.example.notes/.EditActivity t9}",
+        );
+        assert_eq!(top.name, "org.example.notes");
+    }
+
     #[test]
     fn test_parse_top_package_empty() {
         let sample_output = "No top-activity here";
@@ -755,4 +2577,81 @@ mod tests {
         assert_eq!(top.name, "");
         assert_eq!(top.pid, None);
     }
+
+    #[test]
+    fn test_parse_focused_window() {
+        let output = "WINDOW MANAGER WINDOWS (dumpsys window windows)\n\
+                      mCurrentFocus=Window{a1b2c3 u0 com.example/com.example.MainActivity}\n\
+                      mFocusedApp=ActivityRecord{f00ba12 u0 com.example/.MainActivity t42}\n";
+        let focused = parse_focused_window(output).unwrap();
+        assert_eq!(focused.window, "com.example/com.example.MainActivity");
+        assert_eq!(focused.package.as_deref(), Some("com.example"));
+        assert_eq!(focused.focused_app.as_deref(), Some("com.example"));
+
+        let output = "  mCurrentFocus=Window{d4e5f6 u0 Application Error: com.example}\n\
+                      mFocusedApp=ActivityRecord{f00ba12 u0 com.example/.MainActivity t42}\n";
+        let focused = parse_focused_window(output).unwrap();
+        assert_eq!(focused.window, "Application Error: com.example");
+        assert_eq!(focused.package, None);
+        assert_eq!(focused.focused_app.as_deref(), Some("com.example"));
+
+        let output = "  mCurrentFocus=Window{9a8b7c u0 NotificationShade}\n  mFocusedApp=null\n";
+        let focused = parse_focused_window(output).unwrap();
+        assert_eq!(focused.package, None);
+        assert_eq!(focused.focused_app, None);
+
+        assert_eq!(parse_focused_window("  mCurrentFocus=null\n"), None);
+    }
+
+    #[test]
+    fn test_check_run_as_output() {
+        assert_eq!(
+            check_run_as_output("com.example", "<map />".to_string()),
+            Ok("<map />".to_string())
+        );
+        assert_eq!(
+            check_run_as_output(
+                "com.example",
+                "run-as: package not debuggable: com.example".to_string()
+            ),
+            Err(AppManagerError::PermissionDenied(
+                "package not debuggable: com.example".to_string()
+            ))
+        );
+        assert_eq!(
+            check_run_as_output(
+                "com.missing",
+                "run-as: unknown package: com.missing".to_string()
+            ),
+            Err(AppManagerError::PackageNotFound("com.missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_package_uids() {
+        let packages = parse_package_uids(
+            "package:com.spotify.music uid:10123\n\
+             package:com.termux uid:10234,1010234\n\
+             package:android uid:1000\n\
+             package:com.android.settings uid:1000",
+        );
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[&10123], "com.spotify.music");
+        assert_eq!(packages[&10234], "com.termux");
+    }
+
+    #[test]
+    fn test_parse_app_files() {
+        let output =
+            ".\n./shared_prefs\n./databases/app.db\n./databases\n./shared_prefs/settings.xml";
+        assert_eq!(
+            parse_app_files(output),
+            vec![
+                "databases",
+                "databases/app.db",
+                "shared_prefs",
+                "shared_prefs/settings.xml"
+            ]
+        );
+    }
 }
@@ -0,0 +1,139 @@
+use crate::config::adb_device;
+use adb_client::RustADBError;
+use adb_client::server::{ADBServer, DeviceState};
+use adb_client::server_device::ADBServerDevice;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Outcome of a batch per device serial.
+pub type DeviceResults<T> = Vec<(String, Result<T, String>)>;
+
+/// Runs `f` once for every device known to the ADB server and collects the outcome per
+/// serial. A failing device, or one that isn't in `device` state, shows up as an `Err`
+/// entry and doesn't stop the rest of the batch. Fails only when the server can't list
+/// its devices.
+pub fn for_each_device<F, T, E>(
+    server: &mut ADBServer,
+    mut f: F,
+) -> Result<DeviceResults<T>, RustADBError>
+where
+    F: FnMut(&mut ADBServerDevice) -> Result<T, E>,
+    E: std::fmt::Display,
+{
+    let devices = server.devices()?;
+
+    Ok(devices
+        .into_iter()
+        .map(|dev| {
+            let result = match dev.state {
+                DeviceState::Device => {
+                    let mut device = adb_device(dev.identifier.clone());
+                    f(&mut device).map_err(|e| e.to_string())
+                }
+                state => Err(format!("Device is {}", state)),
+            };
+            (dev.identifier, result)
+        })
+        .collect())
+}
+
+/// Runs `f` for every serial on its own thread, at most `max_parallel` at a time, and
+/// returns the results in the order of `serials`. A device that takes longer than
+/// `timeout` gets `None` and frees its slot for the next one, its thread is left to
+/// finish on its own.
+pub fn map_devices_parallel<T, F>(
+    serials: &[String],
+    max_parallel: usize,
+    timeout: Duration,
+    f: F,
+) -> Vec<Option<T>>
+where
+    F: Fn(&mut ADBServerDevice) -> T + Send + Sync + 'static,
+    T: Send + 'static,
+{
+    let f = Arc::new(f);
+    let (tx, rx) = mpsc::channel();
+    let mut results: Vec<Option<T>> = serials.iter().map(|_| None).collect();
+    // (index into serials, deadline) of the lookups still waited for
+    let mut running: Vec<(usize, Instant)> = Vec::new();
+    let mut next = 0;
+
+    loop {
+        while running.len() < max_parallel.max(1) && next < serials.len() {
+            let (f, tx, serial) = (Arc::clone(&f), tx.clone(), serials[next].clone());
+            let index = next;
+            thread::spawn(move || {
+                let mut device = adb_device(serial);
+                let _ = tx.send((index, f(&mut device)));
+            });
+            running.push((index, Instant::now() + timeout));
+            next += 1;
+        }
+
+        let Some(deadline) = running.iter().map(|(_, deadline)| *deadline).min() else {
+            break;
+        };
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok((index, value)) => {
+                // Answers from lookups that already timed out are dropped
+                if let Some(pos) = running.iter().position(|(i, _)| *i == index) {
+                    running.remove(pos);
+                    results[index] = Some(value);
+                }
+            }
+            Err(_) => {
+                let now = Instant::now();
+                running.retain(|(_, deadline)| *deadline > now);
+            }
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_devices_parallel_timeout() {
+        use std::sync::Mutex;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let serials: Vec<String> = ["a", "hung", "b", "c"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        // "hung" blocks until released after the call, the others return right away
+        let (release, released) = mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+
+        let (active_in, max_in) = (Arc::clone(&active), Arc::clone(&max_active));
+        let results =
+            map_devices_parallel(&serials, 2, Duration::from_millis(500), move |device| {
+                let now = active_in.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in.fetch_max(now, Ordering::SeqCst);
+                let serial = device.identifier.clone().unwrap_or_default();
+                if serial == "hung" {
+                    let _ = released.lock().unwrap().recv();
+                }
+                active_in.fetch_sub(1, Ordering::SeqCst);
+                serial.to_uppercase()
+            });
+        drop(release);
+
+        assert_eq!(
+            results,
+            vec![
+                Some("A".to_string()),
+                None,
+                Some("B".to_string()),
+                Some("C".to_string())
+            ]
+        );
+        assert!(max_active.load(Ordering::SeqCst) <= 2);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::device::cancel::CancelToken;
+use crate::device::info::DeviceError;
+use crate::device::shell::{shell_quote, spawn_host_shell, stop_host_shell};
+use adb_client::ADBDeviceExt;
+use adb_client::server_device::ADBServerDevice;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What a line of `bugreportz -p` output means.
+#[derive(Debug, Clone, PartialEq)]
+enum BugreportzLine {
+    Begin,
+    Progress(u32, u32),
+    Ok(String),
+    Fail(String),
+    Other,
+}
+
+/// Captures a bug report to `dest_path` on the host, like `adb bugreport <path>`.
+///
+/// Android 7.1+ builds a zip with `bugreportz` and `progress` gets `(done, total)` from its
+/// progress lines, the zip is then pulled straight to disk. Older devices fall back to the
+/// plain text report, which has no progress and is streamed to `dest_path` as it comes.
+/// Either way takes minutes. `cancel` stops the report on the device and leaves whatever
+/// was written so far.
+pub fn capture_bugreport(
+    device: &mut ADBServerDevice,
+    dest_path: &str,
+    progress: impl FnMut(u32, u32),
+    cancel: &CancelToken,
+) -> Result<(), DeviceError> {
+    let serial = device
+        .identifier
+        .clone()
+        .ok_or_else(|| DeviceError::CommandFailed("device has no serial".to_string()))?;
+
+    match run_bugreportz(&serial, progress, cancel)? {
+        Some(remote_path) => {
+            let file = File::create(dest_path).map_err(|e| DeviceError::IOError(e.to_string()))?;
+            let mut writer = CancelWriter {
+                inner: file,
+                cancel,
+            };
+            let pulled = device.pull(&remote_path, &mut writer).map_err(|e| {
+                if cancel.is_cancelled() {
+                    DeviceError::Cancelled
+                } else {
+                    DeviceError::IOError(e.to_string())
+                }
+            });
+            // adb bugreport doesn't leave the zip behind either
+            let _ =
+                device.shell_command(&format!("rm -f {}", shell_quote(&remote_path)), None, None);
+            pulled
+        }
+        None => capture_text_bugreport(&serial, dest_path, cancel),
+    }
+}
+
+/// Runs `bugreportz -p` and returns the device path of the finished zip, or `None` when
+/// the device has no zipped bug reports and the text form should be used instead.
+fn run_bugreportz(
+    serial: &str,
+    mut progress: impl FnMut(u32, u32),
+    cancel: &CancelToken,
+) -> Result<Option<String>, DeviceError> {
+    let mut child = spawn_host_shell(serial, "bugreportz -p")
+        .map_err(|e| DeviceError::IOError(e.to_string()))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| DeviceError::IOError("Failed to capture stdout".to_string()))?;
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut started = false;
+    let result = loop {
+        if cancel.is_cancelled() {
+            break Err(DeviceError::Cancelled);
+        }
+        let line = match rx.recv_timeout(CANCEL_POLL_INTERVAL) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) if started => {
+                break Err(DeviceError::CommandFailed(
+                    "bugreportz exited before the report was done".to_string(),
+                ));
+            }
+            // bugreportz missing, or too old to know -p
+            Err(RecvTimeoutError::Disconnected) => break Ok(None),
+        };
+
+        match parse_bugreportz_line(&line) {
+            BugreportzLine::Begin => started = true,
+            BugreportzLine::Progress(done, total) => {
+                started = true;
+                progress(done, total);
+            }
+            BugreportzLine::Ok(path) => break Ok(Some(path)),
+            BugreportzLine::Fail(reason) if started => {
+                break Err(DeviceError::CommandFailed(reason));
+            }
+            BugreportzLine::Fail(_) => break Ok(None),
+            BugreportzLine::Other => {}
+        }
+    };
+
+    stop_host_shell(child);
+    result
+}
+
+/// Fails the write once `cancel` is set, which aborts a pull in progress.
+struct CancelWriter<'a, W> {
+    inner: W,
+    cancel: &'a CancelToken,
+}
+
+impl<W: Write> Write for CancelWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.cancel.is_cancelled() {
+            return Err(std::io::Error::other("cancelled"));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn parse_bugreportz_line(line: &str) -> BugreportzLine {
+    let line = line.trim();
+    if line.starts_with("BEGIN:") {
+        BugreportzLine::Begin
+    } else if let Some(progress) = line.strip_prefix("PROGRESS:") {
+        let parsed = progress.split_once('/').and_then(|(done, total)| {
+            Some((done.trim().parse().ok()?, total.trim().parse().ok()?))
+        });
+        match parsed {
+            Some((done, total)) => BugreportzLine::Progress(done, total),
+            None => BugreportzLine::Other,
+        }
+    } else if let Some(path) = line.strip_prefix("OK:") {
+        BugreportzLine::Ok(path.trim().to_string())
+    } else if let Some(reason) = line.strip_prefix("FAIL:") {
+        BugreportzLine::Fail(reason.trim().to_string())
+    } else {
+        BugreportzLine::Other
+    }
+}
+
+/// The pre-7.1 `bugreport`, which prints the whole report as text on stdout.
+fn capture_text_bugreport(
+    serial: &str,
+    dest_path: &str,
+    cancel: &CancelToken,
+) -> Result<(), DeviceError> {
+    let mut child =
+        spawn_host_shell(serial, "bugreport").map_err(|e| DeviceError::IOError(e.to_string()))?;
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| DeviceError::IOError("Failed to capture stdout".to_string()))?;
+
+    let result = (|| {
+        let mut file = File::create(dest_path).map_err(|e| DeviceError::IOError(e.to_string()))?;
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            if cancel.is_cancelled() {
+                return Err(DeviceError::Cancelled);
+            }
+            let read = stdout
+                .read(&mut buffer)
+                .map_err(|e| DeviceError::IOError(e.to_string()))?;
+            if read == 0 {
+                return Ok(());
+            }
+            file.write_all(&buffer[..read])
+                .map_err(|e| DeviceError::IOError(e.to_string()))?;
+        }
+    })();
+
+    stop_host_shell(child);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bugreportz_line() {
+        assert_eq!(
+            parse_bugreportz_line("BEGIN:/bugreports/bugreport-husky-2024.zip"),
+            BugreportzLine::Begin
+        );
+        assert_eq!(
+            parse_bugreportz_line("PROGRESS:1234/5678"),
+            BugreportzLine::Progress(1234, 5678)
+        );
+        assert_eq!(
+            parse_bugreportz_line("OK:/bugreports/bugreport-husky-2024.zip\r"),
+            BugreportzLine::Ok("/bugreports/bugreport-husky-2024.zip".to_string())
+        );
+        assert_eq!(
+            parse_bugreportz_line("FAIL:Could not open /dev/socket/dumpstate"),
+            BugreportzLine::Fail("Could not open /dev/socket/dumpstate".to_string())
+        );
+        assert_eq!(
+            parse_bugreportz_line("/system/bin/sh: bugreportz: not found"),
+            BugreportzLine::Other
+        );
+        assert_eq!(parse_bugreportz_line("PROGRESS:n/a"), BugreportzLine::Other);
+    }
+
+    #[test]
+    fn test_cancel_writer() {
+        let cancel = CancelToken::new();
+        let mut writer = CancelWriter {
+            inner: Vec::new(),
+            cancel: &cancel,
+        };
+        writer.write_all(b"PK").unwrap();
+        cancel.cancel();
+        assert!(writer.write_all(b"more").is_err());
+        assert_eq!(writer.inner, b"PK");
+    }
+}
@@ -0,0 +1,650 @@
+use crate::config::{adb_config, adb_server, host_adb};
+use crate::device::shell::ShellExecutor;
+use adb_client::RustADBError;
+use adb_client::server::ADBServer;
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpStream, ToSocketAddrs};
+use std::ops::Deref;
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+
+const SERVER_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+const SERVER_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keyed by server address and serial, the same serial on another server is another device
+type FeatureCache = HashMap<(SocketAddrV4, String), Vec<String>>;
+
+static DEVICE_FEATURES: Mutex<Option<FeatureCache>> = Mutex::new(None);
+
+/// Runs `adb start-server` when nothing listens on the configured server address yet.
+///
+/// adb_client tries the same on connect but only logs failures, which leaves the caller
+/// with a bare "connection refused". A server on another host can't be started from
+/// here and is reported as refused.
+pub fn ensure_server_started() -> Result<(), ConnectError> {
+    ensure_server_at(adb_config().server_addr())
+}
+
+pub(crate) fn ensure_server_at(addr: SocketAddrV4) -> Result<(), ConnectError> {
+    if TcpStream::connect_timeout(&addr.into(), SERVER_PROBE_TIMEOUT).is_ok() {
+        return Ok(());
+    }
+    if !addr.ip().is_loopback() {
+        return Err(ConnectError::ConnectionRefused(addr.to_string()));
+    }
+
+    // start-server returns once the server accepts connections
+    let output = host_adb()
+        .args(["-P", &addr.port().to_string(), "start-server"])
+        .output()
+        .map_err(spawn_error)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ConnectError::ServerStartFailed(stderr.trim().to_string()));
+    }
+    Ok(())
+}
+
+fn spawn_error(error: std::io::Error) -> ConnectError {
+    match error.kind() {
+        ErrorKind::NotFound => ConnectError::AdbNotFound,
+        _ => ConnectError::ServerStartFailed(error.to_string()),
+    }
+}
+
+/// Connects the ADB server to a device listening on `host:port` and returns its serial.
+pub fn connect_tcp(host: &str, port: u16) -> Result<String, ConnectError> {
+    let address = resolve_address(host, port)?;
+    let mut server = default_server();
+
+    server
+        .connect_device(address)
+        .map_err(|e| classify_error(e, &address.to_string()))?;
+
+    Ok(address.to_string())
+}
+
+/// Disconnects a TCP device by its `host:port` serial.
+pub fn disconnect(serial: &str) -> Result<(), ConnectError> {
+    let address = parse_serial(serial)?;
+    let mut server = default_server();
+
+    server
+        .disconnect_device(address)
+        .map_err(|e| classify_error(e, serial))
+}
+
+/// Pairs with a device using the six digit code shown in its wireless debugging settings.
+///
+/// The pairing port differs from the connect port, call `connect_tcp` afterwards.
+pub fn pair(host: &str, port: u16, code: &str) -> Result<(), ConnectError> {
+    let code = validate_pairing_code(code)?;
+    let address = resolve_address(host, port)?;
+    let mut server = default_server();
+
+    server.pair(address, code).map_err(|e| match e {
+        RustADBError::ADBRequestFailed(msg) if msg.to_lowercase().contains("failed to pair") => {
+            ConnectError::PairingFailed(msg.trim().to_string())
+        }
+        other => classify_error(other, &address.to_string()),
+    })
+}
+
+fn validate_pairing_code(code: &str) -> Result<String, ConnectError> {
+    let code = code.trim();
+    if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+        Ok(code.to_string())
+    } else {
+        Err(ConnectError::InvalidPairingCode(code.to_string()))
+    }
+}
+
+/// Restarts adbd on a USB device so it listens for TCP connections on `port`.
+///
+/// adbd drops the current connection while restarting, which is reported as success.
+pub fn enable_tcpip(device: &mut ADBServerDevice, port: u16) -> Result<(), ConnectError> {
+    match device.tcpip(port) {
+        Ok(()) => Ok(()),
+        Err(RustADBError::IOError(e)) if is_connection_drop(e.kind()) => Ok(()),
+        Err(e) => Err(classify_error(e, &format!("tcpip:{}", port))),
+    }
+}
+
+/// IPv4 address of the device's wlan0 interface, for use with `connect_tcp`.
+///
+/// Read it before `enable_tcpip`, the USB connection is not usable while adbd restarts.
+pub fn get_wlan_ip(device: &mut ADBServerDevice) -> Option<String> {
+    let mut executor = ShellExecutor::new();
+    executor
+        .get_networks(device, false)
+        .ok()?
+        .into_iter()
+        .find(|iface| iface.name == "wlan0" && !iface.ip_address.is_empty())
+        .map(|iface| iface.ip_address)
+}
+
+/// A change in the ADB server's device list. `state` is the server's name for it, e.g.
+/// `device`, `offline` or `unauthorized`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum DeviceEvent {
+    Connected { serial: String, state: String },
+    Disconnected { serial: String },
+    StateChanged { serial: String, state: String },
+}
+
+/// Events from [`track_devices`]. Use it like the `Receiver` it dereferences to, dropping
+/// it closes the tracking socket.
+pub struct DeviceTracker {
+    receiver: mpsc::Receiver<DeviceEvent>,
+    socket: TcpStream,
+}
+
+impl Deref for DeviceTracker {
+    type Target = mpsc::Receiver<DeviceEvent>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
+
+impl Drop for DeviceTracker {
+    fn drop(&mut self) {
+        // Wakes the reader thread out of its blocking read
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+}
+
+/// Follows the device list with `host:track-devices`, so the UI doesn't have to poll. The
+/// devices already attached come first as `Connected` events.
+pub fn track_devices() -> Result<DeviceTracker, ConnectError> {
+    let addr = adb_config().server_addr();
+    ensure_server_at(addr)?;
+
+    let mut socket = TcpStream::connect(addr)
+        .map_err(|e| ConnectError::ConnectionFailed(format!("{}: {}", addr, e)))?;
+    send_server_request(&mut socket, "host:track-devices").map_err(ConnectError::CommandFailed)?;
+    let mut reader = socket
+        .try_clone()
+        .map_err(|e| ConnectError::CommandFailed(e.to_string()))?;
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut known = Vec::new();
+        // Every message is the whole list again
+        while let Ok(message) = read_server_message(&mut reader) {
+            let current = parse_device_list(&message);
+            for event in diff_devices(&known, &current) {
+                // A reconnect may be a different adbd, e.g. after an OTA
+                if let DeviceEvent::Disconnected { serial } = &event {
+                    forget_device_features(addr, serial);
+                }
+                if tx.send(event).is_err() {
+                    return;
+                }
+            }
+            known = current;
+        }
+    });
+
+    Ok(DeviceTracker {
+        receiver: rx,
+        socket,
+    })
+}
+
+/// Sends a raw host service request like `host:version`, `host:features` or
+/// `host-serial:<serial>:get-state` to the ADB server and returns its reply, for what the
+/// typed API doesn't cover. Requests that switch the socket to a device or stream forever
+/// (`host:transport*`, `host:track-*`) are refused.
+pub fn adb_server_command(query: &str) -> Result<String, ConnectError> {
+    query_server(validate_server_query(query)?)
+}
+
+fn query_server(query: &str) -> Result<String, ConnectError> {
+    let addr = adb_config().server_addr();
+    ensure_server_at(addr)?;
+
+    let mut stream = TcpStream::connect(addr)
+        .map_err(|e| ConnectError::ConnectionFailed(format!("{}: {}", addr, e)))?;
+    stream
+        .set_read_timeout(Some(SERVER_REPLY_TIMEOUT))
+        .map_err(|e| ConnectError::CommandFailed(e.to_string()))?;
+    send_server_request(&mut stream, query).map_err(ConnectError::CommandFailed)?;
+
+    // The server closes the socket after answering a host service
+    let mut reply = Vec::new();
+    match stream.read_to_end(&mut reply) {
+        Ok(_) => {}
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+        Err(e) => return Err(ConnectError::CommandFailed(e.to_string())),
+    }
+    Ok(decode_server_reply(&reply))
+}
+
+/// Protocol features the device's adbd and the server both support, e.g. `shell_v2`,
+/// `cmd`, `abb_exec` or `sendrecv_v2`. Cached per ADB server until the device disconnects.
+pub fn get_device_features(serial: &str) -> Result<Vec<String>, ConnectError> {
+    let key = (adb_config().server_addr(), serial.to_string());
+    if let Some(features) = DEVICE_FEATURES
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|cache| cache.get(&key))
+    {
+        return Ok(features.clone());
+    }
+
+    let reply = query_server(&format!("host-serial:{}:features", serial))?;
+    let features = parse_features(&reply);
+    DEVICE_FEATURES
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(key, features.clone());
+    Ok(features)
+}
+
+/// Whether `serial` supports `feature`, false when the features can't be read.
+pub fn has_device_feature(serial: &str, feature: &str) -> bool {
+    get_device_features(serial).is_ok_and(|features| features.iter().any(|f| f == feature))
+}
+
+fn forget_device_features(server: SocketAddrV4, serial: &str) {
+    if let Some(cache) = DEVICE_FEATURES.lock().unwrap().as_mut() {
+        cache.remove(&(server, serial.to_string()));
+    }
+}
+
+fn parse_features(reply: &str) -> Vec<String> {
+    reply
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn validate_server_query(query: &str) -> Result<&str, ConnectError> {
+    let query = query.trim();
+    let invalid = |reason: &str| {
+        Err(ConnectError::InvalidRequest(format!(
+            "{}: {}",
+            query, reason
+        )))
+    };
+
+    if !["host:", "host-serial:", "host-usb:", "host-local:"]
+        .iter()
+        .any(|prefix| query.starts_with(prefix))
+    {
+        return invalid("only host services are supported");
+    }
+    if query.contains(":transport") || query.contains(":track-") {
+        return invalid("transport and tracking requests don't return a reply");
+    }
+    if !query.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+        return invalid("requests are printable ASCII");
+    }
+    // The length prefix is four hex digits
+    if query.len() > 0xffff {
+        return invalid("request too long");
+    }
+    Ok(query)
+}
+
+/// Most replies are one hex length prefixed message, some are bare text or nothing at all.
+fn decode_server_reply(reply: &[u8]) -> String {
+    let len = reply
+        .get(..4)
+        .and_then(|len| std::str::from_utf8(len).ok())
+        .and_then(|len| usize::from_str_radix(len, 16).ok());
+    match len {
+        Some(len) if reply.len() == 4 + len => String::from_utf8_lossy(&reply[4..]).into_owned(),
+        _ => String::from_utf8_lossy(reply).into_owned(),
+    }
+}
+
+/// Sends one request of the ADB server's smart socket protocol and waits for OKAY. A FAIL
+/// comes back as the server's reason.
+pub(crate) fn send_server_request(stream: &mut TcpStream, request: &str) -> Result<(), String> {
+    stream
+        .write_all(format!("{:04x}{}", request.len(), request).as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    let mut status = [0u8; 4];
+    stream.read_exact(&mut status).map_err(|e| e.to_string())?;
+    if &status == b"OKAY" {
+        return Ok(());
+    }
+
+    let reason = read_server_message(stream).unwrap_or_default();
+    Err(format!("{} was refused: {}", request, reason))
+}
+
+/// Reads one hex length prefixed message.
+fn read_server_message(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = std::str::from_utf8(&len)
+        .ok()
+        .and_then(|len| usize::from_str_radix(len, 16).ok())
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "bad message length"))?;
+
+    let mut message = vec![0u8; len];
+    stream.read_exact(&mut message)?;
+    Ok(String::from_utf8_lossy(&message).into_owned())
+}
+
+/// `(serial, state)` pairs of a `serial\tstate` per line device list.
+fn parse_device_list(message: &str) -> Vec<(String, String)> {
+    message
+        .lines()
+        .filter_map(|line| {
+            let (serial, state) = line.split_once('\t')?;
+            Some((serial.trim().to_string(), state.trim().to_string()))
+        })
+        .collect()
+}
+
+fn diff_devices(old: &[(String, String)], new: &[(String, String)]) -> Vec<DeviceEvent> {
+    let mut events = Vec::new();
+
+    for (serial, state) in new {
+        match old.iter().find(|(s, _)| s == serial) {
+            None => events.push(DeviceEvent::Connected {
+                serial: serial.clone(),
+                state: state.clone(),
+            }),
+            Some((_, old_state)) if old_state != state => events.push(DeviceEvent::StateChanged {
+                serial: serial.clone(),
+                state: state.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (serial, _) in old {
+        if !new.iter().any(|(s, _)| s == serial) {
+            events.push(DeviceEvent::Disconnected {
+                serial: serial.clone(),
+            });
+        }
+    }
+
+    events
+}
+
+pub(crate) fn is_connection_drop(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+fn default_server() -> ADBServer {
+    adb_server()
+}
+
+fn resolve_address(host: &str, port: u16) -> Result<SocketAddrV4, ConnectError> {
+    let host = host.trim();
+    if host.is_empty() {
+        return Err(ConnectError::InvalidAddress(host.to_string()));
+    }
+
+    if let Ok(ip) = host.parse::<Ipv4Addr>() {
+        return Ok(SocketAddrV4::new(ip, port));
+    }
+
+    // The ADB server only accepts IPv4 addresses, so resolve hostnames up front
+    (host, port)
+        .to_socket_addrs()
+        .map_err(|e| ConnectError::InvalidAddress(format!("{}: {}", host, e)))?
+        .find_map(|addr| match addr {
+            SocketAddr::V4(v4) => Some(v4),
+            SocketAddr::V6(_) => None,
+        })
+        .ok_or_else(|| ConnectError::InvalidAddress(format!("{}: no IPv4 address", host)))
+}
+
+fn parse_serial(serial: &str) -> Result<SocketAddrV4, ConnectError> {
+    let (host, port) = serial
+        .trim()
+        .rsplit_once(':')
+        .ok_or_else(|| ConnectError::InvalidAddress(serial.to_string()))?;
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| ConnectError::InvalidAddress(serial.to_string()))?;
+
+    resolve_address(host, port)
+}
+
+fn classify_error(error: RustADBError, target: &str) -> ConnectError {
+    match error {
+        RustADBError::ADBRequestFailed(msg) => classify_message(&msg, target),
+        other => ConnectError::CommandFailed(other.to_string()),
+    }
+}
+
+fn classify_message(msg: &str, target: &str) -> ConnectError {
+    let lower = msg.to_lowercase();
+    if lower.contains("connection refused") {
+        ConnectError::ConnectionRefused(target.to_string())
+    } else if lower.contains("failed to connect") || lower.contains("unable to connect") {
+        ConnectError::ConnectionFailed(target.to_string())
+    } else {
+        ConnectError::CommandFailed(msg.trim().to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ConnectError {
+    InvalidAddress(String),
+    ConnectionFailed(String),
+    ConnectionRefused(String),
+    InvalidPairingCode(String),
+    PairingFailed(String),
+    AdbNotFound,
+    ServerStartFailed(String),
+    InvalidRequest(String),
+    CommandFailed(String),
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectError::InvalidAddress(addr) => write!(f, "Invalid address: {}", addr),
+            ConnectError::ConnectionFailed(addr) => write!(f, "Failed to connect to {}", addr),
+            ConnectError::ConnectionRefused(addr) => write!(f, "Connection refused by {}", addr),
+            ConnectError::InvalidPairingCode(code) => {
+                write!(f, "Invalid pairing code '{}', expected six digits", code)
+            }
+            ConnectError::PairingFailed(msg) => write!(f, "Pairing failed: {}", msg),
+            ConnectError::AdbNotFound => write!(
+                f,
+                "adb not found, install the Android platform tools and add them to PATH"
+            ),
+            ConnectError::ServerStartFailed(msg) => {
+                write!(f, "Failed to start the ADB server: {}", msg)
+            }
+            ConnectError::InvalidRequest(msg) => write!(f, "Invalid request {}", msg),
+            ConnectError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_server_query() {
+        assert_eq!(validate_server_query(" host:version\n"), Ok("host:version"));
+        assert!(validate_server_query("host-serial:emulator-5554:get-state").is_ok());
+
+        for query in [
+            "shell:ls",
+            "host:transport:emulator-5554",
+            "host:track-devices",
+            "host:version\u{0}",
+        ] {
+            assert!(matches!(
+                validate_server_query(query),
+                Err(ConnectError::InvalidRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_features() {
+        assert_eq!(
+            parse_features("shell_v2,cmd,stat_v2,abb_exec,sendrecv_v2\n"),
+            vec!["shell_v2", "cmd", "stat_v2", "abb_exec", "sendrecv_v2"]
+        );
+        assert!(parse_features("").is_empty());
+    }
+
+    #[test]
+    fn test_decode_server_reply() {
+        assert_eq!(decode_server_reply(b"00040029"), "0029");
+        assert_eq!(decode_server_reply(b"0006device"), "device");
+        assert_eq!(decode_server_reply(b""), "");
+        assert_eq!(decode_server_reply(b"OKAY"), "OKAY");
+    }
+
+    #[test]
+    fn test_diff_devices() {
+        let old = parse_device_list("emulator-5554\tdevice\nR58M123\tunauthorized\n");
+        let new = parse_device_list("R58M123\tdevice\n192.168.1.5:5555\toffline\n");
+
+        assert_eq!(
+            diff_devices(&old, &new),
+            vec![
+                DeviceEvent::StateChanged {
+                    serial: "R58M123".to_string(),
+                    state: "device".to_string()
+                },
+                DeviceEvent::Connected {
+                    serial: "192.168.1.5:5555".to_string(),
+                    state: "offline".to_string()
+                },
+                DeviceEvent::Disconnected {
+                    serial: "emulator-5554".to_string()
+                },
+            ]
+        );
+        assert!(diff_devices(&new, &new).is_empty());
+        assert!(parse_device_list("").is_empty());
+    }
+
+    #[test]
+    fn test_ensure_server_at_listening() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = match listener.local_addr().unwrap() {
+            SocketAddr::V4(v4) => v4,
+            SocketAddr::V6(_) => unreachable!(),
+        };
+        assert_eq!(ensure_server_at(addr), Ok(()));
+    }
+
+    #[test]
+    fn test_spawn_error() {
+        assert_eq!(
+            spawn_error(std::io::Error::from(ErrorKind::NotFound)),
+            ConnectError::AdbNotFound
+        );
+        assert!(matches!(
+            spawn_error(std::io::Error::from(ErrorKind::PermissionDenied)),
+            ConnectError::ServerStartFailed(_)
+        ));
+    }
+
+    #[test]
+    fn test_resolve_address_ipv4() {
+        let addr = resolve_address("192.168.1.5", 5555).unwrap();
+        assert_eq!(addr.to_string(), "192.168.1.5:5555");
+    }
+
+    #[test]
+    fn test_resolve_address_empty() {
+        assert!(matches!(
+            resolve_address("  ", 5555),
+            Err(ConnectError::InvalidAddress(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_serial() {
+        let addr = parse_serial("10.0.0.2:5555").unwrap();
+        assert_eq!(addr, SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 5555));
+
+        assert!(parse_serial("emulator-5554").is_err());
+        assert!(parse_serial("10.0.0.2:port").is_err());
+    }
+
+    #[test]
+    fn test_classify_message_refused() {
+        let err = classify_message(
+            "failed to connect to '192.168.1.5:5555': Connection refused",
+            "192.168.1.5:5555",
+        );
+        assert_eq!(
+            err,
+            ConnectError::ConnectionRefused("192.168.1.5:5555".to_string())
+        );
+    }
+
+    #[test]
+    fn test_classify_message_failed() {
+        let err = classify_message("failed to connect to 192.168.1.5:5555", "192.168.1.5:5555");
+        assert_eq!(
+            err,
+            ConnectError::ConnectionFailed("192.168.1.5:5555".to_string())
+        );
+    }
+
+    #[test]
+    fn test_classify_message_other() {
+        let err = classify_message("no such device", "192.168.1.5:5555");
+        assert_eq!(
+            err,
+            ConnectError::CommandFailed("no such device".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_pairing_code() {
+        assert_eq!(validate_pairing_code(" 123456 "), Ok("123456".to_string()));
+        assert!(matches!(
+            validate_pairing_code("12345"),
+            Err(ConnectError::InvalidPairingCode(_))
+        ));
+        assert!(matches!(
+            validate_pairing_code("12a456"),
+            Err(ConnectError::InvalidPairingCode(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_connection_drop() {
+        assert!(is_connection_drop(ErrorKind::ConnectionReset));
+        assert!(is_connection_drop(ErrorKind::UnexpectedEof));
+        assert!(!is_connection_drop(ErrorKind::PermissionDenied));
+    }
+
+    #[test]
+    fn test_connect_error_display() {
+        let err = ConnectError::ConnectionRefused("1.2.3.4:5555".to_string());
+        assert!(format!("{}", err).contains("refused"));
+
+        let err2 = ConnectError::InvalidAddress("bad".to_string());
+        assert!(format!("{}", err2).contains("Invalid address"));
+    }
+}
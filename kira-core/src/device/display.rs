@@ -1,23 +1,346 @@
 use crate::BuildInfo;
+use crate::DeviceInfo;
+use crate::DisplayModes;
+use crate::EncryptionState;
+use crate::ScreenInfo;
 use crate::Storage;
+use crate::device::info::{DeviceError, is_64bit_abi, parse_abi_list};
+use crate::device::shell::shell_output;
 use adb_client::ADBDeviceExt;
 use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-pub fn get_max_refresh_rate(device: &mut ADBServerDevice) -> Option<u32> {
-    let output = shell_cmd(device, "dumpsys display")?;
-    let mut max_rate = 0u32;
-
-    for line in output.lines() {
-        if line.contains("refreshRate") || line.contains("RefreshRate") {
-            if let Some(rate) = extract_refresh_rate(line) {
-                if rate > max_rate {
-                    max_rate = rate;
-                }
+/// Reads every field of `DeviceInfo` with one `getprop` dump for the properties and
+/// separate calls only for the dumpsys, df and wm based values.
+pub fn collect_device_info(device: &mut ADBServerDevice, serial: &str) -> DeviceInfo {
+    let props = get_all_props(device);
+    device_info_from(device, serial, &props)
+}
+
+/// [`collect_device_info`] that fails when the properties can't be read at all, instead of
+/// returning an info with every field empty.
+pub fn try_collect_device_info(
+    device: &mut ADBServerDevice,
+    serial: &str,
+) -> Result<DeviceInfo, DeviceError> {
+    let props = get_all_props(device);
+    if props.is_empty() {
+        return Err(DeviceError::CommandFailed(
+            "getprop returned no properties".to_string(),
+        ));
+    }
+    Ok(device_info_from(device, serial, &props))
+}
+
+fn device_info_from(
+    device: &mut ADBServerDevice,
+    serial: &str,
+    props: &HashMap<String, String>,
+) -> DeviceInfo {
+    let prop = |key: &str| props.get(key).filter(|v| !v.is_empty()).cloned();
+    let wm = shell_cmd(device, "wm size; wm density");
+    let abi_list = parse_abi_list(
+        prop("ro.product.cpu.abilist").as_deref(),
+        prop("ro.product.cpu.abi").as_deref(),
+    );
+
+    DeviceInfo {
+        serial: serial.to_string(),
+        model: prop("ro.product.model"),
+        manufacturer: prop("ro.product.manufacturer"),
+        android_version: prop("ro.build.version.release"),
+        sdk_int: prop("ro.build.version.sdk").and_then(|v| v.trim().parse().ok()),
+        codename: prop("ro.build.version.codename"),
+        abi: prop("ro.product.cpu.abi"),
+        supports_64bit: abi_list.iter().any(|abi| is_64bit_abi(abi)),
+        abi_list,
+        slot: prop("ro.boot.slot_suffix"),
+        battery: parse_battery(
+            &shell_cmd(device, "dumpsys battery | grep level").unwrap_or_default(),
+        ),
+        storage: get_storage(device),
+        screen_resolution: wm.as_deref().and_then(|raw| {
+            let size: Vec<&str> = raw.lines().filter(|l| l.contains("size:")).collect();
+            if size.is_empty() {
+                None
+            } else {
+                Some(size.join("\n"))
+            }
+        }),
+        screen: wm.as_deref().and_then(parse_screen_info),
+        refresh_rate: get_max_refresh_rate(device),
+        build: Some(build_info_from_props(props)),
+        encryption: Some(EncryptionState::from_props(
+            prop("ro.crypto.state").as_deref(),
+            prop("ro.crypto.type").as_deref(),
+        )),
+    }
+}
+
+pub fn get_screen_info(device: &mut ADBServerDevice) -> Option<ScreenInfo> {
+    parse_screen_info(&shell_cmd(device, "wm size; wm density")?)
+}
+
+/// Parses combined `wm size` and `wm density` output. When the resolution or density has
+/// been changed the `Override` line is what the device is actually rendering at.
+pub fn parse_screen_info(raw: &str) -> Option<ScreenInfo> {
+    let (width, height) = wm_value(raw, "size")?.split_once('x')?;
+    let density_dpi = wm_value(raw, "density").and_then(|d| d.parse().ok());
+
+    Some(ScreenInfo {
+        width: width.trim().parse().ok()?,
+        height: height.trim().parse().ok()?,
+        density_dpi,
+    })
+}
+
+/// Whether `wm size` reports an override on top of the physical resolution, i.e. whether
+/// [`reset_screen_resolution`] would change anything.
+pub fn has_resolution_override(device: &mut ADBServerDevice) -> bool {
+    shell_cmd(device, "wm size").is_some_and(|raw| has_wm_override(&raw, "size"))
+}
+
+/// Whether `wm density` reports an override on top of the physical density.
+pub fn has_density_override(device: &mut ADBServerDevice) -> bool {
+    shell_cmd(device, "wm density").is_some_and(|raw| has_wm_override(&raw, "density"))
+}
+
+/// The physical and current screen values next to each other, with what looks off about
+/// the combination. [`reset_screen_resolution`] and [`reset_density`] undo both overrides.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DisplayHealth {
+    /// Panel resolution and density, without overrides
+    pub physical: Option<ScreenInfo>,
+    /// What the device renders at
+    pub current: Option<ScreenInfo>,
+    /// Build default from `ro.sf.lcd_density`
+    pub default_density: Option<u32>,
+    pub resolution_overridden: bool,
+    pub density_overridden: bool,
+    /// Human readable, empty when the display looks consistent
+    pub issues: Vec<String>,
+}
+
+// Density may differ this much from what the resolution override calls for
+const DENSITY_MISMATCH_TOLERANCE: f64 = 0.1;
+
+/// Checks whether resolution and density overrides leave the UI scaled inconsistently,
+/// the usual cause of apps looking wrong after `wm density`.
+pub fn get_display_health(device: &mut ADBServerDevice) -> Result<DisplayHealth, DisplayError> {
+    let wm = shell_output(device, "wm size; wm density; getprop ro.sf.lcd_density")
+        .map_err(|e| DisplayError::CommandFailed(e.to_string()))?;
+    let wm = String::from_utf8_lossy(&wm);
+    let lcd_density = wm.lines().last().and_then(|line| line.trim().parse().ok());
+    Ok(parse_display_health(&wm, lcd_density))
+}
+
+fn parse_display_health(raw: &str, lcd_density: Option<u32>) -> DisplayHealth {
+    let physical = wm_physical(raw, "size")
+        .and_then(|size| size.split_once('x'))
+        .and_then(|(width, height)| {
+            Some(ScreenInfo {
+                width: width.trim().parse().ok()?,
+                height: height.trim().parse().ok()?,
+                density_dpi: wm_physical(raw, "density").and_then(|d| d.parse().ok()),
+            })
+        });
+    let current = parse_screen_info(raw);
+    let resolution_overridden = has_wm_override(raw, "size");
+    let density_overridden = has_wm_override(raw, "density");
+    let default_density = lcd_density.or(physical.as_ref().and_then(|p| p.density_dpi));
+
+    let mut issues = Vec::new();
+    if let (Some(physical), Some(current)) = (&physical, &current) {
+        let aspect = |s: &ScreenInfo| s.width as f64 / s.height as f64;
+        if resolution_overridden && (aspect(physical) - aspect(current)).abs() > 0.01 {
+            issues.push(format!(
+                "resolution {}x{} has a different aspect ratio than the {}x{} panel, the UI is stretched",
+                current.width, current.height, physical.width, physical.height
+            ));
+        }
+
+        // Scaling the resolution calls for scaling the density by the same factor
+        if let (Some(default), Some(density)) = (default_density, current.density_dpi) {
+            let scale = current.width.min(current.height) as f64
+                / physical.width.min(physical.height) as f64;
+            let expected = default as f64 * scale;
+            if ((density as f64 - expected) / expected).abs() > DENSITY_MISMATCH_TOLERANCE {
+                issues.push(format!(
+                    "density {} doesn't match the {}x{} resolution, about {} is expected, apps will look too {}",
+                    density,
+                    current.width,
+                    current.height,
+                    expected.round(),
+                    if (density as f64) > expected {
+                        "large"
+                    } else {
+                        "small"
+                    }
+                ));
             }
         }
     }
 
-    if max_rate > 0 { Some(max_rate) } else { None }
+    DisplayHealth {
+        physical,
+        current,
+        default_density,
+        resolution_overridden,
+        density_overridden,
+        issues,
+    }
+}
+
+/// Overrides the resolution the device renders at, like `wm size WxH`.
+pub fn set_screen_resolution(
+    device: &mut ADBServerDevice,
+    width: u32,
+    height: u32,
+) -> Result<(), DisplayError> {
+    if width == 0 || height == 0 {
+        return Err(DisplayError::InvalidValue(format!(
+            "resolution must be positive, got {}x{}",
+            width, height
+        )));
+    }
+    run_wm(device, &format!("wm size {}x{}", width, height))
+}
+
+pub fn reset_screen_resolution(device: &mut ADBServerDevice) -> Result<(), DisplayError> {
+    run_wm(device, "wm size reset")
+}
+
+/// Overrides the display density, like `wm density N`.
+pub fn set_density(device: &mut ADBServerDevice, dpi: u32) -> Result<(), DisplayError> {
+    if dpi == 0 {
+        return Err(DisplayError::InvalidValue(
+            "density must be positive".to_string(),
+        ));
+    }
+    run_wm(device, &format!("wm density {}", dpi))
+}
+
+pub fn reset_density(device: &mut ADBServerDevice) -> Result<(), DisplayError> {
+    run_wm(device, "wm density reset")
+}
+
+fn run_wm(device: &mut ADBServerDevice, command: &str) -> Result<(), DisplayError> {
+    let output =
+        shell_output(device, command).map_err(|e| DisplayError::CommandFailed(e.to_string()))?;
+    check_wm_output(&String::from_utf8_lossy(&output))
+}
+
+/// `wm` prints nothing when it succeeds, exceptions otherwise.
+fn check_wm_output(output: &str) -> Result<(), DisplayError> {
+    let output = output.trim();
+    if output.is_empty() {
+        return Ok(());
+    }
+    // Shell can normally write these, some vendor builds need WRITE_SECURE_SETTINGS
+    if output.contains("SecurityException") || output.contains("Permission Denial") {
+        return Err(DisplayError::PermissionDenied(format!(
+            "wm refused the change, root may be required: {}",
+            output.lines().next().unwrap_or_default()
+        )));
+    }
+    if output.contains("Exception") || output.contains("Error") || output.contains("Usage:") {
+        return Err(DisplayError::CommandFailed(output.to_string()));
+    }
+    Ok(())
+}
+
+fn wm_value<'a>(raw: &'a str, field: &str) -> Option<&'a str> {
+    let value_of = |prefix: &str| {
+        raw.lines()
+            .find_map(|l| l.trim().strip_prefix(&format!("{} {}:", prefix, field)))
+            .map(str::trim)
+    };
+    value_of("Override").or_else(|| value_of("Physical"))
+}
+
+fn wm_physical<'a>(raw: &'a str, field: &str) -> Option<&'a str> {
+    raw.lines()
+        .find_map(|l| l.trim().strip_prefix(&format!("Physical {}:", field)))
+        .map(str::trim)
+}
+
+fn has_wm_override(raw: &str, field: &str) -> bool {
+    raw.lines()
+        .any(|l| l.trim().starts_with(&format!("Override {}:", field)))
+}
+
+pub fn get_all_props(device: &mut ADBServerDevice) -> HashMap<String, String> {
+    shell_cmd(device, "getprop")
+        .map(|raw| parse_props(&raw))
+        .unwrap_or_default()
+}
+
+/// Parses `getprop` output, one `[key]: [value]` pair per line.
+pub fn parse_props(raw: &str) -> HashMap<String, String> {
+    raw.lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once("]: [")?;
+            let key = key.strip_prefix('[')?;
+            let value = value.strip_suffix(']')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+pub fn get_max_refresh_rate(device: &mut ADBServerDevice) -> Option<u32> {
+    get_display_modes(device)?.supported.last().copied()
+}
+
+pub fn get_display_modes(device: &mut ADBServerDevice) -> Option<DisplayModes> {
+    parse_display_modes(&shell_cmd(device, "dumpsys display")?)
+}
+
+/// Parses the `{id=.., fps=..}` mode records from `dumpsys display`.
+///
+/// Older builds don't print a mode list, in that case every `refreshRate` value found is
+/// treated as a supported mode and the highest one as active.
+pub fn parse_display_modes(raw: &str) -> Option<DisplayModes> {
+    let modes: Vec<(u32, u32)> = raw
+        .split('{')
+        .filter_map(|segment| {
+            let record = segment.split('}').next()?;
+            let id = mode_field(record, "id=")?.parse().ok()?;
+            let fps = mode_field(record, "fps=")?.parse::<f64>().ok()?;
+            Some((id, fps.round() as u32))
+        })
+        .collect();
+
+    let mut supported: Vec<u32> = if modes.is_empty() {
+        raw.lines()
+            .filter(|l| l.contains("refreshRate") || l.contains("RefreshRate"))
+            .filter_map(extract_refresh_rate)
+            .filter(|rate| *rate > 0)
+            .collect()
+    } else {
+        modes.iter().map(|(_, fps)| *fps).collect()
+    };
+    supported.sort_unstable();
+    supported.dedup();
+
+    let active_id = ["mActiveModeId=", ", modeId "]
+        .iter()
+        .find_map(|key| mode_field(raw, key)?.parse::<u32>().ok());
+    let active = active_id
+        .and_then(|id| modes.iter().find(|(mode_id, _)| *mode_id == id))
+        .map(|(_, fps)| *fps)
+        .or_else(|| supported.last().copied())?;
+
+    Some(DisplayModes { supported, active })
+}
+
+fn mode_field<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    let start = text.find(key)? + key.len();
+    let rest = &text[start..];
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(rest.len());
+    if end == 0 { None } else { Some(&rest[..end]) }
 }
 
 fn extract_refresh_rate(line: &str) -> Option<u32> {
@@ -53,10 +376,25 @@ pub fn get_storage(device: &mut ADBServerDevice) -> Option<Storage> {
 }
 
 pub fn get_build_info(device: &mut ADBServerDevice) -> Option<BuildInfo> {
-    Some(BuildInfo {
-        security_patch: shell_cmd(device, "getprop ro.build.version.security_patch"),
-        build_id: shell_cmd(device, "getprop ro.build.id"),
-    })
+    let props = get_all_props(device);
+    if props.is_empty() {
+        return None;
+    }
+    Some(build_info_from_props(&props))
+}
+
+fn build_info_from_props(props: &HashMap<String, String>) -> BuildInfo {
+    let prop = |key: &str| props.get(key).filter(|v| !v.is_empty()).cloned();
+
+    BuildInfo {
+        security_patch: prop("ro.build.version.security_patch"),
+        build_id: prop("ro.build.id"),
+        fingerprint: prop("ro.build.fingerprint"),
+        build_type: prop("ro.build.type"),
+        tags: prop("ro.build.tags"),
+        incremental: prop("ro.build.version.incremental"),
+        build_date: prop("ro.build.date"),
+    }
 }
 
 pub fn parse_battery(raw: &str) -> Option<u8> {
@@ -80,3 +418,198 @@ pub fn shell_cmd(device: &mut ADBServerDevice, command: &str) -> Option<String>
         Err(_) => None,
     }
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum DisplayError {
+    CommandFailed(String),
+    InvalidValue(String),
+    PermissionDenied(String),
+}
+
+impl std::fmt::Display for DisplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplayError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+            DisplayError::InvalidValue(msg) => write!(f, "Invalid value: {}", msg),
+            DisplayError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DisplayError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_display_health() {
+        let physical = "Physical size: 1080x2400\nPhysical density: 420\n";
+        let health = parse_display_health(physical, Some(420));
+        assert!(health.issues.is_empty());
+        assert!(!health.resolution_overridden && !health.density_overridden);
+        assert_eq!(health.current, health.physical);
+
+        // Density raised without touching the resolution
+        let raw = format!("{}Override density: 560\n", physical);
+        let health = parse_display_health(&raw, Some(420));
+        assert!(health.density_overridden);
+        assert_eq!(health.current.unwrap().density_dpi, Some(560));
+        assert_eq!(health.issues.len(), 1);
+        assert!(health.issues[0].contains("too large"));
+
+        // Resolution and density scaled down together is fine
+        let raw = format!(
+            "{}Override size: 720x1600\nOverride density: 280\n",
+            physical
+        );
+        assert!(parse_display_health(&raw, Some(420)).issues.is_empty());
+
+        let raw = format!("{}Override size: 1080x1080\n", physical);
+        let health = parse_display_health(&raw, None);
+        assert_eq!(health.default_density, Some(420));
+        assert!(health.issues[0].contains("aspect ratio"));
+    }
+
+    #[test]
+    fn test_build_info_from_props() {
+        let props = parse_props(
+            "[ro.build.fingerprint]: [google/panther/panther:14/UQ1A.240205.004/11269751:user/release-keys]\n\
+             [ro.build.id]: [UQ1A.240205.004]\n\
+             [ro.build.type]: [user]\n\
+             [ro.build.tags]: [release-keys]\n\
+             [ro.build.version.incremental]: [11269751]\n\
+             [ro.build.date]: [Tue Jan  9 23:19:54 UTC 2024]\n\
+             [ro.build.version.security_patch]: []",
+        );
+        let build = build_info_from_props(&props);
+
+        assert_eq!(
+            build.fingerprint.as_deref(),
+            Some("google/panther/panther:14/UQ1A.240205.004/11269751:user/release-keys")
+        );
+        assert_eq!(build.build_id.as_deref(), Some("UQ1A.240205.004"));
+        assert_eq!(build.build_type.as_deref(), Some("user"));
+        assert_eq!(build.tags.as_deref(), Some("release-keys"));
+        assert_eq!(build.incremental.as_deref(), Some("11269751"));
+        assert_eq!(
+            build.build_date.as_deref(),
+            Some("Tue Jan  9 23:19:54 UTC 2024")
+        );
+        assert_eq!(build.security_patch, None);
+    }
+
+    #[test]
+    fn test_check_wm_output() {
+        assert_eq!(check_wm_output(""), Ok(()));
+        assert_eq!(check_wm_output("\n"), Ok(()));
+        assert!(matches!(
+            check_wm_output(
+                "java.lang.SecurityException: Must hold permission android.permission.WRITE_SECURE_SETTINGS\n\tat com.android.server.wm.WindowManagerService.setForcedDisplaySize"
+            ),
+            Err(DisplayError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            check_wm_output("Error: bad size abc"),
+            Err(DisplayError::CommandFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_props() {
+        let raw = "[ro.product.model]: [Pixel 7]\n\
+                   [ro.boot.slot_suffix]: [_b]\n\
+                   [ro.build.version.release]: [14]\n\
+                   [persist.sys.empty]: []\n\
+                   garbage line\n";
+        let props = parse_props(raw);
+
+        assert_eq!(props.len(), 4);
+        assert_eq!(props.get("ro.product.model").unwrap(), "Pixel 7");
+        assert_eq!(props.get("ro.boot.slot_suffix").unwrap(), "_b");
+        assert_eq!(props.get("persist.sys.empty").unwrap(), "");
+    }
+
+    #[test]
+    fn test_parse_screen_info() {
+        let raw = "Physical size: 1080x2400\nPhysical density: 420";
+        assert_eq!(
+            parse_screen_info(raw),
+            Some(ScreenInfo {
+                width: 1080,
+                height: 2400,
+                density_dpi: Some(420),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_screen_info_override() {
+        let raw = "Physical size: 1440x3120\nOverride size: 1080x2340\n\
+                   Physical density: 560\nOverride density: 420";
+        assert_eq!(
+            parse_screen_info(raw),
+            Some(ScreenInfo {
+                width: 1080,
+                height: 2340,
+                density_dpi: Some(420),
+            })
+        );
+    }
+
+    #[test]
+    fn test_has_wm_override() {
+        let raw = "Physical size: 1440x3120\nOverride size: 1080x2340\nPhysical density: 560";
+        assert!(has_wm_override(raw, "size"));
+        assert!(!has_wm_override(raw, "density"));
+        assert!(!has_wm_override("Physical size: 1080x2400", "size"));
+    }
+
+    #[test]
+    fn test_parse_screen_info_without_density() {
+        let info = parse_screen_info("Physical size: 720x1280").unwrap();
+        assert_eq!((info.width, info.height), (720, 1280));
+        assert_eq!(info.density_dpi, None);
+        assert_eq!(parse_screen_info("error"), None);
+    }
+
+    #[test]
+    fn test_parse_display_modes() {
+        let raw = "mActiveModeId=2\n\
+                   mSupportedModes=[{id=1, width=1080, height=2400, fps=60.000004, \
+                   alternativeRefreshRates=[120.00001]}, {id=2, width=1080, height=2400, \
+                   fps=120.00001, alternativeRefreshRates=[60.000004]}]";
+        assert_eq!(
+            parse_display_modes(raw),
+            Some(DisplayModes {
+                supported: vec![60, 120],
+                active: 120,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_display_modes_device_info_line() {
+        let raw = "DisplayDeviceInfo{\"Built-in Screen\": 1080 x 2400, modeId 1, \
+                   defaultModeId 1, supportedModes [{id=1, width=1080, height=2400, fps=60.0}, \
+                   {id=2, width=1080, height=2400, fps=90.0}]}";
+        let modes = parse_display_modes(raw).unwrap();
+        assert_eq!(modes.supported, vec![60, 90]);
+        assert_eq!(modes.active, 60);
+    }
+
+    #[test]
+    fn test_parse_display_modes_fallback() {
+        let raw = "mDefaultRefreshRate=60.0\n  refreshRate 90.0";
+        let modes = parse_display_modes(raw).unwrap();
+        assert_eq!(modes.supported, vec![60, 90]);
+        assert_eq!(modes.active, 90);
+        assert_eq!(parse_display_modes("nothing here"), None);
+    }
+
+    #[test]
+    fn test_parse_props_value_with_brackets() {
+        let props = parse_props("[ro.build.fingerprint]: [google/[x]/y]");
+        assert_eq!(props.get("ro.build.fingerprint").unwrap(), "google/[x]/y");
+    }
+}
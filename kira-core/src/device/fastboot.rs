@@ -1,6 +1,19 @@
+use crate::device::cancel::CancelToken;
+use crate::format::format_bytes;
+use fastboot_protocol::protocol::FastBootResponse;
+use nusb::transfer::{Direction, EndpointType, RequestBuffer};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
 use thiserror::Error;
 
+// A single var is answered right away by a healthy bootloader, a stuck one shouldn't hang
+// the whole read
+const GETVAR_TIMEOUT: Duration = Duration::from_secs(2);
+// `getvar:all` lists every partition and can take a few seconds on big layouts
+const GETVAR_ALL_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FastbootDeviceInfo {
     pub serial: String,
@@ -9,6 +22,33 @@ pub struct FastbootDeviceInfo {
     pub device: Option<String>,
     pub bootloader: Option<String>,
     pub version: Option<String>,
+    /// Vars the device didn't answer in time, their fields are `None`
+    #[serde(default)]
+    pub timed_out: Vec<String>,
+}
+
+impl FastbootDeviceInfo {
+    /// Info for a device that didn't answer at all, every var counts as timed out.
+    fn unanswered() -> Self {
+        Self {
+            serial: "unknown".to_string(),
+            product: None,
+            model: None,
+            device: None,
+            bootloader: None,
+            version: None,
+            timed_out: [
+                "serialno",
+                "product",
+                "model",
+                "device",
+                "bootloader",
+                "version",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,15 +84,41 @@ pub enum FastbootError {
     IoError(#[from] std::io::Error),
     #[error("Fastboot protocol error: {0}")]
     ProtocolError(String),
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("Flashing {partition} failed ({}/{total}): {source}", .index + 1)]
+    FlashFailed {
+        index: usize,
+        total: usize,
+        partition: String,
+        #[source]
+        source: Box<FastbootError>,
+    },
 }
 
 pub struct FastbootCore {
     device: Option<fastboot_protocol::nusb::NusbFastBoot>,
+    raw: Option<RawTransport>,
+    serial: Option<String>,
 }
 
 impl FastbootCore {
     pub fn new() -> Result<Self, FastbootError> {
-        Ok(Self { device: None })
+        Ok(Self {
+            device: None,
+            raw: None,
+            serial: None,
+        })
+    }
+
+    /// Serial numbers of the fastboot devices on USB, without opening them.
+    pub fn list_serials() -> Result<Vec<String>, FastbootError> {
+        let fb_devices = fastboot_protocol::nusb::devices()
+            .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+
+        Ok(fb_devices
+            .filter_map(|info| info.serial_number().map(|s| s.to_string()))
+            .collect())
     }
 
     pub async fn list_devices() -> Result<Vec<FastbootDeviceInfo>, FastbootError> {
@@ -67,20 +133,34 @@ impl FastbootCore {
                 .map(|s| s.to_string())
                 .unwrap_or_else(|| "unknown".to_string());
 
-            devices.push(FastbootDeviceInfo {
-                serial,
-                product: None,
-                model: None,
-                device: None,
-                bootloader: None,
-                version: None,
-            });
+            // A device that is busy (claimed elsewhere) or unreadable is still listed
+            let entry = match fastboot_protocol::nusb::NusbFastBoot::from_info(&info) {
+                Ok(mut fb) => read_device_vars(&mut fb, serial, &HashMap::new(), Vec::new()).await,
+                Err(_) => FastbootDeviceInfo {
+                    serial,
+                    product: None,
+                    model: None,
+                    device: None,
+                    bootloader: None,
+                    version: None,
+                    timed_out: Vec::new(),
+                },
+            };
+
+            devices.push(entry);
         }
 
         Ok(devices)
     }
 
+    /// Opens the fastboot device with `serial`, or the first one found. A connection to a
+    /// different device is closed first, asking again for the current one is a no-op.
     pub async fn connect(&mut self, serial: Option<&str>) -> Result<(), FastbootError> {
+        if self.is_connected() && serial.is_some() && serial == self.serial.as_deref() {
+            return Ok(());
+        }
+        self.disconnect();
+
         let mut fb_devices = fastboot_protocol::nusb::devices()
             .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
 
@@ -91,13 +171,35 @@ impl FastbootCore {
             None => fb_devices.next().ok_or(FastbootError::NoDevice)?,
         };
 
-        let fb = fastboot_protocol::nusb::NusbFastBoot::from_info(&info)
+        let number = fastboot_protocol::nusb::NusbFastBoot::find_fastboot_interface(&info)
+            .ok_or_else(|| FastbootError::ProtocolError("No fastboot interface".to_string()))?;
+        let interface = info.open()?.claim_interface(number)?;
+
+        // The protocol crate only exposes a fixed set of commands, so keep a handle on the
+        // same interface for the ones it doesn't cover (boot, set_active, oem, ...)
+        let raw = RawTransport::from_interface(interface.clone())?;
+        let fb = fastboot_protocol::nusb::NusbFastBoot::from_interface(interface)
             .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
 
         self.device = Some(fb);
+        self.raw = Some(raw);
+        self.serial = info.serial_number().map(|s| s.to_string());
         Ok(())
     }
 
+    /// Releases the USB interface so another `FastbootCore` or the fastboot CLI can claim
+    /// the device. Does nothing when not connected.
+    pub fn disconnect(&mut self) {
+        // Both hold a clone of the claimed interface, it is released once neither does
+        self.raw = None;
+        self.device = None;
+        self.serial = None;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.device.is_some()
+    }
+
     pub async fn get_var(&mut self, var: &str) -> Result<String, FastbootError> {
         let device = self.device.as_mut().ok_or(FastbootError::NoDevice)?;
 
@@ -109,27 +211,51 @@ impl FastbootCore {
         Ok(value)
     }
 
+    /// Reads the vars in [`FastbootDeviceInfo`] with one `getvar:all`, asking one by one for
+    /// whatever that leaves out. A var the device doesn't answer in time is listed in
+    /// `timed_out` instead of failing the whole read, and so is every var after it.
+    ///
+    /// After a timeout the core is disconnected, the late answer would otherwise be read as
+    /// the reply to the next command, e.g. an OKAY for a flash that never ran.
     pub async fn get_all_vars(&mut self) -> Result<FastbootDeviceInfo, FastbootError> {
+        let info = self.read_all_vars().await?;
+        Ok(self.disconnect_if_unanswered(info))
+    }
+
+    fn disconnect_if_unanswered(&mut self, info: FastbootDeviceInfo) -> FastbootDeviceInfo {
+        if !info.timed_out.is_empty() {
+            self.disconnect();
+        }
+        info
+    }
+
+    async fn read_all_vars(&mut self) -> Result<FastbootDeviceInfo, FastbootError> {
         let device = self.device.as_mut().ok_or(FastbootError::NoDevice)?;
+        let dump = match &self.raw {
+            Some(raw) => {
+                match tokio::time::timeout(GETVAR_ALL_TIMEOUT, raw.execute("getvar:all")).await {
+                    Ok(output) => output
+                        .map(|output| parse_getvar_all(&output))
+                        .unwrap_or_default(),
+                    Err(_) => return Ok(FastbootDeviceInfo::unanswered()),
+                }
+            }
+            None => HashMap::new(),
+        };
 
-        let serial = device
-            .get_var("serialno")
+        let mut timed_out = Vec::new();
+        let serial = device_var(device, "serialno", &dump, &mut timed_out)
             .await
-            .unwrap_or_else(|_| "unknown".to_string());
-        let product = device.get_var("product").await.ok();
-        let model = device.get_var("model").await.ok();
-        let dev = device.get_var("device").await.ok();
-        let bootloader = device.get_var("bootloader").await.ok();
-        let version = device.get_var("version").await.ok();
-
-        Ok(FastbootDeviceInfo {
-            serial,
-            product,
-            model,
-            device: dev,
-            bootloader,
-            version,
-        })
+            .unwrap_or_else(|| "unknown".to_string());
+
+        Ok(read_device_vars(device, serial, &dump, timed_out).await)
+    }
+
+    /// Every variable the bootloader reports for `getvar:all`, in one round trip.
+    pub async fn get_all_var_dump(&mut self) -> Result<HashMap<String, String>, FastbootError> {
+        let raw = self.raw.as_ref().ok_or(FastbootError::NoDevice)?;
+        let output = raw.execute("getvar:all").await?;
+        Ok(parse_getvar_all(&output))
     }
 
     pub async fn flash(
@@ -137,21 +263,53 @@ impl FastbootCore {
         partition: FlashPartition,
         image_path: &str,
     ) -> Result<(), FastbootError> {
-        let device = self.device.as_mut().ok_or(FastbootError::NoDevice)?;
-
-        let data = std::fs::read(image_path).map_err(|e| FastbootError::IoError(e))?;
+        self.flash_with_progress(partition, image_path, |_, _| {}, &CancelToken::new())
+            .await
+    }
 
-        let size = data.len() as u32;
+    /// Flashes `image_path` to `partition`, streaming the file in chunks and reporting
+    /// `(bytes_sent, total)` to `progress` after every chunk.
+    ///
+    /// `cancel` is checked before every chunk, a cancelled flash stops with `Cancelled`
+    /// before the flash command is sent.
+    pub async fn flash_with_progress(
+        &mut self,
+        partition: FlashPartition,
+        image_path: &str,
+        progress: impl FnMut(u64, u64),
+        cancel: &CancelToken,
+    ) -> Result<(), FastbootError> {
+        let device = self.device.as_mut().ok_or(FastbootError::NoDevice)?;
 
-        let mut downloader = device
-            .download(size)
+        let image_len = std::fs::metadata(image_path)?.len();
+        let max_download = device
+            .get_var("max-download-size")
             .await
-            .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+            .ok()
+            .and_then(|v| parse_download_size(&v));
+
+        if let Some(max_download) = max_download
+            && image_len > max_download
+        {
+            // Re-splitting an existing sparse image is not supported, only raw ones
+            if is_sparse_image(image_path)? {
+                return Err(FastbootError::CommandError(format!(
+                    "Sparse image {} is {} bytes, more than the device's max-download-size of {} bytes",
+                    image_path, image_len, max_download
+                )));
+            }
+            return flash_raw_in_chunks(
+                device,
+                partition.as_str(),
+                image_path,
+                max_download,
+                progress,
+                cancel,
+            )
+            .await;
+        }
 
-        downloader
-            .extend_from_slice(&data)
-            .await
-            .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+        download_file(device, image_path, progress, cancel).await?;
 
         device
             .flash(partition.as_str())
@@ -161,6 +319,50 @@ impl FastbootCore {
         Ok(())
     }
 
+    /// Flashes each `(partition, image_path)` pair in order, like a factory image script.
+    ///
+    /// `progress` gets `(index, bytes_sent, total)` for the image being flashed. The first
+    /// failure stops the run and comes back as `FlashFailed` with the index of that image,
+    /// later images are not touched. Cancelling through `cancel` returns plain `Cancelled`.
+    pub async fn flash_images(
+        &mut self,
+        images: &[(FlashPartition, String)],
+        mut progress: impl FnMut(usize, u64, u64),
+        cancel: &CancelToken,
+    ) -> Result<(), FastbootError> {
+        for (index, (partition, image_path)) in images.iter().enumerate() {
+            self.flash_with_progress(
+                partition.clone(),
+                image_path,
+                |sent, total| progress(index, sent, total),
+                cancel,
+            )
+            .await
+            .map_err(|e| match e {
+                FastbootError::Cancelled => e,
+                e => FastbootError::FlashFailed {
+                    index,
+                    total: images.len(),
+                    partition: partition.as_str().to_string(),
+                    source: Box::new(e),
+                },
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Downloads `image_path` and boots it once without writing it to any partition.
+    pub async fn boot_image(&mut self, image_path: &str) -> Result<(), FastbootError> {
+        let device = self.device.as_mut().ok_or(FastbootError::NoDevice)?;
+        let raw = self.raw.as_ref().ok_or(FastbootError::NoDevice)?;
+
+        download_file(device, image_path, |_, _| {}, &CancelToken::new()).await?;
+        raw.execute("boot").await?;
+
+        Ok(())
+    }
+
     pub async fn erase(&mut self, partition: FlashPartition) -> Result<(), FastbootError> {
         let device = self.device.as_mut().ok_or(FastbootError::NoDevice)?;
 
@@ -215,6 +417,49 @@ impl FastbootCore {
         }
     }
 
+    pub async fn get_current_slot(&mut self) -> Result<String, FastbootError> {
+        let slot = self.get_var("current-slot").await?;
+        Ok(slot.trim().trim_start_matches('_').to_string())
+    }
+
+    /// Marks `slot` ("a" or "b") as the active slot for the next boot.
+    pub async fn set_active_slot(&mut self, slot: &str) -> Result<(), FastbootError> {
+        if slot != "a" && slot != "b" {
+            return Err(FastbootError::CommandError(format!(
+                "Invalid slot '{}', expected 'a' or 'b'",
+                slot
+            )));
+        }
+
+        if !self.is_slot_supported(slot).await? {
+            return Err(FastbootError::CommandError(format!(
+                "Device does not report slot '{}' (has-slot:{} is not yes)",
+                slot, slot
+            )));
+        }
+
+        let raw = self.raw.as_ref().ok_or(FastbootError::NoDevice)?;
+        raw.execute(&format!("set_active:{}", slot)).await?;
+
+        Ok(())
+    }
+
+    /// Sends `oem <args>` and returns the INFO/OKAY text the bootloader answers with.
+    ///
+    /// Vendor commands are not validated. Some of them (unlocking, wiping, changing
+    /// partition layouts) can wipe user data or leave the device unbootable.
+    pub async fn oem_command(&mut self, args: &str) -> Result<String, FastbootError> {
+        self.raw_command(&format!("oem {}", args.trim())).await
+    }
+
+    /// Sends `cmd` verbatim (e.g. `flashing unlock`) and returns the collected INFO/OKAY text.
+    ///
+    /// Nothing is validated. A wrong command can wipe user data or brick the device.
+    pub async fn raw_command(&mut self, cmd: &str) -> Result<String, FastbootError> {
+        let raw = self.raw.as_ref().ok_or(FastbootError::NoDevice)?;
+        raw.execute(cmd).await
+    }
+
     pub async fn reboot_bootloader(&mut self) -> Result<(), FastbootError> {
         let device = self.device.as_mut().ok_or(FastbootError::NoDevice)?;
 
@@ -256,8 +501,504 @@ impl FastbootCore {
     }
 }
 
+/// Fills [`FastbootDeviceInfo`] from `dump`, asking the device for vars it doesn't have.
+async fn read_device_vars(
+    device: &mut fastboot_protocol::nusb::NusbFastBoot,
+    serial: String,
+    dump: &HashMap<String, String>,
+    mut timed_out: Vec<String>,
+) -> FastbootDeviceInfo {
+    FastbootDeviceInfo {
+        serial,
+        product: device_var(device, "product", dump, &mut timed_out).await,
+        model: device_var(device, "model", dump, &mut timed_out).await,
+        device: device_var(device, "device", dump, &mut timed_out).await,
+        bootloader: device_var(device, "bootloader", dump, &mut timed_out).await,
+        version: device_var(device, "version", dump, &mut timed_out).await,
+        timed_out,
+    }
+}
+
+async fn device_var(
+    device: &mut fastboot_protocol::nusb::NusbFastBoot,
+    var: &str,
+    dump: &HashMap<String, String>,
+    timed_out: &mut Vec<String>,
+) -> Option<String> {
+    if let Some(value) = dump.get(var) {
+        return Some(value.clone());
+    }
+    var_with_timeout(var, device.get_var(var), GETVAR_TIMEOUT, timed_out).await
+}
+
+/// `None` when `read` fails or takes longer than `timeout`, the latter also records `var`
+/// in `timed_out`. Once a var timed out its answer may still come in, so `read` isn't
+/// sent at all and `var` counts as timed out too.
+async fn var_with_timeout<E>(
+    var: &str,
+    read: impl Future<Output = Result<String, E>>,
+    timeout: Duration,
+    timed_out: &mut Vec<String>,
+) -> Option<String> {
+    if !timed_out.is_empty() {
+        timed_out.push(var.to_string());
+        return None;
+    }
+    match tokio::time::timeout(timeout, read).await {
+        Ok(value) => value.ok(),
+        Err(_) => {
+            timed_out.push(var.to_string());
+            None
+        }
+    }
+}
+
+/// Parses the INFO lines of `getvar:all`. Keys of per-partition vars keep their colon,
+/// `partition-size:boot_a:0x4000000` becomes `partition-size:boot_a` -> `0x4000000`.
+fn parse_getvar_all(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            // The prefix the fastboot CLI prints, in case the text was copied from it
+            let line = line.trim().trim_start_matches("(bootloader)").trim();
+            let (key, value) = line.split_once(": ").or_else(|| line.rsplit_once(':'))?;
+            let key = key.trim();
+            (!key.is_empty()).then(|| (key.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+struct RawTransport {
+    interface: nusb::Interface,
+    ep_out: u8,
+    ep_in: u8,
+    max_in: usize,
+}
+
+impl RawTransport {
+    fn from_interface(interface: nusb::Interface) -> Result<Self, FastbootError> {
+        let bulk_endpoint = |direction: Direction| {
+            interface.descriptors().find_map(|alt| {
+                alt.endpoints().find_map(|end| {
+                    (end.transfer_type() == EndpointType::Bulk && end.direction() == direction)
+                        .then(|| (end.address(), end.max_packet_size()))
+                })
+            })
+        };
+
+        let (ep_out, _) = bulk_endpoint(Direction::Out)
+            .ok_or_else(|| FastbootError::ProtocolError("No bulk OUT endpoint".to_string()))?;
+        let (ep_in, max_in) = bulk_endpoint(Direction::In)
+            .ok_or_else(|| FastbootError::ProtocolError("No bulk IN endpoint".to_string()))?;
+
+        Ok(Self {
+            interface,
+            ep_out,
+            ep_in,
+            max_in,
+        })
+    }
+
+    /// Sends `command` and collects INFO/TEXT lines until the device answers OKAY or FAIL.
+    async fn execute(&self, command: &str) -> Result<String, FastbootError> {
+        self.interface
+            .bulk_out(self.ep_out, command.as_bytes().to_vec())
+            .await
+            .status
+            .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+
+        let mut lines = Vec::new();
+        loop {
+            let completion = self
+                .interface
+                .bulk_in(self.ep_in, RequestBuffer::new(self.max_in))
+                .await;
+            completion
+                .status
+                .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+
+            match FastBootResponse::from_bytes(&completion.data)
+                .map_err(|e| FastbootError::ProtocolError(e.to_string()))?
+            {
+                FastBootResponse::Info(text) | FastBootResponse::Text(text) => lines.push(text),
+                FastBootResponse::Okay(text) => {
+                    if !text.is_empty() {
+                        lines.push(text);
+                    }
+                    return Ok(lines.join("\n"));
+                }
+                FastBootResponse::Fail(reason) => {
+                    lines.push(reason);
+                    return Err(FastbootError::ProtocolError(lines.join("\n")));
+                }
+                FastBootResponse::Data(_) => {
+                    return Err(FastbootError::ProtocolError(format!(
+                        "Unexpected data request after {}",
+                        command
+                    )));
+                }
+            }
+        }
+    }
+}
+
+const DOWNLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+async fn download_file(
+    device: &mut fastboot_protocol::nusb::NusbFastBoot,
+    image_path: &str,
+    mut progress: impl FnMut(u64, u64),
+    cancel: &CancelToken,
+) -> Result<(), FastbootError> {
+    let mut file = std::fs::File::open(image_path)?;
+    let total = file.metadata()?.len();
+
+    let size = u32::try_from(total).map_err(|_| {
+        FastbootError::CommandError(format!(
+            "Image {} is too large for a single download ({})",
+            image_path,
+            format_bytes(total)
+        ))
+    })?;
+
+    let mut downloader = device
+        .download(size)
+        .await
+        .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+
+    let mut buffer = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+    let mut sent = 0u64;
+
+    while sent < total {
+        if cancel.is_cancelled() {
+            return Err(FastbootError::Cancelled);
+        }
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Err(FastbootError::CommandError(format!(
+                "Image {} ended after {} of {} bytes",
+                image_path, sent, total
+            )));
+        }
+
+        downloader
+            .extend_from_slice(&buffer[..read])
+            .await
+            .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+
+        sent += read as u64;
+        progress(sent, total);
+    }
+
+    downloader
+        .finish()
+        .await
+        .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+
+    Ok(())
+}
+
+const SPARSE_MAGIC: u32 = 0xed26_ff3a;
+const SPARSE_BLOCK_SIZE: u64 = 4096;
+const SPARSE_FILE_HEADER_LEN: u64 = 28;
+const SPARSE_CHUNK_HEADER_LEN: u64 = 12;
+const SPARSE_CHUNK_RAW: u16 = 0xcac1;
+const SPARSE_CHUNK_DONT_CARE: u16 = 0xcac3;
+
+/// `max-download-size` is reported in hex by most bootloaders, decimal by a few.
+fn parse_download_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+    .filter(|size| *size > 0)
+}
+
+fn is_sparse_image(image_path: &str) -> Result<bool, FastbootError> {
+    let mut magic = [0u8; 4];
+    let mut file = std::fs::File::open(image_path)?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(u32::from_le_bytes(magic) == SPARSE_MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Splits `total_blocks` into `(start_block, blocks)` ranges whose sparse image, headers
+/// included, fits in `max_download` bytes.
+fn sparse_splits(total_blocks: u64, max_download: u64) -> Option<Vec<(u64, u64)>> {
+    let overhead = SPARSE_FILE_HEADER_LEN + 3 * SPARSE_CHUNK_HEADER_LEN;
+    let per_split = max_download.checked_sub(overhead)? / SPARSE_BLOCK_SIZE;
+    if per_split == 0 {
+        return None;
+    }
+
+    let mut splits = Vec::new();
+    let mut start = 0;
+    while start < total_blocks {
+        let blocks = per_split.min(total_blocks - start);
+        splits.push((start, blocks));
+        start += blocks;
+    }
+    Some(splits)
+}
+
+/// Headers around the raw data of one split: the file header and a skip up to
+/// `start` before it, a skip over the rest of the partition after it.
+fn sparse_split_headers(total_blocks: u64, start: u64, blocks: u64) -> (Vec<u8>, Vec<u8>) {
+    let end = start + blocks;
+    let chunks = 1 + u32::from(start > 0) + u32::from(end < total_blocks);
+
+    let mut head = Vec::new();
+    head.extend_from_slice(&SPARSE_MAGIC.to_le_bytes());
+    head.extend_from_slice(&1u16.to_le_bytes());
+    head.extend_from_slice(&0u16.to_le_bytes());
+    head.extend_from_slice(&(SPARSE_FILE_HEADER_LEN as u16).to_le_bytes());
+    head.extend_from_slice(&(SPARSE_CHUNK_HEADER_LEN as u16).to_le_bytes());
+    head.extend_from_slice(&(SPARSE_BLOCK_SIZE as u32).to_le_bytes());
+    head.extend_from_slice(&(total_blocks as u32).to_le_bytes());
+    head.extend_from_slice(&chunks.to_le_bytes());
+    head.extend_from_slice(&0u32.to_le_bytes());
+
+    if start > 0 {
+        push_chunk_header(&mut head, SPARSE_CHUNK_DONT_CARE, start, 0);
+    }
+    push_chunk_header(
+        &mut head,
+        SPARSE_CHUNK_RAW,
+        blocks,
+        blocks * SPARSE_BLOCK_SIZE,
+    );
+
+    let mut tail = Vec::new();
+    if end < total_blocks {
+        push_chunk_header(&mut tail, SPARSE_CHUNK_DONT_CARE, total_blocks - end, 0);
+    }
+
+    (head, tail)
+}
+
+fn push_chunk_header(out: &mut Vec<u8>, chunk_type: u16, blocks: u64, data_len: u64) {
+    out.extend_from_slice(&chunk_type.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&(blocks as u32).to_le_bytes());
+    out.extend_from_slice(&((SPARSE_CHUNK_HEADER_LEN + data_len) as u32).to_le_bytes());
+}
+
+/// Flashes a raw image that doesn't fit in one download as a series of sparse images,
+/// each covering part of the partition and skipping the rest, like `fastboot` does.
+async fn flash_raw_in_chunks(
+    device: &mut fastboot_protocol::nusb::NusbFastBoot,
+    partition: &str,
+    image_path: &str,
+    max_download: u64,
+    mut progress: impl FnMut(u64, u64),
+    cancel: &CancelToken,
+) -> Result<(), FastbootError> {
+    let mut file = std::fs::File::open(image_path)?;
+    let total = file.metadata()?.len();
+    let total_blocks = total.div_ceil(SPARSE_BLOCK_SIZE);
+
+    let splits = sparse_splits(total_blocks, max_download).ok_or_else(|| {
+        FastbootError::CommandError(format!(
+            "max-download-size of {} bytes is too small to split {}",
+            max_download, image_path
+        ))
+    })?;
+
+    let mut buffer = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+    let mut sent = 0u64;
+
+    for (start, blocks) in splits {
+        let (head, tail) = sparse_split_headers(total_blocks, start, blocks);
+        let data_len = blocks * SPARSE_BLOCK_SIZE;
+        let size = u32::try_from(head.len() as u64 + data_len + tail.len() as u64)
+            .map_err(|_| FastbootError::CommandError("Sparse chunk too large".to_string()))?;
+
+        let mut downloader = device
+            .download(size)
+            .await
+            .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+        downloader
+            .extend_from_slice(&head)
+            .await
+            .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+
+        file.seek(SeekFrom::Start(start * SPARSE_BLOCK_SIZE))?;
+        let mut remaining = data_len as usize;
+        while remaining > 0 {
+            if cancel.is_cancelled() {
+                return Err(FastbootError::Cancelled);
+            }
+            let want = remaining.min(buffer.len());
+            let mut read = file.read(&mut buffer[..want])?;
+            if read == 0 {
+                // The last block is zero padded past the end of the file
+                buffer[..want].fill(0);
+                read = want;
+            } else {
+                sent += read as u64;
+                progress(sent, total);
+            }
+
+            downloader
+                .extend_from_slice(&buffer[..read])
+                .await
+                .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+            remaining -= read;
+        }
+
+        downloader
+            .extend_from_slice(&tail)
+            .await
+            .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+        downloader
+            .finish()
+            .await
+            .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+
+        device
+            .flash(partition)
+            .await
+            .map_err(|e| FastbootError::ProtocolError(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+impl Drop for FastbootCore {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
 impl Default for FastbootCore {
     fn default() -> Self {
         Self::new().expect("Failed to create FastbootCore")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disconnect_when_not_connected() {
+        let mut core = FastbootCore::new().unwrap();
+        assert!(!core.is_connected());
+        core.disconnect();
+        core.disconnect();
+        assert!(!core.is_connected());
+    }
+
+    #[test]
+    fn test_unanswered_vars_disconnect() {
+        let mut core = FastbootCore::new().unwrap();
+        core.serial = Some("8A2X0KNB1".to_string());
+
+        let info = core.disconnect_if_unanswered(FastbootDeviceInfo::unanswered());
+        assert_eq!(info.timed_out.len(), 6);
+        assert!(!core.is_connected());
+        assert_eq!(core.serial, None);
+    }
+
+    #[tokio::test]
+    async fn test_var_with_timeout() {
+        let timeout = Duration::from_millis(20);
+        let mut timed_out = Vec::new();
+
+        let ok = async { Ok::<_, ()>("sunfish".to_string()) };
+        assert_eq!(
+            var_with_timeout("product", ok, timeout, &mut timed_out).await,
+            Some("sunfish".to_string())
+        );
+        let failed = async { Err::<String, _>("FAILunknown variable") };
+        assert_eq!(
+            var_with_timeout("model", failed, timeout, &mut timed_out).await,
+            None
+        );
+        assert!(timed_out.is_empty());
+
+        let stuck = std::future::pending::<Result<String, ()>>();
+        assert_eq!(
+            var_with_timeout("version", stuck, timeout, &mut timed_out).await,
+            None
+        );
+        assert_eq!(timed_out, vec!["version".to_string()]);
+
+        // Nothing more is asked after a timeout
+        let ok = async { Ok::<_, ()>("sunfish".to_string()) };
+        assert_eq!(
+            var_with_timeout("device", ok, timeout, &mut timed_out).await,
+            None
+        );
+        assert_eq!(timed_out, vec!["version".to_string(), "device".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_download_size() {
+        assert_eq!(parse_download_size("0x10000000"), Some(0x1000_0000));
+        assert_eq!(parse_download_size(" 268435456\n"), Some(268_435_456));
+        assert_eq!(parse_download_size("0"), None);
+        assert_eq!(parse_download_size("unknown"), None);
+    }
+
+    #[test]
+    fn test_sparse_splits() {
+        // 10 blocks with room for 4 per download
+        let max = SPARSE_FILE_HEADER_LEN + 3 * SPARSE_CHUNK_HEADER_LEN + 4 * SPARSE_BLOCK_SIZE;
+        assert_eq!(sparse_splits(10, max), Some(vec![(0, 4), (4, 4), (8, 2)]));
+        assert_eq!(sparse_splits(10, 100), None);
+    }
+
+    #[test]
+    fn test_sparse_split_headers() {
+        let (head, tail) = sparse_split_headers(10, 4, 4);
+        // file header, leading skip, raw chunk
+        assert_eq!(head.len(), 28 + 12 + 12);
+        assert_eq!(&head[0..4], &SPARSE_MAGIC.to_le_bytes());
+        assert_eq!(&head[16..20], &10u32.to_le_bytes());
+        assert_eq!(&head[20..24], &3u32.to_le_bytes());
+        assert_eq!(&head[28..30], &SPARSE_CHUNK_DONT_CARE.to_le_bytes());
+        assert_eq!(&head[32..36], &4u32.to_le_bytes());
+        assert_eq!(&head[40..42], &SPARSE_CHUNK_RAW.to_le_bytes());
+        assert_eq!(&head[48..52], &(12 + 4 * 4096u32).to_le_bytes());
+        assert_eq!(tail.len(), 12);
+        assert_eq!(&tail[4..8], &2u32.to_le_bytes());
+
+        // The first split has nothing to skip before it, the last nothing after it
+        let (head, tail) = sparse_split_headers(10, 0, 4);
+        assert_eq!(head.len(), 28 + 12);
+        assert_eq!(tail.len(), 12);
+        let (head, tail) = sparse_split_headers(10, 8, 2);
+        assert_eq!(&head[20..24], &2u32.to_le_bytes());
+        assert!(tail.is_empty());
+    }
+
+    #[test]
+    fn test_parse_getvar_all() {
+        let output = "\
+(bootloader) max-download-size:0x10000000
+(bootloader) is-userspace:no
+(bootloader) slot-count:2
+(bootloader) partition-size:boot_a:0x4000000
+(bootloader) partition-type:userdata:f2fs
+(bootloader) version-bootloader: slider-1.2-9152140";
+        let vars = parse_getvar_all(output);
+
+        assert_eq!(vars.get("max-download-size").unwrap(), "0x10000000");
+        assert_eq!(vars.get("is-userspace").unwrap(), "no");
+        assert_eq!(vars.get("slot-count").unwrap(), "2");
+        assert_eq!(vars.get("partition-size:boot_a").unwrap(), "0x4000000");
+        assert_eq!(vars.get("partition-type:userdata").unwrap(), "f2fs");
+        assert_eq!(
+            vars.get("version-bootloader").unwrap(),
+            "slider-1.2-9152140"
+        );
+        assert_eq!(vars.len(), 6);
+    }
+}
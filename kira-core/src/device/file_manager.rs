@@ -1,6 +1,21 @@
+use crate::config::{adb_config, adb_device};
+use crate::device::app_manager::{AppManagerError, is_su_unavailable, run_as};
+use crate::device::cancel::CancelToken;
+use crate::device::paths::{join_remote, normalize_remote, parent_remote};
+use crate::device::shell::{
+    LimitedWriter, LineStream, MAX_OUTPUT_BYTES, shell_command_capped, shell_quote,
+    stream_host_shell_lines,
+};
+use crate::format::{format_bytes, format_percent};
 use adb_client::ADBDeviceExt;
 use adb_client::server_device::ADBServerDevice;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileInfo {
@@ -10,6 +25,7 @@ pub struct FileInfo {
     pub permissions: String,
     pub is_directory: bool,
     pub is_symlink: bool,
+    pub symlink_target: Option<String>,
     pub modified: Option<u64>,
     pub owner: Option<String>,
     pub group: Option<String>,
@@ -23,6 +39,35 @@ pub struct DirectoryListing {
     pub total_size: u64,
     pub files: Vec<FileInfo>,
     pub parent_path: Option<String>,
+    /// More entries follow the requested page, the totals always cover the whole directory
+    #[serde(default)]
+    pub has_more: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum ListSort {
+    #[default]
+    Name,
+    Size,
+    Modified,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Which part of a directory [`list_directory_with`] returns. Directories always come
+/// first and ties are broken by name, so pages stay stable between calls.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct ListOptions {
+    pub offset: usize,
+    /// Everything after `offset` when unset
+    pub limit: Option<usize>,
+    pub sort_by: ListSort,
+    pub order: SortOrder,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +80,26 @@ pub struct StorageInfo {
     pub filesystem: String,
 }
 
+/// A mount that's filling up, with a message ready to show as is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageWarning {
+    pub mount: StorageInfo,
+    pub message: String,
+}
+
+impl From<StorageInfo> for StorageWarning {
+    fn from(mount: StorageInfo) -> Self {
+        let message = format!(
+            "{} is {} full, {} free of {}",
+            mount.path,
+            format_percent(mount.percentage_used),
+            format_bytes(mount.free_bytes),
+            format_bytes(mount.total_bytes)
+        );
+        StorageWarning { mount, message }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileSearchResult {
     pub name: String,
@@ -42,6 +107,8 @@ pub struct FileSearchResult {
     pub size: u64,
     pub is_directory: bool,
     pub matched_line: Option<String>,
+    /// 1-based line of `matched_line`
+    pub line_number: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,8 +135,21 @@ pub fn list_directory(
     device: &mut ADBServerDevice,
     path: &str,
 ) -> Result<DirectoryListing, FileManagerError> {
-    let command = format!("ls -la --time-style=+%s {}", path);
-    let output = run_shell_command(device, &command)?;
+    list_directory_with(device, path, &ListOptions::default())
+}
+
+/// Lists one sorted page of `path`, for folders too big to show at once.
+pub fn list_directory_with(
+    device: &mut ADBServerDevice,
+    path: &str,
+    options: &ListOptions,
+) -> Result<DirectoryListing, FileManagerError> {
+    let path = &normalize_remote(path);
+    let command = format!("ls -la --time-style=+%s {}", shell_quote(path));
+    let output = run_with_app_access(device, path, &command)?;
+    if let Some(err) = error_from_output(&output, path, FileManagerError::PathNotFound) {
+        return Err(err);
+    }
 
     let mut files = Vec::new();
     let mut total_files = 0;
@@ -88,7 +168,8 @@ pub fn list_directory(
         }
     }
 
-    let parent_path = get_parent_path(path);
+    let parent_path = parent_remote(path);
+    let (files, has_more) = sort_and_page(files, options);
 
     Ok(DirectoryListing {
         path: path.to_string(),
@@ -97,15 +178,44 @@ pub fn list_directory(
         total_size,
         files,
         parent_path,
+        has_more,
     })
 }
 
+fn sort_and_page(mut files: Vec<FileInfo>, options: &ListOptions) -> (Vec<FileInfo>, bool) {
+    files.sort_by(|a, b| {
+        let by_key = match options.sort_by {
+            ListSort::Name => std::cmp::Ordering::Equal,
+            ListSort::Size => a.size.cmp(&b.size),
+            ListSort::Modified => a.modified.cmp(&b.modified),
+        };
+        let by_key = by_key.then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        let by_key = match options.order {
+            SortOrder::Ascending => by_key,
+            SortOrder::Descending => by_key.reverse(),
+        };
+        b.is_directory.cmp(&a.is_directory).then(by_key)
+    });
+
+    let total = files.len();
+    let page: Vec<FileInfo> = files
+        .into_iter()
+        .skip(options.offset)
+        .take(options.limit.unwrap_or(usize::MAX))
+        .collect();
+    let has_more = options.offset.saturating_add(page.len()) < total;
+    (page, has_more)
+}
+
 pub fn get_file_info(
     device: &mut ADBServerDevice,
     path: &str,
 ) -> Result<FileInfo, FileManagerError> {
-    let command = format!("ls -la --time-style=+%s -d {}", path);
-    let output = run_shell_command(device, &command)?;
+    let command = format!("ls -la --time-style=+%s -d {}", shell_quote(path));
+    let output = run_with_app_access(device, path, &command)?;
+    if let Some(err) = error_from_output(&output, path, FileManagerError::FileNotFound) {
+        return Err(err);
+    }
 
     let line = output
         .lines()
@@ -115,55 +225,168 @@ pub fn get_file_info(
     parse_ls_line(line, path).ok_or_else(|| FileManagerError::FileNotFound(path.to_string()))
 }
 
-pub fn get_storage_info(
+/// Canonical path behind `path` with every symlink followed, e.g. `/sdcard` to
+/// `/storage/emulated/0`. List the result instead of the link itself.
+pub fn resolve_symlink(
     device: &mut ADBServerDevice,
     path: &str,
-) -> Result<StorageInfo, FileManagerError> {
-    let command = format!("df -k {}", path);
+) -> Result<String, FileManagerError> {
+    let output = run_shell_command(device, &format!("readlink -f {}", shell_quote(path)))?;
+    if let Some(err) = error_from_output(&output, path, FileManagerError::FileNotFound) {
+        return Err(err);
+    }
+
+    let target = output.lines().next().unwrap_or("").trim();
+    if target.starts_with('/') {
+        Ok(target.to_string())
+    } else {
+        Err(FileManagerError::FileNotFound(path.to_string()))
+    }
+}
+
+/// Filesystem path behind a `content://` URI from a share intent, read from the `_data`
+/// column of the media provider. `file://` URIs are decoded directly.
+pub fn resolve_content_uri(
+    device: &mut ADBServerDevice,
+    uri: &str,
+) -> Result<String, FileManagerError> {
+    if let Some(path) = uri.strip_prefix("file://") {
+        return Ok(percent_decode(path));
+    }
+    if !uri.starts_with("content://") {
+        return Err(FileManagerError::PathNotFound(uri.to_string()));
+    }
+
+    let command = format!(
+        "content query --uri {} --projection _data",
+        shell_quote(uri)
+    );
     let output = run_shell_command(device, &command)?;
+    if output.contains("SecurityException") || output.contains("Permission Denial") {
+        return Err(FileManagerError::PermissionDenied(uri.to_string()));
+    }
 
-    for line in output.lines() {
-        if line.contains(path) || line.ends_with(path.trim_start_matches('/')) {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 6 {
-                let filesystem = parts[0].to_string();
-                let total_kb: u64 = parts[1].parse().unwrap_or(0);
-                let used_kb: u64 = parts[2].parse().unwrap_or(0);
-                let free_kb: u64 = parts[3].parse().unwrap_or(0);
-
-                let total_bytes = total_kb * 1024;
-                let used_bytes = used_kb * 1024;
-                let free_bytes = free_kb * 1024;
-                let percentage_used = if total_bytes > 0 {
-                    (used_bytes as f64 / total_bytes as f64) * 100.0
-                } else {
-                    0.0
-                };
-
-                return Ok(StorageInfo {
-                    path: path.to_string(),
-                    total_bytes,
-                    used_bytes,
-                    free_bytes,
-                    percentage_used,
-                    filesystem,
-                });
+    parse_data_column(&output).ok_or_else(|| FileManagerError::PathNotFound(uri.to_string()))
+}
+
+// `Row: 0 _data=/storage/emulated/0/DCIM/Camera/IMG_1.jpg`, or `No result found.`
+fn parse_data_column(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (_, path) = line.trim().strip_prefix("Row:")?.split_once("_data=")?;
+        let path = path.trim();
+        path.starts_with('/').then(|| path.to_string())
+    })
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
             }
         }
     }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Usage of the filesystem that contains `path`. `df` resolves the mount itself, so
+/// `/sdcard/Download` reports the numbers of the `/data` filesystem it lives on.
+pub fn get_storage_info(
+    device: &mut ADBServerDevice,
+    path: &str,
+) -> Result<StorageInfo, FileManagerError> {
+    let output = run_shell_command(device, &format!("df {}", shell_quote(path)))?;
+    if let Some(err) = error_from_output(&output, path, FileManagerError::PathNotFound) {
+        return Err(err);
+    }
 
-    Err(FileManagerError::PathNotFound(path.to_string()))
+    let (_, human_readable) = parse_df(&output);
+    let mut info = storage_for_path(&output, path);
+    // Some busybox builds default to `-h`, some toybox builds print no usable row without `-k`
+    if info.is_none() || human_readable {
+        let output = run_shell_command(device, &format!("df -k {}", shell_quote(path)))?;
+        if let Some(precise) = storage_for_path(&output, path) {
+            info = Some(precise);
+        }
+    }
+
+    info.ok_or_else(|| FileManagerError::PathNotFound(path.to_string()))
+}
+
+// `df <path>` prints a single row for the containing mount, whatever its mount point is
+fn storage_for_path(output: &str, path: &str) -> Option<StorageInfo> {
+    let (rows, _) = parse_df(output);
+    rows.into_iter()
+        .last()
+        .map(|row| row.into_storage_info(path.to_string()))
+}
+
+/// How a search pattern is matched. The default is a case-insensitive substring search,
+/// which is what a search box is expected to do.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SearchOptions {
+    pub case_insensitive: bool,
+    /// Content search only, file names are matched as a glob
+    pub whole_word: bool,
+    /// Treat the pattern as an extended regex instead of literal text. Content search only.
+    pub regex: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            case_insensitive: true,
+            whole_word: false,
+            regex: false,
+        }
+    }
 }
 
+impl SearchOptions {
+    /// `grep` flags, ending with `-e` so a pattern starting with `-` isn't read as an option.
+    fn grep_flags(&self) -> String {
+        let mut flags = String::from(if self.regex { "-E" } else { "-F" });
+        if self.case_insensitive {
+            flags.push_str(" -i");
+        }
+        if self.whole_word {
+            flags.push_str(" -w");
+        }
+        flags.push_str(" -e");
+        flags
+    }
+}
+
+/// Finds files under `base_path` whose name matches the glob `pattern`.
 pub fn search_files(
     device: &mut ADBServerDevice,
     base_path: &str,
     pattern: &str,
     max_depth: u32,
+    options: &SearchOptions,
 ) -> Result<Vec<FileSearchResult>, FileManagerError> {
     let command = format!(
-        "find {} -maxdepth {} -name '{}' 2>/dev/null",
-        base_path, max_depth, pattern
+        "find {} -maxdepth {} {} {} 2>/dev/null",
+        shell_quote(base_path),
+        max_depth,
+        if options.case_insensitive {
+            "-iname"
+        } else {
+            "-name"
+        },
+        shell_quote(pattern)
     );
     let output = run_shell_command(device, &command)?;
 
@@ -180,14 +403,17 @@ pub fn search_files(
             .map(|s| s.to_string_lossy().to_string())
             .unwrap_or_default();
 
-        let is_dir = run_shell_command(device, &format!("test -d {} && echo 1 || echo 0", path))?
-            .trim()
+        let is_dir = run_shell_command(
+            device,
+            &format!("test -d {} && echo 1 || echo 0", shell_quote(path)),
+        )?
+        .trim()
             == "1";
 
         let size = if is_dir {
             0
         } else {
-            run_shell_command(device, &format!("stat -c %s {}", path))
+            run_shell_command(device, &format!("stat -c %s {}", shell_quote(path)))
                 .ok()
                 .and_then(|s| s.trim().parse().ok())
                 .unwrap_or(0)
@@ -199,59 +425,255 @@ pub fn search_files(
             size,
             is_directory: is_dir,
             matched_line: None,
+            line_number: None,
         });
     }
 
     Ok(results)
 }
 
+/// Files searched by [`search_content`] unless the caller asks for more.
+pub const DEFAULT_CONTENT_SEARCH_FILES: usize = 50;
+/// Matching lines kept per file by [`search_content`].
+pub const DEFAULT_MATCHES_PER_FILE: usize = 5;
+
+/// Greps the files under `base_path` for `pattern`. Every matching line is its own result
+/// with its line number, at most `max_matches_per_file` per file across at most
+/// `max_files` files.
 pub fn search_content(
     device: &mut ADBServerDevice,
     base_path: &str,
     pattern: &str,
     file_types: Option<&[&str]>,
+    options: &SearchOptions,
+    max_files: usize,
+    max_matches_per_file: usize,
 ) -> Result<Vec<FileSearchResult>, FileManagerError> {
-    let extensions = file_types.map(|t| t.join(" -o -name ")).unwrap_or_default();
+    let includes: Vec<String> = file_types
+        .unwrap_or_default()
+        .iter()
+        .map(|ext| format!(" --include={}", shell_quote(&format!("*.{}", ext))))
+        .collect();
 
-    let find_cmd = if extensions.is_empty() {
-        format!(
-            "grep -r -l '{}' {} 2>/dev/null | head -50",
-            pattern, base_path
-        )
-    } else {
-        format!(
-            "grep -r -l -E '{}' --include='*.{}' {} 2>/dev/null | head -50",
-            pattern, extensions, base_path
-        )
-    };
+    let command = format!(
+        "grep -r -n -m {}{} {} {} {} 2>/dev/null | head -n {}",
+        max_matches_per_file.max(1),
+        includes.concat(),
+        options.grep_flags(),
+        shell_quote(pattern),
+        shell_quote(base_path),
+        max_files.saturating_mul(max_matches_per_file.max(1))
+    );
+    let output = run_shell_command(device, &command)?;
 
-    let output = run_shell_command(device, &find_cmd)?;
+    Ok(parse_grep_matches(&output, max_files))
+}
 
-    let mut results = Vec::new();
+/// Reads at most `max_bytes` of a text file for previewing. Invalid UTF-8, including a
+/// character cut in half at the limit, is replaced rather than failing the preview.
+pub fn read_file_text(
+    device: &mut ADBServerDevice,
+    path: &str,
+    max_bytes: usize,
+) -> Result<String, FileManagerError> {
+    let bytes = read_file_bytes(device, path, max_bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
 
-    for line in output.lines() {
-        let path = line.trim();
-        if path.is_empty() {
-            continue;
+/// Reads at most `max_bytes` of a file. stderr is kept apart so an error message never
+/// ends up in the returned bytes.
+pub fn read_file_bytes(
+    device: &mut ADBServerDevice,
+    path: &str,
+    max_bytes: usize,
+) -> Result<Vec<u8>, FileManagerError> {
+    if max_bytes == 0 {
+        return Ok(Vec::new());
+    }
+
+    let command = format!("head -c {} {}", max_bytes, shell_quote(path));
+    let mut stdout = LimitedWriter::new(max_bytes);
+    let mut stderr = Vec::new();
+
+    let result = device.shell_command(&command, Some(&mut stdout), Some(&mut stderr));
+    if let Err(e) = result
+        && !stdout.is_truncated()
+    {
+        return Err(FileManagerError::CommandFailed(e.to_string()));
+    }
+
+    let stderr = String::from_utf8_lossy(&stderr);
+    if let Some(err) = error_from_output(&stderr, path, FileManagerError::FileNotFound) {
+        return Err(err);
+    }
+    if !stderr.trim().is_empty() {
+        return Err(FileManagerError::CommandFailed(stderr.trim().to_string()));
+    }
+
+    Ok(stdout.into_inner())
+}
+
+/// What [`pull_file`] transferred.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PullResult {
+    /// Size of the local file once the pull finished
+    pub bytes: u64,
+    /// Whether an earlier partial copy was continued rather than started over
+    pub resumed: bool,
+}
+
+// Resumes restart at a block boundary, the partial block at the end is fetched again
+const PULL_RESUME_BLOCK: u64 = 64 * 1024;
+
+/// Copies `remote_path` to `local_path` on the host. With `resume` a partial copy left by
+/// an interrupted pull is continued with `dd skip=` instead of starting over, which is
+/// what makes multi-GB pulls over Wi-Fi bearable. The result is checked against the
+/// remote size.
+pub fn pull_file(
+    device: &mut ADBServerDevice,
+    remote_path: &str,
+    local_path: &str,
+    resume: bool,
+) -> Result<PullResult, FileManagerError> {
+    let info = get_file_info(device, remote_path)?;
+    if info.is_directory {
+        return Err(FileManagerError::CommandFailed(format!(
+            "{} is a directory",
+            remote_path
+        )));
+    }
+
+    let local_size = if resume {
+        std::fs::metadata(local_path).map_or(0, |m| m.len())
+    } else {
+        0
+    };
+    let offset = resume_offset(local_size, info.size, PULL_RESUME_BLOCK);
+    let io_error =
+        |e: std::io::Error| FileManagerError::CommandFailed(format!("{}: {}", local_path, e));
+
+    if offset == 0 {
+        let mut file = File::create(local_path).map_err(io_error)?;
+        device
+            .pull(&remote_path, &mut file)
+            .map_err(|e| FileManagerError::CommandFailed(e.to_string()))?;
+    } else {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(local_path)
+            .map_err(io_error)?;
+        file.set_len(offset).map_err(io_error)?;
+        file.seek(SeekFrom::End(0)).map_err(io_error)?;
+
+        let command = format!(
+            "dd if={} bs={} skip={}",
+            shell_quote(remote_path),
+            PULL_RESUME_BLOCK,
+            offset / PULL_RESUME_BLOCK
+        );
+        // dd reports its record counts on stderr
+        let mut stderr = Vec::new();
+        device
+            .shell_command(&command, Some(&mut file), Some(&mut stderr))
+            .map_err(|e| FileManagerError::CommandFailed(e.to_string()))?;
+        let stderr = String::from_utf8_lossy(&stderr);
+        if let Some(err) = error_from_output(&stderr, remote_path, FileManagerError::FileNotFound) {
+            return Err(err);
         }
+    }
 
-        let name = std::path::Path::new(path)
-            .file_name()
-            .map(|s| s.to_string_lossy().to_string())
-            .unwrap_or_default();
+    let bytes = std::fs::metadata(local_path).map_err(io_error)?.len();
+    if bytes != info.size {
+        return Err(FileManagerError::CommandFailed(format!(
+            "pulled {} of {} bytes of {}, pull again with resume",
+            bytes, info.size, remote_path
+        )));
+    }
+    Ok(PullResult {
+        bytes,
+        resumed: offset > 0,
+    })
+}
 
-        let matched_line = get_matched_line(device, path, pattern);
+/// Where a resumed pull starts. A local copy larger than the remote file is from some
+/// other version of it and is pulled again from scratch.
+fn resume_offset(local_size: u64, remote_size: u64, block: u64) -> u64 {
+    if local_size > remote_size {
+        0
+    } else {
+        local_size / block * block
+    }
+}
 
-        results.push(FileSearchResult {
-            name,
-            path: path.to_string(),
-            size: 0,
-            is_directory: false,
-            matched_line,
-        });
+/// Runs `command` about `path`, retrying a permission error inside an app's private data
+/// directory with `run-as` for that app, then with `su`. The first answer is kept when
+/// neither gets further.
+fn run_with_app_access(
+    device: &mut ADBServerDevice,
+    path: &str,
+    command: &str,
+) -> Result<String, FileManagerError> {
+    let output = run_shell_command(device, command)?;
+    let denied = matches!(
+        error_from_output(&output, path, FileManagerError::PathNotFound),
+        Some(FileManagerError::PermissionDenied(_))
+    );
+    let Some(package) = app_data_package(path).filter(|_| denied) else {
+        return Ok(output);
+    };
+
+    match run_as(device, package, command) {
+        Ok(output) => return Ok(output),
+        Err(AppManagerError::PackageNotFound(_)) => return Ok(output),
+        Err(_) => {}
+    }
+    match run_shell_command(device, &format!("su -c {}", shell_quote(command))) {
+        Ok(su_output) if !is_su_unavailable(&su_output) => Ok(su_output),
+        _ => Ok(output),
     }
+}
 
-    Ok(results)
+/// Package whose private data directory `path` is in, `com.example` for
+/// `/data/data/com.example/files` or `/data/user/10/com.example`.
+fn app_data_package(path: &str) -> Option<&str> {
+    let rest = path.strip_prefix("/data/data/").or_else(|| {
+        let rest = path.strip_prefix("/data/user/")?;
+        let (user, rest) = rest.split_once('/')?;
+        user.chars().all(|c| c.is_ascii_digit()).then_some(rest)
+    })?;
+    let package = rest.split('/').next()?;
+    (!package.is_empty()).then_some(package)
+}
+
+/// Maps the shell's error message about `path` itself to a specific error. Messages
+/// about other paths, like an unreadable entry inside a listed directory, are ignored.
+fn error_from_output(
+    output: &str,
+    path: &str,
+    not_found: fn(String) -> FileManagerError,
+) -> Option<FileManagerError> {
+    let about_path = format!("{}:", path);
+    let quoted_path = format!("'{}'", path);
+
+    output
+        .lines()
+        .filter(|line| line.contains(&about_path) || line.contains(&quoted_path))
+        .find_map(|line| {
+            if line.contains("No such file or directory") {
+                Some(not_found(path.to_string()))
+            } else if line.contains("Permission denied") {
+                Some(FileManagerError::PermissionDenied(path.to_string()))
+            } else if line.contains("Not a directory") {
+                Some(FileManagerError::NotADirectory(path.to_string()))
+            } else if line.contains("Is a directory") {
+                Some(FileManagerError::CommandFailed(format!(
+                    "{} is a directory",
+                    path
+                )))
+            } else {
+                None
+            }
+        })
 }
 
 pub fn get_file_type(path: &str) -> FileType {
@@ -295,40 +717,168 @@ pub fn get_quick_storage_info(
     device: &mut ADBServerDevice,
 ) -> Result<Vec<StorageInfo>, FileManagerError> {
     let output = run_shell_command(device, "df")?;
-    let mut storages = Vec::new();
+    let (mut rows, human_readable) = parse_df(&output);
+    // Some busybox builds default to `-h`, whose rounded sizes are only a last resort
+    if human_readable {
+        let output = run_shell_command(device, "df -k")?;
+        let (k_rows, still_human) = parse_df(&output);
+        if !still_human || rows.is_empty() {
+            rows = k_rows;
+        }
+    }
 
-    for line in output.lines().skip(1) {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() >= 6 {
-            let path = parts.last().unwrap_or(&"");
-            if path.starts_with('/') && !path.contains(":/") {
-                let filesystem = parts[0].to_string();
-                let total_kb: u64 = parts[1].parse().unwrap_or(0);
-                let used_kb: u64 = parts[2].parse().unwrap_or(0);
-                let free_kb: u64 = parts[3].parse().unwrap_or(0);
-
-                let total_bytes = total_kb * 1024;
-                let used_bytes = used_kb * 1024;
-                let free_bytes = free_kb * 1024;
-                let percentage_used = if total_bytes > 0 {
-                    (used_bytes as f64 / total_bytes as f64) * 100.0
-                } else {
-                    0.0
-                };
-
-                storages.push(StorageInfo {
-                    path: path.to_string(),
-                    total_bytes,
-                    used_bytes,
-                    free_bytes,
-                    percentage_used,
-                    filesystem,
-                });
+    Ok(rows
+        .into_iter()
+        .filter(|row| row.mount_point.starts_with('/') && !row.mount_point.contains(":/"))
+        .map(|row| {
+            let path = row.mount_point.clone();
+            row.into_storage_info(path)
+        })
+        .collect())
+}
+
+/// Warnings for the mounts from [`get_quick_storage_info`] that are more than
+/// `threshold_percent` full.
+/// In-memory and kernel filesystems are left out, like the APEX images that are always
+/// 100% used.
+pub fn low_storage_mounts(
+    device: &mut ADBServerDevice,
+    threshold_percent: f64,
+) -> Result<Vec<StorageWarning>, FileManagerError> {
+    let mounts = get_quick_storage_info(device)?;
+    Ok(filter_low_storage(mounts, threshold_percent)
+        .into_iter()
+        .map(StorageWarning::from)
+        .collect())
+}
+
+const PSEUDO_FILESYSTEMS: [&str; 14] = [
+    "tmpfs",
+    "devtmpfs",
+    "proc",
+    "sysfs",
+    "cgroup",
+    "cgroup2",
+    "debugfs",
+    "tracefs",
+    "pstore",
+    "configfs",
+    "selinuxfs",
+    "bpf",
+    "binder",
+    "none",
+];
+
+fn filter_low_storage(mounts: Vec<StorageInfo>, threshold_percent: f64) -> Vec<StorageInfo> {
+    mounts
+        .into_iter()
+        .filter(|m| m.total_bytes > 0 && m.percentage_used > threshold_percent)
+        .filter(|m| !PSEUDO_FILESYSTEMS.contains(&m.filesystem.as_str()))
+        .filter(|m| !m.path.starts_with("/apex/") && !m.path.starts_with("/bootstrap-apex/"))
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DirChange {
+    Added(FileInfo),
+    Removed(FileInfo),
+    /// Same name with a different size or modification time, holding the new entry
+    Modified(FileInfo),
+}
+
+/// Lists `path` every `interval` on a background thread and sends what changed since the
+/// previous listing. The first listing is only the baseline and happens before this
+/// returns, so a bad path fails right away.
+///
+/// The watcher keeps its own connection to the device. It stops when `cancel` is tripped,
+/// or when the receiver has been dropped and it next has a change to send. Listings that
+/// fail while watching, e.g. while the device reconnects, are skipped.
+pub fn watch_directory(
+    device: &mut ADBServerDevice,
+    path: &str,
+    interval: Duration,
+    cancel: CancelToken,
+) -> Result<mpsc::Receiver<DirChange>, FileManagerError> {
+    let mut previous = list_directory(device, path)?.files;
+
+    let (tx, rx) = mpsc::channel();
+    let mut device = match device.identifier.clone() {
+        Some(serial) => adb_device(serial),
+        None => ADBServerDevice::autodetect(Some(adb_config().server_addr())),
+    };
+    let path = path.to_string();
+
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            if cancel.is_cancelled() {
+                break;
+            }
+
+            let Ok(listing) = list_directory(&mut device, &path) else {
+                continue;
+            };
+            for change in diff_listings(&previous, &listing.files) {
+                if tx.send(change).is_err() {
+                    return;
+                }
+            }
+            previous = listing.files;
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Streams lines appended to the file at `path` from now on, like `tail -f`, for apps that
+/// write their own log files instead of using logcat.
+///
+/// Tripping `cancel` or dropping the stream kills the device side `tail` within a moment.
+pub fn follow_file(
+    device: &mut ADBServerDevice,
+    path: &str,
+    cancel: CancelToken,
+) -> Result<LineStream<String>, FileManagerError> {
+    let info = get_file_info(device, path)?;
+    if info.is_directory {
+        return Err(FileManagerError::CommandFailed(format!(
+            "{} is a directory",
+            path
+        )));
+    }
+    let serial = device
+        .identifier
+        .clone()
+        .ok_or_else(|| FileManagerError::CommandFailed("device has no serial".to_string()))?;
+
+    let command = format!("tail -n 0 -f {}", shell_quote(path));
+    stream_host_shell_lines(&serial, &command, cancel, |line| Some(line.to_string()))
+        .map_err(|e| FileManagerError::CommandFailed(e.to_string()))
+}
+
+/// Changes from `old` to `new`, matched by name: removals first, then additions and
+/// modifications in the order of `new`.
+pub fn diff_listings(old: &[FileInfo], new: &[FileInfo]) -> Vec<DirChange> {
+    let old_by_name: HashMap<&str, &FileInfo> = old.iter().map(|f| (f.name.as_str(), f)).collect();
+    let new_by_name: HashMap<&str, &FileInfo> = new.iter().map(|f| (f.name.as_str(), f)).collect();
+
+    let mut changes: Vec<DirChange> = old
+        .iter()
+        .filter(|f| !new_by_name.contains_key(f.name.as_str()))
+        .map(|f| DirChange::Removed(f.clone()))
+        .collect();
+
+    for file in new {
+        match old_by_name.get(file.name.as_str()) {
+            None => changes.push(DirChange::Added(file.clone())),
+            Some(prev) if prev.size != file.size || prev.modified != file.modified => {
+                changes.push(DirChange::Modified(file.clone()))
             }
+            Some(_) => {}
         }
     }
 
-    Ok(storages)
+    changes
 }
 
 pub fn get_common_directories() -> Vec<(&'static str, &'static str)> {
@@ -346,32 +896,53 @@ pub fn get_common_directories() -> Vec<(&'static str, &'static str)> {
     ]
 }
 
+/// Parses one line of `ls -la --time-style=+%s`, or of plain `ls -la` with toybox's
+/// default `YYYY-MM-DD HH:MM` dates.
+///
+/// The timestamp is the anchor: everything after it is the name, kept as is so names
+/// with spaces survive, and for symlinks split at ` -> ` into name and target.
 fn parse_ls_line(line: &str, base_path: &str) -> Option<FileInfo> {
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    if parts.len() < 9 {
+    let tokens = split_tokens(line);
+    if tokens.len() < 7 {
+        return None;
+    }
+
+    let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+
+    // Character devices print `major, minor` where the size normally is, so the
+    // timestamp is the first epoch or date after a number from column 5 on
+    let (time_idx, name_idx, modified) = (5..tokens.len()).find_map(|i| {
+        if !is_number(tokens[i - 1].1) {
+            return None;
+        }
+        if is_number(tokens[i].1) {
+            return Some((i, i + 1, tokens[i].1.parse().ok()));
+        }
+        let modified = parse_ls_date(tokens[i].1, tokens.get(i + 1)?.1)?;
+        Some((i, i + 2, Some(modified)))
+    })?;
+    if name_idx >= tokens.len() {
         return None;
     }
 
-    let permissions = parts[0].to_string();
+    let permissions = tokens[0].1.to_string();
     let is_directory = permissions.starts_with('d');
     let is_symlink = permissions.starts_with('l');
-    let owner = parts.get(2).map(|s| s.to_string());
-    let group = parts.get(3).map(|s| s.to_string());
-    let size: u64 = parts.get(4).and_then(|s| s.parse().ok()).unwrap_or(0);
-    let modified: Option<u64> = parts.get(5).and_then(|s| s.parse().ok());
-
-    let name = parts[8..].join(" ");
-    let name = name.trim_matches('\n').to_string();
+    let owner = Some(tokens[2].1.to_string());
+    let group = Some(tokens[3].1.to_string());
+    let size: u64 = tokens[time_idx - 1].1.parse().unwrap_or(0);
+
+    let rest = line[tokens[name_idx].0..].trim_end_matches(['\n', '\r']);
+    let (name, symlink_target) = match rest.split_once(" -> ") {
+        Some((name, target)) if is_symlink => (name.to_string(), Some(target.to_string())),
+        _ => (rest.to_string(), None),
+    };
 
     if name == "." || name == ".." {
         return None;
     }
 
-    let path = if base_path.ends_with('/') {
-        format!("{}{}", base_path, name)
-    } else {
-        format!("{}/{}", base_path, name)
-    };
+    let path = join_remote(base_path, &name);
 
     Some(FileInfo {
         name,
@@ -380,30 +951,213 @@ fn parse_ls_line(line: &str, base_path: &str) -> Option<FileInfo> {
         permissions,
         is_directory,
         is_symlink,
+        symlink_target,
         modified,
         owner,
         group,
     })
 }
 
-fn get_parent_path(path: &str) -> Option<String> {
-    let p = std::path::Path::new(path);
-    p.parent().map(|p| p.to_string_lossy().to_string())
+/// `2024-01-05` and `13:37` as Unix time. `ls` prints the device's local time, which is
+/// taken as UTC here since the device timezone isn't known.
+fn parse_ls_date(date: &str, time: &str) -> Option<u64> {
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (hour, minute) = time.split_once(':')?;
+    let (hour, minute): (u64, u64) = (hour.parse().ok()?, minute.parse().ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146_097 + day_of_era - 719_468).ok()?;
+
+    Some(days * 86_400 + hour * 3600 + minute * 60)
+}
+
+fn split_tokens(line: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in line.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                tokens.push((s, &line[s..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        tokens.push((s, &line[s..]));
+    }
+
+    tokens
+}
+
+struct DfRow {
+    filesystem: String,
+    total_kb: u64,
+    used_kb: u64,
+    free_kb: u64,
+    mount_point: String,
+}
+
+impl DfRow {
+    fn into_storage_info(self, path: String) -> StorageInfo {
+        let total_bytes = self.total_kb * 1024;
+        let used_bytes = self.used_kb * 1024;
+        let free_bytes = self.free_kb * 1024;
+        let percentage_used = if total_bytes > 0 {
+            (used_bytes as f64 / total_bytes as f64) * 100.0
+        } else {
+            0.0
+        };
+
+        StorageInfo {
+            path,
+            total_bytes,
+            used_bytes,
+            free_bytes,
+            percentage_used,
+            filesystem: self.filesystem,
+        }
+    }
 }
 
-fn get_matched_line(device: &mut ADBServerDevice, path: &str, pattern: &str) -> Option<String> {
-    let command = format!("grep -n '{}' {} 2>/dev/null | head -1", pattern, path);
-    run_shell_command(device, &command).ok()
+/// Parses `df` rows, rejoining the ones toybox wraps onto a second line when the
+/// filesystem name is too long. The flag is set when sizes came with unit suffixes
+/// (`1.2G`), those are converted but only as precise as df rounded them.
+fn parse_df(output: &str) -> (Vec<DfRow>, bool) {
+    let mut lines: Vec<Vec<&str>> = Vec::new();
+    let mut pending: Option<&str> = None;
+
+    for line in output.lines() {
+        let mut parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() || parts[0] == "Filesystem" {
+            continue;
+        }
+        if parts.len() == 1 {
+            pending = Some(parts[0]);
+            continue;
+        }
+        if let Some(filesystem) = pending.take() {
+            parts.insert(0, filesystem);
+        }
+        lines.push(parts);
+    }
+
+    let human_readable = lines
+        .iter()
+        .filter(|parts| parts.len() >= 6)
+        .any(|parts| parts[1..4].iter().any(|v| v.ends_with(char::is_alphabetic)));
+
+    let rows = lines
+        .into_iter()
+        .filter(|parts| parts.len() >= 6)
+        .map(|parts| DfRow {
+            filesystem: parts[0].to_string(),
+            total_kb: parse_df_size(parts[1], human_readable),
+            used_kb: parse_df_size(parts[2], human_readable),
+            free_kb: parse_df_size(parts[3], human_readable),
+            mount_point: parts[5..].join(" "),
+        })
+        .collect();
+
+    (rows, human_readable)
+}
+
+/// Size column in KiB. In human readable output a bare number is a byte count.
+fn parse_df_size(value: &str, human_readable: bool) -> u64 {
+    let (number, unit) = match value.find(char::is_alphabetic) {
+        Some(idx) => value.split_at(idx),
+        None => (value, ""),
+    };
+    let Ok(number) = number.parse::<f64>() else {
+        return 0;
+    };
+
+    let kb = match unit.trim_end_matches(['i', 'B']) {
+        "" if human_readable => number / 1024.0,
+        "" | "K" | "k" => number,
+        "M" => number * 1024.0,
+        "G" => number * 1024.0 * 1024.0,
+        "T" => number * 1024.0 * 1024.0 * 1024.0,
+        _ => 0.0,
+    };
+    kb.round() as u64
+}
+
+// `path:line:text` from `grep -n`, the first `:<digits>:` ends the path. Binary file
+// notices have no line number and are dropped.
+fn parse_grep_matches(output: &str, max_files: usize) -> Vec<FileSearchResult> {
+    let mut results: Vec<FileSearchResult> = Vec::new();
+    let mut files = 0;
+
+    for line in output.lines() {
+        let Some((path, line_number, text)) = split_grep_line(line) else {
+            continue;
+        };
+        if results.last().is_none_or(|last| last.path != path) {
+            if files == max_files {
+                break;
+            }
+            files += 1;
+        }
+
+        results.push(FileSearchResult {
+            name: std::path::Path::new(path)
+                .file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            path: path.to_string(),
+            size: 0,
+            is_directory: false,
+            matched_line: Some(text.trim_end_matches('\r').to_string()),
+            line_number: Some(line_number),
+        });
+    }
+    results
+}
+
+fn split_grep_line(line: &str) -> Option<(&str, u32, &str)> {
+    let mut from = 0;
+    while let Some(offset) = line[from..].find(':') {
+        let colon = from + offset;
+        let rest = &line[colon + 1..];
+        if colon > 0
+            && let Some((digits, text)) = rest.split_once(':')
+            && let Ok(number) = digits.parse()
+        {
+            return Some((&line[..colon], number, text));
+        }
+        from = colon + 1;
+    }
+    None
 }
 
 fn run_shell_command(
     device: &mut ADBServerDevice,
     command: &str,
 ) -> Result<String, FileManagerError> {
-    let mut output = Vec::new();
-    device
-        .shell_command(&command, Some(&mut output), None)
+    let (output, truncated) = shell_command_capped(device, command, MAX_OUTPUT_BYTES)
         .map_err(|e| FileManagerError::CommandFailed(e.to_string()))?;
+    if truncated {
+        return Err(FileManagerError::CommandFailed(format!(
+            "output exceeded {} bytes",
+            MAX_OUTPUT_BYTES
+        )));
+    }
 
     String::from_utf8(output)
         .map_err(|e| FileManagerError::ParseError(e.to_string()))
@@ -439,6 +1193,97 @@ impl std::error::Error for FileManagerError {}
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_resume_offset() {
+        assert_eq!(resume_offset(0, 1000, 64), 0);
+        assert_eq!(resume_offset(200, 1000, 64), 192);
+        assert_eq!(resume_offset(1000, 1000, 64), 960);
+        assert_eq!(resume_offset(2000, 1000, 64), 0);
+        assert_eq!(resume_offset(63, 1000, 64), 0);
+    }
+
+    #[test]
+    fn test_parse_data_column() {
+        assert_eq!(
+            parse_data_column("Row: 0 _data=/storage/emulated/0/DCIM/Camera/IMG 1.jpg"),
+            Some("/storage/emulated/0/DCIM/Camera/IMG 1.jpg".to_string())
+        );
+        assert_eq!(parse_data_column("No result found."), None);
+        assert_eq!(parse_data_column("Row: 0 _data=NULL"), None);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(
+            percent_decode("/sdcard/Download/My%20File%2B1.pdf"),
+            "/sdcard/Download/My File+1.pdf"
+        );
+        assert_eq!(percent_decode("/sdcard/100%"), "/sdcard/100%");
+    }
+
+    fn file(name: &str, size: u64, modified: u64) -> FileInfo {
+        FileInfo {
+            name: name.to_string(),
+            path: format!("/sdcard/DCIM/{}", name),
+            size,
+            permissions: "-rw-rw----".to_string(),
+            is_directory: false,
+            is_symlink: false,
+            symlink_target: None,
+            modified: Some(modified),
+            owner: None,
+            group: None,
+        }
+    }
+
+    #[test]
+    fn test_filter_low_storage() {
+        let mount = |path: &str, filesystem: &str, percentage_used: f64| StorageInfo {
+            path: path.to_string(),
+            total_bytes: 1024,
+            used_bytes: (percentage_used * 10.24) as u64,
+            free_bytes: 0,
+            percentage_used,
+            filesystem: filesystem.to_string(),
+        };
+        let mounts = vec![
+            mount("/data", "/dev/block/dm-40", 94.0),
+            mount("/cache", "/dev/block/sda13", 12.0),
+            mount("/dev", "tmpfs", 99.0),
+            mount("/apex/com.android.art@1", "/dev/block/loop3", 100.0),
+        ];
+
+        let low = filter_low_storage(mounts, 90.0);
+        assert_eq!(low.len(), 1);
+        assert_eq!(low[0].path, "/data");
+        assert_eq!(
+            StorageWarning::from(low[0].clone()).message,
+            "/data is 94.0% full, 0 B free of 1.0 KB"
+        );
+    }
+
+    #[test]
+    fn test_diff_listings() {
+        let old = vec![
+            file("a.jpg", 10, 100),
+            file("b.jpg", 20, 100),
+            file("c.jpg", 30, 100),
+        ];
+        let new = vec![
+            file("a.jpg", 10, 100),
+            file("c.jpg", 35, 160),
+            file("d.jpg", 40, 170),
+        ];
+
+        let changes = diff_listings(&old, &new);
+        assert_eq!(changes.len(), 3);
+        assert!(matches!(&changes[0], DirChange::Removed(f) if f.name == "b.jpg"));
+        assert!(matches!(&changes[1], DirChange::Modified(f) if f.name == "c.jpg" && f.size == 35));
+        assert!(matches!(&changes[2], DirChange::Added(f) if f.name == "d.jpg"));
+
+        assert!(diff_listings(&new, &new).is_empty());
+    }
+
     #[test]
     fn test_file_info_creation() {
         let info = FileInfo {
@@ -448,6 +1293,7 @@ mod tests {
             permissions: "-rw-r--r--".to_string(),
             is_directory: false,
             is_symlink: false,
+            symlink_target: None,
             modified: Some(1640000000),
             owner: Some("root".to_string()),
             group: Some("root".to_string()),
@@ -466,12 +1312,50 @@ mod tests {
             total_size: 1024000,
             files: Vec::new(),
             parent_path: Some("/".to_string()),
+            has_more: false,
         };
 
         assert_eq!(listing.total_files, 10);
         assert_eq!(listing.total_dirs, 5);
     }
 
+    #[test]
+    fn test_sort_and_page() {
+        let mut camera = file("Camera", 0, 5);
+        camera.is_directory = true;
+        let files = vec![
+            file("b.jpg", 300, 1),
+            file("A.jpg", 100, 3),
+            camera,
+            file("c.jpg", 200, 2),
+        ];
+        let names = |files: &[FileInfo]| files.iter().map(|f| f.name.clone()).collect::<Vec<_>>();
+
+        let (page, has_more) = sort_and_page(files.clone(), &ListOptions::default());
+        assert_eq!(names(&page), vec!["Camera", "A.jpg", "b.jpg", "c.jpg"]);
+        assert!(!has_more);
+
+        let options = ListOptions {
+            offset: 1,
+            limit: Some(2),
+            sort_by: ListSort::Size,
+            order: SortOrder::Descending,
+        };
+        let (page, has_more) = sort_and_page(files.clone(), &options);
+        assert_eq!(names(&page), vec!["b.jpg", "c.jpg"]);
+        assert!(has_more);
+
+        let options = ListOptions {
+            offset: 3,
+            limit: Some(2),
+            sort_by: ListSort::Modified,
+            ..Default::default()
+        };
+        let (page, has_more) = sort_and_page(files, &options);
+        assert_eq!(names(&page), vec!["A.jpg"]);
+        assert!(!has_more);
+    }
+
     #[test]
     fn test_storage_info_creation() {
         let storage = StorageInfo {
@@ -486,6 +1370,72 @@ mod tests {
         assert_eq!(storage.percentage_used, 50.0);
     }
 
+    #[test]
+    fn test_parse_df_wrapped_line() {
+        let output = "\
+Filesystem            1K-blocks    Used Available Use% Mounted on
+/dev/root               5878932 5849888     12660 100% /
+tmpfs                   3793512    1568   3791944   1% /dev
+/dev/block/dm-46
+                      114549416 9123112 105295000   8% /data
+/dev/fuse             114549416 9123112 105295000   8% /storage/emulated
+";
+        let (rows, human_readable) = parse_df(output);
+        assert!(!human_readable);
+        assert_eq!(rows.len(), 4);
+
+        let data = &rows[2];
+        assert_eq!(data.filesystem, "/dev/block/dm-46");
+        assert_eq!(data.mount_point, "/data");
+        assert_eq!(data.total_kb, 114549416);
+        assert_eq!(data.used_kb, 9123112);
+        assert_eq!(data.free_kb, 105295000);
+        assert_eq!(rows[3].filesystem, "/dev/fuse");
+    }
+
+    #[test]
+    fn test_parse_df_human_readable() {
+        let output = "\
+Filesystem      Size  Used Avail Use% Mounted on
+/dev/block/dm-4 1.2G  900M  300M  75% /system
+tmpfs           512K     0  512K   0% /mnt
+";
+        let (rows, human_readable) = parse_df(output);
+        assert!(human_readable);
+        assert_eq!(rows[0].total_kb, 1_258_291);
+        assert_eq!(rows[0].used_kb, 900 * 1024);
+        assert_eq!(rows[0].free_kb, 300 * 1024);
+        assert_eq!(rows[1].total_kb, 512);
+        assert_eq!(rows[1].used_kb, 0);
+
+        let info = rows
+            .into_iter()
+            .next()
+            .unwrap()
+            .into_storage_info("/system".to_string());
+        assert!(info.percentage_used > 70.0 && info.percentage_used < 75.0);
+    }
+
+    #[test]
+    fn test_storage_for_path_uses_containing_mount() {
+        let output = "\
+Filesystem       1K-blocks    Used Available Use% Mounted on
+/dev/block/dm-46 114549416 9123112 105295000   8% /data
+";
+        let info = storage_for_path(output, "/data/media/0/Download").unwrap();
+        assert_eq!(info.path, "/data/media/0/Download");
+        assert_eq!(info.filesystem, "/dev/block/dm-46");
+        assert_eq!(info.total_bytes, 114549416 * 1024);
+
+        assert!(
+            storage_for_path(
+                "Filesystem 1K-blocks Used Available Use% Mounted on\n",
+                "/x"
+            )
+            .is_none()
+        );
+    }
+
     #[test]
     fn test_file_search_result_creation() {
         let result = FileSearchResult {
@@ -494,11 +1444,44 @@ mod tests {
             size: 50_000_000,
             is_directory: false,
             matched_line: None,
+            line_number: None,
         };
 
         assert_eq!(result.size, 50_000_000);
     }
 
+    #[test]
+    fn test_search_options_grep_flags() {
+        assert_eq!(SearchOptions::default().grep_flags(), "-F -i -e");
+        let options = SearchOptions {
+            case_insensitive: false,
+            whole_word: true,
+            regex: true,
+        };
+        assert_eq!(options.grep_flags(), "-E -w -e");
+    }
+
+    #[test]
+    fn test_parse_grep_matches() {
+        let output = "/sdcard/notes.txt:3:TODO: call back\n\
+                      /sdcard/notes.txt:10:another TODO\r\n\
+                      Binary file /sdcard/app.bin matches\n\
+                      /sdcard/log:v2.txt:1:TODO\n\
+                      /sdcard/other.txt:7:TODO";
+
+        let results = parse_grep_matches(output, 2);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].path, "/sdcard/notes.txt");
+        assert_eq!(results[0].line_number, Some(3));
+        assert_eq!(results[0].matched_line.as_deref(), Some("TODO: call back"));
+        assert_eq!(results[1].line_number, Some(10));
+        assert_eq!(results[1].matched_line.as_deref(), Some("another TODO"));
+        assert_eq!(results[2].path, "/sdcard/log:v2.txt");
+        assert_eq!(results[2].name, "log:v2.txt");
+        assert_eq!(results[2].line_number, Some(1));
+    }
+
     #[test]
     fn test_get_file_type_image() {
         let file_type = get_file_type("/sdcard/photo.jpg");
@@ -567,16 +1550,6 @@ mod tests {
         assert!(dirs.iter().any(|(name, _)| *name == "APK Files"));
     }
 
-    #[test]
-    fn test_get_parent_path() {
-        assert_eq!(
-            get_parent_path("/sdcard/Download/file.txt"),
-            Some("/sdcard/Download".to_string())
-        );
-        assert_eq!(get_parent_path("/sdcard"), Some("/".to_string()));
-        assert_eq!(get_parent_path("/"), None);
-    }
-
     #[test]
     fn test_file_manager_error_display() {
         let err = FileManagerError::PathNotFound("/test".to_string());
@@ -612,6 +1585,7 @@ mod tests {
             permissions: "lrwxrwxrwx".to_string(),
             is_directory: false,
             is_symlink: true,
+            symlink_target: Some("/sdcard/target".to_string()),
             modified: None,
             owner: None,
             group: None,
@@ -620,9 +1594,171 @@ mod tests {
         assert!(info.is_symlink);
     }
 
+    #[test]
+    fn test_parse_ls_line_file() {
+        let line = "-rw-rw---- 1 u0_a123 media_rw 20480 1700000000 notes.txt";
+        let info = parse_ls_line(line, "/sdcard").unwrap();
+
+        assert_eq!(info.name, "notes.txt");
+        assert_eq!(info.path, "/sdcard/notes.txt");
+        assert_eq!(info.size, 20480);
+        assert_eq!(info.modified, Some(1700000000));
+        assert_eq!(info.owner.as_deref(), Some("u0_a123"));
+        assert_eq!(info.group.as_deref(), Some("media_rw"));
+        assert!(!info.is_directory);
+    }
+
+    #[test]
+    fn test_parse_ls_line_name_with_spaces() {
+        let line = "drwxrwx--x 2 root sdcard_rw 3452 1699999999 My Photos";
+        let info = parse_ls_line(line, "/sdcard/").unwrap();
+
+        assert_eq!(info.name, "My Photos");
+        assert_eq!(info.path, "/sdcard/My Photos");
+        assert!(info.is_directory);
+
+        let line = "-rw-rw---- 1 root sdcard_rw 10 1699999999 two  spaces 2024.txt";
+        let info = parse_ls_line(line, "/sdcard").unwrap();
+        assert_eq!(info.name, "two  spaces 2024.txt");
+    }
+
+    #[test]
+    fn test_parse_ls_line_symlink() {
+        let line = "lrwxrwxrwx 1 root root 21 1230768000 sdcard -> /storage/self/primary";
+        let info = parse_ls_line(line, "/").unwrap();
+
+        assert!(info.is_symlink);
+        assert_eq!(info.name, "sdcard");
+        assert_eq!(info.path, "/sdcard");
+        assert_eq!(
+            info.symlink_target.as_deref(),
+            Some("/storage/self/primary")
+        );
+    }
+
+    #[test]
+    fn test_parse_ls_line_char_device() {
+        let line = "crw-rw-rw- 1 root root 1, 3 1230768000 null";
+        let info = parse_ls_line(line, "/dev").unwrap();
+
+        assert_eq!(info.name, "null");
+        assert_eq!(info.modified, Some(1230768000));
+    }
+
+    #[test]
+    fn test_parse_ls_line_default_time_style() {
+        let line = "-rw-rw---- 1 u0_a123 media_rw 20480 2023-11-14 22:13 notes 2024.txt";
+        let info = parse_ls_line(line, "/sdcard").unwrap();
+
+        assert_eq!(info.name, "notes 2024.txt");
+        assert_eq!(info.size, 20480);
+        assert_eq!(info.modified, Some(1699999980));
+
+        let line = "crw-rw-rw- 1 root root 1, 3 2009-01-01 00:00 null";
+        let info = parse_ls_line(line, "/dev").unwrap();
+        assert_eq!(info.name, "null");
+        assert_eq!(info.modified, Some(1230768000));
+    }
+
+    #[test]
+    fn test_parse_ls_date() {
+        assert_eq!(parse_ls_date("1970-01-01", "00:00"), Some(0));
+        assert_eq!(parse_ls_date("2024-02-29", "12:30"), Some(1709209800));
+        assert_eq!(parse_ls_date("2024-13-01", "12:30"), None);
+        assert_eq!(parse_ls_date("2024-01-01", "noon"), None);
+        assert_eq!(parse_ls_date("1969-12-31", "23:59"), None);
+    }
+
+    #[test]
+    fn test_parse_ls_line_rejects() {
+        assert!(parse_ls_line("total 48", "/sdcard").is_none());
+        assert!(parse_ls_line("drwxr-xr-x 5 root root 4096 1700000000 .", "/sdcard").is_none());
+        assert!(parse_ls_line("drwxr-xr-x 5 root root 4096 1700000000 ..", "/sdcard").is_none());
+    }
+
+    #[test]
+    fn test_error_from_output() {
+        let missing = FileManagerError::FileNotFound;
+        assert_eq!(
+            error_from_output(
+                "head: /data/x: No such file or directory",
+                "/data/x",
+                missing
+            ),
+            Some(FileManagerError::FileNotFound("/data/x".to_string()))
+        );
+        assert_eq!(
+            error_from_output("head: /data/x: Permission denied", "/data/x", missing),
+            Some(FileManagerError::PermissionDenied("/data/x".to_string()))
+        );
+        assert_eq!(error_from_output("", "/data/x", missing), None);
+    }
+
+    #[test]
+    fn test_error_from_output_listing() {
+        let missing = FileManagerError::PathNotFound;
+        assert_eq!(
+            error_from_output(
+                "ls: /sdcard/nope: No such file or directory",
+                "/sdcard/nope",
+                missing
+            ),
+            Some(FileManagerError::PathNotFound("/sdcard/nope".to_string()))
+        );
+        assert_eq!(
+            error_from_output(
+                "ls: cannot open directory '/data': Permission denied",
+                "/data",
+                missing
+            ),
+            Some(FileManagerError::PermissionDenied("/data".to_string()))
+        );
+        assert_eq!(
+            error_from_output(
+                "ls: /sdcard/a.txt/b: Not a directory",
+                "/sdcard/a.txt/b",
+                missing
+            ),
+            Some(FileManagerError::NotADirectory(
+                "/sdcard/a.txt/b".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_error_from_output_ignores_entries() {
+        let output = "total 8\n\
+                      ls: /data/local/secret: Permission denied\n\
+                      drwxrwx--x 2 shell shell 4096 1700000000 tmp";
+        assert_eq!(
+            error_from_output(output, "/data/local", FileManagerError::PathNotFound),
+            None
+        );
+    }
+
     #[test]
     fn test_file_category_variants() {
         assert_eq!(FileCategory::Directory, FileCategory::Directory);
         assert_ne!(FileCategory::Image, FileCategory::Video);
     }
+
+    #[test]
+    fn test_app_data_package() {
+        assert_eq!(
+            app_data_package("/data/data/com.example/shared_prefs"),
+            Some("com.example")
+        );
+        assert_eq!(
+            app_data_package("/data/user/0/com.example"),
+            Some("com.example")
+        );
+        assert_eq!(
+            app_data_package("/data/user/10/com.work/files"),
+            Some("com.work")
+        );
+        assert_eq!(app_data_package("/data/data"), None);
+        assert_eq!(app_data_package("/data/data/"), None);
+        assert_eq!(app_data_package("/data/user/0"), None);
+        assert_eq!(app_data_package("/sdcard/Download"), None);
+    }
 }
@@ -1,3 +1,5 @@
+use crate::device::shell::shell_output;
+use adb_client::server_device::ADBServerDevice;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -6,13 +8,26 @@ pub struct DeviceInfo {
     pub model: Option<String>,
     pub manufacturer: Option<String>,
     pub android_version: Option<String>,
+    /// API level from `ro.build.version.sdk`
+    pub sdk_int: Option<u32>,
+    /// `REL` on release builds, the preview name otherwise
+    pub codename: Option<String>,
+    /// Primary ABI
     pub abi: Option<String>,
+    /// Every supported ABI in order of preference, from `ro.product.cpu.abilist`
+    #[serde(default)]
+    pub abi_list: Vec<String>,
+    #[serde(default)]
+    pub supports_64bit: bool,
     pub slot: Option<String>,
     pub battery: Option<u8>,
     pub storage: Option<Storage>,
     pub screen_resolution: Option<String>,
+    pub screen: Option<ScreenInfo>,
     pub refresh_rate: Option<u32>,
     pub build: Option<BuildInfo>,
+    #[serde(default)]
+    pub encryption: Option<EncryptionState>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -22,8 +37,156 @@ pub struct Storage {
     pub free: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ScreenInfo {
+    pub width: u32,
+    pub height: u32,
+    pub density_dpi: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct DisplayModes {
+    pub supported: Vec<u32>,
+    pub active: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct BuildInfo {
     pub security_patch: Option<String>,
     pub build_id: Option<String>,
+    pub fingerprint: Option<String>,
+    /// `user`, `userdebug` or `eng`
+    pub build_type: Option<String>,
+    /// e.g. `release-keys` or `test-keys`
+    pub tags: Option<String>,
+    pub incremental: Option<String>,
+    pub build_date: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BootloaderState {
+    Locked,
+    Unlocked,
+    Unknown,
+}
+
+/// How `/data` is encrypted, which decides how a custom recovery has to decrypt it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EncryptionState {
+    Unencrypted,
+    /// File-based encryption, the default since Android 10
+    FileBased,
+    /// Full-disk encryption of the whole `userdata` block device
+    FullDisk,
+    Unknown,
+}
+
+impl EncryptionState {
+    /// From `ro.crypto.state` and `ro.crypto.type`, unset props may be `None` or empty.
+    pub fn from_props(state: Option<&str>, crypto_type: Option<&str>) -> Self {
+        let state = state.map(str::trim).unwrap_or_default();
+        let crypto_type = crypto_type.map(str::trim).unwrap_or_default();
+
+        match (state, crypto_type) {
+            ("unencrypted" | "unsupported", _) => EncryptionState::Unencrypted,
+            ("encrypted", "file") => EncryptionState::FileBased,
+            // ro.crypto.type only exists since Android 7, before that it could only be FDE
+            ("encrypted", "block" | "") => EncryptionState::FullDisk,
+            _ => EncryptionState::Unknown,
+        }
+    }
+}
+
+/// Splits `ro.product.cpu.abilist`. Devices older than Android 5 don't have it and only
+/// report their primary `abi`.
+pub fn parse_abi_list(abilist: Option<&str>, abi: Option<&str>) -> Vec<String> {
+    let abis: Vec<String> = abilist
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    if abis.is_empty() {
+        abi.map(str::trim)
+            .filter(|a| !a.is_empty())
+            .map(|a| vec![a.to_string()])
+            .unwrap_or_default()
+    } else {
+        abis
+    }
+}
+
+pub fn is_64bit_abi(abi: &str) -> bool {
+    matches!(abi, "arm64-v8a" | "x86_64" | "riscv64" | "mips64")
+}
+
+pub fn get_encryption_state(device: &mut ADBServerDevice) -> EncryptionState {
+    let Ok(output) = shell_output(device, "getprop ro.crypto.state; getprop ro.crypto.type") else {
+        return EncryptionState::Unknown;
+    };
+    let output = String::from_utf8_lossy(&output);
+    let mut props = output.lines();
+    EncryptionState::from_props(props.next(), props.next())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum DeviceError {
+    CommandFailed(String),
+    IOError(String),
+    Cancelled,
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+            DeviceError::IOError(msg) => write!(f, "IO Error: {}", msg),
+            DeviceError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_abi_list() {
+        let abis = parse_abi_list(Some("arm64-v8a,armeabi-v7a,armeabi\n"), Some("arm64-v8a"));
+        assert_eq!(abis, vec!["arm64-v8a", "armeabi-v7a", "armeabi"]);
+        assert!(abis.iter().any(|a| is_64bit_abi(a)));
+
+        assert_eq!(
+            parse_abi_list(None, Some("armeabi-v7a")),
+            vec!["armeabi-v7a"]
+        );
+        assert_eq!(parse_abi_list(Some(""), Some("x86")), vec!["x86"]);
+        assert!(parse_abi_list(None, None).is_empty());
+        assert!(!is_64bit_abi("armeabi-v7a"));
+    }
+
+    #[test]
+    fn test_encryption_state_from_props() {
+        let state = |s, t| EncryptionState::from_props(s, t);
+
+        assert_eq!(
+            state(Some("encrypted"), Some("file")),
+            EncryptionState::FileBased
+        );
+        assert_eq!(
+            state(Some("encrypted"), Some("block")),
+            EncryptionState::FullDisk
+        );
+        assert_eq!(state(Some("encrypted"), None), EncryptionState::FullDisk);
+        assert_eq!(
+            state(Some("unencrypted"), Some("")),
+            EncryptionState::Unencrypted
+        );
+        assert_eq!(state(Some(""), Some("")), EncryptionState::Unknown);
+        assert_eq!(state(None, None), EncryptionState::Unknown);
+    }
 }
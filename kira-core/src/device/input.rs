@@ -0,0 +1,196 @@
+use adb_client::ADBDeviceExt;
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum KeyCode {
+    Home,
+    Back,
+    Power,
+    VolumeUp,
+    VolumeDown,
+    Enter,
+    /// Turns the screen on, unlike `Power` it does nothing when already on
+    Wakeup,
+    /// Turns the screen off, does nothing when already off
+    Sleep,
+    Raw(u32),
+}
+
+impl KeyCode {
+    pub fn code(&self) -> u32 {
+        match self {
+            KeyCode::Home => 3,
+            KeyCode::Back => 4,
+            KeyCode::VolumeUp => 24,
+            KeyCode::VolumeDown => 25,
+            KeyCode::Power => 26,
+            KeyCode::Enter => 66,
+            KeyCode::Sleep => 223,
+            KeyCode::Wakeup => 224,
+            KeyCode::Raw(code) => *code,
+        }
+    }
+}
+
+pub fn tap(device: &mut ADBServerDevice, x: u32, y: u32) -> Result<(), InputError> {
+    run_input_command(device, &format!("input tap {} {}", x, y))
+}
+
+pub fn swipe(
+    device: &mut ADBServerDevice,
+    x1: u32,
+    y1: u32,
+    x2: u32,
+    y2: u32,
+    duration_ms: u32,
+) -> Result<(), InputError> {
+    run_input_command(
+        device,
+        &format!("input swipe {} {} {} {} {}", x1, y1, x2, y2, duration_ms),
+    )
+}
+
+/// Drags a finger through `points` in order without lifting it, for gestures a straight
+/// [`swipe`] can't do. Needs `input motionevent`, Android 10 or later.
+pub fn swipe_path(device: &mut ADBServerDevice, points: &[(u32, u32)]) -> Result<(), InputError> {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return Err(InputError::InvalidInput("path is empty".to_string()));
+    };
+
+    let mut events = vec![format!("input motionevent DOWN {} {}", first.0, first.1)];
+    events.extend(
+        points
+            .iter()
+            .skip(1)
+            .map(|(x, y)| format!("input motionevent MOVE {} {}", x, y)),
+    );
+    events.push(format!("input motionevent UP {} {}", last.0, last.1));
+
+    run_input_command(device, &events.join("; "))
+}
+
+/// Types `text` into the focused field.
+pub fn input_text(device: &mut ADBServerDevice, text: &str) -> Result<(), InputError> {
+    if text.is_empty() {
+        return Err(InputError::InvalidInput("text is empty".to_string()));
+    }
+    if text.chars().any(|c| c.is_control()) {
+        return Err(InputError::InvalidInput(
+            "text contains control characters, use key_event instead".to_string(),
+        ));
+    }
+
+    run_input_command(device, &input_text_command(text))
+}
+
+pub fn key_event(device: &mut ADBServerDevice, keycode: KeyCode) -> Result<(), InputError> {
+    run_input_command(device, &format!("input keyevent {}", keycode.code()))
+}
+
+// `input text` has no escape for a literal `%s`, so the text is typed in two goes there,
+// one ending in `%` and the next starting with `s`
+fn input_text_command(text: &str) -> String {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while let Some(i) = rest.find("%s") {
+        chunks.push(&rest[..=i]);
+        rest = &rest[i + 1..];
+    }
+    chunks.push(rest);
+
+    chunks
+        .iter()
+        .map(|chunk| format!("input text {}", escape_input_text(chunk)))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// `input text` reads `%s` as a space, everything else has to survive the device shell
+fn escape_input_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            ' ' => escaped.push_str("%s"),
+            '\\' | '\'' | '"' | '`' | '$' | '&' | '|' | ';' | '<' | '>' | '(' | ')' | '*' | '?'
+            | '~' | '#' | '!' | '[' | ']' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn run_input_command(device: &mut ADBServerDevice, command: &str) -> Result<(), InputError> {
+    let mut output = Vec::new();
+    device
+        .shell_command(&command, Some(&mut output), None)
+        .map_err(|e| InputError::CommandFailed(e.to_string()))?;
+
+    // `input` prints usage or an exception on bad arguments but still exits quietly
+    let output = String::from_utf8_lossy(&output);
+    if output.contains("Exception") || output.contains("Usage:") {
+        return Err(InputError::CommandFailed(output.trim().to_string()));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum InputError {
+    InvalidInput(String),
+    CommandFailed(String),
+}
+
+impl std::fmt::Display for InputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            InputError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_input_text_spaces() {
+        assert_eq!(escape_input_text("hello world"), "hello%sworld");
+    }
+
+    #[test]
+    fn test_escape_input_text_shell_chars() {
+        assert_eq!(escape_input_text("a&b;c"), "a\\&b\\;c");
+        assert_eq!(escape_input_text("it's $5"), "it\\'s%s\\$5");
+        assert_eq!(escape_input_text("(x)"), "\\(x\\)");
+    }
+
+    #[test]
+    fn test_input_text_command() {
+        assert_eq!(input_text_command("50% off"), "input text 50%%soff");
+        assert_eq!(
+            input_text_command("100%sure"),
+            "input text 100%; input text sure"
+        );
+        assert_eq!(
+            input_text_command("%s%s"),
+            "input text %; input text s%; input text s"
+        );
+    }
+
+    #[test]
+    fn test_key_code_values() {
+        assert_eq!(KeyCode::Home.code(), 3);
+        assert_eq!(KeyCode::Back.code(), 4);
+        assert_eq!(KeyCode::Power.code(), 26);
+        assert_eq!(KeyCode::Enter.code(), 66);
+        assert_eq!(KeyCode::Wakeup.code(), 224);
+        assert_eq!(KeyCode::Raw(187).code(), 187);
+    }
+}
@@ -1,9 +1,16 @@
-use adb_client::ADBDeviceExt;
+use crate::device::cancel::CancelToken;
+use crate::device::shell::{
+    LineStream, shell_output, shell_quote, spawn_host_shell, stop_host_shell,
+    stream_host_shell_lines,
+};
 use adb_client::server_device::ADBServerDevice;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::io::{BufRead, BufReader};
+use std::process::Child;
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct LogcatEntry {
@@ -16,7 +23,7 @@ pub struct LogcatEntry {
     pub raw: String,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum LogLevel {
     Verbose,
     Debug,
@@ -27,6 +34,29 @@ pub enum LogLevel {
     Silent,
 }
 
+impl LogLevel {
+    pub const ALL: [LogLevel; 7] = [
+        LogLevel::Verbose,
+        LogLevel::Debug,
+        LogLevel::Info,
+        LogLevel::Warning,
+        LogLevel::Error,
+        LogLevel::Fatal,
+        LogLevel::Silent,
+    ];
+
+    /// Color the UI draws entries of this level in, so every view agrees on it.
+    pub fn color_hex(&self) -> &'static str {
+        match self {
+            LogLevel::Verbose | LogLevel::Silent => "#9E9E9E",
+            LogLevel::Debug => "#2196F3",
+            LogLevel::Info => "#4CAF50",
+            LogLevel::Warning => "#FFC107",
+            LogLevel::Error | LogLevel::Fatal => "#F44336",
+        }
+    }
+}
+
 impl From<char> for LogLevel {
     fn from(c: char) -> Self {
         match c {
@@ -88,21 +118,30 @@ pub struct LogcatFilter {
 }
 
 impl LogcatFilter {
+    /// Lets every entry through, unlike the default which drops debug and verbose.
+    pub fn match_all() -> Self {
+        Self {
+            tag: None,
+            level: None,
+            message_contains: None,
+        }
+    }
+
     pub fn matches(&self, entry: &LogcatEntry) -> bool {
-        if let Some(ref tag) = self.tag {
-            if !entry.tag.contains(tag) {
-                return false;
-            }
+        if let Some(ref tag) = self.tag
+            && !entry.tag.contains(tag)
+        {
+            return false;
         }
-        if let Some(level) = self.level {
-            if entry.level < level {
-                return false;
-            }
+        if let Some(level) = self.level
+            && entry.level < level
+        {
+            return false;
         }
-        if let Some(ref msg) = self.message_contains {
-            if !entry.message.contains(msg) {
-                return false;
-            }
+        if let Some(ref msg) = self.message_contains
+            && !entry.message.contains(msg)
+        {
+            return false;
         }
         true
     }
@@ -146,7 +185,7 @@ pub fn parse_logcat_line(line: &str) -> Option<LogcatEntry> {
 fn parse_threadtime_format(line: &str) -> Option<LogcatEntry> {
     let parts: Vec<&str> = line.split_whitespace().collect();
     if parts.len() >= 7 {
-        let timestamp = parts.get(0).map(|s| s.to_string())?;
+        let timestamp = format!("{} {}", parts[0], parts[1]);
         let pid = parts.get(2)?.parse::<u32>().ok()?;
         let tid = parts.get(3)?.parse::<u32>().ok()?;
         let level_char = parts.get(4)?.chars().next()?;
@@ -168,34 +207,43 @@ fn parse_threadtime_format(line: &str) -> Option<LogcatEntry> {
 }
 
 fn parse_brief_format(line: &str) -> Option<LogcatEntry> {
-    if let Some(bracket_start) = line.find('[') {
-        if let Some(bracket_end) = line.find(']') {
-            let tag = line[bracket_start + 1..bracket_end].to_string();
-            let rest = line[bracket_end + 1..].trim();
-            let level_char = rest.chars().next().unwrap_or('I');
-            let level = LogLevel::from(level_char);
-            let message = rest[2..].trim().to_string();
-
-            return Some(LogcatEntry {
-                timestamp: String::new(),
-                pid: 0,
-                tid: 0,
-                level,
-                tag,
-                message,
-                raw: line.to_string(),
-            });
-        }
+    if let Some(bracket_start) = line.find('[')
+        && let Some(bracket_end) = line.find(']')
+    {
+        let tag = line[bracket_start + 1..bracket_end].to_string();
+        let rest = line[bracket_end + 1..].trim();
+        let level_char = rest.chars().next().unwrap_or('I');
+        let level = LogLevel::from(level_char);
+        let message = rest[2..].trim().to_string();
+
+        return Some(LogcatEntry {
+            timestamp: String::new(),
+            pid: 0,
+            tid: 0,
+            level,
+            tag,
+            message,
+            raw: line.to_string(),
+        });
     }
     None
 }
 
+/// Reads the last `lines` entries of `buffer`. `min_level` and `tag` are handed to logcat
+/// as a filter spec so the device drops the rest, `tag` has to match exactly. Use
+/// [`LogcatFilter`] on the result for substring matching.
 pub fn read_logcat(
     device: &mut ADBServerDevice,
     buffer: LogcatBuffer,
     lines: usize,
+    min_level: Option<LogLevel>,
+    tag: Option<&str>,
 ) -> Result<Vec<LogcatEntry>, LogcatError> {
-    let command = format!("logcat -d -b {} -t {}", buffer.as_str(), lines);
+    let mut command = format!("logcat -d -b {} -t {}", buffer.as_str(), lines);
+    if let Some(spec) = filter_spec(min_level, tag) {
+        command.push(' ');
+        command.push_str(&spec);
+    }
     let output = run_shell_command(device, &command)?;
 
     let entries: Vec<LogcatEntry> = output.lines().filter_map(parse_logcat_line).collect();
@@ -203,57 +251,259 @@ pub fn read_logcat(
     Ok(entries)
 }
 
+// `*:W`, `Tag:V *:S` or `Tag:W *:S`, quoted since the shell would expand `*`
+fn filter_spec(min_level: Option<LogLevel>, tag: Option<&str>) -> Option<String> {
+    match (tag.map(str::trim).filter(|t| !t.is_empty()), min_level) {
+        (Some(tag), level) => {
+            let level = level.unwrap_or(LogLevel::Verbose);
+            Some(format!(
+                "{} '*:S'",
+                shell_quote(&format!("{}:{}", tag, level))
+            ))
+        }
+        (None, Some(level)) => Some(format!("'*:{}'", level)),
+        (None, None) => None,
+    }
+}
+
 pub fn clear_logcat(device: &mut ADBServerDevice, buffer: LogcatBuffer) -> Result<(), LogcatError> {
     let command = format!("logcat -c -b {}", buffer.as_str());
     run_shell_command(device, &command)?;
     Ok(())
 }
 
+/// Streams new entries of `buffer` until the stream is dropped or `cancel` is tripped,
+/// which stops the device side logcat within a moment even when the buffer is quiet.
 pub fn stream_logcat(
     device: &mut ADBServerDevice,
     buffer: LogcatBuffer,
     filter: LogcatFilter,
-) -> Result<mpsc::Receiver<LogcatEntry>, LogcatError> {
-    let (tx, rx) = mpsc::channel();
+    cancel: CancelToken,
+) -> Result<LineStream<LogcatEntry>, LogcatError> {
     let command = format!("logcat -v threadtime -b {}", buffer.as_str());
-
     let serial = device
         .identifier
         .as_ref()
-        .ok_or_else(|| LogcatError::DeviceNotFound)?;
+        .ok_or(LogcatError::DeviceNotFound)?;
 
-    let mut child = std::process::Command::new("adb")
-        .args(["-s", serial, "shell", &command])
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .spawn()
-        .map_err(|e| LogcatError::IOError(e.to_string()))?;
+    stream_host_shell_lines(serial, &command, cancel, move |line| {
+        parse_logcat_line(line).filter(|entry| filter.matches(entry))
+    })
+    .map_err(|e| LogcatError::IOError(e.to_string()))
+}
 
+/// Emits the last `lines` entries of `buffer`, then keeps following new ones.
+///
+/// If the device side logcat exits, e.g. because someone ran `logcat -c`, it is restarted
+/// from the last timestamp seen and entries that were already sent are skipped. Tripping
+/// `cancel` stops the tail, like dropping the stream does, also while nothing is logged.
+pub fn tail_logcat(
+    device: &mut ADBServerDevice,
+    buffer: LogcatBuffer,
+    lines: usize,
+    filter: LogcatFilter,
+    cancel: CancelToken,
+) -> Result<LineStream<LogcatEntry>, LogcatError> {
+    let serial = device
+        .identifier
+        .clone()
+        .ok_or(LogcatError::DeviceNotFound)?;
+
+    // logcat rejects -T 0
+    let command = format!(
+        "logcat -v threadtime -b {} -T {}",
+        buffer.as_str(),
+        lines.max(1)
+    );
+    let mut entries = follow_logcat(&serial, &command, &cancel)?;
+    let (tx, dropped, stream) = LineStream::channel();
+
+    thread::spawn(move || {
+        let mut position = TailPosition::default();
+        let mut idle_restarts = 0;
+
+        loop {
+            let sent_before = position.sent;
+            let mut stopped = false;
+
+            loop {
+                if cancel.is_cancelled() || dropped.is_cancelled() {
+                    stopped = true;
+                    break;
+                }
+                let entry = match entries.recv_timeout(TAIL_POLL_INTERVAL) {
+                    Ok(entry) => entry,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    // The device side logcat exited
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                };
+                if !position.advance(&entry) || !filter.matches(&entry) {
+                    continue;
+                }
+                if tx.send(entry).is_err() {
+                    stopped = true;
+                    break;
+                }
+            }
+            // Stops the device side logcat
+            drop(entries);
+
+            if stopped {
+                break;
+            }
+            // A device that went away exits right away every time, don't spin on it
+            idle_restarts = if position.sent == sent_before {
+                idle_restarts + 1
+            } else {
+                0
+            };
+            if idle_restarts > TAIL_MAX_IDLE_RESTARTS {
+                break;
+            }
+            thread::sleep(TAIL_RESTART_DELAY);
+
+            let command = match &position.timestamp {
+                Some(timestamp) => format!(
+                    "logcat -v threadtime -b {} -T {}",
+                    buffer.as_str(),
+                    shell_quote(timestamp)
+                ),
+                None => format!("logcat -v threadtime -b {}", buffer.as_str()),
+            };
+            entries = match follow_logcat(&serial, &command, &cancel) {
+                Ok(entries) => entries,
+                Err(_) => break,
+            };
+        }
+    });
+
+    Ok(stream)
+}
+
+fn follow_logcat(
+    serial: &str,
+    command: &str,
+    cancel: &CancelToken,
+) -> Result<LineStream<LogcatEntry>, LogcatError> {
+    stream_host_shell_lines(serial, command, cancel.clone(), parse_logcat_line)
+        .map_err(|e| LogcatError::IOError(e.to_string()))
+}
+
+/// Blocks until `buffer` logs an entry that passes `filter` and `predicate`, e.g. an app
+/// logging "login success" before a test moves on, and returns it. Entries from the second
+/// of the call onwards are considered, older ones are skipped.
+///
+/// The device side logcat is stopped before returning, also on `TimedOut`.
+pub fn read_logcat_until(
+    device: &mut ADBServerDevice,
+    buffer: LogcatBuffer,
+    filter: LogcatFilter,
+    predicate: impl Fn(&LogcatEntry) -> bool,
+    timeout: Duration,
+) -> Result<LogcatEntry, LogcatError> {
+    let deadline = Instant::now() + timeout;
+    let serial = device
+        .identifier
+        .clone()
+        .ok_or(LogcatError::DeviceNotFound)?;
+
+    // The device clock, the host's may be off by minutes
+    let now = run_shell_command(device, "date +%s")?;
+    let now: u64 = now
+        .parse()
+        .map_err(|_| LogcatError::ParseError(format!("Unexpected date output: {}", now)))?;
+
+    let command = format!("logcat -v threadtime -b {} -T {}.000", buffer.as_str(), now);
+    let mut child = spawn_logcat(&serial, &command)?;
     let stdout = child
         .stdout
         .take()
         .ok_or(LogcatError::IOError("Failed to capture stdout".to_string()))?;
-    let reader = BufReader::new(stdout);
 
+    let (tx, rx) = mpsc::channel();
     thread::spawn(move || {
-        for line in reader.lines() {
-            match line {
-                Ok(line) => {
-                    if let Some(entry) = parse_logcat_line(&line) {
-                        if filter.matches(&entry) {
-                            if tx.send(entry).is_err() {
-                                break;
-                            }
-                        }
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            if let Some(entry) = parse_logcat_line(&line)
+                && tx.send(entry).is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let found = wait_for_entry(
+        &rx,
+        |entry| filter.matches(entry) && predicate(entry),
+        deadline,
+    );
+    // Ends the reader thread too, its stdout closes
+    stop_host_shell(child);
+    found
+}
+
+fn wait_for_entry(
+    rx: &mpsc::Receiver<LogcatEntry>,
+    predicate: impl Fn(&LogcatEntry) -> bool,
+    deadline: Instant,
+) -> Result<LogcatEntry, LogcatError> {
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(remaining) {
+            Ok(entry) if predicate(&entry) => return Ok(entry),
+            Ok(_) => {}
+            Err(mpsc::RecvTimeoutError::Timeout) => return Err(LogcatError::TimedOut),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err(LogcatError::StreamClosed),
+        }
+    }
+}
+
+const TAIL_RESTART_DELAY: Duration = Duration::from_millis(500);
+const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+const TAIL_MAX_IDLE_RESTARTS: u32 = 5;
+
+/// Where a tail has got to, so a restarted logcat can resume without repeating lines.
+#[derive(Default)]
+struct TailPosition {
+    timestamp: Option<String>,
+    // Lines already seen with `timestamp`, `-T <time>` starts at the first of them again
+    seen_at_timestamp: Vec<String>,
+    sent: usize,
+}
+
+impl TailPosition {
+    /// Records `entry` and returns whether it is new.
+    fn advance(&mut self, entry: &LogcatEntry) -> bool {
+        // `--------- beginning of main` and friends
+        if entry.timestamp.is_empty() {
+            return false;
+        }
+
+        if let Some(last) = &self.timestamp {
+            // MM-DD hh:mm:ss.mmm sorts as text
+            match entry.timestamp.as_str().cmp(last.as_str()) {
+                std::cmp::Ordering::Less => return false,
+                std::cmp::Ordering::Equal => {
+                    if self.seen_at_timestamp.contains(&entry.raw) {
+                        return false;
                     }
+                    self.seen_at_timestamp.push(entry.raw.clone());
+                    self.sent += 1;
+                    return true;
                 }
-                Err(_) => break,
+                std::cmp::Ordering::Greater => {}
             }
         }
-        let _ = child.kill();
-    });
 
-    Ok(rx)
+        self.timestamp = Some(entry.timestamp.clone());
+        self.seen_at_timestamp = vec![entry.raw.clone()];
+        self.sent += 1;
+        true
+    }
+}
+
+fn spawn_logcat(serial: &str, command: &str) -> Result<Child, LogcatError> {
+    spawn_host_shell(serial, command).map_err(|e| LogcatError::IOError(e.to_string()))
 }
 
 pub fn get_logcat_buffers(device: &mut ADBServerDevice) -> Result<Vec<String>, LogcatError> {
@@ -281,12 +531,181 @@ pub fn filter_entries(entries: Vec<LogcatEntry>, filter: LogcatFilter) -> Vec<Lo
     entries.into_iter().filter(|e| filter.matches(e)).collect()
 }
 
+/// Entry count per tag, noisiest first. Tags with the same count are sorted by name.
+pub fn summarize_tags(entries: &[LogcatEntry]) -> Vec<(String, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for entry in entries {
+        *counts.entry(entry.tag.as_str()).or_insert(0) += 1;
+    }
+
+    let mut tags: Vec<(String, usize)> = counts
+        .into_iter()
+        .map(|(tag, count)| (tag.to_string(), count))
+        .collect();
+    tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    tags
+}
+
+pub fn summarize_levels(entries: &[LogcatEntry]) -> HashMap<LogLevel, usize> {
+    let mut counts = HashMap::new();
+    for entry in entries {
+        *counts.entry(entry.level).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// A log entry plus the lines folded into it. Only stack traces have `lines`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LogGroup {
+    pub entry: LogcatEntry,
+    pub lines: Vec<String>,
+}
+
+/// Folds each Java/Kotlin stack trace into the entry that starts it, pidcat style.
+///
+/// A trace starts at `FATAL EXCEPTION` or at an `AndroidRuntime` `Caused by:` and takes in
+/// the following entries with the same pid and tag, even when other processes log in
+/// between.
+pub fn group_stacktraces(entries: Vec<LogcatEntry>) -> Vec<LogGroup> {
+    let mut groups: Vec<LogGroup> = Vec::new();
+    // (pid, tag) of each trace still collecting lines -> index into `groups`
+    let mut open: HashMap<(u32, String), usize> = HashMap::new();
+
+    for entry in entries {
+        let key = (entry.pid, entry.tag.clone());
+        let starts_trace = entry.message.contains("FATAL EXCEPTION");
+
+        if !starts_trace && let Some(&idx) = open.get(&key) {
+            groups[idx].lines.push(entry.message);
+            continue;
+        }
+
+        if starts_trace
+            || (entry.tag == "AndroidRuntime" && entry.message.starts_with("Caused by:"))
+        {
+            open.insert(key, groups.len());
+        }
+        groups.push(LogGroup {
+            entry,
+            lines: Vec::new(),
+        });
+    }
+
+    groups
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum IncidentKind {
+    Anr,
+    NativeCrash,
+    JavaCrash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Incident {
+    pub kind: IncidentKind,
+    pub package: Option<String>,
+    pub pid: u32,
+    pub reason: String,
+    pub timestamp: String,
+}
+
+// How far ahead to look for the lines that complete an incident
+const INCIDENT_LOOKAHEAD: usize = 20;
+
+/// Finds ANRs, native crashes and Java crashes in `entries`.
+pub fn detect_incidents(entries: &[LogcatEntry]) -> Vec<Incident> {
+    let mut incidents = Vec::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        let message = entry.message.as_str();
+
+        let incident = if let Some(rest) = message.split_once("ANR in ").map(|(_, r)| r) {
+            // Logged by system_server: `ANR in com.example (...)`, `PID: 4321`, `Reason: ...`
+            let package = rest.split_whitespace().next().map(String::from);
+            let pid = following(entries, i, |m| m.strip_prefix("PID:"))
+                .and_then(|pid| pid.trim().parse().ok())
+                .unwrap_or(entry.pid);
+            let reason = following(entries, i, |m| m.strip_prefix("Reason:"))
+                .unwrap_or("Application not responding")
+                .trim()
+                .to_string();
+            Some((IncidentKind::Anr, package, pid, reason))
+        } else if let Some(start) = message.find("Fatal signal") {
+            // `Fatal signal 11 (SIGSEGV), code 1 (SEGV_MAPERR), ..., pid 1200 (com.example)`
+            let signal = &message[start..];
+            let reason = signal
+                .split(',')
+                .next()
+                .unwrap_or(signal)
+                .trim()
+                .to_string();
+            let (pid, package) = crashed_process(signal).unwrap_or((entry.pid, None));
+            Some((IncidentKind::NativeCrash, package, pid, reason))
+        } else if message.contains("FATAL EXCEPTION") {
+            // Followed by `Process: com.example, PID: 4321` and the exception itself
+            let process = following(entries, i, |m| m.strip_prefix("Process:"));
+            let package = process
+                .and_then(|p| p.split(',').next())
+                .map(|p| p.trim().to_string());
+            let pid = process
+                .and_then(|p| p.split_once("PID:"))
+                .and_then(|(_, pid)| pid.trim().parse().ok())
+                .unwrap_or(entry.pid);
+            let reason = following(entries, i, |m| {
+                (!m.starts_with("Process:") && !m.starts_with("at ")).then_some(m)
+            })
+            .unwrap_or("")
+            .to_string();
+            Some((IncidentKind::JavaCrash, package, pid, reason))
+        } else {
+            None
+        };
+
+        if let Some((kind, package, pid, reason)) = incident {
+            incidents.push(Incident {
+                kind,
+                package,
+                pid,
+                reason,
+                timestamp: entry.timestamp.clone(),
+            });
+        }
+    }
+
+    incidents
+}
+
+/// First match of `extract` among the next entries logged by the same pid and tag.
+fn following<'a>(
+    entries: &'a [LogcatEntry],
+    idx: usize,
+    extract: impl Fn(&'a str) -> Option<&'a str>,
+) -> Option<&'a str> {
+    let origin = &entries[idx];
+    entries[idx + 1..]
+        .iter()
+        .take(INCIDENT_LOOKAHEAD)
+        .filter(|e| e.pid == origin.pid && e.tag == origin.tag)
+        .find_map(|e| extract(e.message.as_str()))
+}
+
+// The trailing `pid 1200 (com.example)` of a debuggerd `Fatal signal` line
+fn crashed_process(signal: &str) -> Option<(u32, Option<String>)> {
+    let (_, rest) = signal.rsplit_once(", pid ")?;
+    let (pid, name) = rest.split_once(' ').unwrap_or((rest, ""));
+    let pid = pid.parse().ok()?;
+    let name = name.trim().trim_start_matches('(').trim_end_matches(')');
+    Some((pid, (!name.is_empty()).then(|| name.to_string())))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum LogcatError {
     DeviceNotFound,
     IOError(String),
     ParseError(String),
     StreamClosed,
+    TimedOut,
 }
 
 impl std::fmt::Display for LogcatError {
@@ -296,6 +715,7 @@ impl std::fmt::Display for LogcatError {
             LogcatError::IOError(msg) => write!(f, "IO Error: {}", msg),
             LogcatError::ParseError(msg) => write!(f, "Parse Error: {}", msg),
             LogcatError::StreamClosed => write!(f, "Logcat stream closed"),
+            LogcatError::TimedOut => write!(f, "Timed out waiting for a matching entry"),
         }
     }
 }
@@ -303,20 +723,61 @@ impl std::fmt::Display for LogcatError {
 impl std::error::Error for LogcatError {}
 
 fn run_shell_command(device: &mut ADBServerDevice, command: &str) -> Result<String, LogcatError> {
-    let mut output = Vec::new();
-    device
-        .shell_command(&command, Some(&mut output), None)
-        .map_err(|e| LogcatError::IOError(e.to_string()))?;
+    let output = shell_output(device, command).map_err(|e| LogcatError::IOError(e.to_string()))?;
 
     String::from_utf8(output)
         .map_err(|e| LogcatError::ParseError(e.to_string()))
         .map(|s| s.trim().to_string())
 }
 
+impl Default for LogcatEntry {
+    fn default() -> Self {
+        Self {
+            timestamp: String::new(),
+            pid: 0,
+            tid: 0,
+            level: LogLevel::Debug,
+            tag: String::new(),
+            message: String::new(),
+            raw: String::new(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_level_colors() {
+        for level in LogLevel::ALL {
+            let color = level.color_hex();
+            assert_eq!(color.len(), 7, "{:?}", level);
+            assert!(color.starts_with('#'));
+            assert!(color[1..].chars().all(|c| c.is_ascii_hexdigit()));
+        }
+        assert_eq!(LogLevel::Error.color_hex(), LogLevel::Fatal.color_hex());
+        assert_ne!(LogLevel::Debug.color_hex(), LogLevel::Info.color_hex());
+    }
+
+    #[test]
+    fn test_filter_spec() {
+        assert_eq!(filter_spec(None, None), None);
+        assert_eq!(
+            filter_spec(Some(LogLevel::Warning), None),
+            Some("'*:W'".to_string())
+        );
+        assert_eq!(
+            filter_spec(None, Some("ActivityManager")),
+            Some("ActivityManager:V '*:S'".to_string())
+        );
+        assert_eq!(
+            filter_spec(Some(LogLevel::Error), Some("My Tag")),
+            Some("'My Tag:E' '*:S'".to_string())
+        );
+        assert_eq!(filter_spec(None, Some(" ")), None);
+    }
+
     #[test]
     fn test_log_level_from_char() {
         assert_eq!(LogLevel::from('V'), LogLevel::Verbose);
@@ -432,6 +893,7 @@ mod tests {
 
         let entry = parse_logcat_line(line).unwrap();
 
+        assert_eq!(entry.timestamp, "01-15 12:00:00.123");
         assert_eq!(entry.pid, 1234);
         assert_eq!(entry.tid, 5678);
         assert_eq!(entry.level, LogLevel::Info);
@@ -506,6 +968,119 @@ mod tests {
         assert_eq!(filtered[0].tag, "ActivityManager");
     }
 
+    fn entry(tag: &str, level: LogLevel) -> LogcatEntry {
+        LogcatEntry {
+            tag: tag.to_string(),
+            level,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summarize_tags() {
+        let entries = vec![
+            entry("chatty", LogLevel::Info),
+            entry("WifiHAL", LogLevel::Debug),
+            entry("chatty", LogLevel::Info),
+            entry("ActivityManager", LogLevel::Warning),
+            entry("chatty", LogLevel::Info),
+            entry("WifiHAL", LogLevel::Error),
+            entry("Binder", LogLevel::Debug),
+        ];
+
+        assert_eq!(
+            summarize_tags(&entries),
+            vec![
+                ("chatty".to_string(), 3),
+                ("WifiHAL".to_string(), 2),
+                ("ActivityManager".to_string(), 1),
+                ("Binder".to_string(), 1),
+            ]
+        );
+        assert!(summarize_tags(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_summarize_levels() {
+        let entries = vec![
+            entry("a", LogLevel::Info),
+            entry("b", LogLevel::Info),
+            entry("c", LogLevel::Error),
+        ];
+
+        let levels = summarize_levels(&entries);
+        assert_eq!(levels.get(&LogLevel::Info), Some(&2));
+        assert_eq!(levels.get(&LogLevel::Error), Some(&1));
+        assert_eq!(levels.get(&LogLevel::Fatal), None);
+    }
+
+    #[test]
+    fn test_tail_position_skips_replayed_lines() {
+        let line = |raw: &str| parse_logcat_line(raw).unwrap();
+        let mut position = TailPosition::default();
+
+        assert!(!position.advance(&line("--------- beginning of main")));
+        assert!(position.advance(&line("01-15 12:00:00.100  1  1 I A: one")));
+        assert!(position.advance(&line("01-15 12:00:00.200  1  1 I A: two")));
+        assert!(position.advance(&line("01-15 12:00:00.200  1  1 I A: three")));
+
+        // What a restart with -T '01-15 12:00:00.200' prints again
+        assert!(!position.advance(&line("01-15 12:00:00.200  1  1 I A: two")));
+        assert!(!position.advance(&line("01-15 12:00:00.200  1  1 I A: three")));
+        assert!(!position.advance(&line("01-15 12:00:00.100  1  1 I A: one")));
+        assert!(position.advance(&line("01-15 12:00:00.200  1  1 I A: four")));
+        assert!(position.advance(&line("01-15 12:00:01.000  1  1 I A: five")));
+
+        assert_eq!(position.sent, 5);
+        assert_eq!(position.timestamp.as_deref(), Some("01-15 12:00:01.000"));
+    }
+
+    #[test]
+    fn test_group_stacktraces() {
+        let log = "\
+01-15 12:00:00.000  4321  4321 D MainActivity: onResume
+01-15 12:00:01.000  4321  4321 E AndroidRuntime: FATAL EXCEPTION: main
+01-15 12:00:01.000  4321  4321 E AndroidRuntime: Process: com.example.app, PID: 4321
+01-15 12:00:01.000  1000  1200 I ActivityManager: Showing crash dialog
+01-15 12:00:01.000  4321  4321 E AndroidRuntime: java.lang.RuntimeException: Unable to start activity
+01-15 12:00:01.000  4321  4321 E AndroidRuntime: \tat android.app.ActivityThread.performLaunchActivity(ActivityThread.java:3449)
+01-15 12:00:01.000  4321  4321 E AndroidRuntime: Caused by: java.lang.NullPointerException
+01-15 12:00:01.000  4321  4321 E AndroidRuntime: \t... 11 more
+01-15 12:00:02.000  4321  4321 I Process: Sending signal. PID: 4321 SIG: 9";
+        let entries: Vec<LogcatEntry> = log.lines().filter_map(parse_logcat_line).collect();
+
+        let groups = group_stacktraces(entries);
+        assert_eq!(groups.len(), 4);
+
+        assert_eq!(groups[0].entry.tag, "MainActivity");
+        assert!(groups[0].lines.is_empty());
+
+        let crash = &groups[1];
+        assert_eq!(crash.entry.message, "FATAL EXCEPTION: main");
+        assert_eq!(crash.lines.len(), 5);
+        assert!(crash.lines[2].starts_with("at android.app.ActivityThread"));
+        assert_eq!(crash.lines[3], "Caused by: java.lang.NullPointerException");
+
+        // Interleaved entries from other processes stay on their own
+        assert_eq!(groups[2].entry.tag, "ActivityManager");
+        assert_eq!(groups[3].entry.tag, "Process");
+    }
+
+    #[test]
+    fn test_group_stacktraces_caused_by_start() {
+        let log = "\
+01-15 12:00:01.000  555  555 E AndroidRuntime: Caused by: java.io.IOException: closed
+01-15 12:00:01.000  555  555 E AndroidRuntime: \tat okio.Foo.bar(Foo.kt:10)
+01-15 12:00:01.000  555  555 E AndroidRuntime: FATAL EXCEPTION: OkHttp Dispatcher
+01-15 12:00:01.000  555  555 E AndroidRuntime: \tat okio.Baz.qux(Baz.kt:20)";
+        let entries: Vec<LogcatEntry> = log.lines().filter_map(parse_logcat_line).collect();
+
+        let groups = group_stacktraces(entries);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].lines, vec!["at okio.Foo.bar(Foo.kt:10)"]);
+        assert_eq!(groups[1].lines, vec!["at okio.Baz.qux(Baz.kt:20)"]);
+    }
+
     #[test]
     fn test_logcat_entry_default() {
         let entry = LogcatEntry::default();
@@ -530,6 +1105,30 @@ mod tests {
         assert!(format!("{}", err4).contains("closed"));
     }
 
+    #[test]
+    fn test_wait_for_entry() {
+        let entry = |message: &str| LogcatEntry {
+            tag: "LoginActivity".to_string(),
+            message: message.to_string(),
+            ..Default::default()
+        };
+        let deadline = || Instant::now() + Duration::from_millis(50);
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(entry("loading")).unwrap();
+        tx.send(entry("login success")).unwrap();
+        let found = wait_for_entry(&rx, |e| e.message.contains("success"), deadline());
+        assert_eq!(found.unwrap().message, "login success");
+
+        tx.send(entry("loading")).unwrap();
+        let found = wait_for_entry(&rx, |e| e.message.contains("success"), deadline());
+        assert_eq!(found, Err(LogcatError::TimedOut));
+
+        drop(tx);
+        let found = wait_for_entry(&rx, |_| true, deadline());
+        assert_eq!(found, Err(LogcatError::StreamClosed));
+    }
+
     #[test]
     fn test_multiple_tags_filter() {
         let filter = LogcatFilter {
@@ -596,17 +1195,3 @@ mod tests {
         assert_eq!(entry.tag, cloned.tag);
     }
 }
-
-impl Default for LogcatEntry {
-    fn default() -> Self {
-        Self {
-            timestamp: String::new(),
-            pid: 0,
-            tid: 0,
-            level: LogLevel::Debug,
-            tag: String::new(),
-            message: String::new(),
-            raw: String::new(),
-        }
-    }
-}
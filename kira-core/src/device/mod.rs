@@ -1,22 +1,52 @@
+pub mod apk;
 pub mod app_manager;
+pub mod batch;
+pub mod bugreport;
+pub mod cancel;
+pub mod connection;
 pub mod display;
 pub mod fastboot;
 pub mod file_manager;
 pub mod info;
+pub mod input;
 pub mod logcat;
+pub mod paths;
 pub mod performance;
+pub mod power;
 pub mod process;
+pub mod report;
 pub mod root;
+pub mod screen;
+pub mod settings;
 pub mod shell;
+pub mod sideload;
+pub mod snapshot;
 pub mod system;
+pub mod telephony;
+pub mod users;
 
+pub use apk::*;
 pub use app_manager::*;
+pub use batch::*;
+pub use bugreport::*;
+pub use cancel::*;
+pub use connection::*;
 pub use display::*;
 pub use fastboot::*;
 pub use file_manager::*;
 pub use info::*;
+pub use input::*;
 pub use logcat::*;
+pub use paths::*;
+pub use power::*;
 pub use process::*;
+pub use report::*;
 pub use root::*;
+pub use screen::*;
+pub use settings::*;
 pub use shell::*;
+pub use sideload::*;
+pub use snapshot::*;
 pub use system::*;
+pub use telephony::*;
+pub use users::*;
@@ -0,0 +1,82 @@
+/// `path` as an absolute device path: repeated slashes collapsed, `.` dropped and `..`
+/// resolved lexically, without a trailing slash. `..` above the root stays at `/`.
+///
+/// Symlinks aren't followed, `/sdcard/../data` is `/data` even if `/sdcard` points
+/// elsewhere.
+pub fn normalize_remote(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+/// `name` inside directory `base`, normalized. An absolute `name` replaces `base`.
+pub fn join_remote(base: &str, name: &str) -> String {
+    if name.starts_with('/') {
+        normalize_remote(name)
+    } else {
+        normalize_remote(&format!("{}/{}", base, name))
+    }
+}
+
+/// Directory holding `path`, `None` for the root.
+pub fn parent_remote(path: &str) -> Option<String> {
+    let path = normalize_remote(path);
+    let (parent, _) = path.rsplit_once('/')?;
+    match (parent, path.as_str()) {
+        (_, "/") => None,
+        ("", _) => Some("/".to_string()),
+        (parent, _) => Some(parent.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_remote() {
+        assert_eq!(normalize_remote("/sdcard//Download"), "/sdcard/Download");
+        assert_eq!(normalize_remote("/sdcard/Download/"), "/sdcard/Download");
+        assert_eq!(
+            normalize_remote("/sdcard/./DCIM/../Download"),
+            "/sdcard/Download"
+        );
+        assert_eq!(normalize_remote("/../.."), "/");
+        assert_eq!(normalize_remote("sdcard"), "/sdcard");
+        assert_eq!(normalize_remote(""), "/");
+        assert_eq!(normalize_remote("//"), "/");
+    }
+
+    #[test]
+    fn test_join_remote() {
+        assert_eq!(join_remote("/sdcard/", "file.txt"), "/sdcard/file.txt");
+        assert_eq!(join_remote("/", "sdcard"), "/sdcard");
+        assert_eq!(
+            join_remote("/sdcard/DCIM", "../Download"),
+            "/sdcard/Download"
+        );
+        assert_eq!(
+            join_remote("/sdcard", "/data/local/tmp/"),
+            "/data/local/tmp"
+        );
+    }
+
+    #[test]
+    fn test_parent_remote() {
+        assert_eq!(
+            parent_remote("/sdcard/Download/file.txt"),
+            Some("/sdcard/Download".to_string())
+        );
+        assert_eq!(parent_remote("/sdcard/"), Some("/".to_string()));
+        assert_eq!(parent_remote("/sdcard/.."), None);
+        assert_eq!(parent_remote("/"), None);
+    }
+}
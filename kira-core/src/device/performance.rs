@@ -1,10 +1,24 @@
-use adb_client::{ADBDeviceExt, server_device::ADBServerDevice};
+use crate::config::{adb_config, adb_device};
+use crate::device::app_manager::{PER_USER_RANGE, get_top_package, parse_package_uids};
+use crate::device::cancel::CancelToken;
+pub use crate::device::shell::BatteryInfo;
+use crate::device::shell::{
+    BatteryHealth, BatteryStatus, PluggedState, ShellExecutor, shell_output, shell_quote,
+};
+use adb_client::server_device::ADBServerDevice;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum PerformanceError {
     CommandFailed(String),
     ParseError(String),
+    PermissionDenied(String),
+    Unsupported(String),
+    Cancelled,
 }
 
 impl std::fmt::Display for PerformanceError {
@@ -12,6 +26,9 @@ impl std::fmt::Display for PerformanceError {
         match self {
             PerformanceError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
             PerformanceError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+            PerformanceError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+            PerformanceError::Unsupported(msg) => write!(f, "Not supported: {}", msg),
+            PerformanceError::Cancelled => write!(f, "Cancelled"),
         }
     }
 }
@@ -22,9 +39,7 @@ fn run_shell_command(
     device: &mut ADBServerDevice,
     command: &str,
 ) -> Result<String, PerformanceError> {
-    let mut output = Vec::new();
-    device
-        .shell_command(&command, Some(&mut output), None)
+    let output = shell_output(device, command)
         .map_err(|e| PerformanceError::CommandFailed(e.to_string()))?;
 
     String::from_utf8(output)
@@ -51,13 +66,6 @@ pub struct MemoryInfo {
     pub available_kb: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct BatteryInfo {
-    pub level: u32,
-    pub temperature: u32,
-    pub voltage: u32,
-}
-
 pub fn parse_meminfo(output: &str) -> Option<MemoryInfo> {
     let mut total_kb = 0;
     let mut free_kb = 0;
@@ -88,40 +96,283 @@ pub fn parse_meminfo(output: &str) -> Option<MemoryInfo> {
     }
 }
 
+/// Parses `dumpsys battery`, decoding the status, health and plugged codes.
 pub fn parse_battery_info(output: &str) -> Option<BatteryInfo> {
-    let mut level = 0;
-    let mut temperature = 0;
-    let mut voltage = 0;
-    let mut found = false;
+    let mut level = None;
+    let mut scale = 100u32;
+    let mut status = None;
+    let mut health = None;
+    let mut plugged = None;
+    let mut powered_by = Vec::new();
+    let mut voltage = 0u32;
+    let mut temperature = 0i32;
+    let mut technology = String::new();
 
     for line in output.lines() {
-        let line = line.trim();
-        let parts: Vec<&str> = line.splitn(2, ':').collect();
-        if parts.len() == 2 {
-            let key = parts[0].trim();
-            if let Ok(value) = parts[1].trim().parse::<u32>() {
-                match key {
-                    "level" => {
-                        level = value;
-                        found = true;
-                    }
-                    "temperature" => temperature = value,
-                    "voltage" => voltage = value,
-                    _ => {}
-                }
-            }
+        let Some((key, value)) = line.trim().split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "level" => level = value.parse::<u32>().ok(),
+            "scale" => scale = value.parse().unwrap_or(100),
+            "status" => status = value.parse::<u32>().ok(),
+            "health" => health = value.parse::<u32>().ok(),
+            "plugged" => plugged = value.parse::<u32>().ok(),
+            "voltage" => voltage = value.parse().unwrap_or(0),
+            "temperature" => temperature = value.parse().unwrap_or(0),
+            "technology" => technology = value.to_string(),
+            "AC powered" if value == "true" => powered_by.push(PluggedState::Ac),
+            "USB powered" if value == "true" => powered_by.push(PluggedState::Usb),
+            "Wireless powered" if value == "true" => powered_by.push(PluggedState::Wireless),
+            "Dock powered" if value == "true" => powered_by.push(PluggedState::Dock),
+            _ => {}
         }
     }
 
-    if found {
-        Some(BatteryInfo {
-            level,
-            temperature,
-            voltage,
-        })
-    } else {
-        None
+    let level = level?;
+    // Newer builds drop the `plugged:` line, the `* powered:` flags say the same thing
+    let plugged = plugged
+        .map(PluggedState::from_code)
+        .or(powered_by.first().copied())
+        .unwrap_or(PluggedState::Unplugged);
+
+    Some(BatteryInfo {
+        level,
+        scale,
+        percentage: if scale > 0 {
+            (level as f32 / scale as f32 * 100.0) as u32
+        } else {
+            0
+        },
+        status: status.map_or(BatteryStatus::Unknown, BatteryStatus::from_code),
+        health: health.map_or(BatteryHealth::Unknown, BatteryHealth::from_code),
+        plugged,
+        voltage,
+        temperature: temperature as f32 / 10.0,
+        technology,
+    })
+}
+
+/// A wakelock either held right now (`dumpsys power`) or accumulated since the last full
+/// charge (`dumpsys batterystats`).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Wakelock {
+    pub tag: String,
+    /// `PARTIAL_WAKE_LOCK`, `SCREEN_BRIGHT_WAKE_LOCK` and so on
+    pub lock_type: String,
+    pub uid: Option<u32>,
+    pub package_name: Option<String>,
+    /// Time since it was acquired for held locks, total time held for history entries
+    pub held_ms: Option<u64>,
+    pub currently_held: bool,
+}
+
+/// Wakelocks held at the moment, attributed to the app they work for when the power
+/// manager records one.
+pub fn get_wakelocks(device: &mut ADBServerDevice) -> Result<Vec<Wakelock>, PerformanceError> {
+    let output = run_shell_command(device, "dumpsys power")?;
+    let mut wakelocks = parse_power_wakelocks(&output);
+    resolve_wakelock_packages(device, &mut wakelocks);
+    Ok(wakelocks)
+}
+
+/// Wakelock totals per app since the device was last fully charged.
+pub fn get_wakelock_history(
+    device: &mut ADBServerDevice,
+) -> Result<Vec<Wakelock>, PerformanceError> {
+    // The full dump runs into megabytes, only the uid headers and wakelock lines matter
+    let output = run_shell_command(
+        device,
+        "dumpsys batterystats | grep -E '^  ([0-9]+|u[0-9]+[ai][0-9]+):$|^    Wake lock '",
+    )?;
+    let mut wakelocks = parse_batterystats_wakelocks(&output);
+    resolve_wakelock_packages(device, &mut wakelocks);
+    Ok(wakelocks)
+}
+
+fn resolve_wakelock_packages(device: &mut ADBServerDevice, wakelocks: &mut [Wakelock]) {
+    if wakelocks.iter().all(|w| w.package_name.is_some()) {
+        return;
+    }
+    let Ok(output) = run_shell_command(device, "pm list packages -U") else {
+        return;
+    };
+    let packages = parse_package_uids(&output);
+    for wakelock in wakelocks.iter_mut().filter(|w| w.package_name.is_none()) {
+        wakelock.package_name = wakelock
+            .uid
+            .and_then(|uid| packages.get(&(uid % PER_USER_RANGE)).cloned());
+    }
+}
+
+/// Parses the `Wake Locks: size=N` section, where each line looks like
+/// `PARTIAL_WAKE_LOCK 'AudioMix' ACQ=-2m3s12ms (uid=1041 ws=WorkSource{10123 com.app})`.
+fn parse_power_wakelocks(output: &str) -> Vec<Wakelock> {
+    let mut wakelocks = Vec::new();
+    let mut in_section = false;
+
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("Wake Locks:") {
+            in_section = true;
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        // The section ends at the first blank or unindented line
+        if trimmed.is_empty() || !line.starts_with(char::is_whitespace) {
+            break;
+        }
+
+        let Some((lock_type, rest)) = trimmed.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let Some((tag, rest)) = rest
+            .trim_start()
+            .strip_prefix('\'')
+            .and_then(|r| r.split_once('\''))
+        else {
+            continue;
+        };
+
+        let field = |name: &str| {
+            rest.split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                .find_map(|f| f.strip_prefix(name))
+        };
+        // The work source names the app the lock is held for, the uid is often system
+        let work_source = rest
+            .split_once("WorkSource{")
+            .and_then(|(_, ws)| ws.split_once('}'))
+            .map(|(ws, _)| ws);
+        let ws_uid = work_source
+            .and_then(|ws| ws.split_whitespace().next())
+            .and_then(|uid| uid.parse().ok());
+        let ws_package = work_source
+            .and_then(|ws| ws.split_whitespace().nth(1))
+            .map(|p| p.trim_end_matches(',').to_string());
+
+        wakelocks.push(Wakelock {
+            tag: tag.to_string(),
+            lock_type: lock_type.to_string(),
+            uid: ws_uid.or_else(|| field("uid=").and_then(|uid| uid.parse().ok())),
+            package_name: ws_package,
+            held_ms: field("ACQ=").and_then(parse_duration_ms),
+            currently_held: true,
+        });
     }
+
+    wakelocks
+}
+
+/// Parses per-uid blocks of `dumpsys batterystats`:
+///
+/// ```text
+///   u0a219:
+///     Wake lock NlpWakeLock: 3s 12ms partial (52 times) max=620 realtime
+/// ```
+///
+/// The global `All partial wake locks:` list is less indented and skipped.
+fn parse_batterystats_wakelocks(output: &str) -> Vec<Wakelock> {
+    let mut wakelocks = Vec::new();
+    let mut in_uid_block = false;
+    let mut uid = None;
+
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if let Some(header) = trimmed.strip_suffix(':')
+            && !trimmed.contains(' ')
+        {
+            in_uid_block = true;
+            uid = parse_batterystats_uid(header);
+            continue;
+        }
+        if !line.starts_with("    ") {
+            in_uid_block = false;
+        }
+        if !in_uid_block {
+            continue;
+        }
+
+        let Some((tag, totals)) = trimmed
+            .strip_prefix("Wake lock ")
+            .and_then(|rest| rest.rsplit_once(": "))
+        else {
+            continue;
+        };
+
+        // One entry per kind, e.g. `1s full (1 times), 2m 5s partial (3 times)`
+        for total in totals.split("), ") {
+            let Some((time, kind)) = total
+                .split_once(" (")
+                .and_then(|(t, _)| t.trim().rsplit_once(' '))
+            else {
+                continue;
+            };
+            let lock_type = match kind {
+                "partial" => "PARTIAL_WAKE_LOCK",
+                "full" => "FULL_WAKE_LOCK",
+                "window" => "WINDOW",
+                "draw" => "DRAW_WAKE_LOCK",
+                _ => continue,
+            };
+
+            wakelocks.push(Wakelock {
+                tag: tag.to_string(),
+                lock_type: lock_type.to_string(),
+                uid,
+                package_name: None,
+                held_ms: parse_duration_ms(time),
+                currently_held: false,
+            });
+        }
+    }
+
+    wakelocks
+}
+
+/// `1000` or `u0a219`, the latter being app 219 of user 0, i.e. uid 10219.
+fn parse_batterystats_uid(header: &str) -> Option<u32> {
+    if let Ok(uid) = header.parse() {
+        return Some(uid);
+    }
+    let (user, app) = header.strip_prefix('u')?.split_once('a')?;
+    let user: u32 = user.parse().ok()?;
+    let app: u32 = app.parse().ok()?;
+    Some(user * 100_000 + 10_000 + app)
+}
+
+/// Android's compact durations: `-2m3s12ms` in dumpsys power, `1h 2m 5s 30ms` in
+/// batterystats.
+fn parse_duration_ms(raw: &str) -> Option<u64> {
+    let raw = raw.trim().trim_start_matches(['-', '+']);
+    let mut total = 0u64;
+    let mut rest = raw;
+    let mut parsed_any = false;
+
+    while !rest.is_empty() {
+        rest = rest.trim_start();
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let value: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let multiplier = match &rest[..unit_len] {
+            "d" => 86_400_000,
+            "h" => 3_600_000,
+            "m" => 60_000,
+            "s" => 1_000,
+            "ms" => 1,
+            _ => return None,
+        };
+        total += value * multiplier;
+        rest = &rest[unit_len..];
+        parsed_any = true;
+    }
+
+    parsed_any.then_some(total)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -146,7 +397,8 @@ pub fn parse_cpu_stat(output: &str) -> Vec<CpuInfo> {
     let mut cpus = Vec::new();
     for line in output.lines() {
         let line = line.trim();
-        if !line.starts_with("cpu") {
+        // Skip the aggregate "cpu" line, only per-core entries are reported
+        if !line.starts_with("cpu") || !line[3..].starts_with(|c: char| c.is_ascii_digit()) {
             continue;
         }
         let parts: Vec<&str> = line.split_whitespace().collect();
@@ -180,10 +432,10 @@ pub fn get_cpu_info(device: &mut ADBServerDevice) -> Result<Vec<CpuInfo>, Perfor
         let mut speed_map = std::collections::HashMap::new();
         for line in speeds_out.lines() {
             let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() == 2 {
-                if let Ok(speed) = parts[1].parse::<u32>() {
-                    speed_map.insert(parts[0].to_string(), speed / 1000);
-                }
+            if parts.len() == 2
+                && let Ok(speed) = parts[1].parse::<u32>()
+            {
+                speed_map.insert(parts[0].to_string(), speed / 1000);
             }
         }
 
@@ -197,6 +449,454 @@ pub fn get_cpu_info(device: &mut ADBServerDevice) -> Result<Vec<CpuInfo>, Perfor
     Ok(cpus)
 }
 
+fn cpufreq_path(core: u32, file: &str) -> String {
+    format!("/sys/devices/system/cpu/cpu{}/cpufreq/{}", core, file)
+}
+
+/// The cpufreq governor of `core`, e.g. `schedutil`.
+pub fn get_cpu_governor(
+    device: &mut ADBServerDevice,
+    core: u32,
+) -> Result<String, PerformanceError> {
+    read_sysfs(device, &cpufreq_path(core, "scaling_governor"))
+}
+
+/// Governors `core` can be switched to, from `scaling_available_governors`.
+pub fn list_available_governors(
+    device: &mut ADBServerDevice,
+    core: u32,
+) -> Result<Vec<String>, PerformanceError> {
+    let output = read_sysfs(device, &cpufreq_path(core, "scaling_available_governors"))?;
+    Ok(output.split_whitespace().map(|s| s.to_string()).collect())
+}
+
+/// Switches `core` to `governor`, which has to be one of [`list_available_governors`].
+/// On most SoCs this applies to every core of the same cluster. Needs root.
+pub fn set_cpu_governor(
+    device: &mut ADBServerDevice,
+    core: u32,
+    governor: &str,
+) -> Result<(), PerformanceError> {
+    let available = list_available_governors(device, core)?;
+    if !available.iter().any(|g| g == governor) {
+        return Err(PerformanceError::CommandFailed(format!(
+            "Governor '{}' is not available on cpu{}, expected one of: {}",
+            governor,
+            core,
+            available.join(", ")
+        )));
+    }
+
+    let path = cpufreq_path(core, "scaling_governor");
+    write_sysfs_as_root(device, &path, governor)?;
+    if get_cpu_governor(device, core)? != governor {
+        return Err(PerformanceError::PermissionDenied(format!(
+            "{} was not changed, root is needed",
+            path
+        )));
+    }
+    Ok(())
+}
+
+/// Online state of every present core, indexed by core number.
+pub fn get_online_cores(device: &mut ADBServerDevice) -> Result<Vec<bool>, PerformanceError> {
+    let present = read_sysfs(device, "/sys/devices/system/cpu/present")?;
+    let online = read_sysfs(device, "/sys/devices/system/cpu/online")?;
+    let present = parse_cpu_list(&present)
+        .ok_or_else(|| PerformanceError::ParseError(format!("Bad cpu list '{}'", present)))?;
+    let online = parse_cpu_list(&online)
+        .ok_or_else(|| PerformanceError::ParseError(format!("Bad cpu list '{}'", online)))?;
+
+    let count = present.iter().max().map_or(0, |max| max + 1);
+    Ok((0..count).map(|core| online.contains(&core)).collect())
+}
+
+/// Brings `core` online or takes it offline through its `online` node. Needs root.
+/// cpu0 can't be taken offline, and a core without an `online` node can't be changed.
+pub fn set_core_online(
+    device: &mut ADBServerDevice,
+    core: u32,
+    online: bool,
+) -> Result<(), PerformanceError> {
+    let path = format!("/sys/devices/system/cpu/cpu{}/online", core);
+    let hotpluggable = run_shell_command(
+        device,
+        &format!("test -e {} && echo 1 || echo 0", shell_quote(&path)),
+    )? == "1";
+    if !hotpluggable || (core == 0 && !online) {
+        let change = if online {
+            "brought online"
+        } else {
+            "taken offline"
+        };
+        return Err(PerformanceError::CommandFailed(format!(
+            "cpu{} can't be {}",
+            core, change
+        )));
+    }
+
+    write_sysfs_as_root(device, &path, if online { "1" } else { "0" })?;
+    if get_online_cores(device)?.get(core as usize).copied() != Some(online) {
+        return Err(PerformanceError::PermissionDenied(format!(
+            "{} was not changed, root is needed",
+            path
+        )));
+    }
+    Ok(())
+}
+
+/// Parses kernel cpu lists like `0-3,5,7`.
+fn parse_cpu_list(list: &str) -> Option<Vec<u32>> {
+    let mut cores = Vec::new();
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                cores.extend(start.trim().parse::<u32>().ok()?..=end.trim().parse().ok()?)
+            }
+            None => cores.push(part.trim().parse().ok()?),
+        }
+    }
+    Some(cores)
+}
+
+/// Cores that share a maximum frequency, i.e. one big.LITTLE cluster.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CpuCluster {
+    /// `little`, `mid`, `big` or `prime`, by ascending frequency
+    pub label: String,
+    pub cores: Vec<u32>,
+    pub max_freq_khz: u64,
+    /// `physical_package_id` when all cores agree, most ARM kernels report 0 or -1
+    pub package_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CpuTopology {
+    /// Slowest cluster first
+    pub clusters: Vec<CpuCluster>,
+    /// Cores without a readable `cpuinfo_max_freq`, usually offline ones
+    pub unknown_cores: Vec<u32>,
+    /// e.g. `4×1.8GHz + 3×2.4GHz + 1×2.8GHz`
+    pub summary: String,
+}
+
+/// Groups the cores into clusters by their `cpuinfo_max_freq`.
+pub fn get_cpu_topology(device: &mut ADBServerDevice) -> Result<CpuTopology, PerformanceError> {
+    let output = run_shell_command(
+        device,
+        "for i in /sys/devices/system/cpu/cpu[0-9]*; do \
+         echo \"${i##*/} $(cat $i/topology/physical_package_id 2>/dev/null || echo -) \
+         $(cat $i/cpufreq/cpuinfo_max_freq 2>/dev/null || echo -)\"; done",
+    )?;
+
+    let topology = parse_cpu_topology(&output);
+    if topology.clusters.is_empty() && topology.unknown_cores.is_empty() {
+        return Err(PerformanceError::ParseError(format!(
+            "No cores in '{}'",
+            output
+        )));
+    }
+    Ok(topology)
+}
+
+// `cpu4 0 2419200` per core, `-` for unreadable nodes
+fn parse_cpu_topology(output: &str) -> CpuTopology {
+    let mut by_freq: BTreeMap<u64, Vec<(u32, Option<i32>)>> = BTreeMap::new();
+    let mut unknown_cores = Vec::new();
+
+    for line in output.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let [name, package_id, max_freq] = parts[..] else {
+            continue;
+        };
+        let Some(core) = name.strip_prefix("cpu").and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        match max_freq.parse::<u64>() {
+            Ok(freq) if freq > 0 => by_freq
+                .entry(freq)
+                .or_default()
+                .push((core, package_id.parse().ok())),
+            _ => unknown_cores.push(core),
+        }
+    }
+
+    let labels = cluster_labels(by_freq.len());
+    let clusters: Vec<CpuCluster> = by_freq
+        .into_iter()
+        .zip(labels)
+        .map(|((max_freq_khz, mut cores), label)| {
+            cores.sort();
+            let package_id = cores[0]
+                .1
+                .filter(|id| cores.iter().all(|c| c.1 == Some(*id)));
+            CpuCluster {
+                label,
+                cores: cores.into_iter().map(|(core, _)| core).collect(),
+                max_freq_khz,
+                package_id,
+            }
+        })
+        .collect();
+
+    let summary = clusters
+        .iter()
+        .map(|c| format!("{}×{:.1}GHz", c.cores.len(), c.max_freq_khz as f64 / 1e6))
+        .collect::<Vec<_>>()
+        .join(" + ");
+    unknown_cores.sort();
+
+    CpuTopology {
+        clusters,
+        unknown_cores,
+        summary,
+    }
+}
+
+fn cluster_labels(count: usize) -> Vec<String> {
+    let labels: Vec<&str> = match count {
+        0 => Vec::new(),
+        1 => vec!["cpu"],
+        2 => vec!["little", "big"],
+        _ => {
+            let mut labels = vec!["little"];
+            labels.extend(std::iter::repeat_n("mid", count - 3));
+            labels.extend(["big", "prime"]);
+            labels
+        }
+    };
+
+    let mids = labels.iter().filter(|l| **l == "mid").count();
+    let mut mid = 0;
+    labels
+        .into_iter()
+        .map(|label| {
+            if label == "mid" && mids > 1 {
+                mid += 1;
+                format!("mid{}", mid)
+            } else {
+                label.to_string()
+            }
+        })
+        .collect()
+}
+
+fn read_sysfs(device: &mut ADBServerDevice, path: &str) -> Result<String, PerformanceError> {
+    let output = run_shell_command(device, &format!("cat {}", shell_quote(path)))?;
+    if output.contains("No such file or directory") {
+        return Err(PerformanceError::CommandFailed(format!(
+            "{} does not exist",
+            path
+        )));
+    }
+    if output.contains("Permission denied") {
+        return Err(PerformanceError::PermissionDenied(path.to_string()));
+    }
+    Ok(output)
+}
+
+/// Writes `value` to a sysfs node, through `su` unless adbd already runs as root. Callers
+/// read the node back, `su` can exit quietly without running anything.
+fn write_sysfs_as_root(
+    device: &mut ADBServerDevice,
+    path: &str,
+    value: &str,
+) -> Result<(), PerformanceError> {
+    let command = format!("echo {} > {}", shell_quote(value), shell_quote(path));
+    let command = if ShellExecutor::new().is_root_available(device) {
+        command
+    } else {
+        format!("su -c {}", shell_quote(&command))
+    };
+
+    let output = run_shell_command(device, &command)?;
+    let lower = output.to_lowercase();
+    if lower.contains("permission denied")
+        || lower.contains("not permitted")
+        || lower.contains("read-only")
+        || lower.contains("su: not found")
+        || lower.contains("su: inaccessible or not found")
+    {
+        return Err(PerformanceError::PermissionDenied(format!(
+            "Writing {} needs root: {}",
+            path, output
+        )));
+    }
+    if !output.is_empty() {
+        return Err(PerformanceError::CommandFailed(output));
+    }
+    Ok(())
+}
+
+/// How much of a sampling window each core spent below its hardware max frequency while
+/// the device was hot.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ThrottleReport {
+    pub duration_ms: u64,
+    pub samples: u32,
+    pub cores: Vec<CoreThrottle>,
+    pub thermal_peaks: Vec<ThermalPeak>,
+    /// Any core was capped while hot
+    pub throttled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CoreThrottle {
+    pub name: String,
+    /// `cpuinfo_max_freq`, what the hardware can do
+    pub hardware_max_mhz: u32,
+    /// Lowest `scaling_max_freq` ceiling seen in the window
+    pub lowest_max_mhz: u32,
+    pub capped_ms: u64,
+    /// Hottest thermal zone reading while this core was capped
+    pub peak_temperature_c: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ThermalPeak {
+    pub zone: String,
+    pub peak_c: f32,
+}
+
+/// Temperature from which a frequency cap counts as thermal throttling rather than a
+/// power saving policy.
+pub const THROTTLE_HOT_TEMP_C: f32 = 45.0;
+const THROTTLE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Core lines `cpu0 <scaling_max_freq> <cpuinfo_max_freq>`, then `---`, then zone lines
+// `<type> <temp>`
+const THROTTLE_SAMPLE_COMMAND: &str = "for i in /sys/devices/system/cpu/cpu[0-9]*; do \
+     echo \"${i##*/} $(cat $i/cpufreq/scaling_max_freq 2>/dev/null) $(cat $i/cpufreq/cpuinfo_max_freq 2>/dev/null)\"; done; \
+     echo ---; \
+     for z in /sys/class/thermal/thermal_zone*; do echo \"$(cat $z/type 2>/dev/null) $(cat $z/temp 2>/dev/null)\"; done";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ThrottleSample {
+    /// `(core, scaling_max_khz, cpuinfo_max_khz)`
+    cores: Vec<(String, u32, u32)>,
+    /// `(zone type, degrees C)`
+    zones: Vec<(String, f32)>,
+}
+
+/// Samples the frequency ceilings of every core and the thermal zones once a second for
+/// `duration`, e.g. while a benchmark runs, and reports which cores were capped below
+/// their hardware max while a zone was at or above [`THROTTLE_HOT_TEMP_C`].
+pub fn detect_throttling(
+    device: &mut ADBServerDevice,
+    duration: Duration,
+) -> Result<ThrottleReport, PerformanceError> {
+    let started = std::time::Instant::now();
+    let mut last = started;
+    let mut samples = Vec::new();
+
+    loop {
+        let output = run_shell_command(device, THROTTLE_SAMPLE_COMMAND)?;
+        let now = std::time::Instant::now();
+        let elapsed_ms = if samples.is_empty() {
+            0
+        } else {
+            now.duration_since(last).as_millis() as u64
+        };
+        samples.push((elapsed_ms, parse_throttle_sample(&output)));
+        last = now;
+
+        if started.elapsed() >= duration {
+            break;
+        }
+        thread::sleep(THROTTLE_SAMPLE_INTERVAL.min(duration.saturating_sub(started.elapsed())));
+    }
+
+    Ok(build_throttle_report(&samples, THROTTLE_HOT_TEMP_C))
+}
+
+fn parse_throttle_sample(output: &str) -> ThrottleSample {
+    let mut sample = ThrottleSample::default();
+    let mut in_zones = false;
+
+    for line in output.lines().map(str::trim) {
+        if line == "---" {
+            in_zones = true;
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if in_zones {
+            if let [zone, temp] = parts.as_slice()
+                && let Ok(temp) = temp.parse::<f32>()
+            {
+                // Most zones report millidegrees, a few whole degrees
+                let celsius = if temp.abs() > 1000.0 {
+                    temp / 1000.0
+                } else {
+                    temp
+                };
+                // Unused sensors read -273 or garbage
+                if (-40.0..=150.0).contains(&celsius) {
+                    sample.zones.push((zone.to_string(), celsius));
+                }
+            }
+        } else if let [core, scaling, hardware] = parts.as_slice()
+            && let (Ok(scaling), Ok(hardware)) = (scaling.parse(), hardware.parse())
+        {
+            // Offline cores have no cpufreq values and print only their name
+            sample.cores.push((core.to_string(), scaling, hardware));
+        }
+    }
+
+    sample
+}
+
+/// `samples` are `(ms since the previous sample, sample)`, the first one counts for 0 ms.
+fn build_throttle_report(samples: &[(u64, ThrottleSample)], hot_c: f32) -> ThrottleReport {
+    let mut cores: Vec<CoreThrottle> = Vec::new();
+    let mut thermal_peaks: Vec<ThermalPeak> = Vec::new();
+
+    for (elapsed_ms, sample) in samples {
+        for (zone, temp) in &sample.zones {
+            match thermal_peaks.iter_mut().find(|p| &p.zone == zone) {
+                Some(peak) => peak.peak_c = peak.peak_c.max(*temp),
+                None => thermal_peaks.push(ThermalPeak {
+                    zone: zone.clone(),
+                    peak_c: *temp,
+                }),
+            }
+        }
+        let hottest = sample.zones.iter().map(|(_, temp)| *temp).reduce(f32::max);
+        let hot = hottest.is_some_and(|temp| temp >= hot_c);
+
+        for (name, scaling_khz, hardware_khz) in &sample.cores {
+            let core = match cores.iter().position(|c| &c.name == name) {
+                Some(index) => &mut cores[index],
+                None => {
+                    cores.push(CoreThrottle {
+                        name: name.clone(),
+                        hardware_max_mhz: hardware_khz / 1000,
+                        lowest_max_mhz: scaling_khz / 1000,
+                        capped_ms: 0,
+                        peak_temperature_c: None,
+                    });
+                    cores.last_mut().unwrap()
+                }
+            };
+            core.lowest_max_mhz = core.lowest_max_mhz.min(scaling_khz / 1000);
+
+            if hot && scaling_khz < hardware_khz {
+                core.capped_ms += elapsed_ms;
+                let temp = hottest.unwrap_or_default();
+                core.peak_temperature_c =
+                    Some(core.peak_temperature_c.map_or(temp, |peak| peak.max(temp)));
+            }
+        }
+    }
+
+    ThrottleReport {
+        duration_ms: samples.iter().map(|(elapsed_ms, _)| elapsed_ms).sum(),
+        samples: samples.len() as u32,
+        throttled: cores.iter().any(|c| c.peak_temperature_c.is_some()),
+        cores,
+        thermal_peaks,
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FpsData {
     pub flips: u64,
@@ -239,20 +939,491 @@ pub fn get_flips_count(device: &mut ADBServerDevice) -> Result<FpsData, Performa
 pub fn get_uptime(device: &mut ADBServerDevice) -> Result<u64, PerformanceError> {
     let output = run_shell_command(device, "cat /proc/uptime")?;
     let parts: Vec<&str> = output.split_whitespace().collect();
-    if let Some(first) = parts.first() {
-        if let Ok(uptime_sec) = first.parse::<f64>() {
-            return Ok(uptime_sec as u64);
-        }
+    if let Some(first) = parts.first()
+        && let Ok(uptime_sec) = first.parse::<f64>()
+    {
+        return Ok(uptime_sec as u64);
     }
     Err(PerformanceError::ParseError(
         "Could not parse uptime".to_string(),
     ))
 }
 
+/// Compact system health reading for a dashboard gauge.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SystemLoad {
+    pub load_average_1m: f64,
+    pub load_average_5m: f64,
+    pub load_average_15m: f64,
+    pub total_processes: u32,
+    /// `procs_running` from `/proc/stat`, tasks on a CPU or waiting for one
+    pub running_processes: u32,
+}
+
+// One round trip, the process count is the line made of a single number
+const SYSTEM_LOAD_COMMAND: &str = "cat /proc/loadavg; ps -A | wc -l; grep procs_running /proc/stat";
+
+pub fn get_system_load(device: &mut ADBServerDevice) -> Result<SystemLoad, PerformanceError> {
+    let output = run_shell_command(device, SYSTEM_LOAD_COMMAND)?;
+    parse_system_load(&output)
+        .ok_or_else(|| PerformanceError::ParseError("Failed to parse system load".into()))
+}
+
+fn parse_system_load(output: &str) -> Option<SystemLoad> {
+    let mut lines = output.lines().map(str::trim);
+    let load: Vec<f64> = lines
+        .next()?
+        .split_whitespace()
+        .take(3)
+        .filter_map(|s| s.parse().ok())
+        .collect();
+    if load.len() < 3 {
+        return None;
+    }
+
+    let mut total_processes = None;
+    let mut running_processes = None;
+    for line in lines {
+        if let Some(rest) = line.strip_prefix("procs_running") {
+            running_processes = rest.trim().parse().ok();
+        } else if let Ok(count) = line.parse::<u32>() {
+            // minus the header line of ps
+            total_processes = Some(count.saturating_sub(1));
+        }
+    }
+
+    Some(SystemLoad {
+        load_average_1m: load[0],
+        load_average_5m: load[1],
+        load_average_15m: load[2],
+        total_processes: total_processes?,
+        running_processes: running_processes?,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PerformanceSnapshot {
+    pub timestamp_ms: u64,
+    pub memory: Option<MemoryInfo>,
+    pub battery: Option<BatteryInfo>,
+    pub cpu_usage: Option<f32>,
+    /// Frames per second since the previous snapshot
+    pub fps: Option<f32>,
+}
+
+/// Overall CPU usage in percent between two `/proc/stat` samples, summed over all cores.
+pub fn cpu_usage_percent(prev: &[CpuInfo], cur: &[CpuInfo]) -> Option<f32> {
+    let totals = |cpus: &[CpuInfo]| {
+        cpus.iter().fold((0u64, 0u64), |(busy, idle), cpu| {
+            let t = &cpu.times;
+            (
+                busy + t.user + t.nice + t.sys + t.irq + t.softirq,
+                idle + t.idle + t.iowait,
+            )
+        })
+    };
+
+    let (prev_busy, prev_idle) = totals(prev);
+    let (cur_busy, cur_idle) = totals(cur);
+
+    let busy = cur_busy.checked_sub(prev_busy)?;
+    let idle = cur_idle.checked_sub(prev_idle)?;
+    let total = busy + idle;
+
+    if total == 0 {
+        return None;
+    }
+
+    Some(busy as f32 / total as f32 * 100.0)
+}
+
+/// Frames per second between two SurfaceFlinger flip samples.
+pub fn fps_between(prev: &FpsData, cur: &FpsData) -> Option<f32> {
+    let flips = cur.flips.checked_sub(prev.flips)?;
+    let elapsed_ms = cur.timestamp_ms.checked_sub(prev.timestamp_ms)?;
+
+    if elapsed_ms == 0 {
+        return None;
+    }
+
+    Some(flips as f32 * 1000.0 / elapsed_ms as f32)
+}
+
+const FLIP_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+const LATENCY_WINDOW_NS: i64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FpsSource {
+    /// Present timestamps of the foreground app's layer
+    PresentFence,
+    /// Difference of two SurfaceFlinger flip counts, covers every layer on screen
+    Flips,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FrameRate {
+    pub fps: f32,
+    pub source: FpsSource,
+    /// Layer the frames were counted on, `None` for [`FpsSource::Flips`]
+    pub layer: Option<String>,
+}
+
+/// Frames per second of the foreground app, from the present timestamps SurfaceFlinger
+/// keeps for its layer. Devices that don't report them fall back to two flip counts
+/// taken `FLIP_SAMPLE_INTERVAL` apart.
+pub fn get_frame_rate(device: &mut ADBServerDevice) -> Result<FrameRate, PerformanceError> {
+    if let Some(rate) = present_fence_rate(device) {
+        return Ok(rate);
+    }
+
+    let prev = get_flips_count(device)?;
+    thread::sleep(FLIP_SAMPLE_INTERVAL);
+    let cur = get_flips_count(device)?;
+    let fps = fps_between(&prev, &cur)
+        .ok_or_else(|| PerformanceError::ParseError("Flip count went backwards".to_string()))?;
+
+    Ok(FrameRate {
+        fps,
+        source: FpsSource::Flips,
+        layer: None,
+    })
+}
+
+fn present_fence_rate(device: &mut ADBServerDevice) -> Option<FrameRate> {
+    let top = get_top_package(device).ok()?;
+    let layers = run_shell_command(device, "dumpsys SurfaceFlinger --list").ok()?;
+    let layer = find_app_layer(&layers, &top.name)?;
+
+    let command = format!("dumpsys SurfaceFlinger --latency {}", shell_quote(&layer));
+    let output = run_shell_command(device, &command).ok()?;
+
+    Some(FrameRate {
+        fps: parse_latency_fps(&output)?,
+        source: FpsSource::PresentFence,
+        layer: Some(layer),
+    })
+}
+
+/// The layer `package` draws into. Games render into a SurfaceView on top of their
+/// window, so that one wins over the window layer.
+fn find_app_layer(layers: &str, package: &str) -> Option<String> {
+    if package.is_empty() {
+        return None;
+    }
+
+    let prefix = format!("{}/", package);
+    let candidates: Vec<&str> = layers
+        .lines()
+        .map(str::trim)
+        .filter(|layer| layer.contains(&prefix))
+        .collect();
+
+    candidates
+        .iter()
+        .find(|layer| layer.starts_with("SurfaceView"))
+        .or_else(|| candidates.first())
+        .map(|layer| layer.to_string())
+}
+
+/// FPS from `--latency` output: the refresh period, then one `desired actual ready` line
+/// of nanosecond timestamps per recent frame. Unpresented frames read 0 or `i64::MAX`.
+///
+/// The history holds the last 128 frames however old, so only the ones presented within
+/// `LATENCY_WINDOW_NS` of the newest count.
+fn parse_latency_fps(output: &str) -> Option<f32> {
+    let presents: Vec<i64> = output
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1)?.parse().ok())
+        .filter(|&present| present != 0 && present != i64::MAX)
+        .collect();
+
+    let last = *presents.iter().max()?;
+    let recent: Vec<i64> = presents
+        .into_iter()
+        .filter(|&present| last - present <= LATENCY_WINDOW_NS)
+        .collect();
+    let first = *recent.iter().min()?;
+    let elapsed_ns = Some(last - first).filter(|&ns| ns > 0)?;
+    Some((recent.len() - 1) as f32 * 1e9 / elapsed_ns as f32)
+}
+
+/// One reading of each performance source. A source that fails doesn't hide the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PerformanceProfile {
+    pub memory: Result<MemoryInfo, String>,
+    pub battery: Result<BatteryInfo, String>,
+    pub cpu: Result<Vec<CpuInfo>, String>,
+    pub fps: Result<FpsData, String>,
+    pub uptime: Result<u64, String>,
+}
+
+pub fn get_performance_profile(device: &mut ADBServerDevice) -> PerformanceProfile {
+    PerformanceProfile {
+        memory: get_memory_info(device).map_err(|e| e.to_string()),
+        battery: get_battery_info(device).map_err(|e| e.to_string()),
+        cpu: get_cpu_info(device).map_err(|e| e.to_string()),
+        fps: get_flips_count(device).map_err(|e| e.to_string()),
+        uptime: get_uptime(device).map_err(|e| e.to_string()),
+    }
+}
+
+/// Samples memory, battery, CPU usage and fps every `interval` on a background thread.
+///
+/// The monitor keeps its own connection to the device and stops once the returned
+/// receiver is dropped or `cancel` is tripped.
+pub fn start_monitor(
+    device: &ADBServerDevice,
+    interval: Duration,
+    cancel: CancelToken,
+) -> (JoinHandle<()>, mpsc::Receiver<PerformanceSnapshot>) {
+    let (tx, rx) = mpsc::channel();
+
+    let mut device = match device.identifier.clone() {
+        Some(serial) => adb_device(serial),
+        None => ADBServerDevice::autodetect(Some(adb_config().server_addr())),
+    };
+
+    let handle = thread::spawn(move || {
+        let mut prev_cpu = run_shell_command(&mut device, "cat /proc/stat")
+            .map(|out| parse_cpu_stat(&out))
+            .unwrap_or_default();
+        let mut prev_flips = get_flips_count(&mut device).ok();
+
+        loop {
+            thread::sleep(interval);
+            if cancel.is_cancelled() {
+                break;
+            }
+
+            let cur_cpu = run_shell_command(&mut device, "cat /proc/stat")
+                .map(|out| parse_cpu_stat(&out))
+                .unwrap_or_default();
+            let cpu_usage = cpu_usage_percent(&prev_cpu, &cur_cpu);
+            prev_cpu = cur_cpu;
+
+            let cur_flips = get_flips_count(&mut device).ok();
+            let fps = prev_flips
+                .as_ref()
+                .zip(cur_flips.as_ref())
+                .and_then(|(prev, cur)| fps_between(prev, cur));
+            prev_flips = cur_flips;
+
+            let snapshot = PerformanceSnapshot {
+                timestamp_ms: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64,
+                memory: get_memory_info(&mut device).ok(),
+                battery: get_battery_info(&mut device).ok(),
+                cpu_usage,
+                fps,
+            };
+
+            if tx.send(snapshot).is_err() {
+                break;
+            }
+        }
+    });
+
+    (handle, rx)
+}
+
+/// Clears the battery history, so a drain test starts from zero.
+pub fn reset_battery_stats(device: &mut ADBServerDevice) -> Result<(), PerformanceError> {
+    let output = run_shell_command(device, "dumpsys batterystats --reset")?;
+    if output.trim() == "Battery stats reset." {
+        Ok(())
+    } else if output.contains("Permission Denial") {
+        Err(PerformanceError::PermissionDenied(output))
+    } else {
+        Err(PerformanceError::CommandFailed(output))
+    }
+}
+
+/// Vendor nodes that stop charging at a percentage, tried in order.
+const CHARGE_LIMIT_NODES: [&str; 3] = [
+    // Mainline power_supply, also used by several ChromeOS derived kernels
+    "/sys/class/power_supply/battery/charge_control_end_threshold",
+    // Pixel
+    "/sys/devices/platform/google,charger/charge_stop_level",
+    // Samsung, "Protect battery"
+    "/sys/class/power_supply/battery/batt_full_capacity",
+];
+
+/// Stops charging at `percent`, e.g. 80 to spare the battery. Needs root and a kernel
+/// with one of the known charge control nodes.
+pub fn set_charge_limit(
+    device: &mut ADBServerDevice,
+    percent: u32,
+) -> Result<(), PerformanceError> {
+    if !(50..=100).contains(&percent) {
+        return Err(PerformanceError::CommandFailed(format!(
+            "Charge limit must be between 50 and 100%, got {}",
+            percent
+        )));
+    }
+
+    let candidates: Vec<String> = CHARGE_LIMIT_NODES.iter().map(|n| shell_quote(n)).collect();
+    let output = run_shell_command(
+        device,
+        &format!(
+            "for n in {}; do [ -e \"$n\" ] && echo \"$n\"; done",
+            candidates.join(" ")
+        ),
+    )?;
+
+    // Some kernels expose a node that exists but ignores writes, so move on to the next
+    let mut last_error = None;
+    for node in output.lines().map(str::trim).filter(|n| !n.is_empty()) {
+        match write_charge_limit(device, node, percent) {
+            Ok(()) => return Ok(()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        PerformanceError::Unsupported("no known charge control node on this device".to_string())
+    }))
+}
+
+fn write_charge_limit(
+    device: &mut ADBServerDevice,
+    node: &str,
+    percent: u32,
+) -> Result<(), PerformanceError> {
+    write_sysfs_as_root(device, node, &percent.to_string())?;
+    if read_sysfs(device, node)?.trim() != percent.to_string() {
+        return Err(PerformanceError::PermissionDenied(format!(
+            "{} was not changed, root is needed",
+            node
+        )));
+    }
+    Ok(())
+}
+
+/// Reads the battery level `samples` times, `interval` apart, returning
+/// `(timestamp_ms, level)` pairs. Failed reads are skipped.
+pub fn monitor_battery(
+    device: &mut ADBServerDevice,
+    interval: Duration,
+    samples: usize,
+    cancel: &CancelToken,
+) -> Result<Vec<(u64, u32)>, PerformanceError> {
+    let mut history = Vec::with_capacity(samples);
+
+    for i in 0..samples {
+        if i > 0 {
+            thread::sleep(interval);
+        }
+        if cancel.is_cancelled() {
+            return Err(PerformanceError::Cancelled);
+        }
+        if let Ok(battery) = get_battery_info(device) {
+            let timestamp_ms = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            history.push((timestamp_ms, battery.percentage));
+        }
+    }
+
+    Ok(history)
+}
+
+/// Extrapolates the least squares trend of `samples` to 100% when charging or 0% when
+/// discharging. Returns `None` when the level isn't changing.
+pub fn estimate_time_remaining(samples: &[(u64, u32)]) -> Option<Duration> {
+    if samples.len() < 2 {
+        return None;
+    }
+
+    let n = samples.len() as f64;
+    let t0 = samples[0].0;
+    let points: Vec<(f64, f64)> = samples
+        .iter()
+        .map(|(t, level)| (t.saturating_sub(t0) as f64, *level as f64))
+        .collect();
+
+    let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+    let mean_l = points.iter().map(|(_, l)| l).sum::<f64>() / n;
+    let cov: f64 = points
+        .iter()
+        .map(|(t, l)| (t - mean_t) * (l - mean_l))
+        .sum();
+    let var: f64 = points.iter().map(|(t, _)| (t - mean_t).powi(2)).sum();
+
+    if var == 0.0 {
+        return None;
+    }
+
+    // percent per millisecond
+    let slope = cov / var;
+    if slope.abs() < f64::EPSILON {
+        return None;
+    }
+
+    let current = samples.last()?.1 as f64;
+    let target = if slope > 0.0 { 100.0 } else { 0.0 };
+    let remaining_ms = (target - current) / slope;
+
+    if remaining_ms.is_finite() && remaining_ms >= 0.0 {
+        Some(Duration::from_millis(remaining_ms as u64))
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_find_app_layer() {
+        let layers = "Display 0 name=\"Built-in Screen\"\n\
+                      com.example.game/com.example.game.MainActivity#312\n\
+                      SurfaceView[com.example.game/com.example.game.MainActivity](BLAST)#318\n\
+                      StatusBar#75\n";
+
+        assert_eq!(
+            find_app_layer(layers, "com.example.game").as_deref(),
+            Some("SurfaceView[com.example.game/com.example.game.MainActivity](BLAST)#318")
+        );
+        assert_eq!(
+            find_app_layer("com.example/com.example.Main#1", "com.ex"),
+            None
+        );
+        assert_eq!(find_app_layer(layers, ""), None);
+    }
+
+    #[test]
+    fn test_parse_latency_fps() {
+        // 16.6ms refresh, five frames 20ms apart, the newest not presented yet
+        let output = "16666666\n\
+                      1000000000\t1000000000\t990000000\n\
+                      1020000000\t1020000000\t1010000000\n\
+                      1040000000\t1040000000\t1030000000\n\
+                      1060000000\t1060000000\t1050000000\n\
+                      1080000000\t1080000000\t1070000000\n\
+                      1100000000\t9223372036854775807\t1090000000\n\
+                      0\t0\t0\n";
+
+        let fps = parse_latency_fps(output).unwrap();
+        assert!((fps - 50.0).abs() < 0.01, "{}", fps);
+
+        // A burst long ago doesn't drag down the rate of the last second
+        let output = "16666666\n\
+                      1000000000\t1000000000\t990000000\n\
+                      1010000000\t1010000000\t1000000000\n\
+                      5000000000\t5000000000\t4990000000\n\
+                      5500000000\t5500000000\t5490000000\n\
+                      6000000000\t6000000000\t5990000000\n";
+        let fps = parse_latency_fps(output).unwrap();
+        assert!((fps - 2.0).abs() < 0.01, "{}", fps);
+
+        assert_eq!(parse_latency_fps("16666666\n"), None);
+        assert_eq!(parse_latency_fps("16666666\n0\t0\t0\n"), None);
+    }
+
     #[test]
     fn test_parse_flips_count() {
         let sample_output = "Build: android
@@ -291,6 +1462,175 @@ OtherSurface=888";
         assert_eq!(result[1].times.softirq, 3302);
     }
 
+    #[test]
+    fn test_throttle_report() {
+        let cool = parse_throttle_sample(
+            "cpu0 1804800 1804800\ncpu7 3014400 3014400\n---\ncpu-0-0 38500\nbattery 31000",
+        );
+        let hot = parse_throttle_sample(
+            "cpu0 1804800 1804800\ncpu7 1900800 3014400\ncpu8\n---\ncpu-1-0 71200\n\
+             battery 36000\nunused -273000",
+        );
+        assert_eq!(hot.cores.len(), 2);
+        assert_eq!(hot.zones.len(), 2);
+
+        let report = build_throttle_report(
+            &[
+                (0, cool.clone()),
+                (1000, hot.clone()),
+                (1000, hot),
+                (1000, cool),
+            ],
+            THROTTLE_HOT_TEMP_C,
+        );
+        assert_eq!(report.duration_ms, 3000);
+        assert_eq!(report.samples, 4);
+        assert!(report.throttled);
+
+        let big = report.cores.iter().find(|c| c.name == "cpu7").unwrap();
+        assert_eq!(big.hardware_max_mhz, 3014);
+        assert_eq!(big.lowest_max_mhz, 1900);
+        assert_eq!(big.capped_ms, 2000);
+        assert_eq!(big.peak_temperature_c, Some(71.2));
+
+        let little = report.cores.iter().find(|c| c.name == "cpu0").unwrap();
+        assert_eq!(little.capped_ms, 0);
+        assert_eq!(little.peak_temperature_c, None);
+
+        let battery = report
+            .thermal_peaks
+            .iter()
+            .find(|p| p.zone == "battery")
+            .unwrap();
+        assert_eq!(battery.peak_c, 36.0);
+    }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,5,7\n"), Some(vec![0, 1, 2, 3, 5, 7]));
+        assert_eq!(parse_cpu_list("0"), Some(vec![0]));
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+    }
+
+    #[test]
+    fn test_parse_cpu_topology() {
+        let output = "cpu0 0 1804800\ncpu1 0 1804800\ncpu2 0 1804800\ncpu3 0 1804800\n\
+                      cpu4 1 2419200\ncpu5 1 2419200\ncpu6 1 2419200\ncpu7 - 2841600\n\
+                      cpu8 - -\n";
+
+        let topology = parse_cpu_topology(output);
+
+        let labels: Vec<&str> = topology.clusters.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["little", "big", "prime"]);
+        assert_eq!(topology.clusters[0].cores, vec![0, 1, 2, 3]);
+        assert_eq!(topology.clusters[0].package_id, Some(0));
+        assert_eq!(topology.clusters[1].package_id, Some(1));
+        assert_eq!(topology.clusters[2].cores, vec![7]);
+        assert_eq!(topology.clusters[2].package_id, None);
+        assert_eq!(topology.unknown_cores, vec![8]);
+        assert_eq!(topology.summary, "4×1.8GHz + 3×2.4GHz + 1×2.8GHz");
+
+        let two = parse_cpu_topology("cpu0 0 1800000\ncpu1 0 2200000\n");
+        let labels: Vec<&str> = two.clusters.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["little", "big"]);
+        assert_eq!(
+            cluster_labels(5),
+            vec!["little", "mid1", "mid2", "big", "prime"]
+        );
+        assert_eq!(cluster_labels(1), vec!["cpu"]);
+    }
+
+    #[test]
+    fn test_parse_system_load() {
+        let sample_output = "7.92 8.13 8.41 3/2871 23377\n     612\nprocs_running 3";
+        assert_eq!(
+            parse_system_load(sample_output),
+            Some(SystemLoad {
+                load_average_1m: 7.92,
+                load_average_5m: 8.13,
+                load_average_15m: 8.41,
+                total_processes: 611,
+                running_processes: 3,
+            })
+        );
+
+        assert_eq!(parse_system_load("7.92 8.13 8.41 3/2871 23377\n612"), None);
+        assert_eq!(parse_system_load(""), None);
+    }
+
+    #[test]
+    fn test_parse_power_wakelocks() {
+        let sample_output = "POWER MANAGER (dumpsys power)
+Wake Locks: size=3
+  PARTIAL_WAKE_LOCK              'AudioMix' ACQ=-2m3s12ms (uid=1041 ws=WorkSource{10123 com.spotify.music})
+  PARTIAL_WAKE_LOCK              '*alarm*' ACQ=-5ms (uid=1000 pid=1502)
+  SCREEN_BRIGHT_WAKE_LOCK        'WindowManager' ON_AFTER_RELEASE ACQ=-1s (uid=10219 pid=8812)
+
+Suspend Blockers: size=4
+  PowerManagerService.WakeLocks: ref count=1
+";
+        let wakelocks = parse_power_wakelocks(sample_output);
+        assert_eq!(wakelocks.len(), 3);
+        assert_eq!(
+            wakelocks[0],
+            Wakelock {
+                tag: "AudioMix".to_string(),
+                lock_type: "PARTIAL_WAKE_LOCK".to_string(),
+                uid: Some(10123),
+                package_name: Some("com.spotify.music".to_string()),
+                held_ms: Some(123_012),
+                currently_held: true,
+            }
+        );
+        assert_eq!(wakelocks[1].uid, Some(1000));
+        assert_eq!(wakelocks[1].package_name, None);
+        assert_eq!(wakelocks[2].lock_type, "SCREEN_BRIGHT_WAKE_LOCK");
+        assert_eq!(wakelocks[2].held_ms, Some(1000));
+
+        assert!(parse_power_wakelocks("Wake Locks: size=0\n\nSuspend Blockers:").is_empty());
+    }
+
+    #[test]
+    fn test_parse_batterystats_wakelocks() {
+        let sample_output = "  All partial wake locks:
+  Wake lock u0a219 NlpWakeLock: 3s 12ms (52 times) max=620 realtime
+  1000:
+    Wake lock *alarm*: 1m 2s 5ms partial (112 times) max=3020 actual=3100 realtime
+  u0a219:
+    Wake lock NlpWakeLock: 3s 12ms partial (52 times) max=620 realtime
+    Wake lock WindowManager: 1s full (1 times), 2m 5s window (3 times) realtime
+";
+        let wakelocks = parse_batterystats_wakelocks(sample_output);
+        assert_eq!(wakelocks.len(), 4);
+        assert_eq!(wakelocks[0].tag, "*alarm*");
+        assert_eq!(wakelocks[0].uid, Some(1000));
+        assert_eq!(wakelocks[0].held_ms, Some(62_005));
+        assert_eq!(
+            wakelocks[1],
+            Wakelock {
+                tag: "NlpWakeLock".to_string(),
+                lock_type: "PARTIAL_WAKE_LOCK".to_string(),
+                uid: Some(10219),
+                package_name: None,
+                held_ms: Some(3_012),
+                currently_held: false,
+            }
+        );
+        assert_eq!(wakelocks[2].lock_type, "FULL_WAKE_LOCK");
+        assert_eq!(wakelocks[3].lock_type, "WINDOW");
+        assert_eq!(wakelocks[3].held_ms, Some(125_000));
+    }
+
+    #[test]
+    fn test_parse_duration_ms() {
+        assert_eq!(parse_duration_ms("-2m3s12ms"), Some(123_012));
+        assert_eq!(parse_duration_ms("1h 2m 5s 30ms"), Some(3_725_030));
+        assert_eq!(parse_duration_ms("1d"), Some(86_400_000));
+        assert_eq!(parse_duration_ms(""), None);
+        assert_eq!(parse_duration_ms("5x"), None);
+    }
+
     #[test]
     fn test_parse_meminfo() {
         let sample_output = "
@@ -329,10 +1669,112 @@ OtherSurface=888";
         ";
         let expected = BatteryInfo {
             level: 85,
-            temperature: 320, // 32.0 C
-            voltage: 4123,    // 4.123 V
+            scale: 100,
+            percentage: 85,
+            status: BatteryStatus::Charging,
+            health: BatteryHealth::Good,
+            plugged: PluggedState::Usb,
+            voltage: 4123,
+            temperature: 32.0,
+            technology: "Li-poly".to_string(),
         };
 
         assert_eq!(parse_battery_info(sample_output), Some(expected));
     }
+
+    #[test]
+    fn test_parse_battery_info_codes() {
+        let output = "status: 5\nhealth: 3\nplugged: 1\nlevel: 50\nscale: 200\n";
+        let battery = parse_battery_info(output).unwrap();
+        assert_eq!(battery.status, BatteryStatus::Full);
+        assert_eq!(battery.health, BatteryHealth::Overheat);
+        assert_eq!(battery.plugged, PluggedState::Ac);
+        assert_eq!(battery.percentage, 25);
+
+        let unplugged = parse_battery_info("status: 3\nlevel: 10\n").unwrap();
+        assert_eq!(unplugged.status, BatteryStatus::Discharging);
+        assert_eq!(unplugged.health, BatteryHealth::Unknown);
+        assert_eq!(unplugged.plugged, PluggedState::Unplugged);
+
+        assert_eq!(parse_battery_info("status: 2\n"), None);
+    }
+
+    fn cpu(name: &str, user: u64, idle: u64) -> CpuInfo {
+        CpuInfo {
+            name: name.to_string(),
+            times: CpuTimes {
+                user,
+                nice: 0,
+                sys: 0,
+                idle,
+                iowait: 0,
+                irq: 0,
+                softirq: 0,
+            },
+            speed_mhz: None,
+        }
+    }
+
+    #[test]
+    fn test_cpu_usage_percent() {
+        let prev = vec![cpu("cpu0", 100, 100), cpu("cpu1", 100, 100)];
+        let cur = vec![cpu("cpu0", 150, 150), cpu("cpu1", 200, 100)];
+
+        // busy delta 150, idle delta 50
+        assert_eq!(cpu_usage_percent(&prev, &cur), Some(75.0));
+        assert_eq!(cpu_usage_percent(&prev, &prev), None);
+        assert_eq!(cpu_usage_percent(&cur, &prev), None);
+    }
+
+    #[test]
+    fn test_fps_between() {
+        let prev = FpsData {
+            flips: 1000,
+            timestamp_ms: 10_000,
+        };
+        let cur = FpsData {
+            flips: 1060,
+            timestamp_ms: 11_000,
+        };
+
+        assert_eq!(fps_between(&prev, &cur), Some(60.0));
+        assert_eq!(fps_between(&prev, &prev), None);
+    }
+
+    #[test]
+    fn test_estimate_time_remaining_charging() {
+        // 1% per minute, 40% left to full
+        let samples = vec![(0, 58), (60_000, 59), (120_000, 60)];
+        assert_close(
+            estimate_time_remaining(&samples),
+            Duration::from_secs(40 * 60),
+        );
+    }
+
+    #[test]
+    fn test_estimate_time_remaining_discharging() {
+        let samples = vec![(1_000, 20), (121_000, 18)];
+        assert_close(
+            estimate_time_remaining(&samples),
+            Duration::from_secs(18 * 60),
+        );
+    }
+
+    // The estimate goes through a float rate, so it may be off by rounding
+    fn assert_close(actual: Option<Duration>, expected: Duration) {
+        let actual = actual.expect("no estimate");
+        assert!(
+            actual.abs_diff(expected) < Duration::from_millis(10),
+            "{:?} is not close to {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_estimate_time_remaining_flat() {
+        assert_eq!(estimate_time_remaining(&[(0, 50), (60_000, 50)]), None);
+        assert_eq!(estimate_time_remaining(&[(0, 50), (0, 51)]), None);
+        assert_eq!(estimate_time_remaining(&[(0, 50)]), None);
+    }
 }
@@ -0,0 +1,293 @@
+use crate::device::display::get_screen_info;
+use crate::device::input::{KeyCode, input_text, key_event, swipe, swipe_path};
+use crate::device::shell::shell_output;
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+
+// Time for the screen to turn on or the bouncer to slide in before the next input
+const UNLOCK_SETTLE: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ScreenState {
+    On,
+    Off,
+    /// Always-on display or ambient mode, the screen shows a dim clock
+    Dozing,
+}
+
+/// Whether the display is on, off or dozing, from `dumpsys power`.
+pub fn get_screen_state(device: &mut ADBServerDevice) -> Result<ScreenState, PowerError> {
+    let output = run_shell_command(device, "dumpsys power")?;
+    parse_screen_state(&output).ok_or_else(|| {
+        PowerError::ParseError("No display power state in dumpsys power".to_string())
+    })
+}
+
+/// Turns the screen on. Doesn't dismiss the lock screen, see [`is_locked`].
+pub fn wake(device: &mut ADBServerDevice) -> Result<(), PowerError> {
+    key_event(device, KeyCode::Wakeup).map_err(|e| PowerError::CommandFailed(e.to_string()))
+}
+
+/// Turns the screen off.
+pub fn sleep(device: &mut ADBServerDevice) -> Result<(), PowerError> {
+    key_event(device, KeyCode::Sleep).map_err(|e| PowerError::CommandFailed(e.to_string()))
+}
+
+/// Whether the keyguard is showing, which is also the case while the screen is off on a
+/// device with a lock screen.
+pub fn is_locked(device: &mut ADBServerDevice) -> Result<bool, PowerError> {
+    let output = run_shell_command(device, "dumpsys window policy")?;
+    parse_locked(&output)
+        .ok_or_else(|| PowerError::ParseError("No keyguard state in dumpsys window".to_string()))
+}
+
+/// Where the pattern dots sit on screen, as fractions of the screen size: the centre of
+/// the top left dot and of the bottom right one. The default fits AOSP's portrait lock
+/// screen, skins that move the grid need their own.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct PatternGrid {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Default for PatternGrid {
+    fn default() -> Self {
+        Self {
+            left: 0.25,
+            top: 0.58,
+            right: 0.75,
+            bottom: 0.83,
+        }
+    }
+}
+
+impl PatternGrid {
+    /// Screen position of dot `dot`, numbered 1 to 9 like a phone keypad.
+    fn dot_position(&self, dot: u8, width: u32, height: u32) -> (u32, u32) {
+        let (row, col) = ((dot - 1) / 3, (dot - 1) % 3);
+        let x = self.left + (self.right - self.left) * col as f32 / 2.0;
+        let y = self.top + (self.bottom - self.top) * row as f32 / 2.0;
+        (
+            (x * width as f32).round() as u32,
+            (y * height as f32).round() as u32,
+        )
+    }
+}
+
+/// Wakes the device, dismisses the keyguard and enters `pin`. Only numeric PINs work,
+/// passwords with letters, biometrics and smart lock aren't supported.
+pub fn unlock_with_pin(device: &mut ADBServerDevice, pin: &str) -> Result<(), PowerError> {
+    if !(4..=16).contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(PowerError::InvalidInput(
+            "a PIN is 4 to 16 digits".to_string(),
+        ));
+    }
+    if !show_bouncer(device)? {
+        return Ok(());
+    }
+
+    input_text(device, pin).map_err(|e| PowerError::CommandFailed(e.to_string()))?;
+    key_event(device, KeyCode::Enter).map_err(|e| PowerError::CommandFailed(e.to_string()))?;
+    check_unlocked(device)
+}
+
+/// Wakes the device, dismisses the keyguard and draws the pattern through `dots`, numbered
+/// 1 to 9 from the top left like a phone keypad. `grid` says where the dots are, see
+/// [`PatternGrid`]. Biometrics and smart lock aren't supported.
+pub fn unlock_with_pattern(
+    device: &mut ADBServerDevice,
+    dots: &[u8],
+    grid: &PatternGrid,
+) -> Result<(), PowerError> {
+    check_pattern(dots)?;
+    let screen = get_screen_info(device)
+        .ok_or_else(|| PowerError::CommandFailed("Could not read the screen size".to_string()))?;
+    if !show_bouncer(device)? {
+        return Ok(());
+    }
+
+    let path: Vec<(u32, u32)> = dots
+        .iter()
+        .map(|&dot| grid.dot_position(dot, screen.width, screen.height))
+        .collect();
+    swipe_path(device, &path).map_err(|e| PowerError::CommandFailed(e.to_string()))?;
+    check_unlocked(device)
+}
+
+fn check_pattern(dots: &[u8]) -> Result<(), PowerError> {
+    if dots.len() < 4 {
+        return Err(PowerError::InvalidInput(
+            "a pattern connects at least 4 dots".to_string(),
+        ));
+    }
+    if let Some(dot) = dots.iter().find(|dot| !(1..=9).contains(*dot)) {
+        return Err(PowerError::InvalidInput(format!(
+            "dot {} is outside 1 to 9",
+            dot
+        )));
+    }
+    if (1..dots.len()).any(|i| dots[..i].contains(&dots[i])) {
+        return Err(PowerError::InvalidInput(
+            "a pattern can't use a dot twice".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Wakes the screen and swipes the keyguard away. Returns false when the device turned
+/// out not to be locked, or the swipe alone unlocked it.
+fn show_bouncer(device: &mut ADBServerDevice) -> Result<bool, PowerError> {
+    if get_screen_state(device)? != ScreenState::On {
+        wake(device)?;
+        thread::sleep(UNLOCK_SETTLE);
+    }
+    if !is_locked(device)? {
+        return Ok(false);
+    }
+
+    let screen = get_screen_info(device)
+        .ok_or_else(|| PowerError::CommandFailed("Could not read the screen size".to_string()))?;
+    let x = screen.width / 2;
+    swipe(device, x, screen.height * 4 / 5, x, screen.height / 4, 200)
+        .map_err(|e| PowerError::CommandFailed(e.to_string()))?;
+    thread::sleep(UNLOCK_SETTLE);
+    is_locked(device)
+}
+
+fn check_unlocked(device: &mut ADBServerDevice) -> Result<(), PowerError> {
+    thread::sleep(UNLOCK_SETTLE);
+    if is_locked(device)? {
+        Err(PowerError::UnlockFailed(
+            "still locked, wrong credential or the lock screen isn't a PIN or pattern".to_string(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// `Display Power: state=ON`, or `mWakefulness=Asleep` on builds without the former
+fn parse_screen_state(output: &str) -> Option<ScreenState> {
+    let value = |key: &str| {
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(key))
+            .map(str::trim)
+    };
+
+    if let Some(state) = value("Display Power: state=") {
+        return match state {
+            "ON" | "VR" | "ON_SUSPEND" => Some(ScreenState::On),
+            "OFF" => Some(ScreenState::Off),
+            "DOZE" | "DOZE_SUSPEND" => Some(ScreenState::Dozing),
+            _ => None,
+        };
+    }
+
+    match value("mWakefulness=")? {
+        // A screensaver keeps the display on
+        "Awake" | "Dreaming" => Some(ScreenState::On),
+        "Asleep" => Some(ScreenState::Off),
+        "Dozing" => Some(ScreenState::Dozing),
+        _ => None,
+    }
+}
+
+// `mShowingLockscreen=true` up to Android 9, `isKeyguardShowing=true` after
+fn parse_locked(output: &str) -> Option<bool> {
+    output
+        .split_whitespace()
+        .find_map(|field| {
+            field
+                .strip_prefix("mShowingLockscreen=")
+                .or_else(|| field.strip_prefix("isKeyguardShowing="))
+        })
+        .and_then(|value| value.parse().ok())
+}
+
+fn run_shell_command(device: &mut ADBServerDevice, command: &str) -> Result<String, PowerError> {
+    let output =
+        shell_output(device, command).map_err(|e| PowerError::CommandFailed(e.to_string()))?;
+    Ok(String::from_utf8_lossy(&output).trim().to_string())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum PowerError {
+    InvalidInput(String),
+    CommandFailed(String),
+    ParseError(String),
+    UnlockFailed(String),
+}
+
+impl std::fmt::Display for PowerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PowerError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            PowerError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+            PowerError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+            PowerError::UnlockFailed(msg) => write!(f, "Unlock failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for PowerError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_screen_state() {
+        let output = "POWER MANAGER (dumpsys power)\n\
+                      mWakefulness=Awake\n\
+                      Display Power: state=OFF\n";
+        assert_eq!(parse_screen_state(output), Some(ScreenState::Off));
+        assert_eq!(
+            parse_screen_state("Display Power: state=DOZE_SUSPEND"),
+            Some(ScreenState::Dozing)
+        );
+        assert_eq!(
+            parse_screen_state("  mWakefulness=Dreaming\n"),
+            Some(ScreenState::On)
+        );
+        assert_eq!(parse_screen_state("nothing here"), None);
+    }
+
+    #[test]
+    fn test_pattern_dots() {
+        let grid = PatternGrid::default();
+        assert_eq!(grid.dot_position(1, 1080, 2400), (270, 1392));
+        assert_eq!(grid.dot_position(5, 1080, 2400), (540, 1692));
+        assert_eq!(grid.dot_position(9, 1080, 2400), (810, 1992));
+
+        assert_eq!(check_pattern(&[1, 2, 3, 6, 9]), Ok(()));
+        assert!(matches!(
+            check_pattern(&[1, 2, 3]),
+            Err(PowerError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            check_pattern(&[1, 2, 3, 0]),
+            Err(PowerError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            check_pattern(&[1, 2, 3, 2]),
+            Err(PowerError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_locked() {
+        let modern = "    KeyguardServiceDelegate\n\
+                      showing=true\n\
+                      isKeyguardShowing=true\n";
+        assert_eq!(parse_locked(modern), Some(true));
+        assert_eq!(
+            parse_locked("mShowingLockscreen=false mShowingDream=false"),
+            Some(false)
+        );
+        assert_eq!(parse_locked("WINDOW MANAGER POLICY STATE"), None);
+    }
+}
@@ -1,6 +1,14 @@
-use adb_client::ADBDeviceExt;
+use crate::device::performance::parse_cpu_stat;
+use crate::device::shell::{
+    MAX_OUTPUT_BYTES, shell_command_capped, shell_quote, spawn_host_shell, stop_host_shell,
+};
 use adb_client::server_device::ADBServerDevice;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ProcessInfo {
@@ -20,6 +28,18 @@ pub struct MemoryInfo {
     pub low_memory: bool,
 }
 
+/// Memory of a single process in KiB, from `/proc/<pid>/status` and, when readable,
+/// `/proc/<pid>/smaps_rollup`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ProcessMemory {
+    pub pid: u32,
+    pub rss_kb: u64,
+    pub vss_kb: u64,
+    pub swap_kb: u64,
+    pub shared_kb: u64,
+    pub pss_kb: Option<u64>,
+}
+
 pub fn list_processes(
     device: &mut ADBServerDevice,
     apps_only: bool,
@@ -68,33 +88,33 @@ pub fn list_processes(
                     continue;
                 }
                 let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() > std::cmp::max(pid_idx, std::cmp::max(cpu_idx, mem_idx)) {
-                    if let Ok(pid) = parts[pid_idx].parse::<u32>() {
-                        let user = parts.get(user_idx).unwrap_or(&"").to_string();
-                        let cpu = parts.get(cpu_idx).unwrap_or(&"0.0").to_string();
-                        let mem = parts.get(mem_idx).unwrap_or(&"0M").to_string();
-
-                        let name = if parts.len() > args_idx {
-                            parts[args_idx..].join(" ")
-                        } else {
-                            parts.last().unwrap_or(&"").to_string()
-                        };
-
-                        if name.contains("top -n 1") {
-                            continue;
-                        }
-
-                        let process = ProcessInfo {
-                            pid,
-                            name,
-                            user: user.clone(),
-                            cpu,
-                            mem,
-                        };
-
-                        if !apps_only || (user.starts_with("u0_a") || user.starts_with("u10")) {
-                            processes.push(process);
-                        }
+                if parts.len() > std::cmp::max(pid_idx, std::cmp::max(cpu_idx, mem_idx))
+                    && let Ok(pid) = parts[pid_idx].parse::<u32>()
+                {
+                    let user = parts.get(user_idx).unwrap_or(&"").to_string();
+                    let cpu = parts.get(cpu_idx).unwrap_or(&"0.0").to_string();
+                    let mem = parts.get(mem_idx).unwrap_or(&"0M").to_string();
+
+                    let name = if parts.len() > args_idx {
+                        parts[args_idx..].join(" ")
+                    } else {
+                        parts.last().unwrap_or(&"").to_string()
+                    };
+
+                    if name.contains("top -n 1") {
+                        continue;
+                    }
+
+                    let process = ProcessInfo {
+                        pid,
+                        name,
+                        user: user.clone(),
+                        cpu,
+                        mem,
+                    };
+
+                    if !apps_only || (user.starts_with("u0_a") || user.starts_with("u10")) {
+                        processes.push(process);
                     }
                 }
             }
@@ -106,40 +126,60 @@ pub fn list_processes(
         }
     }
 
-    // Fallback to ps
-    let output = run_shell_command(device, "ps")?;
+    // Fallback to ps, with the columns spelled out since toybox and toolbox differ
+    let output = run_shell_command(device, PS_COMMAND)?;
+    let mut processes = parse_ps(&output);
+    if processes.is_empty() {
+        // toolbox ps (Android 7 and older) has no -o, its default layout still ends in NAME
+        processes = parse_ps(&run_shell_command(device, "ps")?);
+    }
 
-    let mut processes: Vec<ProcessInfo> = output
-        .lines()
-        .skip(1)
+    if apps_only {
+        processes.retain(|p| p.user.starts_with("u0_a") || p.user.starts_with("u10"));
+    }
+    processes.truncate(50);
+
+    Ok(processes)
+}
+
+const PS_COMMAND: &str = "ps -A -o USER:20,PID,PPID,NAME";
+
+/// Rows of `ps` output, located through the header so both [`PS_COMMAND`] and the
+/// legacy toolbox layout work. NAME is the last column and keeps any spaces in it.
+fn parse_ps(output: &str) -> Vec<ProcessInfo> {
+    let mut lines = output.lines();
+    let Some(header) = lines.find(|l| l.trim_start().starts_with("USER")) else {
+        return Vec::new();
+    };
+    let headers: Vec<&str> = header.split_whitespace().collect();
+    let column = |name: &str| headers.iter().position(|h| *h == name);
+    let (Some(user_idx), Some(pid_idx), Some(name_idx)) =
+        (column("USER"), column("PID"), column("NAME"))
+    else {
+        return Vec::new();
+    };
+    // toolbox prints the state letter after PC without a header for it
+    let name_idx = if column("PC").is_some() && column("S").is_none() {
+        name_idx + 1
+    } else {
+        name_idx
+    };
+
+    lines
         .filter_map(|line| {
             let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 9 {
-                let user = parts[0].to_string();
-                let pid = parts[1].parse::<u32>().ok()?;
-                let name = parts.last()?.to_string();
-                let process = ProcessInfo {
-                    pid,
-                    name,
-                    user: user.clone(),
-                    cpu: "0".to_string(),
-                    mem: "0".to_string(),
-                };
-
-                if !apps_only || (user.starts_with("u0_a") || user.starts_with("u10")) {
-                    Some(process)
-                } else {
-                    None
-                }
-            } else {
-                None
+            if parts.len() <= name_idx {
+                return None;
             }
+            Some(ProcessInfo {
+                pid: parts[pid_idx].parse().ok()?,
+                name: parts[name_idx..].join(" "),
+                user: parts[user_idx].to_string(),
+                cpu: "0".to_string(),
+                mem: "0".to_string(),
+            })
         })
-        .collect();
-
-    processes.truncate(50);
-
-    Ok(processes)
+        .collect()
 }
 
 pub fn kill_process(device: &mut ADBServerDevice, pid: u32) -> Result<(), ProcessError> {
@@ -153,7 +193,10 @@ pub fn kill_process(device: &mut ADBServerDevice, pid: u32) -> Result<(), Proces
 }
 
 pub fn kill_package(device: &mut ADBServerDevice, package_name: &str) -> Result<(), ProcessError> {
-    let output = run_shell_command(device, &format!("am force-stop {}", package_name))?;
+    let output = run_shell_command(
+        device,
+        &format!("am force-stop {}", shell_quote(package_name)),
+    )?;
 
     if output.contains("Error") || output.contains("failed") {
         return Err(ProcessError::PackageNotFound(package_name.to_string()));
@@ -162,48 +205,235 @@ pub fn kill_package(device: &mut ADBServerDevice, package_name: &str) -> Result<
     Ok(())
 }
 
+/// Kills every cached background process with `am kill-all`. Unlike a force-stop this
+/// leaves foreground apps and running services alone.
+pub fn kill_all_background(device: &mut ADBServerDevice) -> Result<(), ProcessError> {
+    let output = run_shell_command(device, "am kill-all")?;
+    check_am_kill_output(&output)
+}
+
+/// Kills the cached processes of `package_name` with `am kill`, a lighter step than
+/// [`kill_package`]. Fails when the package still has processes afterwards, which means
+/// it wasn't in the background.
+pub fn kill_background(
+    device: &mut ADBServerDevice,
+    package_name: &str,
+) -> Result<(), ProcessError> {
+    let output = run_shell_command(device, &format!("am kill {}", shell_quote(package_name)))?;
+    check_am_kill_output(&output)?;
+
+    let remaining = find_process_by_package(device, package_name)?;
+    if !remaining.is_empty() {
+        return Err(ProcessError::CommandFailed(format!(
+            "{} is still running {} process(es), it is not in the background",
+            package_name,
+            remaining.len()
+        )));
+    }
+
+    Ok(())
+}
+
+fn check_am_kill_output(output: &str) -> Result<(), ProcessError> {
+    if output.contains("SecurityException") || output.contains("Permission Denial") {
+        return Err(ProcessError::PermissionDenied);
+    }
+    if output.contains("Error") || output.contains("Exception") {
+        return Err(ProcessError::CommandFailed(output.to_string()));
+    }
+    Ok(())
+}
+
 pub fn get_process_memory(
     device: &mut ADBServerDevice,
     pid: u32,
-) -> Result<MemoryInfo, ProcessError> {
+) -> Result<ProcessMemory, ProcessError> {
     let output = run_shell_command(device, &format!("cat /proc/{}/status", pid))?;
+    if output.contains("No such file or directory") {
+        return Err(ProcessError::ProcessNotFound(pid));
+    }
+
+    let mut memory = parse_process_status(pid, &output);
+
+    // smaps_rollup needs root or the same uid, status alone is the fallback
+    if let Ok(rollup) = run_shell_command(
+        device,
+        &format!("cat /proc/{}/smaps_rollup 2>/dev/null", pid),
+    ) {
+        apply_smaps_rollup(&mut memory, &rollup);
+    }
+
+    Ok(memory)
+}
 
-    let mut mem_total = String::new();
-    let mut mem_free = String::new();
-    let mut mem_used = String::new();
-    let mut threshold = String::new();
-    let mut low_memory = false;
-
-    for line in output.lines() {
-        if line.starts_with("VmRSS:") {
-            mem_used = line.split_whitespace().nth(1).unwrap_or("0").to_string();
-        } else if line.starts_with("VmSize:") {
-            mem_total = line.split_whitespace().nth(1).unwrap_or("0").to_string();
+fn parse_process_status(pid: u32, output: &str) -> ProcessMemory {
+    let mut memory = ProcessMemory {
+        pid,
+        ..Default::default()
+    };
+
+    for (field, value) in kb_fields(output) {
+        match field {
+            "VmRSS" => memory.rss_kb = value,
+            "VmSize" => memory.vss_kb = value,
+            "VmSwap" => memory.swap_kb = value,
+            // file backed and shmem pages are the shareable part of RSS
+            "RssFile" | "RssShmem" => memory.shared_kb += value,
+            _ => {}
         }
     }
 
-    let meminfo = run_shell_command(device, "cat /proc/meminfo")?;
-    for line in meminfo.lines() {
-        if line.starts_with("MemFree:") {
-            mem_free = line.split_whitespace().nth(1).unwrap_or("0").to_string();
-        } else if line.starts_with("MemAvailable:") {
-            threshold = line.split_whitespace().nth(1).unwrap_or("0").to_string();
-        } else if line.starts_with("LowMemory:") {
-            low_memory = line.contains("yes") || line.contains("1");
+    memory
+}
+
+fn apply_smaps_rollup(memory: &mut ProcessMemory, output: &str) {
+    let mut shared_kb = None;
+
+    for (field, value) in kb_fields(output) {
+        match field {
+            "Pss" => memory.pss_kb = Some(value),
+            "Shared_Clean" | "Shared_Dirty" => *shared_kb.get_or_insert(0) += value,
+            _ => {}
         }
     }
 
-    Ok(MemoryInfo {
-        total: mem_total,
-        free: mem_free,
-        used: mem_used,
-        threshold,
-        low_memory,
+    if let Some(shared_kb) = shared_kb {
+        memory.shared_kb = shared_kb;
+    }
+}
+
+/// `Name:   1234 kB` lines as (name, value) pairs.
+fn kb_fields(output: &str) -> impl Iterator<Item = (&str, u64)> {
+    output.lines().filter_map(|line| {
+        let (field, rest) = line.split_once(':')?;
+        let value = rest.split_whitespace().next()?.parse::<u64>().ok()?;
+        Some((field.trim(), value))
+    })
+}
+
+/// PSS breakdown of one process from the App Summary table of `dumpsys meminfo`, in KiB.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ProcessMemSummary {
+    pub pid: u32,
+    pub process_name: String,
+    pub total_pss_kb: u64,
+    pub java_heap_kb: u64,
+    pub native_heap_kb: u64,
+    pub code_kb: u64,
+    pub graphics_kb: u64,
+}
+
+/// Totals over every process of the package, with the per-process entries kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AppMemInfo {
+    pub package_name: String,
+    pub total_pss_kb: u64,
+    pub java_heap_kb: u64,
+    pub native_heap_kb: u64,
+    pub code_kb: u64,
+    pub graphics_kb: u64,
+    pub processes: Vec<ProcessMemSummary>,
+}
+
+pub fn get_app_meminfo(
+    device: &mut ADBServerDevice,
+    package_name: &str,
+) -> Result<AppMemInfo, ProcessError> {
+    let output = run_shell_command(
+        device,
+        &format!("dumpsys meminfo {}", shell_quote(package_name)),
+    )?;
+
+    parse_app_meminfo(package_name, &output)
+        .ok_or_else(|| ProcessError::PackageNotFound(package_name.to_string()))
+}
+
+fn parse_app_meminfo(package_name: &str, output: &str) -> Option<AppMemInfo> {
+    // Each process starts with `** MEMINFO in pid 1234 [com.example.app] **`
+    let processes: Vec<ProcessMemSummary> = output
+        .split("** MEMINFO in pid ")
+        .skip(1)
+        .filter_map(parse_meminfo_section)
+        .collect();
+
+    if processes.is_empty() {
+        return None;
+    }
+
+    let sum = |f: fn(&ProcessMemSummary) -> u64| processes.iter().map(f).sum();
+    Some(AppMemInfo {
+        package_name: package_name.to_string(),
+        total_pss_kb: sum(|p| p.total_pss_kb),
+        java_heap_kb: sum(|p| p.java_heap_kb),
+        native_heap_kb: sum(|p| p.native_heap_kb),
+        code_kb: sum(|p| p.code_kb),
+        graphics_kb: sum(|p| p.graphics_kb),
+        processes,
     })
 }
 
+fn parse_meminfo_section(section: &str) -> Option<ProcessMemSummary> {
+    let header = section.lines().next()?;
+    let pid = header.split_whitespace().next()?.parse().ok()?;
+    let process_name = header
+        .split_once('[')
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_default();
+
+    let mut summary = ProcessMemSummary {
+        pid,
+        process_name,
+        ..Default::default()
+    };
+
+    let first_number = |rest: &str| {
+        rest.split_whitespace()
+            .next()
+            .and_then(|v| v.parse::<u64>().ok())
+    };
+
+    let mut in_summary = false;
+    for line in section.lines() {
+        let line = line.trim();
+        if line.starts_with("App Summary") {
+            in_summary = true;
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("TOTAL PSS:") {
+            summary.total_pss_kb = first_number(rest).unwrap_or(0);
+        } else if let Some(rest) = line.strip_prefix("TOTAL:") {
+            // older releases print `TOTAL:` in the summary instead of `TOTAL PSS:`
+            if summary.total_pss_kb == 0 {
+                summary.total_pss_kb = first_number(rest).unwrap_or(0);
+            }
+        }
+
+        if !in_summary {
+            continue;
+        }
+
+        let Some((field, rest)) = line.split_once(':') else {
+            continue;
+        };
+        let Some(value) = first_number(rest) else {
+            continue;
+        };
+        match field.trim() {
+            "Java Heap" => summary.java_heap_kb = value,
+            "Native Heap" => summary.native_heap_kb = value,
+            "Code" => summary.code_kb = value,
+            "Graphics" => summary.graphics_kb = value,
+            _ => {}
+        }
+    }
+
+    Some(summary)
+}
+
 pub fn list_running_services(device: &mut ADBServerDevice) -> Result<Vec<String>, ProcessError> {
-    let output = run_shell_command(device, "dumpsys activity services")?;
+    let output = dump_services(device)?;
 
     let services: Vec<String> = output
         .lines()
@@ -221,6 +451,58 @@ pub fn list_running_services(device: &mut ADBServerDevice) -> Result<Vec<String>
     Ok(services)
 }
 
+/// A service running in the foreground, which keeps its process alive and shows a
+/// notification while it does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ForegroundService {
+    pub package_name: String,
+    /// Fully qualified class, `.Name` shorthands are expanded with the package
+    pub service_class: String,
+    pub foreground_id: Option<u32>,
+}
+
+pub fn list_foreground_services(
+    device: &mut ADBServerDevice,
+) -> Result<Vec<ForegroundService>, ProcessError> {
+    let output = dump_services(device)?;
+    Ok(parse_foreground_services(&output))
+}
+
+fn dump_services(device: &mut ADBServerDevice) -> Result<String, ProcessError> {
+    run_shell_command(device, "dumpsys activity services")
+}
+
+fn parse_foreground_services(output: &str) -> Vec<ForegroundService> {
+    // Every service record starts with `* ServiceRecord{6f3a1b2 u0 com.pkg/.Service}`
+    output
+        .split("* ServiceRecord{")
+        .skip(1)
+        .filter_map(|record| {
+            let (header, details) = record.split_once('}')?;
+            let fields: Vec<&str> = details.split_whitespace().collect();
+            if !fields.contains(&"isForeground=true") {
+                return None;
+            }
+
+            let (package_name, class) = header.split_whitespace().last()?.split_once('/')?;
+            let service_class = match class.strip_prefix('.') {
+                Some(_) => format!("{}{}", package_name, class),
+                None => class.to_string(),
+            };
+            let foreground_id = fields
+                .iter()
+                .find_map(|f| f.strip_prefix("foregroundId="))
+                .and_then(|id| id.parse().ok());
+
+            Some(ForegroundService {
+                package_name: package_name.to_string(),
+                service_class,
+                foreground_id,
+            })
+        })
+        .collect()
+}
+
 pub fn find_process_by_package(
     device: &mut ADBServerDevice,
     package_name: &str,
@@ -235,6 +517,279 @@ pub fn find_process_by_package(
     Ok(matching)
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TopSort {
+    Cpu,
+    Memory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProcessStat {
+    pub pid: u32,
+    pub name: String,
+    /// Share of the whole device over the sample window, all cores busy is 100
+    pub cpu_percent: f32,
+    pub rss_kb: u64,
+}
+
+const TOP_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+// One round trip for the system totals and every process, exited ones are just skipped
+const PROC_STAT_DUMP: &str = "cat /proc/stat /proc/[0-9]*/stat 2>/dev/null";
+
+/// The `count` busiest processes by CPU or resident memory, with CPU usage measured
+/// between two samples of `/proc/<pid>/stat`.
+pub fn top_processes(
+    device: &mut ADBServerDevice,
+    sort_by: TopSort,
+    count: usize,
+) -> Result<Vec<ProcessStat>, ProcessError> {
+    let first = run_shell_command(device, PROC_STAT_DUMP)?;
+    thread::sleep(TOP_SAMPLE_INTERVAL);
+    let second = run_shell_command(device, PROC_STAT_DUMP)?;
+    let rss = run_shell_command(device, "grep VmRSS /proc/[0-9]*/status 2>/dev/null")?;
+
+    Ok(rank_processes(&first, &second, &rss, sort_by, count))
+}
+
+fn rank_processes(
+    first: &str,
+    second: &str,
+    rss: &str,
+    sort_by: TopSort,
+    count: usize,
+) -> Vec<ProcessStat> {
+    let (total_before, before) = parse_proc_sample(first);
+    let (total_after, after) = parse_proc_sample(second);
+    let rss = parse_vmrss(rss);
+    let elapsed = total_after.saturating_sub(total_before);
+
+    let mut stats: Vec<ProcessStat> = after
+        .into_iter()
+        .filter_map(|(pid, (name, ticks))| {
+            // A pid missing from the first sample, or reused by another process, has no
+            // baseline to measure against
+            let (prev_name, prev_ticks) = before.get(&pid)?;
+            if *prev_name != name {
+                return None;
+            }
+            let cpu_percent = if elapsed > 0 {
+                ticks.saturating_sub(*prev_ticks) as f32 / elapsed as f32 * 100.0
+            } else {
+                0.0
+            };
+            Some(ProcessStat {
+                pid,
+                name,
+                cpu_percent,
+                rss_kb: rss.get(&pid).copied().unwrap_or(0),
+            })
+        })
+        .collect();
+
+    match sort_by {
+        TopSort::Cpu => stats.sort_by(|a, b| {
+            b.cpu_percent
+                .total_cmp(&a.cpu_percent)
+                .then(b.rss_kb.cmp(&a.rss_kb))
+        }),
+        TopSort::Memory => stats.sort_by(|a, b| {
+            b.rss_kb
+                .cmp(&a.rss_kb)
+                .then(b.cpu_percent.total_cmp(&a.cpu_percent))
+        }),
+    }
+    stats.truncate(count);
+    stats
+}
+
+/// Total jiffies of all cores and `pid -> (comm, utime + stime)`.
+fn parse_proc_sample(output: &str) -> (u64, HashMap<u32, (String, u64)>) {
+    let total = parse_cpu_stat(output)
+        .iter()
+        .map(|cpu| {
+            let t = &cpu.times;
+            t.user + t.nice + t.sys + t.idle + t.iowait + t.irq + t.softirq
+        })
+        .sum();
+
+    let processes = output
+        .lines()
+        .filter(|line| line.starts_with(|c: char| c.is_ascii_digit()))
+        .filter_map(parse_pid_stat)
+        .map(|(pid, name, ticks)| (pid, (name, ticks)))
+        .collect();
+
+    (total, processes)
+}
+
+// `1234 (com.example) S 1 ...`, the name can itself contain spaces and parentheses
+fn parse_pid_stat(line: &str) -> Option<(u32, String, u64)> {
+    let open = line.find(" (")?;
+    let close = line.rfind(')')?;
+    let pid = line[..open].parse().ok()?;
+    let name = line.get(open + 2..close)?.to_string();
+
+    let fields: Vec<&str> = line[close + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+
+    Some((pid, name, utime + stime))
+}
+
+// `/proc/1234/status:VmRSS:    5678 kB`
+fn parse_vmrss(output: &str) -> HashMap<u32, u64> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (pid, rest) = line.strip_prefix("/proc/")?.split_once('/')?;
+            let (_, value) = rest.split_once("VmRSS:")?;
+            Some((
+                pid.parse().ok()?,
+                value.split_whitespace().next()?.parse().ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// Runs `top` in batch mode on the device and sends every process of each refresh.
+///
+/// `top` measures CPU usage itself, which is cheaper than sampling `/proc` for every pid.
+/// Dropping the receiver kills `top` on the device once the next refresh comes in.
+pub fn stream_top(
+    device: &mut ADBServerDevice,
+    interval: Duration,
+) -> Result<mpsc::Receiver<Vec<ProcessStat>>, ProcessError> {
+    let serial = device
+        .identifier
+        .as_ref()
+        .ok_or_else(|| ProcessError::CommandFailed("device has no serial".to_string()))?;
+    // toybox top takes fractional seconds
+    let command = format!("top -b -d {}", interval.as_secs_f32().max(0.1));
+
+    let mut child = spawn_host_shell(serial, &command)
+        .map_err(|e| ProcessError::CommandFailed(e.to_string()))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| ProcessError::CommandFailed("Failed to capture stdout".to_string()))?;
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut parser = TopParser::default();
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            if let Some(frame) = parser.push_line(&line)
+                && tx.send(frame).is_err()
+            {
+                break;
+            }
+        }
+        if let Some(frame) = parser.finish() {
+            let _ = tx.send(frame);
+        }
+        stop_host_shell(child);
+    });
+
+    Ok(rx)
+}
+
+/// Splits `top -b` output into frames. Every frame reprints the summary header and the
+/// column titles, a frame is done at the first line after its process rows.
+#[derive(Debug, Default)]
+struct TopParser {
+    columns: Option<TopColumns>,
+    // CPU capacity from the `800%cpu` summary, 100 per core
+    capacity: Option<f32>,
+    rows: Vec<ProcessStat>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TopColumns {
+    pid: usize,
+    cpu: usize,
+    rss: usize,
+    name: usize,
+}
+
+impl TopParser {
+    fn push_line(&mut self, line: &str) -> Option<Vec<ProcessStat>> {
+        if let Some(columns) = self.columns
+            && let Some(stat) = parse_top_row(line, columns, self.capacity)
+        {
+            self.rows.push(stat);
+            return None;
+        }
+
+        let frame = self.finish();
+        if let Some(capacity) = parse_cpu_capacity(line) {
+            self.capacity = Some(capacity);
+        }
+        if let Some(columns) = parse_top_columns(line) {
+            self.columns = Some(columns);
+        }
+        frame
+    }
+
+    fn finish(&mut self) -> Option<Vec<ProcessStat>> {
+        if self.rows.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.rows))
+        }
+    }
+}
+
+// `  PID USER PR NI VIRT  RES  SHR S[%CPU] %MEM TIME+ ARGS`, the sort column is bracketed
+fn parse_top_columns(line: &str) -> Option<TopColumns> {
+    let line = line.replace(['[', ']'], " ");
+    let titles: Vec<&str> = line.split_whitespace().collect();
+    let find = |names: &[&str]| titles.iter().position(|t| names.contains(t));
+
+    let columns = TopColumns {
+        pid: find(&["PID"])?,
+        cpu: find(&["%CPU", "CPU%"])?,
+        rss: find(&["RES", "RSS"])?,
+        name: find(&["ARGS", "CMD", "NAME", "Name", "COMMAND"])?,
+    };
+    // The name runs to the end of the line and may contain spaces
+    (columns.name == titles.len() - 1).then_some(columns)
+}
+
+// `800%cpu  13%user ...`
+fn parse_cpu_capacity(line: &str) -> Option<f32> {
+    let first = line.split_whitespace().next()?;
+    let capacity: f32 = first.strip_suffix("%cpu")?.parse().ok()?;
+    (capacity > 0.0).then_some(capacity)
+}
+
+fn parse_top_row(line: &str, columns: TopColumns, capacity: Option<f32>) -> Option<ProcessStat> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() <= columns.name {
+        return None;
+    }
+
+    let cpu: f32 = fields[columns.cpu].trim_end_matches('%').parse().ok()?;
+    Some(ProcessStat {
+        pid: fields[columns.pid].parse().ok()?,
+        name: fields[columns.name..].join(" "),
+        cpu_percent: capacity.map_or(cpu, |capacity| cpu / capacity * 100.0),
+        rss_kb: parse_top_size_kb(fields[columns.rss])?,
+    })
+}
+
+// toybox prints `4.1M`, `334M`, `1.2G` and plain bytes, older tops `5678K`
+fn parse_top_size_kb(value: &str) -> Option<u64> {
+    let (number, scale) = match value.char_indices().last()? {
+        (i, 'K') => (&value[..i], 1.0),
+        (i, 'M') => (&value[..i], 1024.0),
+        (i, 'G') => (&value[..i], 1024.0 * 1024.0),
+        (i, 'T') => (&value[..i], 1024.0 * 1024.0 * 1024.0),
+        _ => (value, 1.0 / 1024.0),
+    };
+    let number: f64 = number.parse().ok()?;
+    Some((number * scale) as u64)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ProcessError {
     ProcessNotFound(u32),
@@ -257,10 +812,14 @@ impl std::fmt::Display for ProcessError {
 impl std::error::Error for ProcessError {}
 
 fn run_shell_command(device: &mut ADBServerDevice, command: &str) -> Result<String, ProcessError> {
-    let mut output = Vec::new();
-    device
-        .shell_command(&command, Some(&mut output), None)
+    let (output, truncated) = shell_command_capped(device, command, MAX_OUTPUT_BYTES)
         .map_err(|e| ProcessError::CommandFailed(e.to_string()))?;
+    if truncated {
+        return Err(ProcessError::CommandFailed(format!(
+            "output exceeded {} bytes",
+            MAX_OUTPUT_BYTES
+        )));
+    }
 
     String::from_utf8(output)
         .map_err(|e| ProcessError::CommandFailed(e.to_string()))
@@ -374,27 +933,7 @@ mod tests {
                          u0_a123        1234     1   10240   5120 0                   0 S com.example.app\n\
                          u0_a456        5678     1   20480   8192 0                   0 S system_server";
 
-        let processes: Vec<ProcessInfo> = ps_output
-            .lines()
-            .skip(1)
-            .filter_map(|line| {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 9 {
-                    let user = parts[0].to_string();
-                    let pid = parts[1].parse::<u32>().ok()?;
-                    let name = parts.last()?.to_string();
-                    Some(ProcessInfo {
-                        pid,
-                        name,
-                        user,
-                        cpu: "0".to_string(),
-                        mem: "0".to_string(),
-                    })
-                } else {
-                    None
-                }
-            })
-            .collect();
+        let processes = parse_ps(ps_output);
 
         assert_eq!(processes.len(), 2);
         assert_eq!(processes[0].pid, 1234);
@@ -403,6 +942,70 @@ mod tests {
         assert_eq!(processes[1].name, "system_server");
     }
 
+    // `ps -A -o USER:20,PID,PPID,NAME` on a Pixel 3 running Android 9 (toybox 0.7.6)
+    const PS_ANDROID_9: &str = "\
+USER                   PID  PPID NAME
+root                     1     0 init
+root                     2     0 [kthreadd]
+root                    46     2 [kworker/0:1]
+system                 812   595 system_server
+u0_a87                4242   596 com.example.app
+u0_a87                4301   596 com.example.app:remote
+";
+
+    // The same command on a Pixel 7 running Android 14 (toybox 0.8.9)
+    const PS_ANDROID_14: &str = "\
+USER                   PID  PPID NAME
+root                     1     0 init
+root                   118     2 [kworker/u17:1-kverityd]
+root                   214     2 [irq/284-dwc3 gadget]
+system                1502   978 system_server
+u0_a219              10733   978 com.google.android.apps.photos
+u0_a219              10790   978 com.google.android.apps.photos:Camera Sync
+";
+
+    // Plain `ps` from toolbox on Android 7
+    const PS_TOOLBOX: &str = "\
+USER      PID   PPID  VSIZE  RSS   WCHAN              PC  NAME
+root      1     0     9304   1076  SyS_epoll_ 0000000000 S /init
+u0_a87    4242  596   1688620 98540 SyS_epoll_ 0000000000 S com.example.app
+";
+
+    #[test]
+    fn test_parse_ps_android_9() {
+        let processes = parse_ps(PS_ANDROID_9);
+        assert_eq!(processes.len(), 6);
+        assert_eq!(processes[2].name, "[kworker/0:1]");
+        assert_eq!(processes[3].user, "system");
+        assert_eq!(processes[3].pid, 812);
+        assert_eq!(processes[5].name, "com.example.app:remote");
+    }
+
+    #[test]
+    fn test_parse_ps_android_14() {
+        let processes = parse_ps(PS_ANDROID_14);
+        assert_eq!(processes.len(), 6);
+        assert_eq!(processes[2].name, "[irq/284-dwc3 gadget]");
+        assert_eq!(processes[4].user, "u0_a219");
+        assert_eq!(processes[4].pid, 10733);
+        assert_eq!(
+            processes[5].name,
+            "com.google.android.apps.photos:Camera Sync"
+        );
+    }
+
+    #[test]
+    fn test_parse_ps_toolbox_and_unusable_output() {
+        let processes = parse_ps(PS_TOOLBOX);
+        assert_eq!(processes.len(), 2);
+        assert_eq!(processes[1].pid, 4242);
+        assert_eq!(processes[0].name, "/init");
+        assert_eq!(processes[1].name, "com.example.app");
+
+        assert!(parse_ps("bad pid 'USER:20,PID,PPID,NAME'").is_empty());
+        assert!(parse_ps("").is_empty());
+    }
+
     #[test]
     fn test_package_name_filter() {
         let processes = vec![
@@ -451,6 +1054,61 @@ mod tests {
         assert_eq!(command, "am force-stop com.paget96.batteryguru");
     }
 
+    const DUMPSYS_SERVICES: &str = "\
+ACTIVITY MANAGER SERVICES (dumpsys activity services)
+  User 0 active services:
+  * ServiceRecord{6f3a1b2 u0 com.spotify.music/.playback.SpotifyService}
+    intent={act=com.spotify.mobile.android.service.action.START cmp=com.spotify.music/.playback.SpotifyService}
+    packageName=com.spotify.music
+    processName=com.spotify.music
+    isForeground=true foregroundId=20 foregroundNoti=Notification(channel=playback_channel)
+    createTime=-1h2m5s startingBgTimeout=--
+  * ServiceRecord{8c21d40 u0 com.google.android.gms/.chimera.PersistentIntentOperationService}
+    intent={cmp=com.google.android.gms/.chimera.PersistentIntentOperationService}
+    packageName=com.google.android.gms
+    isForeground=false foregroundId=0 foregroundNoti=null
+  * ServiceRecord{19e7f55 u0 com.example.tracker/com.example.location.TrackingService}
+    packageName=com.example.tracker
+    isForeground=true foregroundId=1 foregroundNoti=Notification(channel=tracking)
+";
+
+    #[test]
+    fn test_parse_foreground_services() {
+        let services = parse_foreground_services(DUMPSYS_SERVICES);
+        assert_eq!(
+            services,
+            vec![
+                ForegroundService {
+                    package_name: "com.spotify.music".to_string(),
+                    service_class: "com.spotify.music.playback.SpotifyService".to_string(),
+                    foreground_id: Some(20),
+                },
+                ForegroundService {
+                    package_name: "com.example.tracker".to_string(),
+                    service_class: "com.example.location.TrackingService".to_string(),
+                    foreground_id: Some(1),
+                },
+            ]
+        );
+        assert!(parse_foreground_services("").is_empty());
+    }
+
+    #[test]
+    fn test_check_am_kill_output() {
+        assert!(check_am_kill_output("").is_ok());
+        assert_eq!(
+            check_am_kill_output(
+                "Exception occurred while executing 'kill-all':\n\
+                 java.lang.SecurityException: Permission Denial: killAllBackgroundProcesses()"
+            ),
+            Err(ProcessError::PermissionDenied)
+        );
+        assert!(matches!(
+            check_am_kill_output("Error: Unknown option: --foo"),
+            Err(ProcessError::CommandFailed(_))
+        ));
+    }
+
     #[test]
     fn test_proc_status_parsing() {
         let status_output = "Name:\tcom.example.app\n\
@@ -470,6 +1128,181 @@ mod tests {
         assert_eq!(mem_used, "512000");
     }
 
+    #[test]
+    fn test_parse_process_status() {
+        let status_output = "Name:\tcom.example.app\n\
+                            Pid:\t1234\n\
+                            VmSize:\t1024000 kB\n\
+                            VmRSS:\t512000 kB\n\
+                            RssAnon:\t400000 kB\n\
+                            RssFile:\t100000 kB\n\
+                            RssShmem:\t12000 kB\n\
+                            VmSwap:\t2048 kB";
+
+        assert_eq!(
+            parse_process_status(1234, status_output),
+            ProcessMemory {
+                pid: 1234,
+                rss_kb: 512000,
+                vss_kb: 1024000,
+                swap_kb: 2048,
+                shared_kb: 112000,
+                pss_kb: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_apply_smaps_rollup() {
+        let rollup = "12c00000-7ffe1234f000 ---p 00000000 00:00 0  [rollup]\n\
+                      Rss:              512000 kB\n\
+                      Pss:              301234 kB\n\
+                      Shared_Clean:      90000 kB\n\
+                      Shared_Dirty:       5000 kB\n\
+                      Private_Clean:     17000 kB";
+        let mut memory = ProcessMemory {
+            pid: 1234,
+            shared_kb: 112000,
+            ..Default::default()
+        };
+
+        apply_smaps_rollup(&mut memory, rollup);
+
+        assert_eq!(memory.pss_kb, Some(301234));
+        assert_eq!(memory.shared_kb, 95000);
+
+        let mut untouched = memory.clone();
+        apply_smaps_rollup(&mut untouched, "");
+        assert_eq!(untouched, memory);
+    }
+
+    const MEMINFO_OUTPUT: &str = "Applications Memory Usage (in Kilobytes):
+Uptime: 123456 Realtime: 123456
+
+** MEMINFO in pid 4321 [com.example.app] **
+                   Pss  Private  Private  SwapPss      Rss     Heap     Heap     Heap
+                 Total    Dirty    Clean    Dirty    Total     Size    Alloc     Free
+                ------   ------   ------   ------   ------   ------   ------   ------
+  Native Heap     6789     6700        0       12     7890    12288     9000     3288
+  Dalvik Heap     5000     4900        0        0     6000    10000     8000     2000
+        TOTAL    30000    25000     1000       12    60000    22288    17000     5288
+
+ App Summary
+                       Pss(KB)                        Rss(KB)
+                        ------                         ------
+           Java Heap:    12345                          23456
+         Native Heap:     6789                           7890
+                Code:     4567                          12345
+               Stack:      123                            456
+            Graphics:     2345                           2345
+       Private Other:      789
+              System:     3042
+             Unknown:                                    1234
+
+           TOTAL PSS:    30000            TOTAL RSS:    60000       TOTAL SWAP PSS:       12
+
+** MEMINFO in pid 4400 [com.example.app:sync] **
+ App Summary
+           Java Heap:     1000
+         Native Heap:      500
+                Code:      250
+            Graphics:        0
+           TOTAL PSS:     2000            TOTAL RSS:     4000
+";
+
+    #[test]
+    fn test_parse_app_meminfo() {
+        let info = parse_app_meminfo("com.example.app", MEMINFO_OUTPUT).unwrap();
+
+        assert_eq!(info.processes.len(), 2);
+        assert_eq!(
+            info.processes[0],
+            ProcessMemSummary {
+                pid: 4321,
+                process_name: "com.example.app".to_string(),
+                total_pss_kb: 30000,
+                java_heap_kb: 12345,
+                native_heap_kb: 6789,
+                code_kb: 4567,
+                graphics_kb: 2345,
+            }
+        );
+        assert_eq!(info.processes[1].process_name, "com.example.app:sync");
+        assert_eq!(info.total_pss_kb, 32000);
+        assert_eq!(info.java_heap_kb, 13345);
+        assert_eq!(info.code_kb, 4817);
+    }
+
+    #[test]
+    fn test_parse_app_meminfo_not_running() {
+        assert_eq!(
+            parse_app_meminfo("com.missing", "No process found for: com.missing"),
+            None
+        );
+    }
+
+    const STAT_FIRST: &str = "\
+cpu  1000 0 500 8000 0 0 0 0 0 0
+cpu0 500 0 250 4000 0 0 0 0 0 0
+cpu1 500 0 250 4000 0 0 0 0 0 0
+1 (init) S 0 1 1 0 -1 4194560 5000 0 10 0 100 200 0 0 20 0 1 0 0 0 0
+812 (surfaceflinger) S 1 812 0 0 -1 4194560 900 0 0 0 1000 500 0 0 -2 0 1 0 0 0 0
+4242 (Binder:4242_2 (x)) S 600 600 0 0 -1 4194624 77 0 0 0 10 10 0 0 20 0 1 0 0 0 0
+9001 (sh) S 1 9001 0 0 -1 0 0 0 0 0 5 5 0 0 20 0 1 0 0 0 0
+";
+
+    const STAT_SECOND: &str = "\
+cpu  1100 0 550 8350 0 0 0 0 0 0
+cpu0 550 0 275 4175 0 0 0 0 0 0
+cpu1 550 0 275 4175 0 0 0 0 0 0
+1 (init) S 0 1 1 0 -1 4194560 5000 0 10 0 100 200 0 0 20 0 1 0 0 0 0
+812 (surfaceflinger) S 1 812 0 0 -1 4194560 900 0 0 0 1040 510 0 0 -2 0 1 0 0 0 0
+4242 (Binder:4242_2 (x)) S 600 600 0 0 -1 4194624 77 0 0 0 20 25 0 0 20 0 1 0 0 0 0
+9001 (toybox) S 1 9001 0 0 -1 0 0 0 0 0 1 1 0 0 20 0 1 0 0 0 0
+9100 (logcat) S 1 9100 0 0 -1 0 0 0 0 0 2 2 0 0 20 0 1 0 0 0 0
+";
+
+    const VMRSS: &str = "\
+/proc/1/status:VmRSS:\t    3000 kB
+/proc/812/status:VmRSS:\t   40000 kB
+/proc/4242/status:VmRSS:\t  120000 kB
+";
+
+    #[test]
+    fn test_parse_pid_stat() {
+        assert_eq!(
+            parse_pid_stat(
+                "4242 (Binder:4242_2 (x)) S 600 600 0 0 -1 4194624 77 0 0 0 10 10 0 0 20 0"
+            ),
+            Some((4242, "Binder:4242_2 (x)".to_string(), 20))
+        );
+        assert_eq!(parse_pid_stat("4242 (short) S 600"), None);
+    }
+
+    #[test]
+    fn test_rank_processes_by_cpu() {
+        let top = rank_processes(STAT_FIRST, STAT_SECOND, VMRSS, TopSort::Cpu, 2);
+        assert_eq!(top.len(), 2);
+
+        // 50 of 500 jiffies
+        assert_eq!(top[0].pid, 812);
+        assert_eq!(top[0].name, "surfaceflinger");
+        assert!((top[0].cpu_percent - 10.0).abs() < 0.01);
+        assert_eq!(top[0].rss_kb, 40000);
+
+        assert_eq!(top[1].pid, 4242);
+        assert!((top[1].cpu_percent - 5.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_rank_processes_skips_exited_and_reused_pids() {
+        let top = rank_processes(STAT_FIRST, STAT_SECOND, VMRSS, TopSort::Memory, 10);
+        let pids: Vec<u32> = top.iter().map(|p| p.pid).collect();
+        // 9001 was reused by another process and 9100 started during the sample
+        assert_eq!(pids, vec![4242, 812, 1]);
+        assert_eq!(top[2].cpu_percent, 0.0);
+    }
+
     #[test]
     fn test_process_error_debug() {
         let err = ProcessError::ProcessNotFound(999);
@@ -477,4 +1310,59 @@ mod tests {
         assert!(debug_str.contains("ProcessNotFound"));
         assert!(debug_str.contains("999"));
     }
+
+    #[test]
+    fn test_top_parser_frames() {
+        let header = "Tasks: 510 total,   1 running, 509 sleeping,   0 stopped,   0 zombie
+  Mem:  5701592K total,  5403604K used,   297988K free,    33364K buffers
+ Swap:  4194300K total,  1158144K used,  3036156K free,  2244052K cached
+800%cpu  13%user   0%nice  19%sys 768%idle   0%iow   0%irq   0%sirq   0%host
+  PID USER         PR  NI VIRT  RES  SHR S[%CPU] %MEM     TIME+ ARGS";
+        let rows = " 5315 shell        20   0  10G 4.1M 3.2M R 16.0   0.0   0:00.04 top -b -d 1
+ 1023 system       18  -2  15G 334M 221M S  8.0   6.0  24:12.01 system_server
+   12 root         20   0    0    0    0 S  0.0   0.0   0:01.00 [ksoftirqd/0]";
+
+        let mut parser = TopParser::default();
+        let mut frames = Vec::new();
+        for line in format!("{}\n{}\n{}\n{}", header, rows, header, rows).lines() {
+            frames.extend(parser.push_line(line));
+        }
+        frames.extend(parser.finish());
+
+        assert_eq!(frames.len(), 2);
+        let frame = &frames[0];
+        assert_eq!(frame.len(), 3);
+        assert_eq!(frame[0].pid, 5315);
+        assert_eq!(frame[0].name, "top -b -d 1");
+        // Normalized to the whole device, 8 cores
+        assert_eq!(frame[0].cpu_percent, 2.0);
+        assert_eq!(frame[0].rss_kb, 4198);
+        assert_eq!(frame[1].name, "system_server");
+        assert_eq!(frame[1].rss_kb, 334 * 1024);
+        assert_eq!(frame[2].rss_kb, 0);
+        assert_eq!(frames[1], frames[0]);
+    }
+
+    #[test]
+    fn test_parse_top_columns() {
+        let columns = parse_top_columns(
+            "  PID USER         PR  NI VIRT  RES  SHR S[%CPU] %MEM     TIME+ ARGS",
+        )
+        .unwrap();
+        assert_eq!(
+            (columns.pid, columns.cpu, columns.rss, columns.name),
+            (0, 8, 5, 11)
+        );
+        assert!(parse_top_columns("  PID USER ARGS %CPU RES").is_none());
+        assert!(parse_top_columns("Tasks: 510 total").is_none());
+    }
+
+    #[test]
+    fn test_parse_top_size_kb() {
+        assert_eq!(parse_top_size_kb("5678K"), Some(5678));
+        assert_eq!(parse_top_size_kb("1.5M"), Some(1536));
+        assert_eq!(parse_top_size_kb("1G"), Some(1024 * 1024));
+        assert_eq!(parse_top_size_kb("2048"), Some(2));
+        assert_eq!(parse_top_size_kb("-"), None);
+    }
 }
@@ -0,0 +1,381 @@
+use crate::device::app_manager::{PackageFilter, list_installed_packages};
+use crate::device::collect_device_info;
+use crate::device::info::DeviceError;
+use crate::device::performance::{PerformanceProfile, get_performance_profile};
+use crate::device::root::{RootStatus, has_root_access};
+use crate::format::{format_bytes, format_duration_secs, format_percent};
+use crate::{DeviceInfo, Storage};
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::{Display, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const UNAVAILABLE: &str = "unavailable";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Markdown,
+}
+
+/// Everything worth attaching to a hardware bug report, as saved by [`export_report`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceReport {
+    /// Unix time in seconds
+    pub generated_at: u64,
+    pub info: DeviceInfo,
+    pub performance: PerformanceProfile,
+    /// Third-party packages, sorted
+    pub packages: Result<Vec<String>, String>,
+    pub root: RootStatus,
+}
+
+/// Collects a [`DeviceReport`]. Parts that fail are recorded as such instead of failing
+/// the whole report.
+pub fn collect_report(device: &mut ADBServerDevice) -> DeviceReport {
+    let serial = device.identifier.clone().unwrap_or_default();
+    let packages = list_installed_packages(device, PackageFilter::ThirdParty)
+        .map(|mut packages| {
+            packages.sort();
+            packages
+        })
+        .map_err(|e| e.to_string());
+
+    DeviceReport {
+        generated_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        info: collect_device_info(device, &serial),
+        performance: get_performance_profile(device),
+        packages,
+        root: has_root_access(device),
+    }
+}
+
+/// Collects a report and writes it to `path` on the host. Returns what was written.
+pub fn export_report(
+    device: &mut ADBServerDevice,
+    path: &str,
+    format: ReportFormat,
+) -> Result<DeviceReport, DeviceError> {
+    let report = collect_report(device);
+    let contents = match format {
+        ReportFormat::Json => serde_json::to_string_pretty(&report)
+            .map_err(|e| DeviceError::CommandFailed(e.to_string()))?,
+        ReportFormat::Markdown => render_markdown(&report),
+    };
+
+    std::fs::write(path, contents).map_err(|e| DeviceError::IOError(e.to_string()))?;
+    Ok(report)
+}
+
+/// Renders `report` as a Markdown document meant to be pasted into an issue.
+pub fn render_markdown(report: &DeviceReport) -> String {
+    let info = &report.info;
+    let build = info.build.clone().unwrap_or_default();
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "# Device report: {} ({})\n",
+        info.model.as_deref().unwrap_or(UNAVAILABLE),
+        info.serial
+    );
+    let _ = writeln!(out, "Generated at {} (Unix time)\n", report.generated_at);
+
+    let _ = writeln!(out, "## Device\n");
+    let _ = writeln!(out, "| Field | Value |\n| --- | --- |");
+    let rows = [
+        ("Manufacturer", or_unavailable(info.manufacturer.as_ref())),
+        ("Model", or_unavailable(info.model.as_ref())),
+        (
+            "Android version",
+            or_unavailable(info.android_version.as_ref()),
+        ),
+        ("SDK", or_unavailable(info.sdk_int)),
+        ("ABI", or_unavailable(info.abi.as_ref())),
+        ("Slot", or_unavailable(info.slot.as_ref())),
+        ("Screen", or_unavailable(info.screen_resolution.as_ref())),
+        (
+            "Refresh rate",
+            or_unavailable(info.refresh_rate.map(|hz| format!("{} Hz", hz))),
+        ),
+        (
+            "Storage",
+            or_unavailable(info.storage.as_ref().map(storage_summary)),
+        ),
+        ("Security patch", or_unavailable(build.security_patch)),
+        ("Build ID", or_unavailable(build.build_id)),
+        ("Build type", or_unavailable(build.build_type)),
+        ("Fingerprint", or_unavailable(build.fingerprint)),
+        ("Root", format!("{:?}", report.root)),
+    ];
+    for (field, value) in rows {
+        let _ = writeln!(out, "| {} | {} |", field, value);
+    }
+
+    let perf = &report.performance;
+    let _ = writeln!(out, "\n## Performance\n");
+    let _ = writeln!(out, "| Field | Value |\n| --- | --- |");
+    let rows = [
+        (
+            "Memory",
+            or_error(perf.memory.as_ref().map(|m| {
+                format!(
+                    "{} available of {}",
+                    format_bytes(m.available_kb * 1024),
+                    format_bytes(m.total_kb * 1024)
+                )
+            })),
+        ),
+        (
+            "Battery",
+            or_error(perf.battery.as_ref().map(|b| {
+                format!(
+                    "{}%, {:?}, {:?}, {:.1} °C, {} mV",
+                    b.percentage, b.status, b.health, b.temperature, b.voltage
+                )
+            })),
+        ),
+        (
+            "CPU cores",
+            or_error(perf.cpu.as_ref().map(|cpus| cpus.len())),
+        ),
+        (
+            "Uptime",
+            or_error(perf.uptime.as_ref().map(|s| format_duration_secs(*s))),
+        ),
+    ];
+    for (field, value) in rows {
+        let _ = writeln!(out, "| {} | {} |", field, value);
+    }
+
+    match &report.packages {
+        Ok(packages) => {
+            let _ = writeln!(out, "\n## Installed apps ({})\n", packages.len());
+            for package in packages {
+                let _ = writeln!(out, "- {}", package);
+            }
+        }
+        Err(e) => {
+            let _ = writeln!(out, "\n## Installed apps\n\n{} ({})", UNAVAILABLE, e);
+        }
+    }
+
+    out
+}
+
+/// Reads a report saved by [`export_report`] in [`ReportFormat::Json`].
+pub fn load_report(path: &str) -> Result<DeviceReport, DeviceError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| DeviceError::IOError(e.to_string()))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| DeviceError::CommandFailed(format!("{} is not a device report: {}", path, e)))
+}
+
+/// One difference between two reports. Added packages only have `after`, removed ones
+/// only `before`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// What changed from report `a` to report `b`, e.g. before and after an OTA. Packages are
+/// compared when both reports have the list.
+pub fn diff_reports(a: &DeviceReport, b: &DeviceReport) -> Vec<FieldDiff> {
+    let build = |report: &DeviceReport| report.info.build.clone().unwrap_or_default();
+    let (build_a, build_b) = (build(a), build(b));
+
+    let fields = [
+        (
+            "android_version",
+            a.info.android_version.clone(),
+            b.info.android_version.clone(),
+        ),
+        (
+            "sdk_int",
+            a.info.sdk_int.map(|v| v.to_string()),
+            b.info.sdk_int.map(|v| v.to_string()),
+        ),
+        (
+            "security_patch",
+            build_a.security_patch,
+            build_b.security_patch,
+        ),
+        ("build_id", build_a.build_id, build_b.build_id),
+        ("incremental", build_a.incremental, build_b.incremental),
+        ("fingerprint", build_a.fingerprint, build_b.fingerprint),
+        ("build_type", build_a.build_type, build_b.build_type),
+        (
+            "root",
+            Some(format!("{:?}", a.root)),
+            Some(format!("{:?}", b.root)),
+        ),
+    ];
+
+    let mut diffs: Vec<FieldDiff> = fields
+        .into_iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|(field, before, after)| FieldDiff {
+            field: field.to_string(),
+            before,
+            after,
+        })
+        .collect();
+
+    if let (Ok(before), Ok(after)) = (&a.packages, &b.packages) {
+        let before: BTreeSet<&String> = before.iter().collect();
+        let after: BTreeSet<&String> = after.iter().collect();
+
+        diffs.extend(after.difference(&before).map(|package| FieldDiff {
+            field: "package".to_string(),
+            before: None,
+            after: Some(package.to_string()),
+        }));
+        diffs.extend(before.difference(&after).map(|package| FieldDiff {
+            field: "package".to_string(),
+            before: Some(package.to_string()),
+            after: None,
+        }));
+    }
+
+    diffs
+}
+
+/// `df` reports 1K blocks, anything else (like `-h` output) is shown as is.
+fn storage_summary(storage: &Storage) -> String {
+    let kb = |value: &str| value.parse::<u64>().ok();
+    match (kb(&storage.used), kb(&storage.free), kb(&storage.total)) {
+        (Some(used), Some(free), Some(total)) if total > 0 => format!(
+            "{} used, {} free of {} ({} full)",
+            format_bytes(used * 1024),
+            format_bytes(free * 1024),
+            format_bytes(total * 1024),
+            format_percent(used as f64 / total as f64 * 100.0)
+        ),
+        _ => format!(
+            "{} used, {} free of {}",
+            storage.used, storage.free, storage.total
+        ),
+    }
+}
+
+fn or_unavailable<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(|| UNAVAILABLE.to_string(), |v| v.to_string())
+}
+
+fn or_error<T: Display>(value: Result<T, &String>) -> String {
+    value.map_or_else(|e| format!("{} ({})", UNAVAILABLE, e), |v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::info::BuildInfo;
+    use crate::device::performance::MemoryInfo;
+
+    fn report(patch: &str, packages: &[&str]) -> DeviceReport {
+        DeviceReport {
+            generated_at: 0,
+            info: DeviceInfo {
+                serial: "emulator-5554".to_string(),
+                android_version: Some("14".to_string()),
+                build: Some(BuildInfo {
+                    security_patch: Some(patch.to_string()),
+                    fingerprint: Some(format!("google/husky/husky:14/{}:user/release-keys", patch)),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            performance: PerformanceProfile {
+                memory: Err(String::new()),
+                battery: Err(String::new()),
+                cpu: Err(String::new()),
+                fps: Err(String::new()),
+                uptime: Err(String::new()),
+            },
+            packages: Ok(packages.iter().map(|p| p.to_string()).collect()),
+            root: RootStatus::NotRooted,
+        }
+    }
+
+    #[test]
+    fn test_diff_reports() {
+        let before = report("2024-01-05", &["com.example.a", "com.example.b"]);
+        let after = report("2024-02-05", &["com.example.b", "com.example.c"]);
+
+        let diffs = diff_reports(&before, &after);
+
+        let diff = |field: &str, before: Option<&str>, after: Option<&str>| FieldDiff {
+            field: field.to_string(),
+            before: before.map(str::to_string),
+            after: after.map(str::to_string),
+        };
+        assert_eq!(
+            diffs,
+            vec![
+                diff("security_patch", Some("2024-01-05"), Some("2024-02-05")),
+                diff(
+                    "fingerprint",
+                    Some("google/husky/husky:14/2024-01-05:user/release-keys"),
+                    Some("google/husky/husky:14/2024-02-05:user/release-keys"),
+                ),
+                diff("package", None, Some("com.example.c")),
+                diff("package", Some("com.example.a"), None),
+            ]
+        );
+        assert!(diff_reports(&before, &before).is_empty());
+
+        let mut unlisted = after.clone();
+        unlisted.packages = Err("pm failed".to_string());
+        assert_eq!(diff_reports(&before, &unlisted).len(), 2);
+    }
+
+    #[test]
+    fn test_render_markdown_marks_failures_unavailable() {
+        let report = DeviceReport {
+            generated_at: 1_700_000_000,
+            info: DeviceInfo {
+                serial: "emulator-5554".to_string(),
+                model: Some("Pixel 8".to_string()),
+                android_version: Some("14".to_string()),
+                storage: Some(Storage {
+                    total: "117153040".to_string(),
+                    used: "29288260".to_string(),
+                    free: "87864780".to_string(),
+                }),
+                ..Default::default()
+            },
+            performance: PerformanceProfile {
+                memory: Ok(MemoryInfo {
+                    total_kb: 8 * 1024 * 1024,
+                    free_kb: 1024 * 1024,
+                    available_kb: 3 * 1024 * 1024,
+                }),
+                battery: Err("Command failed: dumpsys battery".to_string()),
+                cpu: Ok(Vec::new()),
+                fps: Err("no flips".to_string()),
+                uptime: Ok(90_061),
+            },
+            packages: Ok(vec!["com.example.app".to_string()]),
+            root: RootStatus::NotRooted,
+        };
+
+        let markdown = render_markdown(&report);
+
+        assert!(markdown.starts_with("# Device report: Pixel 8 (emulator-5554)"));
+        assert!(markdown.contains("| Android version | 14 |"));
+        assert!(markdown.contains("| Manufacturer | unavailable |"));
+        assert!(
+            markdown.contains("| Storage | 27.9 GB used, 83.8 GB free of 111.7 GB (25.0% full) |")
+        );
+        assert!(markdown.contains("| Memory | 3.0 GB available of 8.0 GB |"));
+        assert!(markdown.contains("| Battery | unavailable (Command failed: dumpsys battery) |"));
+        assert!(markdown.contains("| Uptime | 1d 1h |"));
+        assert!(markdown.contains("## Installed apps (1)\n\n- com.example.app\n"));
+    }
+}
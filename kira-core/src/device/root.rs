@@ -1,5 +1,10 @@
-use adb_client::ADBDeviceExt;
+use crate::device::app_manager::{PER_USER_RANGE, is_su_unavailable, parse_package_uids};
+use crate::device::shell::{shell_output, shell_quote};
 use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const MAGISK_POLICIES_QUERY: &str = "magisk --sqlite 'SELECT * FROM policies'";
 
 pub fn is_rooted(device: &mut ADBServerDevice) -> bool {
     let output = run_shell_command(device, "id");
@@ -20,25 +25,39 @@ pub fn check_su_binary(device: &mut ADBServerDevice) -> bool {
 
     for path in su_paths {
         let output = run_shell_command(device, &format!("ls -l {}", path));
-        if let Some(result) = output {
-            if !result.contains("No such file") && !result.contains("not found") {
-                return true;
-            }
+        if let Some(result) = output
+            && !result.contains("No such file")
+            && !result.contains("not found")
+        {
+            return true;
         }
     }
     false
 }
 
-pub fn get_root_apps(device: &mut ADBServerDevice) -> Vec<String> {
-    let output = run_shell_command(device, "pm list packages -3");
-    match output {
-        Some(result) => result
-            .lines()
-            .filter_map(|line| line.strip_prefix("package:"))
-            .map(|s| s.to_string())
-            .collect(),
-        None => Vec::new(),
+/// Apps with a root grant in Magisk's superuser database, allowed or denied. Needs root
+/// to read the database, other root managers aren't supported.
+pub fn get_root_apps(device: &mut ADBServerDevice) -> Result<Vec<RootGrant>, RootError> {
+    let output = run_shell_command(
+        device,
+        &format!("su -c {}", shell_quote(MAGISK_POLICIES_QUERY)),
+    )
+    .ok_or_else(|| RootError::CommandFailed("magisk --sqlite".to_string()))?;
+
+    if is_su_unavailable(&output) {
+        return Err(RootError::NotRooted);
+    }
+    if output.contains("magisk: not found") || output.contains("magisk: inaccessible") {
+        return Err(RootError::Unsupported(
+            "root isn't provided by Magisk".to_string(),
+        ));
     }
+    if output.contains("Error:") || output.contains("no such table") {
+        return Err(RootError::CommandFailed(output));
+    }
+
+    let packages = run_shell_command(device, "pm list packages -U").unwrap_or_default();
+    Ok(parse_policies(&output, &parse_package_uids(&packages)))
 }
 
 pub fn has_root_access(device: &mut ADBServerDevice) -> RootStatus {
@@ -51,21 +70,83 @@ pub fn has_root_access(device: &mut ADBServerDevice) -> RootStatus {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RootStatus {
     Rooted,
     SuBinaryExists,
     NotRooted,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SuPolicy {
+    /// Ask on every request
+    Query,
+    Deny,
+    Allow,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootGrant {
+    pub uid: u32,
+    /// `None` when no installed package has the uid, e.g. the app was removed
+    pub package: Option<String>,
+    pub policy: SuPolicy,
+    /// Unix time the grant expires, 0 for never
+    pub until: u64,
+}
+
 fn run_shell_command(device: &mut ADBServerDevice, command: &str) -> Option<String> {
-    let mut output = Vec::new();
-    match device.shell_command(&command, Some(&mut output), None) {
-        Ok(_) => String::from_utf8(output).ok()?.trim().to_string().into(),
-        Err(_) => None,
+    let output = shell_output(device, command).ok()?;
+    Some(String::from_utf8(output).ok()?.trim().to_string())
+}
+
+// `uid=10234|policy=2|until=0|logging=1|notification=1`
+fn parse_policies(output: &str, packages: &HashMap<u32, String>) -> Vec<RootGrant> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let field = |name: &str| {
+                line.trim()
+                    .split('|')
+                    .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+            };
+            let uid: u32 = field("uid")?.parse().ok()?;
+            let policy = match field("policy")? {
+                "0" => SuPolicy::Query,
+                "1" => SuPolicy::Deny,
+                "2" => SuPolicy::Allow,
+                _ => return None,
+            };
+
+            Some(RootGrant {
+                uid,
+                package: packages.get(&(uid % PER_USER_RANGE)).cloned(),
+                policy,
+                until: field("until").and_then(|t| t.parse().ok()).unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RootError {
+    NotRooted,
+    Unsupported(String),
+    CommandFailed(String),
+}
+
+impl std::fmt::Display for RootError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RootError::NotRooted => write!(f, "Root access is not available"),
+            RootError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
+            RootError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+        }
     }
 }
 
+impl std::error::Error for RootError {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,6 +243,30 @@ mod tests {
         assert!(packages.contains(&"org.root.check".to_string()));
     }
 
+    #[test]
+    fn test_parse_policies() {
+        let packages = parse_package_uids(
+            "package:com.topjohnwu.magisk uid:10180\n\
+             package:com.termux uid:10234,1010234\n\
+             package:android uid:1000",
+        );
+        let output = "uid=10234|policy=2|until=0|logging=1|notification=1\n\
+                      uid=1010180|policy=1|until=1700000000|logging=1|notification=1\n\
+                      uid=10999|policy=0|until=0|logging=1|notification=1";
+
+        let grants = parse_policies(output, &packages);
+
+        assert_eq!(grants.len(), 3);
+        assert_eq!(grants[0].package.as_deref(), Some("com.termux"));
+        assert_eq!(grants[0].policy, SuPolicy::Allow);
+        assert_eq!(grants[1].uid, 1010180);
+        assert_eq!(grants[1].package.as_deref(), Some("com.topjohnwu.magisk"));
+        assert_eq!(grants[1].policy, SuPolicy::Deny);
+        assert_eq!(grants[1].until, 1700000000);
+        assert_eq!(grants[2].package, None);
+        assert_eq!(grants[2].policy, SuPolicy::Query);
+    }
+
     #[test]
     fn test_run_shell_command_none_case() {
         let result: Option<String> = None;
@@ -0,0 +1,257 @@
+use crate::device::shell::{shell_output, shell_quote};
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const SCREENSHOT_REMOTE_PATH: &str = "/data/local/tmp/kira_screencap.png";
+
+/// Captures the current screen as PNG bytes.
+///
+/// The image is written to a temp file on the device and pulled over the sync protocol,
+/// so it never goes through a shell stream that could translate `\n` into `\r\n`.
+pub fn capture_screenshot(device: &mut ADBServerDevice) -> Result<Vec<u8>, CaptureError> {
+    run_shell_command(device, &format!("screencap -p {}", SCREENSHOT_REMOTE_PATH))?;
+
+    let mut png = Vec::new();
+    let pulled = device
+        .pull(&SCREENSHOT_REMOTE_PATH, &mut png)
+        .map_err(|e| CaptureError::IOError(e.to_string()));
+    let _ = run_shell_command(device, &format!("rm -f {}", SCREENSHOT_REMOTE_PATH));
+    pulled?;
+
+    if !is_png(&png) {
+        return Err(CaptureError::InvalidImage(format!(
+            "screencap returned {} bytes without a PNG signature",
+            png.len()
+        )));
+    }
+
+    Ok(png)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ScreenRecordOptions {
+    pub bit_rate: Option<u32>,
+    pub size: Option<(u32, u32)>,
+    pub time_limit_secs: Option<u32>,
+}
+
+impl ScreenRecordOptions {
+    fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(bit_rate) = self.bit_rate {
+            args.push(format!("--bit-rate {}", bit_rate));
+        }
+        if let Some((width, height)) = self.size {
+            args.push(format!("--size {}x{}", width, height));
+        }
+        if let Some(limit) = self.time_limit_secs {
+            args.push(format!("--time-limit {}", limit));
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScreenRecording {
+    pub pid: u32,
+    pub remote_path: String,
+    pub started_at_ms: u64,
+    pub time_limit_secs: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RecordingResult {
+    pub host_path: String,
+    pub duration_ms: u64,
+    pub file_size: u64,
+}
+
+/// Starts `screenrecord` in the background on the device and returns a handle for
+/// `stop_screenrecord`.
+pub fn start_screenrecord(
+    device: &mut ADBServerDevice,
+    remote_path: &str,
+    opts: ScreenRecordOptions,
+) -> Result<ScreenRecording, CaptureError> {
+    let mut command = vec!["screenrecord".to_string()];
+    command.extend(opts.to_args());
+    command.push(shell_quote(remote_path));
+
+    let output = run_shell_command(
+        device,
+        &format!("nohup {} > /dev/null 2>&1 & echo $!", command.join(" ")),
+    )?;
+
+    let pid = output
+        .lines()
+        .last()
+        .and_then(|l| l.trim().parse::<u32>().ok())
+        .ok_or_else(|| {
+            CaptureError::CommandFailed(format!("screenrecord did not start: {}", output))
+        })?;
+
+    Ok(ScreenRecording {
+        pid,
+        remote_path: remote_path.to_string(),
+        started_at_ms: now_ms(),
+        time_limit_secs: opts.time_limit_secs,
+    })
+}
+
+/// Interrupts a running recording, waits for the mp4 to be finalized and pulls it to
+/// `host_path`. The remote file is removed afterwards.
+pub fn stop_screenrecord(
+    device: &mut ADBServerDevice,
+    recording: &ScreenRecording,
+    host_path: &str,
+) -> Result<RecordingResult, CaptureError> {
+    let stopped_at_ms = now_ms();
+
+    // SIGINT lets screenrecord write the moov atom, a plain kill leaves an unplayable file
+    let _ = run_shell_command(device, &format!("kill -2 {}", recording.pid));
+
+    for _ in 0..50 {
+        let alive = run_shell_command(
+            device,
+            &format!("kill -0 {} 2>/dev/null && echo 1 || echo 0", recording.pid),
+        )?;
+        if alive.trim() != "1" {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let mut file =
+        std::fs::File::create(host_path).map_err(|e| CaptureError::IOError(e.to_string()))?;
+    device
+        .pull(&recording.remote_path, &mut file)
+        .map_err(|e| CaptureError::IOError(e.to_string()))?;
+    let _ = run_shell_command(
+        device,
+        &format!("rm -f {}", shell_quote(&recording.remote_path)),
+    );
+
+    let file_size = file
+        .metadata()
+        .map_err(|e| CaptureError::IOError(e.to_string()))?
+        .len();
+
+    Ok(RecordingResult {
+        host_path: host_path.to_string(),
+        duration_ms: recording_duration_ms(recording, stopped_at_ms),
+        file_size,
+    })
+}
+
+fn recording_duration_ms(recording: &ScreenRecording, stopped_at_ms: u64) -> u64 {
+    let elapsed = stopped_at_ms.saturating_sub(recording.started_at_ms);
+    match recording.time_limit_secs {
+        Some(limit) => elapsed.min(limit as u64 * 1000),
+        None => elapsed,
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn is_png(data: &[u8]) -> bool {
+    data.starts_with(&PNG_SIGNATURE)
+}
+
+fn run_shell_command(device: &mut ADBServerDevice, command: &str) -> Result<String, CaptureError> {
+    let output =
+        shell_output(device, command).map_err(|e| CaptureError::CommandFailed(e.to_string()))?;
+
+    String::from_utf8(output)
+        .map_err(|e| CaptureError::IOError(e.to_string()))
+        .map(|s| s.trim().to_string())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum CaptureError {
+    CommandFailed(String),
+    IOError(String),
+    InvalidImage(String),
+}
+
+impl std::fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+            CaptureError::IOError(msg) => write!(f, "IO Error: {}", msg),
+            CaptureError::InvalidImage(msg) => write!(f, "Invalid image: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_png() {
+        let mut data = PNG_SIGNATURE.to_vec();
+        data.extend_from_slice(b"IHDR");
+        assert!(is_png(&data));
+    }
+
+    #[test]
+    fn test_is_png_crlf_corrupted() {
+        // What the signature looks like after a shell stream turned \n into \r\n
+        let corrupted = [
+            0x89, b'P', b'N', b'G', b'\r', b'\r', b'\n', 0x1a, b'\r', b'\n',
+        ];
+        assert!(!is_png(&corrupted));
+        assert!(!is_png(&[]));
+    }
+
+    #[test]
+    fn test_screenrecord_options_to_args() {
+        let opts = ScreenRecordOptions {
+            bit_rate: Some(8_000_000),
+            size: Some((1280, 720)),
+            time_limit_secs: Some(30),
+        };
+        assert_eq!(
+            opts.to_args(),
+            vec!["--bit-rate 8000000", "--size 1280x720", "--time-limit 30"]
+        );
+        assert!(ScreenRecordOptions::default().to_args().is_empty());
+    }
+
+    #[test]
+    fn test_recording_duration_capped_by_time_limit() {
+        let recording = ScreenRecording {
+            pid: 1234,
+            remote_path: "/sdcard/rec.mp4".to_string(),
+            started_at_ms: 1_000,
+            time_limit_secs: Some(10),
+        };
+        assert_eq!(recording_duration_ms(&recording, 6_000), 5_000);
+        assert_eq!(recording_duration_ms(&recording, 60_000), 10_000);
+
+        let unlimited = ScreenRecording {
+            time_limit_secs: None,
+            ..recording
+        };
+        assert_eq!(recording_duration_ms(&unlimited, 60_000), 59_000);
+    }
+
+    #[test]
+    fn test_capture_error_display() {
+        let err = CaptureError::InvalidImage("empty".to_string());
+        assert!(format!("{}", err).contains("Invalid image"));
+
+        let err2 = CaptureError::CommandFailed("screencap".to_string());
+        assert!(format!("{}", err2).contains("Command failed"));
+    }
+}
@@ -0,0 +1,468 @@
+use crate::device::shell::{shell_output, shell_quote};
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Tables of the Android settings provider, as named by the `settings` command.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SettingsNamespace {
+    System,
+    Secure,
+    Global,
+}
+
+impl SettingsNamespace {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettingsNamespace::System => "system",
+            SettingsNamespace::Secure => "secure",
+            SettingsNamespace::Global => "global",
+        }
+    }
+}
+
+/// Value of `key`, or `None` when it isn't set. `settings get` prints `null` for those.
+pub fn get_setting(
+    device: &mut ADBServerDevice,
+    namespace: SettingsNamespace,
+    key: &str,
+) -> Result<Option<String>, SettingsError> {
+    validate_key(key)?;
+    let output = run_settings_command(
+        device,
+        &format!("settings get {} {}", namespace.as_str(), shell_quote(key)),
+    )?;
+    Ok(setting_value(&output))
+}
+
+pub fn put_setting(
+    device: &mut ADBServerDevice,
+    namespace: SettingsNamespace,
+    key: &str,
+    value: &str,
+) -> Result<(), SettingsError> {
+    validate_key(key)?;
+    run_settings_command(
+        device,
+        &format!(
+            "settings put {} {} {}",
+            namespace.as_str(),
+            shell_quote(key),
+            shell_quote(value)
+        ),
+    )?;
+    Ok(())
+}
+
+/// Removes `key`, returning whether it was set.
+pub fn delete_setting(
+    device: &mut ADBServerDevice,
+    namespace: SettingsNamespace,
+    key: &str,
+) -> Result<bool, SettingsError> {
+    validate_key(key)?;
+    let output = run_settings_command(
+        device,
+        &format!(
+            "settings delete {} {}",
+            namespace.as_str(),
+            shell_quote(key)
+        ),
+    )?;
+    // "Deleted 1 rows", older releases print nothing
+    Ok(!output.starts_with("Deleted 0"))
+}
+
+pub fn list_settings(
+    device: &mut ADBServerDevice,
+    namespace: SettingsNamespace,
+) -> Result<HashMap<String, String>, SettingsError> {
+    let output = run_settings_command(device, &format!("settings list {}", namespace.as_str()))?;
+    Ok(parse_settings_list(&output))
+}
+
+fn setting_value(output: &str) -> Option<String> {
+    match output.trim() {
+        "null" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+/// Parses `key=value` lines. Keys can't contain `=` but values can, and unset values
+/// show up as `null` and are left out.
+fn parse_settings_list(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .filter_map(|(key, value)| {
+            let key = key.trim();
+            (!key.is_empty())
+                .then(|| setting_value(value).map(|value| (key.to_string(), value)))
+                .flatten()
+        })
+        .collect()
+}
+
+/// Shows a dot wherever the screen is touched, handy for recordings and demos.
+pub fn set_show_taps(device: &mut ADBServerDevice, enabled: bool) -> Result<(), SettingsError> {
+    put_setting(
+        device,
+        SettingsNamespace::System,
+        "show_touches",
+        bool_value(enabled),
+    )
+}
+
+/// Overlays the pointer location bar and touch trails.
+pub fn set_pointer_location(
+    device: &mut ADBServerDevice,
+    enabled: bool,
+) -> Result<(), SettingsError> {
+    put_setting(
+        device,
+        SettingsNamespace::System,
+        "pointer_location",
+        bool_value(enabled),
+    )
+}
+
+/// Turns airplane mode on or off and returns the state read back from the setting.
+pub fn set_airplane_mode(
+    device: &mut ADBServerDevice,
+    enabled: bool,
+) -> Result<bool, SettingsError> {
+    put_setting(
+        device,
+        SettingsNamespace::Global,
+        "airplane_mode_on",
+        bool_value(enabled),
+    )?;
+
+    // The radios only react to the broadcast, which newer releases reserve for the
+    // system. `cmd connectivity` (Android 11 and later) is allowed from the shell.
+    let broadcast = format!(
+        "am broadcast -a android.intent.action.AIRPLANE_MODE --ez state {}",
+        enabled
+    );
+    let broadcast_sent = matches!(
+        run_settings_command(device, &broadcast),
+        Ok(output) if !output.contains("Permission Denial")
+    );
+    if !broadcast_sent {
+        run_settings_command(
+            device,
+            &format!("cmd connectivity airplane-mode {}", svc_action(enabled)),
+        )?;
+    }
+
+    wait_for_bool_setting(device, "airplane_mode_on", enabled)
+}
+
+/// Toggles Wi-Fi with `svc`, which needs no root. Returns whether Wi-Fi is on afterwards.
+pub fn set_wifi(device: &mut ADBServerDevice, enabled: bool) -> Result<bool, SettingsError> {
+    run_settings_command(device, &format!("svc wifi {}", svc_action(enabled)))?;
+    wait_for_bool_setting(device, "wifi_on", enabled)
+}
+
+/// Toggles mobile data with `svc`. Returns whether data is on afterwards.
+pub fn set_mobile_data(device: &mut ADBServerDevice, enabled: bool) -> Result<bool, SettingsError> {
+    run_settings_command(device, &format!("svc data {}", svc_action(enabled)))?;
+    wait_for_bool_setting(device, "mobile_data", enabled)
+}
+
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(3);
+const SETTLE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Rereads the global `key` until it matches `expected` or [`SETTLE_TIMEOUT`] passes,
+/// since the radios update their setting once they have actually switched.
+fn wait_for_bool_setting(
+    device: &mut ADBServerDevice,
+    key: &str,
+    expected: bool,
+) -> Result<bool, SettingsError> {
+    let deadline = Instant::now() + SETTLE_TIMEOUT;
+    loop {
+        let value = get_setting(device, SettingsNamespace::Global, key)?;
+        let enabled = setting_enabled(value.as_deref());
+        if enabled == expected || Instant::now() >= deadline {
+            return Ok(enabled);
+        }
+        thread::sleep(SETTLE_POLL_INTERVAL);
+    }
+}
+
+// `wifi_on` is 2 while Wi-Fi stays on in airplane mode
+fn setting_enabled(value: Option<&str>) -> bool {
+    value.is_some_and(|v| v != "0")
+}
+
+fn svc_action(enabled: bool) -> &'static str {
+    if enabled { "enable" } else { "disable" }
+}
+
+const ANIMATION_SCALE_KEYS: [&str; 3] = [
+    "window_animation_scale",
+    "transition_animation_scale",
+    "animator_duration_scale",
+];
+
+/// Sets the window, transition and animator scales to `scale` together, `0` turns
+/// animations off. Developer options only offer values up to 10.
+pub fn set_animation_scale(device: &mut ADBServerDevice, scale: f32) -> Result<(), SettingsError> {
+    let value = animation_scale_value(scale)?;
+    for key in ANIMATION_SCALE_KEYS {
+        put_setting(device, SettingsNamespace::Global, key, &value)?;
+    }
+    Ok(())
+}
+
+/// For UI test runs, same as `set_animation_scale(device, 0.0)`.
+pub fn disable_animations(device: &mut ADBServerDevice) -> Result<(), SettingsError> {
+    set_animation_scale(device, 0.0)
+}
+
+pub fn enable_animations(device: &mut ADBServerDevice) -> Result<(), SettingsError> {
+    set_animation_scale(device, 1.0)
+}
+
+fn animation_scale_value(scale: f32) -> Result<String, SettingsError> {
+    if !(0.0..=10.0).contains(&scale) {
+        return Err(SettingsError::InvalidValue(format!(
+            "animation scale must be between 0 and 10, got {}",
+            scale
+        )));
+    }
+    Ok(scale.to_string())
+}
+
+/// Screen rotation, clockwise from the natural orientation, which is portrait on phones.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+    ReversePortrait,
+    ReverseLandscape,
+}
+
+impl Orientation {
+    /// `Surface.ROTATION_*`, the value of `user_rotation`
+    pub fn rotation(&self) -> u8 {
+        match self {
+            Orientation::Portrait => 0,
+            Orientation::Landscape => 1,
+            Orientation::ReversePortrait => 2,
+            Orientation::ReverseLandscape => 3,
+        }
+    }
+
+    pub fn from_rotation(rotation: u8) -> Option<Self> {
+        match rotation {
+            0 => Some(Orientation::Portrait),
+            1 => Some(Orientation::Landscape),
+            2 => Some(Orientation::ReversePortrait),
+            3 => Some(Orientation::ReverseLandscape),
+            _ => None,
+        }
+    }
+}
+
+/// The rotation the screen is drawn at right now, whether forced or from auto-rotate.
+pub fn get_orientation(device: &mut ADBServerDevice) -> Result<Orientation, SettingsError> {
+    // Not through `run_settings_command`, the dump may quote exceptions from apps
+    let output = shell_output(device, "dumpsys input")
+        .map_err(|e| SettingsError::CommandFailed(e.to_string()))?;
+    parse_input_orientation(&String::from_utf8_lossy(&output)).ok_or_else(|| {
+        SettingsError::ParseError("no display orientation in dumpsys input".to_string())
+    })
+}
+
+/// Turns auto-rotate off and locks the screen to `orientation`. Apps that request a fixed
+/// orientation still get theirs.
+pub fn set_orientation(
+    device: &mut ADBServerDevice,
+    orientation: Orientation,
+) -> Result<(), SettingsError> {
+    put_setting(
+        device,
+        SettingsNamespace::System,
+        "accelerometer_rotation",
+        bool_value(false),
+    )?;
+    put_setting(
+        device,
+        SettingsNamespace::System,
+        "user_rotation",
+        &orientation.rotation().to_string(),
+    )
+}
+
+/// Turns auto-rotate back on.
+pub fn reset_orientation(device: &mut ADBServerDevice) -> Result<(), SettingsError> {
+    put_setting(
+        device,
+        SettingsNamespace::System,
+        "accelerometer_rotation",
+        bool_value(true),
+    )
+}
+
+// `SurfaceOrientation: 1` up to Android 9, the internal viewport's `orientation=1` after
+fn parse_input_orientation(output: &str) -> Option<Orientation> {
+    let rotation = output.lines().map(str::trim).find_map(|line| {
+        if let Some(value) = line.strip_prefix("SurfaceOrientation:") {
+            return value.trim().parse().ok();
+        }
+        if line.starts_with("Viewport INTERNAL") {
+            return line
+                .split([',', ' '])
+                .find_map(|field| field.strip_prefix("orientation="))
+                .and_then(|value| value.parse().ok());
+        }
+        None
+    })?;
+    Orientation::from_rotation(rotation)
+}
+
+fn bool_value(enabled: bool) -> &'static str {
+    if enabled { "1" } else { "0" }
+}
+
+fn validate_key(key: &str) -> Result<(), SettingsError> {
+    if key.is_empty() || key.chars().any(char::is_whitespace) {
+        return Err(SettingsError::InvalidKey(key.to_string()));
+    }
+    Ok(())
+}
+
+fn run_settings_command(
+    device: &mut ADBServerDevice,
+    command: &str,
+) -> Result<String, SettingsError> {
+    let output =
+        shell_output(device, command).map_err(|e| SettingsError::CommandFailed(e.to_string()))?;
+
+    let output = String::from_utf8_lossy(&output).trim().to_string();
+    check_settings_output(&output)?;
+    Ok(output)
+}
+
+/// `settings` reports failures on stdout with a zero exit code.
+fn check_settings_output(output: &str) -> Result<(), SettingsError> {
+    if output.contains("SecurityException") || output.contains("Permission denial") {
+        return Err(SettingsError::PermissionDenied(
+            output.lines().next().unwrap_or_default().to_string(),
+        ));
+    }
+    if output.contains("Exception") || output.starts_with("Invalid") {
+        return Err(SettingsError::CommandFailed(output.to_string()));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum SettingsError {
+    InvalidKey(String),
+    InvalidValue(String),
+    PermissionDenied(String),
+    CommandFailed(String),
+    ParseError(String),
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::InvalidKey(key) => write!(f, "Invalid setting key: '{}'", key),
+            SettingsError::InvalidValue(msg) => write!(f, "Invalid setting value: {}", msg),
+            SettingsError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+            SettingsError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+            SettingsError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setting_value() {
+        assert_eq!(setting_value("1\n"), Some("1".to_string()));
+        assert_eq!(setting_value("null"), None);
+        assert_eq!(setting_value(""), Some(String::new()));
+    }
+
+    #[test]
+    fn test_parse_settings_list() {
+        let output =
+            "adb_enabled=1\nhttp_proxy=null\nwifi_watchdog_on=\nurl=https://a.b/?x=1\nbogus line";
+        let settings = parse_settings_list(output);
+        assert_eq!(settings.len(), 3);
+        assert_eq!(settings["adb_enabled"], "1");
+        assert_eq!(settings["wifi_watchdog_on"], "");
+        assert_eq!(settings["url"], "https://a.b/?x=1");
+        assert!(!settings.contains_key("http_proxy"));
+    }
+
+    #[test]
+    fn test_setting_enabled() {
+        assert!(setting_enabled(Some("1")));
+        assert!(setting_enabled(Some("2")));
+        assert!(!setting_enabled(Some("0")));
+        assert!(!setting_enabled(None));
+    }
+
+    #[test]
+    fn test_parse_input_orientation() {
+        let output = "INPUT MANAGER (dumpsys input)\n\
+                      Input Reader State:\n\
+                      Viewport INTERNAL: displayId=0, uniqueId=local:4619827259835644672, \
+                      port=0, orientation=3, logicalFrame=[0, 0, 2400, 1080], isActive=true\n";
+        assert_eq!(
+            parse_input_orientation(output),
+            Some(Orientation::ReverseLandscape)
+        );
+        assert_eq!(
+            parse_input_orientation("    SurfaceOrientation: 1\n"),
+            Some(Orientation::Landscape)
+        );
+        assert_eq!(parse_input_orientation("SurfaceOrientation: 7"), None);
+        assert_eq!(parse_input_orientation("nothing"), None);
+    }
+
+    #[test]
+    fn test_animation_scale_value() {
+        assert_eq!(animation_scale_value(0.0), Ok("0".to_string()));
+        assert_eq!(animation_scale_value(0.5), Ok("0.5".to_string()));
+        assert_eq!(animation_scale_value(10.0), Ok("10".to_string()));
+        assert!(animation_scale_value(10.5).is_err());
+        assert!(animation_scale_value(-1.0).is_err());
+        assert!(animation_scale_value(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("show_touches").is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key("show touches").is_err());
+    }
+
+    #[test]
+    fn test_check_settings_output() {
+        assert_eq!(check_settings_output(""), Ok(()));
+        assert_eq!(check_settings_output("1"), Ok(()));
+        assert!(matches!(
+            check_settings_output(
+                "Security exception: Permission denial: writing to settings requires:android.permission.WRITE_SECURE_SETTINGS\njava.lang.SecurityException: Permission denial"
+            ),
+            Err(SettingsError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            check_settings_output("Invalid namespace 'foo'"),
+            Err(SettingsError::CommandFailed(_))
+        ));
+    }
+}
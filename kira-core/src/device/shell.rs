@@ -1,8 +1,23 @@
-use adb_client::ADBDeviceExt;
+use crate::config::{adb_config, adb_device, host_adb};
+use crate::device::app_manager::is_su_unavailable;
+use crate::device::cancel::CancelToken;
+use crate::device::connection::{has_device_feature, send_server_request};
+use crate::device::parse_props;
+use crate::device::performance::parse_battery_info;
 use adb_client::server_device::ADBServerDevice;
+use adb_client::{ADBDeviceExt, RustADBError};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::io::{BufRead, Write};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{Shutdown, TcpStream};
+use std::process::{Child, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Most output kept from a single shell command. Anything past it is dropped and the
+/// command is cut off, so `cat /dev/urandom` or an unbounded logcat can't exhaust memory.
+pub const MAX_OUTPUT_BYTES: usize = 8 * 1024 * 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandOutput {
@@ -10,6 +25,8 @@ pub struct CommandOutput {
     pub stderr: String,
     pub exit_code: i32,
     pub duration_ms: u64,
+    #[serde(default)]
+    pub truncated: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,16 +38,27 @@ pub struct ShellSession {
 }
 
 pub struct ShellExecutor {
+    #[allow(dead_code)]
     sessions: HashMap<String, ShellSession>,
+    timeout: Option<Duration>,
 }
 
 impl ShellExecutor {
     pub fn new() -> Self {
         Self {
             sessions: HashMap::new(),
+            timeout: None,
         }
     }
 
+    /// Gives up on commands still running after `timeout`, e.g. `logcat` or `top` typed
+    /// into a terminal, with `ShellError::Timeout` holding the output so far. Such commands
+    /// run without shell_v2, so stderr is part of stdout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     pub fn execute(
         &mut self,
         device: &mut ADBServerDevice,
@@ -38,7 +66,33 @@ impl ShellExecutor {
     ) -> Result<CommandOutput, ShellError> {
         let start = std::time::Instant::now();
 
-        let output = run_shell_command(device, command)?;
+        // shell_v2 keeps stderr apart and reports the real exit code. It can't be stopped
+        // at a deadline though, so timed commands take the plain shell below
+        if self.timeout.is_none()
+            && let Some(serial) = device.identifier.as_deref()
+            && has_device_feature(serial, SHELL_V2_FEATURE)
+        {
+            let mut stdout = LimitedWriter::new(MAX_OUTPUT_BYTES);
+            let mut stderr = LimitedWriter::new(MAX_OUTPUT_BYTES);
+            let exit_code =
+                match device.shell_command(&command, Some(&mut stdout), Some(&mut stderr)) {
+                    Ok(exit) => exit.map_or(-1, i32::from),
+                    Err(_) if stdout.truncated || stderr.truncated => -1,
+                    Err(e) => return Err(ShellError::CommandFailed(e.to_string())),
+                };
+            return Ok(CommandOutput {
+                stdout: String::from_utf8_lossy(&stdout.buf).trim().to_string(),
+                stderr: String::from_utf8_lossy(&stderr.buf).trim().to_string(),
+                exit_code,
+                duration_ms: start.elapsed().as_millis() as u64,
+                truncated: stdout.truncated || stderr.truncated,
+            });
+        }
+
+        let (output, truncated) = match self.timeout {
+            Some(timeout) => run_shell_command_timed(device, command, MAX_OUTPUT_BYTES, timeout)?,
+            None => run_shell_command_capped(device, command, MAX_OUTPUT_BYTES)?,
+        };
         let duration_ms = start.elapsed().as_millis() as u64;
 
         let (stdout, stderr) = if output.contains("error:") || output.contains("Error:") {
@@ -49,6 +103,7 @@ impl ShellExecutor {
                     stderr: format!("error:{}", parts[1]),
                     exit_code: 1,
                     duration_ms,
+                    truncated,
                 });
             }
             (output, String::new())
@@ -61,6 +116,7 @@ impl ShellExecutor {
             stderr,
             exit_code: 0,
             duration_ms,
+            truncated,
         })
     }
 
@@ -69,7 +125,7 @@ impl ShellExecutor {
         device: &mut ADBServerDevice,
         command: &str,
     ) -> Result<CommandOutput, ShellError> {
-        let su_command = format!("su -c '{}'", command.replace("'", "'\\''"));
+        let su_command = format!("su -c {}", shell_quote(command));
         self.execute(device, &su_command)
     }
 
@@ -86,7 +142,7 @@ impl ShellExecutor {
         device: &mut ADBServerDevice,
         key: &str,
     ) -> Result<String, ShellError> {
-        let output = run_shell_command(device, &format!("getprop {}", key))?;
+        let output = run_shell_command(device, &format!("getprop {}", shell_quote(key)))?;
         Ok(output.trim().to_string())
     }
 
@@ -96,8 +152,24 @@ impl ShellExecutor {
         key: &str,
         value: &str,
     ) -> Result<(), ShellError> {
-        run_shell_command(device, &format!("setprop {} {}", key, value))?;
-        Ok(())
+        let output = run_shell_command(
+            device,
+            &format!("setprop {} {}", shell_quote(key), shell_quote(value)),
+        )?;
+        check_setprop_output(&output)
+    }
+
+    /// Reads only the requested `keys` from a single `getprop` dump. Keys that aren't set
+    /// on the device are left out of the map.
+    pub fn get_props(
+        &mut self,
+        device: &mut ADBServerDevice,
+        keys: &[String],
+    ) -> Result<HashMap<String, String>, ShellError> {
+        let output = run_shell_command(device, "getprop")?;
+        let mut props = parse_props(&output);
+        props.retain(|key, _| keys.contains(key));
+        Ok(props)
     }
 
     pub fn list_files(
@@ -105,7 +177,7 @@ impl ShellExecutor {
         device: &mut ADBServerDevice,
         path: &str,
     ) -> Result<Vec<FileEntry>, ShellError> {
-        let output = run_shell_command(device, &format!("ls -la {}", path))?;
+        let output = run_shell_command(device, &format!("ls -la {}", shell_quote(path)))?;
 
         let entries: Vec<FileEntry> = output
             .lines()
@@ -187,7 +259,7 @@ impl ShellExecutor {
             total_memory: total_mem,
             free_memory: free_mem,
             available_memory: available_mem,
-            load_average_1m: load.get(0).copied().unwrap_or(0.0),
+            load_average_1m: load.first().copied().unwrap_or(0.0),
             load_average_5m: load.get(1).copied().unwrap_or(0.0),
             load_average_15m: load.get(2).copied().unwrap_or(0.0),
         })
@@ -209,6 +281,33 @@ impl ShellExecutor {
         Ok(output.trim().to_string())
     }
 
+    /// Switches SELinux between enforcing and permissive with `setenforce`, through `su`
+    /// unless adbd already runs as root.
+    pub fn set_selinux_enforcing(
+        &mut self,
+        device: &mut ADBServerDevice,
+        enforce: bool,
+    ) -> Result<(), ShellError> {
+        let command = format!("setenforce {}", if enforce { 1 } else { 0 });
+        let command = if self.is_root_available(device) {
+            command
+        } else {
+            format!("su -c {}", shell_quote(&command))
+        };
+        let output = run_shell_command(device, &command)?;
+        check_setenforce_output(&output)?;
+
+        // su may exit quietly without running anything, so confirm the mode took
+        let expected = if enforce { "Enforcing" } else { "Permissive" };
+        if !self
+            .get_selinux_status(device)?
+            .eq_ignore_ascii_case(expected)
+        {
+            return Err(ShellError::PermissionDenied);
+        }
+        Ok(())
+    }
+
     pub fn get_mounts(
         &mut self,
         device: &mut ADBServerDevice,
@@ -235,62 +334,24 @@ impl ShellExecutor {
         Ok(mounts)
     }
 
+    /// Interfaces from `ip addr show`. Loopback is skipped unless `include_loopback` is set.
     pub fn get_networks(
         &mut self,
         device: &mut ADBServerDevice,
+        include_loopback: bool,
     ) -> Result<Vec<NetworkInterface>, ShellError> {
         let output = run_shell_command(device, "ip addr show")?;
-
-        let mut interfaces = Vec::new();
-        let mut current_iface: Option<NetworkInterface> = None;
-
-        for line in output.lines() {
-            if line.contains(": ") {
-                if let Some(iface) = current_iface.take() {
-                    interfaces.push(iface);
-                }
-                let name = line
-                    .split(": ")
-                    .nth(1)
-                    .unwrap_or("")
-                    .split_whitespace()
-                    .next()
-                    .unwrap_or("")
-                    .to_string();
-                if !name.is_empty() && name != "lo" {
-                    current_iface = Some(NetworkInterface {
-                        name,
-                        state: if line.contains("state UP") {
-                            "UP".to_string()
-                        } else {
-                            "DOWN".to_string()
-                        },
-                        ip_address: String::new(),
-                    });
-                }
-            } else if let Some(ref mut iface) = current_iface {
-                if line.contains("inet ") {
-                    let inet = line.trim().split_whitespace().nth(1).unwrap_or("");
-                    if let Some(ip) = inet.split('/').next() {
-                        iface.ip_address = ip.to_string();
-                    }
-                }
-            }
-        }
-
-        if let Some(iface) = current_iface {
-            interfaces.push(iface);
-        }
-
-        Ok(interfaces)
+        Ok(parse_ip_addr(&output, include_loopback))
     }
 
+    /// `dumpsys` for `service`, which may carry arguments like `meminfo com.example`. Each
+    /// word is quoted, so nothing but dumpsys runs.
     pub fn run_dumpsys(
         &mut self,
         device: &mut ADBServerDevice,
         service: &str,
     ) -> Result<String, ShellError> {
-        let output = run_shell_command(device, &format!("dumpsys {}", service))?;
+        let output = run_shell_command(device, &dumpsys_command(service))?;
         Ok(output)
     }
 
@@ -298,70 +359,55 @@ impl ShellExecutor {
         &mut self,
         device: &mut ADBServerDevice,
     ) -> Result<BatteryInfo, ShellError> {
-        let output = self.run_dumpsys(device, "battery")?;
-
-        let mut level = 0u32;
-        let mut scale = 100u32;
-        let mut status = String::new();
-        let mut health = String::new();
-        let mut plugged = String::new();
-        let mut voltage = 0u32;
-        let mut temperature = 0i32;
-        let mut technology = String::new();
-
-        for line in output.lines() {
-            let line = line.trim();
-            if line.starts_with("level:") {
-                level = line
-                    .split_whitespace()
-                    .nth(1)
-                    .and_then(|s| s.parse().ok())
-                    .unwrap_or(0);
-            } else if line.starts_with("scale:") {
-                scale = line
-                    .split_whitespace()
-                    .nth(1)
-                    .and_then(|s| s.parse().ok())
-                    .unwrap_or(100);
-            } else if line.starts_with("status:") {
-                status = line.split_whitespace().nth(1).unwrap_or("").to_string();
-            } else if line.starts_with("health:") {
-                health = line.split_whitespace().nth(1).unwrap_or("").to_string();
-            } else if line.starts_with("plugged:") {
-                plugged = line.split_whitespace().nth(1).unwrap_or("").to_string();
-            } else if line.starts_with("voltage:") {
-                voltage = line
-                    .split_whitespace()
-                    .nth(1)
-                    .and_then(|s| s.parse().ok())
-                    .unwrap_or(0);
-            } else if line.starts_with("temperature:") {
-                temperature = line
-                    .split_whitespace()
-                    .nth(1)
-                    .and_then(|s| s.parse().ok())
-                    .unwrap_or(0);
-            } else if line.starts_with("technology:") {
-                technology = line.split_whitespace().nth(1).unwrap_or("").to_string();
-            }
-        }
+        self.run_dumpsys_typed(device)
+    }
 
-        Ok(BatteryInfo {
-            level,
-            scale,
-            percentage: if scale > 0 {
-                (level as f32 / scale as f32 * 100.0) as u32
-            } else {
-                0
-            },
-            status,
-            health,
-            plugged,
-            voltage,
-            temperature: temperature as f32 / 10.0,
-            technology,
+    /// Dumps `T::SERVICE` and parses it into `T`, e.g. `run_dumpsys_typed::<BatteryInfo>`.
+    pub fn run_dumpsys_typed<T: DumpsysParser>(
+        &mut self,
+        device: &mut ADBServerDevice,
+    ) -> Result<T, ShellError> {
+        let output = self.run_dumpsys(device, T::SERVICE)?;
+        T::parse(&output).ok_or_else(|| {
+            ShellError::CommandFailed(format!("unexpected dumpsys {} output", T::SERVICE))
         })
     }
+
+    /// Names of the services `dumpsys` can dump, from `dumpsys -l`.
+    pub fn list_dumpsys_services(
+        &mut self,
+        device: &mut ADBServerDevice,
+    ) -> Result<Vec<String>, ShellError> {
+        let output = run_shell_command(device, "dumpsys -l")?;
+        Ok(parse_dumpsys_services(&output))
+    }
+}
+
+/// A type that can be read from the output of `dumpsys <SERVICE>`. Implement it to make a
+/// new parser available through [`ShellExecutor::run_dumpsys_typed`].
+pub trait DumpsysParser: Sized {
+    const SERVICE: &'static str;
+
+    fn parse(output: &str) -> Option<Self>;
+}
+
+impl DumpsysParser for BatteryInfo {
+    const SERVICE: &'static str = "battery";
+
+    fn parse(output: &str) -> Option<Self> {
+        parse_battery_info(output)
+    }
+}
+
+fn parse_dumpsys_services(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("Currently running services:"))
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
 }
 
 impl Default for ShellExecutor {
@@ -370,6 +416,251 @@ impl Default for ShellExecutor {
     }
 }
 
+/// Text on the device clipboard, empty when there is none. Needs `cmd clipboard`, which
+/// older releases don't have.
+pub fn get_clipboard(device: &mut ADBServerDevice) -> Result<String, ShellError> {
+    let output = run_shell_command(device, "cmd clipboard get-text")?;
+    check_clipboard_output(&output)?;
+    Ok(match output.as_str() {
+        "null" => String::new(),
+        text => text.to_string(),
+    })
+}
+
+pub fn set_clipboard(device: &mut ADBServerDevice, text: &str) -> Result<(), ShellError> {
+    let output = run_shell_command(
+        device,
+        &format!("cmd clipboard set-text {}", shell_quote(text)),
+    )?;
+    check_clipboard_output(&output)
+}
+
+fn check_clipboard_output(output: &str) -> Result<(), ShellError> {
+    let unsupported = [
+        "Can't find service",
+        "No shell command implementation",
+        "Unknown command",
+    ];
+    if unsupported.iter().any(|m| output.contains(m)) {
+        return Err(ShellError::Unsupported(
+            "clipboard access needs a newer Android version".to_string(),
+        ));
+    }
+    if output.contains("SecurityException") {
+        return Err(ShellError::PermissionDenied);
+    }
+    Ok(())
+}
+
+/// One line of the kernel log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KernelLogEntry {
+    /// Seconds since boot
+    pub timestamp: Option<f64>,
+    /// Syslog priority, 0 (emergency) to 7 (debug)
+    pub level: Option<u8>,
+    pub message: String,
+    pub raw: String,
+}
+
+/// The kernel ring buffer, which holds driver and boot messages logcat never sees.
+///
+/// Release builds only let root read it. `su` is tried when the shell user is refused,
+/// `PermissionDenied` means neither works.
+pub fn read_dmesg(device: &mut ADBServerDevice) -> Result<Vec<KernelLogEntry>, ShellError> {
+    let command = dmesg_command(device, "-r")?;
+    // The newest messages are the interesting ones, keep those when the log is huge
+    let tail = with_configured_retry(device, |device| {
+        let mut tail = TailWriter::new(MAX_OUTPUT_BYTES);
+        device.shell_command(&command, Some(&mut tail), None)?;
+        Ok(tail)
+    })
+    .map_err(|e| ShellError::CommandFailed(e.to_string()))?;
+
+    // Drivers log whatever bytes they like
+    Ok(String::from_utf8_lossy(&tail.into_lines())
+        .lines()
+        .filter_map(parse_kernel_log_line)
+        .collect())
+}
+
+/// Streams the kernel log like `dmesg -w`, the existing buffer first. Needs the same
+/// access as [`read_dmesg`]. Tripping `cancel` or dropping the stream stops the device
+/// side `dmesg` within a moment.
+pub fn follow_dmesg(
+    device: &mut ADBServerDevice,
+    cancel: CancelToken,
+) -> Result<LineStream<KernelLogEntry>, ShellError> {
+    let command = dmesg_command(device, "-r -w")?;
+    let serial = device
+        .identifier
+        .clone()
+        .ok_or(ShellError::DeviceNotFound)?;
+
+    stream_host_shell_lines(&serial, &command, cancel, parse_kernel_log_line)
+        .map_err(|e| ShellError::IOError(e.to_string()))
+}
+
+// pstore on current kernels, the older ram console on Android 5 era ones
+const LAST_KMSG_PATHS: [&str; 3] = [
+    "/sys/fs/pstore/console-ramoops-0",
+    "/sys/fs/pstore/console-ramoops",
+    "/proc/last_kmsg",
+];
+
+/// Kernel log of the previous boot, kept in RAM across a reboot, for finding out why a
+/// device crashed or boot loops. Needs root, and a kernel built with pstore or the ram
+/// console.
+pub fn read_last_kmsg(device: &mut ADBServerDevice) -> Result<String, ShellError> {
+    // Prints the path it found first, an empty log is still a log
+    let script = format!(
+        "for f in {}; do if [ -r \"$f\" ]; then echo \"$f\"; cat \"$f\"; exit 0; fi; done",
+        LAST_KMSG_PATHS.join(" ")
+    );
+
+    let output = last_kmsg_output(device, &script)?;
+    if let Some(log) = strip_found_path(&output) {
+        return Ok(log);
+    }
+
+    let output = last_kmsg_output(device, &format!("su -c {} 2>&1", shell_quote(&script)))?;
+    if let Some(log) = strip_found_path(&output) {
+        return Ok(log);
+    }
+    if output.is_empty() {
+        return Err(ShellError::Unsupported(
+            "the kernel keeps no log of the previous boot".to_string(),
+        ));
+    }
+    Err(ShellError::PermissionDenied)
+}
+
+fn last_kmsg_output(device: &mut ADBServerDevice, command: &str) -> Result<String, ShellError> {
+    let (output, _) = shell_command_capped(device, command, MAX_OUTPUT_BYTES)
+        .map_err(|e| ShellError::CommandFailed(e.to_string()))?;
+    Ok(String::from_utf8_lossy(&output).trim().to_string())
+}
+
+/// The log after the `echo "$f"` line, `None` when no known file was read.
+fn strip_found_path(output: &str) -> Option<String> {
+    let (path, log) = output.split_once('\n').unwrap_or((output, ""));
+    LAST_KMSG_PATHS
+        .contains(&path.trim())
+        .then(|| log.to_string())
+}
+
+/// `dmesg <args>` as the shell user when that's allowed, through `su` otherwise.
+fn dmesg_command(device: &mut ADBServerDevice, args: &str) -> Result<String, ShellError> {
+    let probe = run_shell_command(device, "dmesg -r 2>&1 | head -n 1")?;
+    if !is_dmesg_denied(&probe) {
+        return Ok(format!("dmesg {}", args));
+    }
+
+    let probe = run_shell_command(
+        device,
+        &format!("su -c {} 2>&1", shell_quote("dmesg -r | head -n 1")),
+    )?;
+    if is_su_unavailable(&probe) || is_dmesg_denied(&probe) {
+        return Err(ShellError::PermissionDenied);
+    }
+    Ok(format!("su -c {}", shell_quote(&format!("dmesg {}", args))))
+}
+
+// `dmesg: klogctl: Permission denied`, some builds print nothing at all
+fn is_dmesg_denied(output: &str) -> bool {
+    output.is_empty() || output.contains("Permission denied") || output.contains("klogctl")
+}
+
+/// `<6>[ 1234.567890] message`, the level and timestamp both optional. The `[    T123]`
+/// caller id some kernels add after the timestamp is dropped.
+fn parse_kernel_log_line(line: &str) -> Option<KernelLogEntry> {
+    let raw = line.trim_end_matches(['\n', '\r']);
+    if raw.trim().is_empty() {
+        return None;
+    }
+
+    let strip_level = |rest: &str| -> (Option<u8>, String) {
+        rest.strip_prefix('<')
+            .and_then(|r| r.split_once('>'))
+            .and_then(|(level, rest)| Some((level.parse::<u8>().ok()? & 7, rest.to_string())))
+            .map_or((None, rest.to_string()), |(level, rest)| {
+                (Some(level), rest)
+            })
+    };
+
+    let (mut level, mut rest) = strip_level(raw);
+    let mut timestamp = None;
+    if let Some((stamp, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']'))
+        && let Ok(seconds) = stamp.trim().parse::<f64>()
+    {
+        timestamp = Some(seconds);
+        rest = after.to_string();
+    }
+    if let Some((caller, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']'))
+        && caller.trim().starts_with(['T', 'C'])
+        && caller.trim()[1..].chars().all(|c| c.is_ascii_digit())
+    {
+        rest = after.to_string();
+    }
+    if level.is_none() {
+        (level, rest) = strip_level(rest.trim_start());
+    }
+
+    Some(KernelLogEntry {
+        timestamp,
+        level,
+        message: rest.trim().to_string(),
+        raw: raw.to_string(),
+    })
+}
+
+/// Runs `f` on a fresh connection to `serial` on tokio's blocking pool, so async callers
+/// don't stall their runtime while adb does its I/O.
+pub async fn run_blocking<T, E, F>(serial: String, f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut ADBServerDevice) -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    let task = tokio::task::spawn_blocking(move || {
+        let mut device = adb_device(serial);
+        f(&mut device)
+    });
+
+    match task.await {
+        Ok(result) => result,
+        // Only a panic in `f` ends up here, pass it on instead of hiding it in an error
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// Async version of [`ShellExecutor::execute`].
+pub async fn execute_async(serial: String, command: String) -> Result<CommandOutput, ShellError> {
+    run_blocking(serial, move |device| {
+        ShellExecutor::new().execute(device, &command)
+    })
+    .await
+}
+
+/// Async version of [`ShellExecutor::execute_as_root`].
+pub async fn execute_as_root_async(
+    serial: String,
+    command: String,
+) -> Result<CommandOutput, ShellError> {
+    run_blocking(serial, move |device| {
+        ShellExecutor::new().execute_as_root(device, &command)
+    })
+    .await
+}
+
+/// Async version of [`ShellExecutor::get_prop`].
+pub async fn get_prop_async(serial: String, key: String) -> Result<String, ShellError> {
+    run_blocking(serial, move |device| {
+        ShellExecutor::new().get_prop(device, &key)
+    })
+    .await
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileEntry {
     pub name: String,
@@ -402,28 +693,151 @@ pub struct NetworkInterface {
     pub name: String,
     pub state: String,
     pub ip_address: String,
+    #[serde(default)]
+    pub ipv6_addresses: Vec<String>,
+    #[serde(default)]
+    pub mac_address: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct BatteryInfo {
     pub level: u32,
     pub scale: u32,
     pub percentage: u32,
-    pub status: String,
-    pub health: String,
-    pub plugged: String,
+    pub status: BatteryStatus,
+    pub health: BatteryHealth,
+    pub plugged: PluggedState,
     pub voltage: u32,
     pub temperature: f32,
     pub technology: String,
 }
 
+/// `BatteryManager.BATTERY_STATUS_*`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BatteryStatus {
+    Unknown,
+    Charging,
+    Discharging,
+    NotCharging,
+    Full,
+}
+
+impl BatteryStatus {
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            2 => BatteryStatus::Charging,
+            3 => BatteryStatus::Discharging,
+            4 => BatteryStatus::NotCharging,
+            5 => BatteryStatus::Full,
+            _ => BatteryStatus::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for BatteryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatteryStatus::Unknown => write!(f, "Unknown"),
+            BatteryStatus::Charging => write!(f, "Charging"),
+            BatteryStatus::Discharging => write!(f, "Discharging"),
+            BatteryStatus::NotCharging => write!(f, "Not charging"),
+            BatteryStatus::Full => write!(f, "Full"),
+        }
+    }
+}
+
+/// `BatteryManager.BATTERY_HEALTH_*`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BatteryHealth {
+    Unknown,
+    Good,
+    Overheat,
+    Dead,
+    OverVoltage,
+    UnspecifiedFailure,
+    Cold,
+}
+
+impl BatteryHealth {
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            2 => BatteryHealth::Good,
+            3 => BatteryHealth::Overheat,
+            4 => BatteryHealth::Dead,
+            5 => BatteryHealth::OverVoltage,
+            6 => BatteryHealth::UnspecifiedFailure,
+            7 => BatteryHealth::Cold,
+            _ => BatteryHealth::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for BatteryHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatteryHealth::Unknown => write!(f, "Unknown"),
+            BatteryHealth::Good => write!(f, "Good"),
+            BatteryHealth::Overheat => write!(f, "Overheat"),
+            BatteryHealth::Dead => write!(f, "Dead"),
+            BatteryHealth::OverVoltage => write!(f, "Over voltage"),
+            BatteryHealth::UnspecifiedFailure => write!(f, "Unspecified failure"),
+            BatteryHealth::Cold => write!(f, "Cold"),
+        }
+    }
+}
+
+/// `BatteryManager.BATTERY_PLUGGED_*`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PluggedState {
+    Unplugged,
+    Ac,
+    Usb,
+    Wireless,
+    Dock,
+    Unknown(u32),
+}
+
+impl PluggedState {
+    /// The code is a bit mask, when several sources are set the first one wins.
+    pub fn from_code(code: u32) -> Self {
+        if code == 0 {
+            PluggedState::Unplugged
+        } else if code & 1 != 0 {
+            PluggedState::Ac
+        } else if code & 2 != 0 {
+            PluggedState::Usb
+        } else if code & 4 != 0 {
+            PluggedState::Wireless
+        } else if code & 8 != 0 {
+            PluggedState::Dock
+        } else {
+            PluggedState::Unknown(code)
+        }
+    }
+}
+
+impl std::fmt::Display for PluggedState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluggedState::Unplugged => write!(f, "Unplugged"),
+            PluggedState::Ac => write!(f, "AC"),
+            PluggedState::Usb => write!(f, "USB"),
+            PluggedState::Wireless => write!(f, "Wireless"),
+            PluggedState::Dock => write!(f, "Dock"),
+            PluggedState::Unknown(code) => write!(f, "Unknown ({})", code),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ShellError {
     DeviceNotFound,
     CommandFailed(String),
-    Timeout,
+    /// Carries the output printed before the command was given up on
+    Timeout(String),
     PermissionDenied,
     IOError(String),
+    Unsupported(String),
 }
 
 impl std::fmt::Display for ShellError {
@@ -431,30 +845,658 @@ impl std::fmt::Display for ShellError {
         match self {
             ShellError::DeviceNotFound => write!(f, "Device not found"),
             ShellError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
-            ShellError::Timeout => write!(f, "Command timed out"),
+            ShellError::Timeout(partial) if partial.is_empty() => write!(f, "Command timed out"),
+            ShellError::Timeout(partial) => {
+                write!(f, "Command timed out, output so far:\n{}", partial)
+            }
             ShellError::PermissionDenied => write!(f, "Permission denied"),
             ShellError::IOError(msg) => write!(f, "IO Error: {}", msg),
+            ShellError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
         }
     }
 }
 
 impl std::error::Error for ShellError {}
 
+fn parse_ip_addr(output: &str, include_loopback: bool) -> Vec<NetworkInterface> {
+    let mut interfaces = Vec::new();
+    let mut current_iface: Option<NetworkInterface> = None;
+    // Set while skipping the address lines of an interface that was filtered out
+    let mut skipping = false;
+
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) && line.contains(": ") {
+            if let Some(iface) = current_iface.take() {
+                interfaces.push(iface);
+            }
+            // `23: rmnet_data0@rmnet_ipa0: <...>` -> `rmnet_data0`
+            let name = line
+                .split(": ")
+                .nth(1)
+                .unwrap_or("")
+                .split('@')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_string();
+            skipping = name.is_empty() || (name == "lo" && !include_loopback);
+            if !skipping {
+                current_iface = Some(NetworkInterface {
+                    name,
+                    state: if line.contains("state UP") {
+                        "UP".to_string()
+                    } else {
+                        "DOWN".to_string()
+                    },
+                    ip_address: String::new(),
+                    ipv6_addresses: Vec::new(),
+                    mac_address: None,
+                });
+            }
+            continue;
+        }
+
+        if skipping {
+            continue;
+        }
+        let Some(iface) = current_iface.as_mut() else {
+            continue;
+        };
+
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("inet"), Some(addr)) if iface.ip_address.is_empty() => {
+                iface.ip_address = addr.split('/').next().unwrap_or("").to_string();
+            }
+            (Some("inet6"), Some(addr)) => {
+                iface
+                    .ipv6_addresses
+                    .push(addr.split('/').next().unwrap_or("").to_string());
+            }
+            (Some("link/ether"), Some(mac)) => {
+                iface.mac_address = Some(mac.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(iface) = current_iface {
+        interfaces.push(iface);
+    }
+
+    interfaces
+}
+
+// setprop exits quietly even when init rejects the change, e.g. a ro. prop or no root
+fn check_setprop_output(output: &str) -> Result<(), ShellError> {
+    if output.contains("Failed to set property") {
+        return Err(ShellError::CommandFailed(output.trim().to_string()));
+    }
+    Ok(())
+}
+
+/// `setenforce` prints nothing on success. Denials come from the policy, a shell user
+/// without root, or a missing or refusing `su`.
+fn check_setenforce_output(output: &str) -> Result<(), ShellError> {
+    let lower = output.to_lowercase();
+    if ["denied", "not permitted", "couldn't", "not found"]
+        .iter()
+        .any(|m| lower.contains(m))
+    {
+        return Err(ShellError::PermissionDenied);
+    }
+    if !output.trim().is_empty() {
+        return Err(ShellError::CommandFailed(output.trim().to_string()));
+    }
+    Ok(())
+}
+
+fn dumpsys_command(service: &str) -> String {
+    let args: Vec<String> = service.split_whitespace().map(shell_quote).collect();
+    format!("dumpsys {}", args.join(" "))
+}
+
+/// Quotes `arg` for the device shell so spaces, quotes and `$()` are passed through
+/// literally. Arguments made only of characters the shell never interprets are left
+/// as they are to keep commands readable.
+pub fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "._/:@%+=,-".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        return arg.to_string();
+    }
+
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Collects up to `limit` bytes and then fails the write, which makes adb_client stop
+/// reading and drop the shell connection.
+pub(crate) struct LimitedWriter {
+    buf: Vec<u8>,
+    limit: usize,
+    truncated: bool,
+}
+
+impl LimitedWriter {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            limit,
+            truncated: false,
+        }
+    }
+
+    pub(crate) fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub(crate) fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Write for LimitedWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let room = self.limit.saturating_sub(self.buf.len());
+        if data.len() <= room {
+            self.buf.extend_from_slice(data);
+            return Ok(data.len());
+        }
+
+        self.buf.extend_from_slice(&data[..room]);
+        self.truncated = true;
+        Err(std::io::Error::other("shell output limit reached"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps the last `limit` bytes written to it, dropping older ones as more comes in.
+struct TailWriter {
+    buf: Vec<u8>,
+    limit: usize,
+    truncated: bool,
+}
+
+impl TailWriter {
+    fn new(limit: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            limit,
+            truncated: false,
+        }
+    }
+
+    fn trim(&mut self) {
+        if self.buf.len() > self.limit {
+            self.buf.drain(..self.buf.len() - self.limit);
+            self.truncated = true;
+        }
+    }
+
+    /// What was kept, without the partial line at the start when older bytes were dropped.
+    fn into_lines(mut self) -> Vec<u8> {
+        self.trim();
+        if self.truncated {
+            let start = self
+                .buf
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(0, |i| i + 1);
+            self.buf.drain(..start);
+        }
+        self.buf
+    }
+}
+
+impl Write for TailWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        // Trimming on every write would move the buffer each time
+        if self.buf.len() > self.limit * 2 {
+            self.trim();
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `command` keeping at most `limit` bytes of output. Returns the bytes and whether
+/// the output was cut off.
+pub(crate) fn shell_command_capped(
+    device: &mut ADBServerDevice,
+    command: &str,
+    limit: usize,
+) -> Result<(Vec<u8>, bool), RustADBError> {
+    with_configured_retry(device, |device| {
+        let mut writer = LimitedWriter::new(limit);
+        match device.shell_command(&command, Some(&mut writer), None) {
+            Ok(_) => {}
+            Err(_) if writer.truncated => {}
+            Err(e) => return Err(e),
+        }
+        Ok((writer.buf, writer.truncated))
+    })
+}
+
+/// Runs `command` and collects its output, retrying as configured by `retry_attempts`.
+pub(crate) fn shell_output(
+    device: &mut ADBServerDevice,
+    command: &str,
+) -> Result<Vec<u8>, RustADBError> {
+    with_configured_retry(device, |device| {
+        let mut output = Vec::new();
+        device.shell_command(&command, Some(&mut output), None)?;
+        Ok(output)
+    })
+}
+
+pub const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Calls `f` up to `attempts` times while it fails before the request reached the device,
+/// e.g. when a Wi-Fi link blips and the server can't be reached or reports the device
+/// offline. A connection dropped mid-command isn't retried since the command may already
+/// have run. The handle is recreated before each retry and the wait grows by `backoff`
+/// every time.
+pub fn with_retry<T>(
+    device: &mut ADBServerDevice,
+    attempts: u32,
+    backoff: Duration,
+    mut f: impl FnMut(&mut ADBServerDevice) -> Result<T, RustADBError>,
+) -> Result<T, RustADBError> {
+    let mut attempt = 1;
+    loop {
+        match f(device) {
+            Err(e) if attempt < attempts && is_transient(&e) => {
+                thread::sleep(backoff * attempt);
+                if let Some(serial) = device.identifier.clone() {
+                    *device = adb_device(serial);
+                }
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn with_configured_retry<T>(
+    device: &mut ADBServerDevice,
+    f: impl FnMut(&mut ADBServerDevice) -> Result<T, RustADBError>,
+) -> Result<T, RustADBError> {
+    with_retry(device, adb_config().retry_attempts, RETRY_BACKOFF, f)
+}
+
+fn is_transient(error: &RustADBError) -> bool {
+    match error {
+        // Nothing was sent yet, the connection never opened
+        RustADBError::IOError(e) => matches!(
+            e.kind(),
+            ErrorKind::ConnectionRefused | ErrorKind::NotConnected
+        ),
+        // The server marks a wireless device offline until it reconnects, and rejects
+        // the transport before the command is sent
+        RustADBError::ADBRequestFailed(msg) => msg.contains("device offline"),
+        _ => false,
+    }
+}
+
+/// Runs `command` through the host `adb shell` with a watcher on stdin: closing stdin, or
+/// the adb client going away, kills the command on the device instead of leaving it
+/// running. Stop it with [`stop_host_shell`].
+pub(crate) fn spawn_host_shell(serial: &str, command: &str) -> std::io::Result<Child> {
+    let script = format!(
+        "{} & pid=$!; (read _; kill $pid) >/dev/null 2>&1 & wait $pid",
+        command
+    );
+
+    host_adb()
+        .args(adb_config().host_args())
+        .args(["-s", serial, "shell", &script])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+}
+
+pub(crate) fn stop_host_shell(mut child: Child) {
+    drop(child.stdin.take());
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+const STREAM_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What [`stream_host_shell_lines`] produces, used like the `Receiver` it wraps. Dropping
+/// it stops the command on the device within a moment.
+pub struct LineStream<T> {
+    rx: mpsc::Receiver<T>,
+    dropped: CancelToken,
+}
+
+impl<T> LineStream<T> {
+    /// A stream with the sender feeding it and a token that is tripped once the stream is
+    /// dropped, for producers that need to notice that while they have nothing to send.
+    pub(crate) fn channel() -> (mpsc::Sender<T>, CancelToken, Self) {
+        let (tx, rx) = mpsc::channel();
+        let dropped = CancelToken::new();
+        (tx, dropped.clone(), LineStream { rx, dropped })
+    }
+}
+
+impl<T> std::ops::Deref for LineStream<T> {
+    type Target = mpsc::Receiver<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.rx
+    }
+}
+
+impl<T> Iterator for LineStream<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<T> Drop for LineStream<T> {
+    fn drop(&mut self) {
+        self.dropped.cancel();
+    }
+}
+
+/// Runs `command` with [`spawn_host_shell`] and streams its output lines through `parse`,
+/// skipping the ones it returns `None` for. The command is stopped once `cancel` is
+/// tripped or the stream is dropped, and the stream ends when the command exits.
+pub(crate) fn stream_host_shell_lines<T: Send + 'static>(
+    serial: &str,
+    command: &str,
+    cancel: CancelToken,
+    parse: impl Fn(&str) -> Option<T> + Send + 'static,
+) -> std::io::Result<LineStream<T>> {
+    let mut child = spawn_host_shell(serial, command)?;
+    let Some(stdout) = child.stdout.take() else {
+        stop_host_shell(child);
+        return Err(std::io::Error::other("Failed to capture stdout"));
+    };
+
+    let (lines_tx, lines_rx) = mpsc::channel();
+    thread::spawn(move || {
+        // Output isn't always valid UTF-8, don't stop over it
+        for line in BufReader::new(stdout).split(b'\n') {
+            let Ok(line) = line else { break };
+            if let Some(item) = parse(String::from_utf8_lossy(&line).trim_end_matches('\r'))
+                && lines_tx.send(item).is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let (tx, dropped, stream) = LineStream::channel();
+    thread::spawn(move || {
+        while !cancel.is_cancelled() && !dropped.is_cancelled() {
+            match lines_rx.recv_timeout(STREAM_POLL_INTERVAL) {
+                Ok(item) => {
+                    if tx.send(item).is_err() {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                // The command exited, e.g. the device went away
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        stop_host_shell(child);
+    });
+
+    Ok(stream)
+}
+
 fn run_shell_command(device: &mut ADBServerDevice, command: &str) -> Result<String, ShellError> {
-    let mut output = Vec::new();
-    device
-        .shell_command(&command, Some(&mut output), None)
+    run_shell_command_capped(device, command, MAX_OUTPUT_BYTES).map(|(output, _)| output)
+}
+
+fn run_shell_command_capped(
+    device: &mut ADBServerDevice,
+    command: &str,
+    limit: usize,
+) -> Result<(String, bool), ShellError> {
+    let (output, truncated) = shell_command_capped(device, command, limit)
         .map_err(|e| ShellError::CommandFailed(e.to_string()))?;
 
-    String::from_utf8(output)
-        .map_err(|e| ShellError::IOError(e.to_string()))
-        .map(|s| s.trim().to_string())
+    // a cut can land inside a multi-byte character
+    let output = if truncated {
+        String::from_utf8_lossy(&output).into_owned()
+    } else {
+        String::from_utf8(output).map_err(|e| ShellError::IOError(e.to_string()))?
+    };
+
+    Ok((output.trim().to_string(), truncated))
+}
+
+const SHELL_V2_FEATURE: &str = "shell_v2";
+
+/// What the thread running a timed command hands back.
+enum TimedChunk {
+    Output(Vec<u8>),
+    Done(Result<(), String>),
+}
+
+/// Passes output on to the waiting caller and fails once the caller stopped listening.
+struct ChannelWriter(mpsc::Sender<TimedChunk>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .send(TimedChunk::Output(buf.to_vec()))
+            .map_err(|_| std::io::Error::new(ErrorKind::BrokenPipe, "no longer waited for"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// [`run_shell_command_capped`] that returns after `timeout` at the latest. The command
+/// runs on its own connection, which is shut down once the call returns so adbd ends a
+/// command still running and the thread reading it exits.
+fn run_shell_command_timed(
+    device: &ADBServerDevice,
+    command: &str,
+    limit: usize,
+    timeout: Duration,
+) -> Result<(String, bool), ShellError> {
+    let mut stream = TcpStream::connect(adb_config().server_addr())
+        .map_err(|e| ShellError::CommandFailed(e.to_string()))?;
+    let transport = match &device.identifier {
+        Some(serial) => format!("host:transport:{}", serial),
+        None => "host:transport-any".to_string(),
+    };
+    send_server_request(&mut stream, &transport).map_err(ShellError::CommandFailed)?;
+    send_server_request(&mut stream, &format!("shell:{}", command))
+        .map_err(ShellError::CommandFailed)?;
+    let connection = stream
+        .try_clone()
+        .map_err(|e| ShellError::IOError(e.to_string()))?;
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let result = std::io::copy(&mut stream, &mut ChannelWriter(tx.clone()))
+            .map(|_| ())
+            .map_err(|e| e.to_string());
+        let _ = tx.send(TimedChunk::Done(result));
+    });
+
+    let collected = collect_timed_output(&rx, limit, Instant::now() + timeout);
+    let _ = connection.shutdown(Shutdown::Both);
+    let (output, truncated) = collected?;
+    // a cut can land inside a multi-byte character
+    Ok((
+        String::from_utf8_lossy(&output).trim().to_string(),
+        truncated,
+    ))
+}
+
+fn collect_timed_output(
+    rx: &mpsc::Receiver<TimedChunk>,
+    limit: usize,
+    deadline: Instant,
+) -> Result<(Vec<u8>, bool), ShellError> {
+    let mut output = Vec::new();
+    loop {
+        let chunk = match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(chunk) => chunk,
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(ShellError::Timeout(
+                    String::from_utf8_lossy(&output).trim().to_string(),
+                ));
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok((output, false)),
+        };
+
+        match chunk {
+            TimedChunk::Output(data) => {
+                let room = limit - output.len();
+                if data.len() > room {
+                    output.extend_from_slice(&data[..room]);
+                    return Ok((output, true));
+                }
+                output.extend_from_slice(&data);
+            }
+            TimedChunk::Done(Ok(())) => return Ok((output, false)),
+            TimedChunk::Done(Err(e)) => return Err(ShellError::CommandFailed(e)),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_tail_writer() {
+        let mut tail = TailWriter::new(10);
+        tail.write_all(b"one\ntwo\n").unwrap();
+        assert_eq!(tail.into_lines(), b"one\ntwo\n");
+
+        let mut tail = TailWriter::new(10);
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            tail.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(tail.into_lines(), b"fourth\n");
+    }
+
+    #[test]
+    fn test_line_stream_drop() {
+        let (tx, rx) = mpsc::channel();
+        let dropped = CancelToken::new();
+        let mut stream = LineStream {
+            rx,
+            dropped: dropped.clone(),
+        };
+        tx.send("line").unwrap();
+        drop(tx);
+        assert_eq!(stream.next(), Some("line"));
+        assert_eq!(stream.next(), None);
+        assert!(!dropped.is_cancelled());
+
+        drop(stream);
+        assert!(dropped.is_cancelled());
+    }
+
+    #[test]
+    fn test_collect_timed_output() {
+        let (tx, rx) = mpsc::channel();
+        tx.send(TimedChunk::Output(b"12:00 I/Tag: one\n".to_vec()))
+            .unwrap();
+        tx.send(TimedChunk::Output(b"12:01 I/Tag: two\n".to_vec()))
+            .unwrap();
+
+        // Still running when the time is up, the output so far comes with the error
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert_eq!(
+            collect_timed_output(&rx, MAX_OUTPUT_BYTES, deadline),
+            Err(ShellError::Timeout(
+                "12:00 I/Tag: one\n12:01 I/Tag: two".to_string()
+            ))
+        );
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(TimedChunk::Output(b"abcdef".to_vec())).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert_eq!(
+            collect_timed_output(&rx, 4, deadline),
+            Ok((b"abcd".to_vec(), true))
+        );
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(TimedChunk::Output(b"done".to_vec())).unwrap();
+        tx.send(TimedChunk::Done(Ok(()))).unwrap();
+        assert_eq!(
+            collect_timed_output(&rx, MAX_OUTPUT_BYTES, deadline),
+            Ok((b"done".to_vec(), false))
+        );
+    }
+
+    #[test]
+    fn test_with_retry_transient_errors() {
+        let mut device = adb_device("192.168.1.5:5555".to_string());
+        let mut calls = 0;
+        let result = with_retry(&mut device, 3, Duration::ZERO, |_| {
+            calls += 1;
+            if calls < 3 {
+                Err(RustADBError::IOError(ErrorKind::ConnectionRefused.into()))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Out of attempts, the last error is returned
+        calls = 0;
+        let result: Result<(), _> = with_retry(&mut device, 2, Duration::ZERO, |_| {
+            calls += 1;
+            Err(RustADBError::IOError(ErrorKind::NotConnected.into()))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 2);
+        assert_eq!(device.identifier.as_deref(), Some("192.168.1.5:5555"));
+    }
+
+    #[test]
+    fn test_with_retry_not_on_command_failures() {
+        let mut device = adb_device("emulator-5554".to_string());
+        let mut calls = 0;
+        let result: Result<(), _> = with_retry(&mut device, 3, Duration::ZERO, |_| {
+            calls += 1;
+            Err(RustADBError::ADBRequestFailed(
+                "device 'x' not found".to_string(),
+            ))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        assert!(is_transient(&RustADBError::ADBRequestFailed(
+            "device offline".to_string()
+        )));
+        assert!(!is_transient(&RustADBError::IOError(
+            ErrorKind::PermissionDenied.into()
+        )));
+        // The command may have run before the connection dropped
+        assert!(!is_transient(&RustADBError::IOError(
+            ErrorKind::ConnectionReset.into()
+        )));
+        assert!(!is_transient(&RustADBError::IOError(
+            ErrorKind::TimedOut.into()
+        )));
+    }
+
     #[test]
     fn test_command_output_creation() {
         let output = CommandOutput {
@@ -462,6 +1504,7 @@ mod tests {
             stderr: "".to_string(),
             exit_code: 0,
             duration_ms: 100,
+            truncated: false,
         };
 
         assert_eq!(output.stdout, "Hello World");
@@ -469,6 +1512,60 @@ mod tests {
         assert_eq!(output.duration_ms, 100);
     }
 
+    #[tokio::test]
+    async fn test_run_blocking_returns_result() {
+        // Creating the handle doesn't connect, so no device is needed
+        let result = run_blocking("emulator-5554".to_string(), |_| Ok::<_, ShellError>(42)).await;
+        assert_eq!(result, Ok(42));
+
+        let result: Result<(), ShellError> = run_blocking("emulator-5554".to_string(), |_| {
+            Err(ShellError::Timeout(String::new()))
+        })
+        .await;
+        assert_eq!(result, Err(ShellError::Timeout(String::new())));
+    }
+
+    #[test]
+    fn test_shell_quote_safe() {
+        assert_eq!(shell_quote("com.example.app"), "com.example.app");
+        assert_eq!(
+            shell_quote("/sdcard/Download/a-b_c.txt"),
+            "/sdcard/Download/a-b_c.txt"
+        );
+    }
+
+    #[test]
+    fn test_shell_quote_spaces_and_quotes() {
+        assert_eq!(shell_quote("/sdcard/My Photos"), "'/sdcard/My Photos'");
+        assert_eq!(shell_quote("it's.txt"), "'it'\\''s.txt'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_shell_quote_injection() {
+        assert_eq!(shell_quote("a; rm -rf /"), "'a; rm -rf /'");
+        assert_eq!(shell_quote("$(reboot)"), "'$(reboot)'");
+        assert_eq!(shell_quote("`id`"), "'`id`'");
+    }
+
+    #[test]
+    fn test_limited_writer_under_limit() {
+        let mut writer = LimitedWriter::new(16);
+        writer.write_all(b"hello").unwrap();
+        writer.write_all(b" world").unwrap();
+        assert_eq!(writer.buf, b"hello world");
+        assert!(!writer.truncated);
+    }
+
+    #[test]
+    fn test_limited_writer_truncates() {
+        let mut writer = LimitedWriter::new(8);
+        writer.write_all(b"12345").unwrap();
+        assert!(writer.write_all(b"67890").is_err());
+        assert_eq!(writer.buf, b"12345678");
+        assert!(writer.truncated);
+    }
+
     #[test]
     fn test_shell_executor_new() {
         let executor = ShellExecutor::new();
@@ -541,21 +1638,77 @@ mod tests {
             name: "wlan0".to_string(),
             state: "UP".to_string(),
             ip_address: "192.168.1.100".to_string(),
+            ipv6_addresses: Vec::new(),
+            mac_address: None,
         };
 
         assert_eq!(iface.name, "wlan0");
         assert_eq!(iface.state, "UP");
     }
 
+    const IP_ADDR_SAMPLE: &str = "\
+1: lo: <LOOPBACK,UP,LOWER_UP> mtu 65536 qdisc noqueue state UNKNOWN group default qlen 1000
+    link/loopback 00:00:00:00:00:00 brd 00:00:00:00:00:00
+    inet 127.0.0.1/8 scope host lo
+       valid_lft forever preferred_lft forever
+    inet6 ::1/128 scope host
+       valid_lft forever preferred_lft forever
+2: dummy0: <BROADCAST,NOARP> mtu 1500 qdisc noop state DOWN group default qlen 1000
+    link/ether 6a:1c:93:2e:0f:41 brd ff:ff:ff:ff:ff:ff
+23: wlan0: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500 qdisc mq state UP group default qlen 3000
+    link/ether 02:00:00:44:55:66 brd ff:ff:ff:ff:ff:ff
+    inet 192.168.1.42/24 brd 192.168.1.255 scope global wlan0
+       valid_lft forever preferred_lft forever
+    inet6 2001:db8::1c2d:3e4f/64 scope global dynamic mngtmpaddr
+       valid_lft 86318sec preferred_lft 14318sec
+    inet6 fe80::ff:fe44:5566/64 scope link
+       valid_lft forever preferred_lft forever
+31: rmnet_data0@rmnet_ipa0: <UP,LOWER_UP> mtu 1500 qdisc mq state UNKNOWN group default qlen 1000
+    link/[530]
+    inet6 2001:db8:100::7/64 scope global
+       valid_lft forever preferred_lft forever
+";
+
+    #[test]
+    fn test_parse_ip_addr() {
+        let interfaces = parse_ip_addr(IP_ADDR_SAMPLE, false);
+        let names: Vec<&str> = interfaces.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["dummy0", "wlan0", "rmnet_data0"]);
+
+        let wlan = &interfaces[1];
+        assert_eq!(wlan.state, "UP");
+        assert_eq!(wlan.ip_address, "192.168.1.42");
+        assert_eq!(
+            wlan.ipv6_addresses,
+            vec!["2001:db8::1c2d:3e4f", "fe80::ff:fe44:5566"]
+        );
+        assert_eq!(wlan.mac_address.as_deref(), Some("02:00:00:44:55:66"));
+
+        let rmnet = &interfaces[2];
+        assert!(rmnet.ip_address.is_empty());
+        assert_eq!(rmnet.ipv6_addresses, vec!["2001:db8:100::7"]);
+        assert_eq!(rmnet.mac_address, None);
+    }
+
+    #[test]
+    fn test_parse_ip_addr_include_loopback() {
+        let interfaces = parse_ip_addr(IP_ADDR_SAMPLE, true);
+        let lo = &interfaces[0];
+        assert_eq!(lo.name, "lo");
+        assert_eq!(lo.ip_address, "127.0.0.1");
+        assert_eq!(lo.ipv6_addresses, vec!["::1"]);
+        assert_eq!(lo.mac_address, None);
+    }
+
     #[test]
     fn test_battery_info_creation() {
         let battery = BatteryInfo {
             level: 75,
             scale: 100,
             percentage: 75,
-            status: "Charging".to_string(),
-            health: "Good".to_string(),
-            plugged: "USB".to_string(),
+            status: BatteryStatus::Charging,
+            health: BatteryHealth::Good,
+            plugged: PluggedState::Usb,
             voltage: 4200,
             temperature: 25.5,
             technology: "Li-ion".to_string(),
@@ -565,6 +1718,46 @@ mod tests {
         assert_eq!(battery.temperature, 25.5);
     }
 
+    #[test]
+    fn test_dumpsys_command() {
+        assert_eq!(dumpsys_command("battery"), "dumpsys battery");
+        assert_eq!(
+            dumpsys_command(" meminfo  com.example "),
+            "dumpsys meminfo com.example"
+        );
+        assert_eq!(
+            dumpsys_command("battery;reboot"),
+            "dumpsys 'battery;reboot'"
+        );
+    }
+
+    #[test]
+    fn test_parse_dumpsys_services() {
+        let output = "Currently running services:\n  SurfaceFlinger\n  activity\n  battery\n  \
+                      android.hardware.power.IPower/default\n";
+        assert_eq!(
+            parse_dumpsys_services(output),
+            vec![
+                "SurfaceFlinger",
+                "activity",
+                "battery",
+                "android.hardware.power.IPower/default"
+            ]
+        );
+        assert!(parse_dumpsys_services("").is_empty());
+    }
+
+    #[test]
+    fn test_battery_codes() {
+        assert_eq!(BatteryStatus::from_code(2), BatteryStatus::Charging);
+        assert_eq!(BatteryStatus::from_code(42), BatteryStatus::Unknown);
+        assert_eq!(BatteryHealth::from_code(3), BatteryHealth::Overheat);
+        assert_eq!(PluggedState::from_code(2), PluggedState::Usb);
+        assert_eq!(PluggedState::from_code(0), PluggedState::Unplugged);
+        assert_eq!(PluggedState::from_code(16), PluggedState::Unknown(16));
+        assert_eq!(BatteryStatus::NotCharging.to_string(), "Not charging");
+    }
+
     #[test]
     fn test_shell_error_display() {
         let err = ShellError::DeviceNotFound;
@@ -579,8 +1772,8 @@ mod tests {
 
     #[test]
     fn test_shell_error_variants() {
-        let err1 = ShellError::Timeout;
-        let err2 = ShellError::Timeout;
+        let err1 = ShellError::Timeout(String::new());
+        let err2 = ShellError::Timeout(String::new());
         assert_eq!(err1, err2);
 
         let err3 = ShellError::PermissionDenied;
@@ -595,6 +1788,7 @@ mod tests {
             stderr: "error message".to_string(),
             exit_code: 1,
             duration_ms: 50,
+            truncated: false,
         };
 
         assert_eq!(output.stderr, "error message");
@@ -636,6 +1830,94 @@ mod tests {
         assert_eq!(command, "setprop debug.sf.hwc 1");
     }
 
+    #[test]
+    fn test_parse_kernel_log_line() {
+        let entry =
+            parse_kernel_log_line("<6>[    0.000000] Booting Linux on physical CPU 0x0").unwrap();
+        assert_eq!(entry.level, Some(6));
+        assert_eq!(entry.timestamp, Some(0.0));
+        assert_eq!(entry.message, "Booting Linux on physical CPU 0x0");
+
+        let entry = parse_kernel_log_line("<3>[ 1234.567890][  T812] ufshcd: link lost").unwrap();
+        assert_eq!(entry.level, Some(3));
+        assert_eq!(entry.timestamp, Some(1234.56789));
+        assert_eq!(entry.message, "ufshcd: link lost");
+
+        let entry = parse_kernel_log_line("[   12.500000] <4>thermal: cpu0 throttled").unwrap();
+        assert_eq!(entry.level, Some(4));
+        assert_eq!(entry.message, "thermal: cpu0 throttled");
+
+        let entry = parse_kernel_log_line("plain line").unwrap();
+        assert_eq!((entry.level, entry.timestamp), (None, None));
+        assert_eq!(entry.message, "plain line");
+
+        assert_eq!(parse_kernel_log_line("   "), None);
+    }
+
+    #[test]
+    fn test_strip_found_path() {
+        assert_eq!(
+            strip_found_path("/sys/fs/pstore/console-ramoops-0\n[  1.0] init: panic\n[  1.1] end"),
+            Some("[  1.0] init: panic\n[  1.1] end".to_string())
+        );
+        assert_eq!(strip_found_path("/proc/last_kmsg"), Some(String::new()));
+        assert_eq!(strip_found_path(""), None);
+        assert_eq!(
+            strip_found_path("/system/bin/sh: su: inaccessible or not found"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_dmesg_denied() {
+        assert!(is_dmesg_denied("dmesg: klogctl: Permission denied"));
+        assert!(is_dmesg_denied(""));
+        assert!(!is_dmesg_denied("<6>[    0.000000] Booting Linux"));
+    }
+
+    #[test]
+    fn test_check_clipboard_output() {
+        assert!(check_clipboard_output("https://example.com/?token=abc").is_ok());
+        assert!(matches!(
+            check_clipboard_output("cmd: Can't find service: clipboard"),
+            Err(ShellError::Unsupported(_))
+        ));
+        assert!(matches!(
+            check_clipboard_output("Unknown command: get-text"),
+            Err(ShellError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_setenforce_output() {
+        assert!(check_setenforce_output("").is_ok());
+        assert_eq!(
+            check_setenforce_output(
+                "setenforce: Couldn't set enforcing status to '0': Permission denied"
+            ),
+            Err(ShellError::PermissionDenied)
+        );
+        assert_eq!(
+            check_setenforce_output("/system/bin/sh: su: inaccessible or not found"),
+            Err(ShellError::PermissionDenied)
+        );
+        assert!(matches!(
+            check_setenforce_output("usage: setenforce [enforcing|permissive|1|0]"),
+            Err(ShellError::CommandFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_setprop_output() {
+        assert!(check_setprop_output("").is_ok());
+
+        let output = "Failed to set property 'ro.debuggable' to '1'.\nSee dmesg for error reason.";
+        match check_setprop_output(output) {
+            Err(ShellError::CommandFailed(msg)) => assert!(msg.contains("ro.debuggable")),
+            other => panic!("expected CommandFailed, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_shell_command_format_su() {
         let cmd = "ls -la";
@@ -656,9 +1938,9 @@ mod tests {
             level: 50,
             scale: 100,
             percentage: 50,
-            status: "Discharging".to_string(),
-            health: "Good".to_string(),
-            plugged: "".to_string(),
+            status: BatteryStatus::Discharging,
+            health: BatteryHealth::Good,
+            plugged: PluggedState::Unplugged,
             voltage: 3700,
             temperature: 30.0,
             technology: "Li-ion".to_string(),
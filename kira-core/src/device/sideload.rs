@@ -0,0 +1,260 @@
+use crate::config::adb_config;
+use crate::device::apk::zip_has_entry;
+use crate::device::connection::send_server_request;
+use adb_client::server::DeviceState;
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+
+const UPDATE_BINARY_ENTRY: &str = "META-INF/com/google/android/update-binary";
+// A/B OTAs are applied by update_engine and ship a payload instead of an updater
+const AB_PAYLOAD_ENTRY: &str = "payload.bin";
+
+// Same block size the adb CLI offers to recovery
+const SIDELOAD_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Streams the OTA package at `zip_path` to a device sitting in recovery's "Apply update
+/// from ADB" screen, like `adb sideload` does.
+///
+/// Recovery asks for the package block by block and reads most of it twice (verify, then
+/// install), `progress` gets `(bytes, total)` for the furthest point read so far.
+pub fn sideload(
+    device: &mut ADBServerDevice,
+    zip_path: &str,
+    progress: impl FnMut(u64, u64),
+) -> Result<(), SideloadError> {
+    check_ota_package(zip_path)?;
+    let mut file = File::open(zip_path)
+        .map_err(|e| SideloadError::InvalidPackage(format!("{}: {}", zip_path, e)))?;
+    let total = file
+        .metadata()
+        .map_err(|e| SideloadError::InvalidPackage(format!("{}: {}", zip_path, e)))?
+        .len();
+
+    let serial = device.identifier.clone();
+    ensure_sideload_state(serial.as_deref())?;
+
+    let mut stream = TcpStream::connect(adb_config().server_addr())
+        .map_err(|e| SideloadError::TransferFailed(e.to_string()))?;
+    let transport = match &serial {
+        Some(serial) => format!("host:transport:{}", serial),
+        None => "host:transport-any".to_string(),
+    };
+    send_server_request(&mut stream, &transport).map_err(SideloadError::TransferFailed)?;
+    send_server_request(
+        &mut stream,
+        &format!("sideload-host:{}:{}", total, SIDELOAD_BLOCK_SIZE),
+    )
+    .map_err(SideloadError::TransferFailed)?;
+
+    serve_blocks(&mut stream, &mut file, total, SIDELOAD_BLOCK_SIZE, progress)
+}
+
+fn check_ota_package(zip_path: &str) -> Result<(), SideloadError> {
+    let has_entry = |entry| {
+        zip_has_entry(zip_path, entry)
+            .map_err(|e| SideloadError::InvalidPackage(format!("{}: {}", zip_path, e)))
+    };
+
+    if has_entry(UPDATE_BINARY_ENTRY)? || has_entry(AB_PAYLOAD_ENTRY)? {
+        Ok(())
+    } else {
+        Err(SideloadError::InvalidPackage(format!(
+            "{} is not an OTA package, it has no {}",
+            zip_path, UPDATE_BINARY_ENTRY
+        )))
+    }
+}
+
+fn ensure_sideload_state(serial: Option<&str>) -> Result<(), SideloadError> {
+    let devices = adb_config()
+        .server()
+        .devices()
+        .map_err(|e| SideloadError::TransferFailed(e.to_string()))?;
+    let state = devices
+        .into_iter()
+        .find(|d| serial.is_none_or(|s| d.identifier == s))
+        .map(|d| d.state);
+
+    match state {
+        Some(DeviceState::Sideload) => Ok(()),
+        Some(DeviceState::Recovery) => Err(SideloadError::NotInSideload(
+            "device is in recovery, choose \"Apply update from ADB\" first".to_string(),
+        )),
+        Some(state) => Err(SideloadError::NotInSideload(format!(
+            "device is {}, reboot it to sideload first",
+            state
+        ))),
+        None => Err(SideloadError::NotInSideload("device not found".to_string())),
+    }
+}
+
+/// Answers recovery's block requests (8 ASCII digits) until it sends DONEDONE or FAILFAIL.
+fn serve_blocks<S: Read + Write, F: Read + Seek>(
+    stream: &mut S,
+    file: &mut F,
+    total: u64,
+    block_size: u64,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<(), SideloadError> {
+    let mut buffer = vec![0u8; block_size as usize];
+    let mut furthest = 0;
+
+    loop {
+        let mut request = [0u8; 8];
+        stream
+            .read_exact(&mut request)
+            .map_err(|e| SideloadError::TransferFailed(e.to_string()))?;
+
+        match &request {
+            b"DONEDONE" => return Ok(()),
+            b"FAILFAIL" => {
+                return Err(SideloadError::InstallFailed(
+                    "recovery rejected the package, check the device screen".to_string(),
+                ));
+            }
+            _ => {}
+        }
+
+        let block: u64 = std::str::from_utf8(&request)
+            .ok()
+            .and_then(|block| block.parse().ok())
+            .ok_or_else(|| {
+                SideloadError::TransferFailed(format!(
+                    "Unexpected request {:?}",
+                    String::from_utf8_lossy(&request)
+                ))
+            })?;
+        let offset = block * block_size;
+        if offset >= total {
+            return Err(SideloadError::TransferFailed(format!(
+                "Block {} is past the end of the package",
+                block
+            )));
+        }
+
+        let len = block_size.min(total - offset) as usize;
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut buffer[..len]))
+            .map_err(|e| SideloadError::InvalidPackage(e.to_string()))?;
+        stream
+            .write_all(&buffer[..len])
+            .map_err(|e| SideloadError::TransferFailed(e.to_string()))?;
+
+        if offset + len as u64 > furthest {
+            furthest = offset + len as u64;
+            progress(furthest, total);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum SideloadError {
+    InvalidPackage(String),
+    NotInSideload(String),
+    TransferFailed(String),
+    InstallFailed(String),
+}
+
+impl std::fmt::Display for SideloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SideloadError::InvalidPackage(msg) => write!(f, "Invalid package: {}", msg),
+            SideloadError::NotInSideload(msg) => write!(f, "Not in sideload mode: {}", msg),
+            SideloadError::TransferFailed(msg) => write!(f, "Transfer failed: {}", msg),
+            SideloadError::InstallFailed(msg) => write!(f, "Install failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SideloadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Reads scripted block requests and records what was written back.
+    struct FakeRecovery {
+        requests: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for FakeRecovery {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.requests.read(buf)
+        }
+    }
+
+    impl Write for FakeRecovery {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn recovery(requests: &str) -> FakeRecovery {
+        FakeRecovery {
+            requests: Cursor::new(requests.as_bytes().to_vec()),
+            sent: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_serve_blocks() {
+        let package: Vec<u8> = (0..10).collect();
+        // Verify pass, then the install pass reads the start again
+        let mut stream = recovery("000000000000000100000002000000000000000000000001DONEDONE");
+        let mut updates = Vec::new();
+
+        let result = serve_blocks(
+            &mut stream,
+            &mut Cursor::new(&package),
+            10,
+            4,
+            |bytes, total| updates.push((bytes, total)),
+        );
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(&stream.sent[..10], &package[..]);
+        assert_eq!(stream.sent.len(), 10 + 4 + 4 + 4);
+        assert_eq!(updates, vec![(4, 10), (8, 10), (10, 10)]);
+    }
+
+    #[test]
+    fn test_serve_blocks_errors() {
+        let package = [0u8; 10];
+        let serve = |requests| {
+            serve_blocks(
+                &mut recovery(requests),
+                &mut Cursor::new(&package),
+                10,
+                4,
+                |_, _| {},
+            )
+        };
+
+        assert!(matches!(
+            serve("00000000FAILFAIL"),
+            Err(SideloadError::InstallFailed(_))
+        ));
+        assert!(matches!(
+            serve("00000003"),
+            Err(SideloadError::TransferFailed(_))
+        ));
+        assert!(matches!(
+            serve("garbage!"),
+            Err(SideloadError::TransferFailed(_))
+        ));
+        // Connection closed without DONEDONE
+        assert!(matches!(
+            serve("00000000"),
+            Err(SideloadError::TransferFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,30 @@
+use crate::DeviceInfo;
+use crate::device::app_manager::{TopPackage, get_top_package};
+use crate::device::performance::{PerformanceProfile, get_performance_profile};
+use crate::device::root::{RootStatus, has_root_access};
+use crate::device::try_collect_device_info;
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+
+/// Device info, performance readings, foreground app and root state in one go, for
+/// screens that show all of them at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceSnapshot {
+    pub info: Result<DeviceInfo, String>,
+    pub performance: PerformanceProfile,
+    pub top_package: Result<TopPackage, String>,
+    pub root: RootStatus,
+}
+
+/// Reads the properties with a single `getprop` dump, see [`try_collect_device_info`]. Each
+/// part is collected even when an earlier one failed.
+pub fn get_device_snapshot(device: &mut ADBServerDevice) -> DeviceSnapshot {
+    let serial = device.identifier.clone().unwrap_or_default();
+
+    DeviceSnapshot {
+        info: try_collect_device_info(device, &serial).map_err(|e| e.to_string()),
+        performance: get_performance_profile(device),
+        top_package: get_top_package(device).map_err(|e| e.to_string()),
+        root: has_root_access(device),
+    }
+}
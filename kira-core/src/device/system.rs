@@ -1,4 +1,8 @@
+use crate::config::{adb_config, adb_device, adb_server};
+use crate::device::connection::send_server_request;
+use crate::device::{BootloaderState, DeviceError, FastbootCore, parse_props};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RebootMode {
@@ -23,11 +27,446 @@ impl From<RebootMode> for adb_client::RebootType {
     }
 }
 
+impl RebootMode {
+    /// Mode names accepted by `from_str`.
+    pub const NAMES: [&'static str; 6] = [
+        "normal",
+        "recovery",
+        "bootloader",
+        "fastboot",
+        "sideload",
+        "sideload-auto-reboot",
+    ];
+}
+
+impl std::str::FromStr for RebootMode {
+    type Err = RebootError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(RebootMode::Normal),
+            "recovery" => Ok(RebootMode::Recovery),
+            "bootloader" => Ok(RebootMode::Bootloader),
+            "fastboot" => Ok(RebootMode::Fastboot),
+            "sideload" => Ok(RebootMode::Sideload),
+            "sideload-auto-reboot" => Ok(RebootMode::SideloadAutoReboot),
+            _ => Err(RebootError::InvalidMode(s.to_string())),
+        }
+    }
+}
+
 use adb_client::ADBDeviceExt;
+use adb_client::server::{ADBServer, DeviceState};
 use adb_client::server_device::ADBServerDevice;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Some devices take a while to shut down after accepting the command
+const REBOOT_DROP_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Reboots into `mode` and checks that the device really went down: it has to drop off
+/// the ADB server within a few seconds or come back with a lower uptime. A command that
+/// was accepted but swallowed ends up as `NotRebooted`.
+pub fn reboot(device: &mut ADBServerDevice, mode: RebootMode) -> Result<(), RebootError> {
+    let uptime_before = read_uptime(device);
+    device
+        .reboot(mode.into())
+        .map_err(|e| RebootError::CommandFailed(e.to_string()))?;
+    confirm_rebooted(device, uptime_before)
+}
+
+/// Reboots to a target `RebootMode` doesn't name, like `adb reboot edl` for Qualcomm's
+/// emergency download mode. A reason the bootloader doesn't know is usually ignored and
+/// the device just restarts normally.
+pub fn reboot_custom(device: &mut ADBServerDevice, reason: &str) -> Result<(), RebootError> {
+    let reason = reason.trim();
+    if !is_valid_reboot_reason(reason) {
+        return Err(RebootError::InvalidReason(reason.to_string()));
+    }
+    let uptime_before = read_uptime(device);
+    reboot_with_reason(device, reason)?;
+    confirm_rebooted(device, uptime_before)
+}
+
+// adb_client only sends the reboot targets it knows, so talk to adbd's reboot service
+// directly the way `adb reboot <reason>` does
+fn reboot_with_reason(device: &ADBServerDevice, reason: &str) -> Result<(), RebootError> {
+    let mut stream = TcpStream::connect(adb_config().server_addr())
+        .map_err(|e| RebootError::CommandFailed(e.to_string()))?;
+    let transport = match &device.identifier {
+        Some(serial) => format!("host:transport:{}", serial),
+        None => "host:transport-any".to_string(),
+    };
+    send_server_request(&mut stream, &transport).map_err(RebootError::CommandFailed)?;
+    send_server_request(&mut stream, &format!("reboot:{}", reason))
+        .map_err(RebootError::CommandFailed)
+}
+
+fn confirm_rebooted(
+    device: &mut ADBServerDevice,
+    uptime_before: Option<f64>,
+) -> Result<(), RebootError> {
+    let serial = device.identifier.clone();
+    let deadline = Instant::now() + REBOOT_DROP_TIMEOUT;
+    let mut server = adb_server();
+
+    while Instant::now() < deadline {
+        if !is_listed(&mut server, serial.as_deref(), None)? {
+            return Ok(());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    // An emulator can be back before a poll saw it gone
+    if rebooted_since(uptime_before, read_uptime(device)) {
+        return Ok(());
+    }
+    Err(RebootError::NotRebooted(format!(
+        "device was still online {}s after the reboot command",
+        REBOOT_DROP_TIMEOUT.as_secs()
+    )))
+}
+
+fn read_uptime(device: &mut ADBServerDevice) -> Option<f64> {
+    let mut output = Vec::new();
+    device
+        .shell_command(&"cat /proc/uptime", Some(&mut output), None)
+        .ok()?;
+    parse_uptime(&String::from_utf8_lossy(&output))
+}
+
+fn parse_uptime(output: &str) -> Option<f64> {
+    output.split_whitespace().next()?.parse().ok()
+}
+
+fn rebooted_since(before: Option<f64>, after: Option<f64>) -> bool {
+    matches!((before, after), (Some(before), Some(after)) if after < before)
+}
 
-pub fn reboot(device: &mut ADBServerDevice, mode: RebootMode) -> Result<(), anyhow::Error> {
-    let reboot_type: adb_client::RebootType = mode.into();
-    device.reboot(reboot_type)?;
-    Ok(())
+fn is_valid_reboot_reason(reason: &str) -> bool {
+    !reason.is_empty()
+        && reason
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ',' | '.'))
+}
+
+/// Reboots the device and blocks until it is usable again in the target mode.
+///
+/// For `Normal` that means `sys.boot_completed` is `1`. Bootloader and fastboot are not
+/// visible to the ADB server, so for those it only waits for the device to drop off.
+pub fn reboot_and_wait(
+    device: &mut ADBServerDevice,
+    mode: RebootMode,
+    timeout: Duration,
+) -> Result<(), RebootError> {
+    let serial = device.identifier.clone();
+    let deadline = Instant::now() + timeout;
+    let mut server = adb_server();
+
+    // Only returns once the device went offline
+    reboot(device, mode)?;
+
+    let expected = match expected_state(mode) {
+        Some(state) => state,
+        None => return Ok(()),
+    };
+
+    while !is_listed(&mut server, serial.as_deref(), Some(&expected))? {
+        if Instant::now() >= deadline {
+            return Err(RebootError::Timeout(format!(
+                "device did not reappear in {} mode",
+                expected
+            )));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    if mode != RebootMode::Normal {
+        return Ok(());
+    }
+
+    let mut device = match &serial {
+        Some(serial) => adb_device(serial.clone()),
+        None => ADBServerDevice::autodetect(Some(adb_config().server_addr())),
+    };
+    loop {
+        let mut output = Vec::new();
+        if device
+            .shell_command(&"getprop sys.boot_completed", Some(&mut output), None)
+            .is_ok()
+            && is_boot_completed(&String::from_utf8_lossy(&output))
+        {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(RebootError::BootIncomplete(
+                "sys.boot_completed not set before timeout".to_string(),
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Reads the bootloader lock state from the boot properties.
+///
+/// Vendors disagree on which of `ro.boot.flash.locked`, `ro.boot.vbmeta.device_state`
+/// and `ro.boot.verifiedbootstate` they set, so they are checked in that order.
+pub fn get_bootloader_state(device: &mut ADBServerDevice) -> Result<BootloaderState, DeviceError> {
+    let mut output = Vec::new();
+    device
+        .shell_command(&"getprop", Some(&mut output), None)
+        .map_err(|e| DeviceError::CommandFailed(e.to_string()))?;
+    let raw = String::from_utf8(output).map_err(|e| DeviceError::IOError(e.to_string()))?;
+
+    Ok(bootloader_state_from_props(&parse_props(&raw)))
+}
+
+fn bootloader_state_from_props(props: &HashMap<String, String>) -> BootloaderState {
+    let prop = |key: &str| props.get(key).map(|v| v.trim().to_lowercase());
+
+    match prop("ro.boot.flash.locked").as_deref() {
+        Some("1") => return BootloaderState::Locked,
+        Some("0") => return BootloaderState::Unlocked,
+        _ => {}
+    }
+
+    match prop("ro.boot.vbmeta.device_state").as_deref() {
+        Some("locked") => return BootloaderState::Locked,
+        Some("unlocked") => return BootloaderState::Unlocked,
+        _ => {}
+    }
+
+    // yellow is a locked bootloader with a user supplied key, red is a failed verification
+    match prop("ro.boot.verifiedbootstate").as_deref() {
+        Some("green") | Some("yellow") => BootloaderState::Locked,
+        Some("orange") => BootloaderState::Unlocked,
+        _ => BootloaderState::Unknown,
+    }
+}
+
+/// What a device is running, as far as the host can tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeviceMode {
+    Adb,
+    Recovery,
+    Sideload,
+    Fastboot,
+    Offline,
+    Unauthorized,
+    NotFound,
+}
+
+/// Looks `serial` up in the ADB server's device list, then among the fastboot devices on
+/// USB, which the ADB server doesn't see. Use it after a reboot to the bootloader to know
+/// when to switch to `FastbootCore`.
+pub fn detect_device_mode(serial: &str) -> DeviceMode {
+    let mut server = adb_server();
+    let adb_state = server
+        .devices()
+        .ok()
+        .and_then(|devices| devices.into_iter().find(|d| d.identifier == serial))
+        .map(|d| d.state);
+
+    if let Some(mode) = adb_state.as_ref().and_then(mode_from_adb_state) {
+        return mode;
+    }
+    // An adb entry that went offline may be the device sitting in the bootloader
+    if FastbootCore::list_serials().is_ok_and(|serials| serials.iter().any(|s| s == serial)) {
+        return DeviceMode::Fastboot;
+    }
+
+    match adb_state {
+        Some(_) => DeviceMode::Offline,
+        None => DeviceMode::NotFound,
+    }
+}
+
+fn mode_from_adb_state(state: &DeviceState) -> Option<DeviceMode> {
+    match state {
+        DeviceState::Device => Some(DeviceMode::Adb),
+        DeviceState::Recovery | DeviceState::Rescue => Some(DeviceMode::Recovery),
+        DeviceState::Sideload => Some(DeviceMode::Sideload),
+        DeviceState::Bootloader => Some(DeviceMode::Fastboot),
+        DeviceState::Unauthorized | DeviceState::Authorizing => Some(DeviceMode::Unauthorized),
+        _ => None,
+    }
+}
+
+fn expected_state(mode: RebootMode) -> Option<DeviceState> {
+    match mode {
+        RebootMode::Normal => Some(DeviceState::Device),
+        RebootMode::Recovery => Some(DeviceState::Recovery),
+        RebootMode::Sideload | RebootMode::SideloadAutoReboot => Some(DeviceState::Sideload),
+        RebootMode::Bootloader | RebootMode::Fastboot => None,
+    }
+}
+
+// With no serial any device counts, matching what `ADBServerDevice::autodetect` would pick
+fn is_listed(
+    server: &mut ADBServer,
+    serial: Option<&str>,
+    state: Option<&DeviceState>,
+) -> Result<bool, RebootError> {
+    let devices = server
+        .devices()
+        .map_err(|e| RebootError::CommandFailed(e.to_string()))?;
+
+    Ok(devices.iter().any(|d| {
+        serial.is_none_or(|s| d.identifier == s)
+            && match state {
+                Some(state) => std::mem::discriminant(&d.state) == std::mem::discriminant(state),
+                None => !matches!(d.state, DeviceState::Offline),
+            }
+    }))
+}
+
+fn is_boot_completed(output: &str) -> bool {
+    output.trim() == "1"
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum RebootError {
+    CommandFailed(String),
+    Timeout(String),
+    BootIncomplete(String),
+    InvalidMode(String),
+    InvalidReason(String),
+    NotRebooted(String),
+}
+
+impl std::fmt::Display for RebootError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RebootError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+            RebootError::Timeout(msg) => write!(f, "Timed out: {}", msg),
+            RebootError::BootIncomplete(msg) => write!(f, "Boot did not complete: {}", msg),
+            RebootError::InvalidMode(mode) => write!(
+                f,
+                "Unknown reboot mode '{}', expected one of: {}",
+                mode,
+                RebootMode::NAMES.join(", ")
+            ),
+            RebootError::NotRebooted(msg) => write!(f, "Device did not reboot: {}", msg),
+            RebootError::InvalidReason(reason) => write!(
+                f,
+                "Invalid reboot reason '{}', use letters, digits, '-', '_', ',' or '.'",
+                reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RebootError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_from_adb_state() {
+        assert_eq!(
+            mode_from_adb_state(&DeviceState::Device),
+            Some(DeviceMode::Adb)
+        );
+        assert_eq!(
+            mode_from_adb_state(&DeviceState::Rescue),
+            Some(DeviceMode::Recovery)
+        );
+        assert_eq!(
+            mode_from_adb_state(&DeviceState::Authorizing),
+            Some(DeviceMode::Unauthorized)
+        );
+        // Left to the fastboot lookup
+        assert_eq!(mode_from_adb_state(&DeviceState::Offline), None);
+    }
+
+    #[test]
+    fn test_reboot_mode_from_str() {
+        for name in RebootMode::NAMES {
+            assert!(name.parse::<RebootMode>().is_ok(), "{} should parse", name);
+        }
+        assert_eq!("recovery".parse::<RebootMode>(), Ok(RebootMode::Recovery));
+
+        let err = "recvery".parse::<RebootMode>().unwrap_err();
+        assert_eq!(err, RebootError::InvalidMode("recvery".to_string()));
+        assert!(err.to_string().contains("recovery, bootloader"));
+    }
+
+    #[test]
+    fn test_is_boot_completed() {
+        assert!(is_boot_completed("1\n"));
+        assert!(!is_boot_completed(""));
+        assert!(!is_boot_completed("0"));
+    }
+
+    fn props(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_bootloader_state_flash_locked() {
+        let state = bootloader_state_from_props(&props(&[
+            ("ro.boot.flash.locked", "0"),
+            ("ro.boot.verifiedbootstate", "green"),
+        ]));
+        assert_eq!(state, BootloaderState::Unlocked);
+    }
+
+    #[test]
+    fn test_bootloader_state_fallbacks() {
+        let state =
+            bootloader_state_from_props(&props(&[("ro.boot.vbmeta.device_state", "locked")]));
+        assert_eq!(state, BootloaderState::Locked);
+
+        let state = bootloader_state_from_props(&props(&[("ro.boot.verifiedbootstate", "orange")]));
+        assert_eq!(state, BootloaderState::Unlocked);
+
+        let state = bootloader_state_from_props(&props(&[("ro.boot.verifiedbootstate", "red")]));
+        assert_eq!(state, BootloaderState::Unknown);
+
+        assert_eq!(
+            bootloader_state_from_props(&HashMap::new()),
+            BootloaderState::Unknown
+        );
+    }
+
+    #[test]
+    fn test_expected_state() {
+        assert!(matches!(
+            expected_state(RebootMode::Normal),
+            Some(DeviceState::Device)
+        ));
+        assert!(matches!(
+            expected_state(RebootMode::Recovery),
+            Some(DeviceState::Recovery)
+        ));
+        assert!(expected_state(RebootMode::Bootloader).is_none());
+    }
+
+    #[test]
+    fn test_rebooted_since() {
+        let before = parse_uptime("8345.21 30112.50\n");
+        assert_eq!(before, Some(8345.21));
+
+        assert!(rebooted_since(before, parse_uptime("12.03 40.11")));
+        assert!(!rebooted_since(before, parse_uptime("8365.80 30190.02")));
+        // Unreachable device, no evidence either way
+        assert!(!rebooted_since(before, None));
+        assert!(!rebooted_since(None, parse_uptime("12.03 40.11")));
+    }
+
+    #[test]
+    fn test_is_valid_reboot_reason() {
+        assert!(is_valid_reboot_reason("edl"));
+        assert!(is_valid_reboot_reason("dynsystem"));
+        assert!(is_valid_reboot_reason("recovery,quiescent"));
+        assert!(!is_valid_reboot_reason(""));
+        assert!(!is_valid_reboot_reason("edl; rm -rf /"));
+        assert!(!is_valid_reboot_reason("a b"));
+    }
 }
@@ -0,0 +1,327 @@
+use crate::device::parse_props;
+use crate::device::shell::shell_output;
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TelephonyInfo {
+    /// One entry per modem slot, empty on Wi-Fi only devices
+    pub slots: Vec<SimSlot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SimSlot {
+    pub slot: u32,
+    pub operator_name: Option<String>,
+    /// `READY`, `ABSENT`, `PIN_REQUIRED` and so on
+    pub sim_state: Option<String>,
+    /// RSRP for LTE and NR, RSCP for 3G, RSSI for GSM
+    pub signal_dbm: Option<i32>,
+    /// `5G` for NR, including LTE anchored NR, otherwise the radio name like `LTE`
+    pub network_type: Option<String>,
+}
+
+/// Operator, SIM state, signal and network type of every SIM slot, from the `gsm.*`
+/// properties and `dumpsys telephony.registry`.
+pub fn get_telephony_info(device: &mut ADBServerDevice) -> Result<TelephonyInfo, TelephonyError> {
+    let props = parse_props(&run_shell_command(device, "getprop")?);
+    // Wi-Fi only devices have no telephony.registry service at all
+    let registry = run_shell_command(device, "dumpsys telephony.registry").unwrap_or_default();
+
+    Ok(parse_telephony_info(&props, &registry))
+}
+
+fn parse_telephony_info(props: &HashMap<String, String>, registry: &str) -> TelephonyInfo {
+    let noril = props
+        .get("ro.radio.noril")
+        .is_some_and(|v| matches!(v.as_str(), "yes" | "true" | "1"));
+    if noril {
+        return TelephonyInfo::default();
+    }
+
+    // Dual SIM devices keep one comma separated entry per slot
+    let per_slot = |key: &str| -> Vec<Option<String>> {
+        props
+            .get(key)
+            .filter(|v| !v.is_empty())
+            .map(|v| {
+                v.split(',')
+                    .map(|s| Some(s.trim().to_string()).filter(|s| !s.is_empty()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let operators = per_slot("gsm.operator.alpha");
+    let sim_states = per_slot("gsm.sim.state");
+    let network_types = per_slot("gsm.network.type");
+    let phones = parse_registry_phones(registry);
+
+    let count = phones.len().max(sim_states.len());
+    let slots = (0..count)
+        .map(|i| {
+            let phone = phones.get(i);
+            let prop_network = network_types
+                .get(i)
+                .cloned()
+                .flatten()
+                .filter(|t| !t.eq_ignore_ascii_case("unknown"))
+                .map(|t| network_type_name(&t));
+
+            SimSlot {
+                slot: i as u32,
+                operator_name: operators.get(i).cloned().flatten(),
+                sim_state: sim_states.get(i).cloned().flatten(),
+                signal_dbm: phone.and_then(|p| p.signal_dbm),
+                network_type: phone.and_then(|p| p.network_type.clone()).or(prop_network),
+            }
+        })
+        .collect();
+
+    TelephonyInfo { slots }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct RegistryPhone {
+    signal_dbm: Option<i32>,
+    network_type: Option<String>,
+}
+
+/// Splits `dumpsys telephony.registry` into its `Phone Id=N` sections.
+fn parse_registry_phones(registry: &str) -> Vec<RegistryPhone> {
+    let mut phones = Vec::new();
+    let mut current: Option<RegistryPhone> = None;
+
+    for line in registry.lines() {
+        let line = line.trim();
+        if line.starts_with("Phone Id=") {
+            phones.extend(current.take());
+            current = Some(RegistryPhone::default());
+            continue;
+        }
+        let Some(phone) = current.as_mut() else {
+            continue;
+        };
+
+        if let Some(signal) = line.strip_prefix("mSignalStrength=") {
+            phone.signal_dbm = parse_signal_dbm(signal);
+        } else if let Some(display) = line.strip_prefix("mTelephonyDisplayInfo=") {
+            phone.network_type = parse_display_network(display);
+        }
+    }
+    phones.extend(current);
+
+    phones
+}
+
+// Field holding the dBm value of each `CellSignalStrength*` kind
+const SIGNAL_FIELDS: [(&str, &str); 6] = [
+    ("Nr", "ssRsrp"),
+    ("Lte", "rsrp"),
+    ("Wcdma", "rscp"),
+    ("Tdscdma", "rscp"),
+    ("Gsm", "rssi"),
+    ("Cdma", "cdmaDbm"),
+];
+
+/// Reads the serving cell's dBm out of `SignalStrength:{mCdma=...,mLte=...,primary=...}`.
+/// Without a `primary` entry the first kind with a valid value is used.
+fn parse_signal_dbm(signal: &str) -> Option<i32> {
+    let value_of = |kind: &str, field: &str| {
+        let start = signal.find(&format!("m{}=CellSignalStrength{}", kind, kind))?;
+        let segment = &signal[start..];
+        // the next kind starts with `,m`, the trailer with `,primary`
+        let end = [",m", ",primary"]
+            .iter()
+            .filter_map(|sep| segment[1..].find(sep).map(|e| e + 1))
+            .min()
+            .unwrap_or(segment.len());
+        signal_field(&segment[..end], field)
+    };
+
+    let primary = signal
+        .split_once("primary=CellSignalStrength")
+        .and_then(|(_, rest)| rest.split(|c: char| !c.is_ascii_alphabetic()).next());
+    if let Some(primary) = primary
+        && let Some((kind, field)) = SIGNAL_FIELDS.iter().find(|(k, _)| *k == primary)
+    {
+        return value_of(kind, field);
+    }
+
+    SIGNAL_FIELDS
+        .iter()
+        .find_map(|(kind, field)| value_of(kind, field))
+}
+
+/// `rsrp=-93` or `ssRsrp = -95`. `Integer.MAX_VALUE` marks a value the modem didn't
+/// report.
+fn signal_field(segment: &str, field: &str) -> Option<i32> {
+    segment.match_indices(field).find_map(|(at, _)| {
+        // the field must not be the tail of a longer name like `csiRsrp`
+        let preceded_by_name = segment[..at]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric());
+        if preceded_by_name {
+            return None;
+        }
+        let rest = segment[at + field.len()..].trim_start().strip_prefix('=')?;
+        let rest = rest.trim_start();
+        let end = rest
+            .char_indices()
+            .find(|(i, c)| !(c.is_ascii_digit() || (*i == 0 && *c == '-')))
+            .map_or(rest.len(), |(i, _)| i);
+        let value: i32 = rest[..end].parse().ok()?;
+        (value != i32::MAX && value < 0).then_some(value)
+    })
+}
+
+/// `TelephonyDisplayInfo {network=LTE, overrideNetwork=NR_NSA, isRoaming=false}`, where
+/// the override tells LTE anchored 5G apart from plain LTE.
+fn parse_display_network(display: &str) -> Option<String> {
+    let field = |name: &str| {
+        display
+            .split(['{', '}', ','])
+            .find_map(|f| f.trim().strip_prefix(name))
+            .map(str::trim)
+    };
+
+    if field("overrideNetwork=").is_some_and(|o| o.starts_with("NR")) {
+        return Some("5G".to_string());
+    }
+    field("network=")
+        .filter(|n| !n.eq_ignore_ascii_case("unknown"))
+        .map(network_type_name)
+}
+
+fn network_type_name(name: &str) -> String {
+    match name {
+        "NR" => "5G".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn run_shell_command(
+    device: &mut ADBServerDevice,
+    command: &str,
+) -> Result<String, TelephonyError> {
+    let output =
+        shell_output(device, command).map_err(|e| TelephonyError::CommandFailed(e.to_string()))?;
+
+    String::from_utf8(output)
+        .map_err(|e| TelephonyError::ParseError(e.to_string()))
+        .map(|s| s.trim().to_string())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum TelephonyError {
+    CommandFailed(String),
+    ParseError(String),
+}
+
+impl std::fmt::Display for TelephonyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TelephonyError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+            TelephonyError::ParseError(msg) => write!(f, "Parse error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TelephonyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGISTRY: &str = "\
+last known state:
+  Phone Id=0
+    mCallState=0
+    mServiceState=...
+    mSignalStrength=SignalStrength:{mCdma=CellSignalStrengthCdma: cdmaDbm=2147483647 cdmaEcio=2147483647 level=0,mGsm=CellSignalStrengthGsm: rssi=2147483647 ber=2147483647 mTa=2147483647 level=0,mWcdma=CellSignalStrengthWcdma: ss=2147483647 ber=2147483647 rscp=2147483647 ecno=2147483647 level=0,mTdscdma=CellSignalStrengthTdscdma: rssi=2147483647 ber=2147483647 rscp=2147483647 level=0,mLte=CellSignalStrengthLte: rssi=-61 rsrp=-93 rsrq=-11 rssnr=30 cqi=2147483647 ta=2147483647 level=3 parametersUseForLevel=0,mNr=CellSignalStrengthNr:{ csiRsrp = -80 csiRsrq = 2147483647 ssRsrp = 2147483647 ssRsrq = 2147483647 ssSinr = 2147483647 level = 0 },primary=CellSignalStrengthLte,mLteAsPrimaryInNrNsa=true}
+    mTelephonyDisplayInfo=TelephonyDisplayInfo {network=LTE, overrideNetwork=NR_NSA, isRoaming=false}
+  Phone Id=1
+    mCallState=0
+    mSignalStrength=SignalStrength:{mCdma=CellSignalStrengthCdma: cdmaDbm=2147483647 level=0,mGsm=CellSignalStrengthGsm: rssi=2147483647 ber=2147483647 level=0,mWcdma=CellSignalStrengthWcdma: ss=2147483647 ber=2147483647 rscp=2147483647 ecno=2147483647 level=0,mTdscdma=CellSignalStrengthTdscdma: rssi=2147483647 level=0,mLte=CellSignalStrengthLte: rssi=2147483647 rsrp=2147483647 level=0,mNr=CellSignalStrengthNr:{ csiRsrp = 2147483647 ssRsrp = 2147483647 level = 0 },primary=CellSignalStrengthCdma}
+    mTelephonyDisplayInfo=TelephonyDisplayInfo {network=UNKNOWN, overrideNetwork=NONE, isRoaming=false}
+";
+
+    fn props(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_telephony_info_dual_sim() {
+        let props = props(&[
+            ("gsm.operator.alpha", "Vodafone,"),
+            ("gsm.sim.state", "READY,ABSENT"),
+            ("gsm.network.type", "LTE,Unknown"),
+        ]);
+        let info = parse_telephony_info(&props, REGISTRY);
+
+        assert_eq!(
+            info.slots,
+            vec![
+                SimSlot {
+                    slot: 0,
+                    operator_name: Some("Vodafone".to_string()),
+                    sim_state: Some("READY".to_string()),
+                    signal_dbm: Some(-93),
+                    network_type: Some("5G".to_string()),
+                },
+                SimSlot {
+                    slot: 1,
+                    operator_name: None,
+                    sim_state: Some("ABSENT".to_string()),
+                    signal_dbm: None,
+                    network_type: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_telephony_info_wifi_only() {
+        let registry = "Can't find service: telephony.registry";
+        assert!(parse_telephony_info(&props(&[]), registry).slots.is_empty());
+
+        let noril = props(&[("ro.radio.noril", "yes"), ("gsm.sim.state", "ABSENT")]);
+        assert!(parse_telephony_info(&noril, "").slots.is_empty());
+    }
+
+    #[test]
+    fn test_parse_signal_dbm() {
+        assert_eq!(
+            parse_signal_dbm(
+                "SignalStrength:{mLte=CellSignalStrengthLte: rssi=2147483647 rsrp=2147483647 level=0,\
+                 mNr=CellSignalStrengthNr:{ csiRsrp = -80 ssRsrp = -101 level = 2 },primary=CellSignalStrengthNr}"
+            ),
+            Some(-101)
+        );
+        // Android 9 prints bare numbers, nothing to read there
+        assert_eq!(
+            parse_signal_dbm("SignalStrength: 99 0 -120 -160 -120 -1 -1 28 -91 -11"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_display_network() {
+        assert_eq!(
+            parse_display_network("TelephonyDisplayInfo {network=NR, overrideNetwork=NONE}"),
+            Some("5G".to_string())
+        );
+        assert_eq!(
+            parse_display_network("TelephonyDisplayInfo {network=HSPAP, overrideNetwork=NONE}"),
+            Some("HSPAP".to_string())
+        );
+        assert_eq!(
+            parse_display_network("TelephonyDisplayInfo {network=UNKNOWN, overrideNetwork=NONE}"),
+            None
+        );
+    }
+}
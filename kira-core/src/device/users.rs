@@ -0,0 +1,182 @@
+use crate::device::shell::{shell_output, shell_quote};
+use adb_client::server_device::ADBServerDevice;
+use serde::{Deserialize, Serialize};
+
+// android.content.pm.UserInfo flags
+const FLAG_PRIMARY: u32 = 0x1;
+const FLAG_ADMIN: u32 = 0x2;
+const FLAG_GUEST: u32 = 0x4;
+const FLAG_MANAGED_PROFILE: u32 = 0x20;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserInfo {
+    pub id: u32,
+    pub name: String,
+    /// Raw `UserInfo.FLAG_*` bits
+    pub flags: u32,
+    /// The owner, user 0 on most devices
+    pub is_primary: bool,
+    pub is_admin: bool,
+    pub is_guest: bool,
+    /// A work profile rather than a full user
+    pub is_managed_profile: bool,
+    pub running: bool,
+}
+
+/// Users and profiles from `pm list users`.
+pub fn list_users(device: &mut ADBServerDevice) -> Result<Vec<UserInfo>, UserError> {
+    let output = run_user_command(device, "pm list users")?;
+    Ok(parse_users(&output))
+}
+
+/// Creates a full user called `name` and returns its id. Needs the device to allow more
+/// than one user, and on some builds root.
+pub fn create_user(device: &mut ADBServerDevice, name: &str) -> Result<u32, UserError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(UserError::InvalidUser("name can't be empty".to_string()));
+    }
+
+    let output = run_user_command(device, &format!("pm create-user {}", shell_quote(name)))?;
+    // `Success: created user id 11`
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Success: created user id "))
+        .and_then(|id| id.trim().parse().ok())
+        .ok_or(UserError::CommandFailed(output))
+}
+
+/// Removes user `id` with all of its apps and data.
+pub fn remove_user(device: &mut ADBServerDevice, id: u32) -> Result<(), UserError> {
+    if id == 0 {
+        return Err(UserError::InvalidUser(
+            "the system user can't be removed".to_string(),
+        ));
+    }
+
+    let output = run_user_command(device, &format!("pm remove-user {}", id))?;
+    if output.starts_with("Success") {
+        Ok(())
+    } else {
+        Err(user_failure(&output, id))
+    }
+}
+
+/// Brings user `id` to the foreground, like picking it from the lock screen.
+pub fn switch_user(device: &mut ADBServerDevice, id: u32) -> Result<(), UserError> {
+    // Prints nothing when it worked
+    let output = run_user_command(device, &format!("am switch-user {}", id))?;
+    if output.is_empty() {
+        Ok(())
+    } else {
+        Err(user_failure(&output, id))
+    }
+}
+
+fn run_user_command(device: &mut ADBServerDevice, command: &str) -> Result<String, UserError> {
+    let output =
+        shell_output(device, command).map_err(|e| UserError::CommandFailed(e.to_string()))?;
+
+    let output = String::from_utf8_lossy(&output).trim().to_string();
+    if output.contains("SecurityException") || output.contains("Permission Denial") {
+        return Err(UserError::PermissionDenied(format!(
+            "{}, root may be required",
+            output.lines().next().unwrap_or_default()
+        )));
+    }
+    Ok(output)
+}
+
+fn user_failure(output: &str, id: u32) -> UserError {
+    if output.contains("doesn't exist") || output.contains("does not exist") {
+        UserError::UserNotFound(id)
+    } else {
+        UserError::CommandFailed(output.to_string())
+    }
+}
+
+// `	UserInfo{10:Work profile:1030} running`, the name may contain colons
+fn parse_users(output: &str) -> Vec<UserInfo> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let (info, state) = line.strip_prefix("UserInfo{")?.rsplit_once('}')?;
+            let (id, rest) = info.split_once(':')?;
+            let (name, flags) = rest.rsplit_once(':')?;
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+
+            Some(UserInfo {
+                id: id.parse().ok()?,
+                name: name.to_string(),
+                flags,
+                is_primary: flags & FLAG_PRIMARY != 0,
+                is_admin: flags & FLAG_ADMIN != 0,
+                is_guest: flags & FLAG_GUEST != 0,
+                is_managed_profile: flags & FLAG_MANAGED_PROFILE != 0,
+                running: state.trim() == "running",
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum UserError {
+    UserNotFound(u32),
+    InvalidUser(String),
+    PermissionDenied(String),
+    CommandFailed(String),
+}
+
+impl std::fmt::Display for UserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserError::UserNotFound(id) => write!(f, "User {} not found", id),
+            UserError::InvalidUser(msg) => write!(f, "Invalid user: {}", msg),
+            UserError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+            UserError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for UserError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_users() {
+        let output = "Users:\n\
+                      \tUserInfo{0:Owner:c13} running\n\
+                      \tUserInfo{10:Work: Acme:1030} running\n\
+                      \tUserInfo{11:Guest:414}\n";
+
+        let users = parse_users(output);
+
+        assert_eq!(users.len(), 3);
+        assert_eq!(users[0].id, 0);
+        assert_eq!(users[0].name, "Owner");
+        assert!(users[0].is_primary && users[0].is_admin && users[0].running);
+
+        assert_eq!(users[1].name, "Work: Acme");
+        assert!(users[1].is_managed_profile);
+        assert!(!users[1].is_primary);
+
+        assert_eq!(users[2].id, 11);
+        assert!(users[2].is_guest);
+        assert!(!users[2].running);
+    }
+
+    #[test]
+    fn test_user_failure() {
+        assert_eq!(
+            user_failure("Error: user 42 doesn't exist", 42),
+            UserError::UserNotFound(42)
+        );
+        assert!(matches!(
+            user_failure("Error: couldn't remove user id 10", 10),
+            UserError::CommandFailed(_)
+        ));
+    }
+}
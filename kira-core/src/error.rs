@@ -0,0 +1,111 @@
+use crate::device::performance::PerformanceError;
+use crate::device::{
+    AppManagerError, CaptureError, ConnectError, DeviceError, DisplayError, FastbootError,
+    FileManagerError, InputError, LogcatError, ProcessError, RebootError, SettingsError,
+    ShellError, SideloadError, TelephonyError, UserError,
+};
+use adb_client::RustADBError;
+use thiserror::Error;
+
+/// Single error type for `KiraCore` and functions that combine several device modules.
+/// Module functions keep returning their own error so callers can still match on it.
+#[derive(Debug, Error)]
+pub enum KiraError {
+    #[error("Device {0} not found")]
+    DeviceNotFound(String),
+    #[error("ADB error: {0}")]
+    Adb(#[from] RustADBError),
+    #[error(transparent)]
+    AppManager(#[from] AppManagerError),
+    #[error(transparent)]
+    FileManager(#[from] FileManagerError),
+    #[error(transparent)]
+    Shell(#[from] ShellError),
+    #[error(transparent)]
+    Process(#[from] ProcessError),
+    #[error(transparent)]
+    Performance(#[from] PerformanceError),
+    #[error(transparent)]
+    Logcat(#[from] LogcatError),
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    #[error(transparent)]
+    Capture(#[from] CaptureError),
+    #[error(transparent)]
+    Display(#[from] DisplayError),
+    #[error(transparent)]
+    Input(#[from] InputError),
+    #[error(transparent)]
+    Reboot(#[from] RebootError),
+    #[error(transparent)]
+    Fastboot(#[from] FastbootError),
+    #[error(transparent)]
+    Settings(#[from] SettingsError),
+    #[error(transparent)]
+    Telephony(#[from] TelephonyError),
+    #[error(transparent)]
+    Sideload(#[from] SideloadError),
+    #[error(transparent)]
+    User(#[from] UserError),
+}
+
+impl KiraError {
+    /// Whether the device or the ADB server couldn't be reached, as opposed to a command
+    /// that ran and failed.
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            KiraError::DeviceNotFound(_) => true,
+            KiraError::Connect(e) => matches!(
+                e,
+                ConnectError::ConnectionFailed(_)
+                    | ConnectError::ConnectionRefused(_)
+                    | ConnectError::ServerStartFailed(_)
+            ),
+            KiraError::Adb(RustADBError::IOError(_)) => true,
+            KiraError::Adb(RustADBError::ADBRequestFailed(msg)) => {
+                msg.contains("device offline") || msg.contains("not found")
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kira_error_from_module_errors() {
+        let err: KiraError = ProcessError::PermissionDenied.into();
+        assert!(matches!(err, KiraError::Process(_)));
+        assert_eq!(err.to_string(), "Permission denied to kill process");
+
+        let err: KiraError = AppManagerError::PackageNotFound("com.test".to_string()).into();
+        assert_eq!(err.to_string(), "Package not found: com.test");
+    }
+
+    #[test]
+    fn test_kira_error_question_mark() {
+        fn combined() -> Result<(), KiraError> {
+            Err(FileManagerError::PermissionDenied("/data".to_string()))?;
+            Ok(())
+        }
+
+        assert!(matches!(combined(), Err(KiraError::FileManager(_))));
+    }
+
+    #[test]
+    fn test_is_connection_error() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert!(KiraError::Adb(RustADBError::IOError(refused)).is_connection_error());
+        assert!(
+            KiraError::Adb(RustADBError::ADBRequestFailed(
+                "device 'emulator-5554' not found".to_string()
+            ))
+            .is_connection_error()
+        );
+        assert!(!KiraError::from(ProcessError::PermissionDenied).is_connection_error());
+    }
+}
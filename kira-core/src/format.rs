@@ -0,0 +1,80 @@
+const BYTE_UNITS: [&str; 5] = ["KB", "MB", "GB", "TB", "PB"];
+
+/// `1288490188` as `1.2 GB`. Units are powers of 1024, below 1 KB the exact count is shown.
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // 1023.96 KB would print as 1024.0 KB
+    while value >= 1023.95 && unit < BYTE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, BYTE_UNITS[unit])
+}
+
+/// `3725` as `1h 2m`. Seconds are only shown below an hour, minutes only below a day.
+pub fn format_duration_secs(secs: u64) -> String {
+    let (days, hours, minutes, seconds) = (
+        secs / 86_400,
+        secs % 86_400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    );
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// `42.345` as `42.3%`.
+pub fn format_percent(percent: f64) -> String {
+    if percent.is_finite() {
+        format!("{:.1}%", percent)
+    } else {
+        "-".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1.0 KB");
+        assert_eq!(format_bytes(1024 * 1024 - 1), "1.0 MB");
+        assert_eq!(format_bytes(1024 * 1024), "1.0 MB");
+        assert_eq!(format_bytes(1_288_490_188), "1.2 GB");
+        assert_eq!(format_bytes(u64::MAX), "16384.0 PB");
+    }
+
+    #[test]
+    fn test_format_duration_secs() {
+        assert_eq!(format_duration_secs(0), "0s");
+        assert_eq!(format_duration_secs(59), "59s");
+        assert_eq!(format_duration_secs(60), "1m 0s");
+        assert_eq!(format_duration_secs(3599), "59m 59s");
+        assert_eq!(format_duration_secs(3725), "1h 2m");
+        assert_eq!(format_duration_secs(90_061), "1d 1h");
+    }
+
+    #[test]
+    fn test_format_percent() {
+        assert_eq!(format_percent(0.0), "0.0%");
+        assert_eq!(format_percent(42.345), "42.3%");
+        assert_eq!(format_percent(100.0), "100.0%");
+        assert_eq!(format_percent(f64::NAN), "-");
+    }
+}
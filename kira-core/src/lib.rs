@@ -1,5 +1,11 @@
 #[allow(non_snake_case)]
 pub mod KiraCore;
+pub mod config;
 pub mod device;
+pub mod error;
+pub mod format;
 
+pub use config::*;
 pub use device::*;
+pub use error::*;
+pub use format::*;
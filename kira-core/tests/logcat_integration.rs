@@ -1,4 +1,7 @@
-use kira_core::device::{LogLevel, LogcatBuffer, LogcatEntry, LogcatFilter, parse_logcat_line};
+use kira_core::device::{
+    IncidentKind, LogLevel, LogcatBuffer, LogcatEntry, LogcatFilter, detect_incidents,
+    parse_logcat_line,
+};
 
 #[test]
 fn test_parse_real_logcat_line_threadtime() {
@@ -303,3 +306,47 @@ fn test_parse_battery_service_event() {
     assert!(entry.message.contains("level="));
     assert!(entry.message.contains("75"));
 }
+
+#[test]
+fn test_detect_incidents() {
+    let log = "\
+01-15 12:10:00.000  1000  2345 E ActivityManager: ANR in com.paget96.batteryguru (com.paget96.batteryguru/.MainActivity)
+01-15 12:10:00.000  1000  2345 E ActivityManager: PID: 7777
+01-15 12:10:00.000  1000  2345 E ActivityManager: Reason: Input dispatching timed out
+01-15 10:30:49.000  3333  4444 F libc: Fatal signal 11 (SIGSEGV), code 1
+01-15 10:31:00.000  1500  1600 F libc: Fatal signal 6 (SIGABRT), code -1 (SI_QUEUE) in tid 1600 (RenderThread), pid 1500 (com.example.game)
+01-15 10:32:00.000  4321  4321 E AndroidRuntime: FATAL EXCEPTION: main
+01-15 10:32:00.000  4321  4321 E AndroidRuntime: Process: com.example.app, PID: 4321
+01-15 10:32:00.000  4321  4321 E AndroidRuntime: java.lang.NullPointerException: Attempt to invoke virtual method
+01-15 10:32:00.000  4321  4321 E AndroidRuntime: \tat com.example.app.MainActivity.onCreate(MainActivity.kt:42)";
+    let entries: Vec<LogcatEntry> = log.lines().filter_map(parse_logcat_line).collect();
+
+    let incidents = detect_incidents(&entries);
+    assert_eq!(incidents.len(), 4);
+
+    assert_eq!(incidents[0].kind, IncidentKind::Anr);
+    assert_eq!(
+        incidents[0].package.as_deref(),
+        Some("com.paget96.batteryguru")
+    );
+    assert_eq!(incidents[0].pid, 7777);
+    assert_eq!(incidents[0].reason, "Input dispatching timed out");
+
+    assert_eq!(incidents[1].kind, IncidentKind::NativeCrash);
+    assert_eq!(incidents[1].package, None);
+    assert_eq!(incidents[1].pid, 3333);
+    assert_eq!(incidents[1].reason, "Fatal signal 11 (SIGSEGV)");
+
+    assert_eq!(incidents[2].kind, IncidentKind::NativeCrash);
+    assert_eq!(incidents[2].package.as_deref(), Some("com.example.game"));
+    assert_eq!(incidents[2].pid, 1500);
+
+    assert_eq!(incidents[3].kind, IncidentKind::JavaCrash);
+    assert_eq!(incidents[3].package.as_deref(), Some("com.example.app"));
+    assert_eq!(incidents[3].pid, 4321);
+    assert!(
+        incidents[3]
+            .reason
+            .starts_with("java.lang.NullPointerException")
+    );
+}
@@ -1,229 +1,1722 @@
-use adb_client::server::ADBServer;
+use adb_client::server::DeviceState;
 use adb_client::server_device::ADBServerDevice;
+use kira_core::config::{
+    KiraConfig, adb_config, adb_device, adb_path, adb_server, set_adb_config, set_adb_path,
+};
+use kira_core::device::logcat::{self, LogLevel, LogcatBuffer, LogcatEntry, LogcatFilter};
 use kira_core::device::performance::{
-    BatteryInfo, CpuInfo, FpsData, MemoryInfo, get_battery_info, get_cpu_info, get_flips_count,
-    get_memory_info,
+    self, PerformanceProfile, SystemLoad, ThrottleReport, Wakelock, monitor_battery,
+};
+use kira_core::device::shell::{
+    self, CommandOutput, DeviceStatus, MountInfo, NetworkInterface, ShellExecutor,
 };
-use kira_core::device::shell::{CommandOutput, ShellExecutor};
 use kira_core::device::{
-    self, AppInfo, InstallResult, PackageFilter, TopPackage, UninstallResult, get_app_info,
-    install_app, list_installed_packages, uninstall_app,
+    self, AppInfo, CancelToken, FastbootCore, FastbootDeviceInfo, FlashPartition, InstallOptions,
+    InstallPreview, InstallResult, InstallStage, PackageFilter, SettingsNamespace, TopPackage,
+    UninstallResult, get_app_info, install_app_with_progress, list_installed_packages,
+    uninstall_app,
 };
 use serde::{Deserialize, Serialize};
-use std::net::{Ipv4Addr, SocketAddrV4};
-use tauri::command;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::async_runtime::Mutex as AsyncMutex;
+use tauri::{AppHandle, Emitter, Manager, State, command};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeviceListItem {
     pub serial: String,
     pub model: Option<String>,
+    pub state: String,
 }
 
-#[command]
-fn get_devices() -> Result<Vec<DeviceListItem>, String> {
-    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
-    let mut server = ADBServer::new(addr);
+/// Fails with a message the UI can show as is when `serial` is missing or not in
+/// `device` state, instead of letting the shell call hang or fail opaquely.
+fn ensure_device_ready(serial: &str) -> Result<(), String> {
+    ensure_device_in(serial, &[DeviceState::Device])
+}
+
+/// [`ensure_device_ready`] for commands that also work in other states, like rebooting
+/// out of recovery.
+fn ensure_device_in(serial: &str, allowed: &[DeviceState]) -> Result<(), String> {
+    device::ensure_server_started().map_err(|e| e.to_string())?;
+    let mut server = adb_server();
 
     let devices = server.devices().map_err(|e| e.to_string())?;
+    let dev = devices
+        .iter()
+        .find(|d| d.identifier == serial)
+        .ok_or_else(|| format!("Device {} not found", serial))?;
+
+    match dev.state {
+        ref state if allowed.contains(state) => Ok(()),
+        DeviceState::Unauthorized => Err(format!(
+            "Device {} is unauthorized, accept the USB debugging prompt on the device",
+            serial
+        )),
+        DeviceState::Offline => Err(format!(
+            "Device {} is offline, reconnect it and try again",
+            serial
+        )),
+        ref state => Err(format!("Device {} is not ready ({})", serial, state)),
+    }
+}
 
-    let mut result = Vec::new();
-    for dev in devices {
-        let serial = dev.identifier.clone();
+/// Async commands run their adb work through this so it stays off the runtime's threads.
+async fn on_device<T, F>(serial: String, f: F) -> Result<T, String>
+where
+    F: FnOnce(&mut ADBServerDevice) -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+{
+    shell::run_blocking(serial.clone(), move |device| {
+        ensure_device_ready(&serial)?;
+        f(device)
+    })
+    .await
+}
 
-        let mut device = ADBServerDevice::new(serial.clone(), None);
-        let model = device::shell_cmd(&mut device, "getprop ro.product.model");
+/// Cancel tokens of the operations still running, by the id the UI started them with or
+/// the one [`start_operation`] handed out.
+#[derive(Default)]
+struct Operations {
+    tokens: Mutex<HashMap<String, CancelToken>>,
+    next_id: AtomicU64,
+}
 
-        result.push(DeviceListItem { serial, model });
+impl Operations {
+    fn start(&self, id: &str) -> CancelToken {
+        let token = CancelToken::new();
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), token.clone());
+        token
     }
 
-    Ok(result)
+    /// Starts an operation under a fresh id like `install-3`.
+    fn start_new(&self, kind: &str) -> (String, CancelToken) {
+        let id = format!(
+            "{}-{}",
+            kind,
+            self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+        );
+        let token = self.start(&id);
+        (id, token)
+    }
+
+    fn finish(&self, id: &str) {
+        self.tokens.lock().unwrap().remove(id);
+    }
+}
+
+/// What a background operation is doing, sent as `operation-status` events.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum OperationStatus {
+    Started,
+    Progress { stage: String, percent: Option<u32> },
+    Done { result: serde_json::Value },
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct OperationEvent {
+    id: String,
+    #[serde(flatten)]
+    status: OperationStatus,
+}
+
+/// Emits the `operation-status` events of one operation and carries its cancel token.
+#[derive(Clone)]
+struct OperationReporter {
+    app: AppHandle,
+    id: String,
+    cancel: CancelToken,
+}
+
+impl OperationReporter {
+    fn emit(&self, status: OperationStatus) {
+        let _ = self.app.emit(
+            "operation-status",
+            OperationEvent {
+                id: self.id.clone(),
+                status,
+            },
+        );
+    }
+
+    fn progress(&self, stage: &str, percent: Option<u32>) {
+        self.emit(OperationStatus::Progress {
+            stage: stage.to_string(),
+            percent,
+        });
+    }
+
+    /// `progress` with `done` out of `total` as a percentage, only when it moved past
+    /// `last`.
+    fn percent(&self, stage: &str, done: u64, total: u64, last: &mut Option<u32>) {
+        let percent = (done * 100 / total.max(1)) as u32;
+        if *last != Some(percent) {
+            *last = Some(percent);
+            self.progress(stage, Some(percent));
+        }
+    }
+}
+
+/// Runs `work` on a background thread and returns right away with the id its
+/// `operation-status` events carry: `started`, any `progress`, then `done` or `failed`.
+/// `cancel_operation` with that id cancels the reporter's token, work that hasn't
+/// started yet doesn't run at all.
+fn start_operation<T, F>(app: AppHandle, serial: String, kind: &str, work: F) -> String
+where
+    F: FnOnce(&mut ADBServerDevice, &OperationReporter) -> Result<T, String> + Send + 'static,
+    T: Serialize + Send + 'static,
+{
+    let (id, cancel) = app.state::<Operations>().start_new(kind);
+    let reporter = OperationReporter {
+        app,
+        id: id.clone(),
+        cancel,
+    };
+
+    tauri::async_runtime::spawn(async move {
+        reporter.emit(OperationStatus::Started);
+        let worker = reporter.clone();
+        let result = shell::run_blocking(serial, move |device| {
+            if worker.cancel.is_cancelled() {
+                return Err("Cancelled".to_string());
+            }
+            work(device, &worker)
+        })
+        .await
+        .and_then(|result| serde_json::to_value(result).map_err(|e| e.to_string()));
+        reporter.app.state::<Operations>().finish(&reporter.id);
+        reporter.emit(match result {
+            Ok(result) => OperationStatus::Done { result },
+            Err(error) => OperationStatus::Failed { error },
+        });
+    });
+    id
 }
 
+/// Returns false when no operation with `id` is running, e.g. because it already finished.
 #[command]
-fn get_device_info(serial: String) -> Result<device::DeviceInfo, String> {
-    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
-    let mut server = ADBServer::new(addr);
+fn cancel_operation(id: String, operations: State<'_, Operations>) -> bool {
+    match operations.tokens.lock().unwrap().get(&id) {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Sends a raw host service request such as `host:version` to the ADB server, for
+/// debugging and what the typed commands don't cover.
+#[command]
+fn adb_server_command(query: String) -> Result<String, String> {
+    device::adb_server_command(&query).map_err(|e| e.to_string())
+}
+
+#[command]
+fn get_device_features(serial: String) -> Result<Vec<String>, String> {
+    device::get_device_features(&serial).map_err(|e| e.to_string())
+}
+
+/// Points every later command at the ADB server on `host:port`, for adb running on
+/// another machine or port. Without it `ANDROID_ADB_SERVER_ADDRESS`/`_PORT` or
+/// `localhost:5037` are used.
+#[command]
+fn set_adb_server(host: String, port: u16) -> Result<KiraConfig, String> {
+    let adb_host = host
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid IPv4 address: {}", host))?;
+    let config = KiraConfig {
+        adb_host,
+        adb_port: port,
+        ..adb_config()
+    };
+    set_adb_config(config.clone());
+    Ok(config)
+}
+
+/// Points the host `adb` commands at `path`, an empty path goes back to the SDK or `PATH`
+/// one. Returns the binary now in use.
+#[command]
+fn set_adb_executable(path: String) -> Result<String, String> {
+    let path = path.trim();
+    if path.is_empty() {
+        set_adb_path(None);
+    } else {
+        let path = std::path::PathBuf::from(path);
+        if !path.is_file() {
+            return Err(format!("adb not found at {}", path.display()));
+        }
+        set_adb_path(Some(path));
+    }
+    Ok(adb_path().display().to_string())
+}
+
+/// Emits a `device-event` for every device that connects, disconnects or changes state,
+/// starting with the ones already attached, until `cancel_operation(tracking_id)`.
+#[command]
+fn start_device_tracking(
+    app: AppHandle,
+    tracking_id: String,
+    operations: State<'_, Operations>,
+) -> Result<(), String> {
+    let tracker = device::track_devices().map_err(|e| e.to_string())?;
+    let cancel = operations.start(&tracking_id);
+
+    std::thread::spawn(move || {
+        while !cancel.is_cancelled() {
+            match tracker.recv_timeout(Duration::from_millis(500)) {
+                Ok(event) => {
+                    if app.emit("device-event", event).is_err() {
+                        break;
+                    }
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        app.state::<Operations>().finish(&tracking_id);
+    });
+
+    Ok(())
+}
+
+const MODEL_LOOKUP_WORKERS: usize = 4;
+const MODEL_LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[command]
+fn get_devices() -> Result<Vec<DeviceListItem>, String> {
+    device::ensure_server_started().map_err(|e| e.to_string())?;
+    let mut server = adb_server();
 
     let devices = server.devices().map_err(|e| e.to_string())?;
-    let _ = devices
+
+    // Unauthorized and offline devices stall or fail on any shell call
+    let reachable: Vec<String> = devices
         .iter()
-        .find(|d| d.identifier == serial)
-        .ok_or_else(|| format!("Device {} not found", serial))?;
+        .filter(|dev| matches!(dev.state, DeviceState::Device | DeviceState::Recovery))
+        .map(|dev| dev.identifier.clone())
+        .collect();
+    let models = device::map_devices_parallel(
+        &reachable,
+        MODEL_LOOKUP_WORKERS,
+        MODEL_LOOKUP_TIMEOUT,
+        |device| device::shell_cmd(device, "getprop ro.product.model"),
+    );
+    let mut models: HashMap<String, Option<String>> = reachable
+        .into_iter()
+        .zip(models)
+        .map(|(serial, model)| (serial, model.flatten()))
+        .collect();
 
-    let mut device = ADBServerDevice::new(serial.clone(), None);
-
-    let info = device::DeviceInfo {
-        serial: serial.clone(),
-        model: device::shell_cmd(&mut device, "getprop ro.product.model"),
-        manufacturer: device::shell_cmd(&mut device, "getprop ro.product.manufacturer"),
-        android_version: device::shell_cmd(&mut device, "getprop ro.build.version.release"),
-        abi: device::shell_cmd(&mut device, "getprop ro.product.cpu.abi"),
-        slot: device::shell_cmd(&mut device, "getprop ro.boot.slot_suffix"),
-        battery: device::parse_battery(
-            &device::shell_cmd(&mut device, "dumpsys battery | grep level").unwrap_or_default(),
-        ),
-        storage: device::get_storage(&mut device),
-        screen_resolution: device::shell_cmd(&mut device, "wm size"),
-        refresh_rate: device::get_max_refresh_rate(&mut device),
-        build: device::get_build_info(&mut device),
-    };
+    let result = devices
+        .into_iter()
+        .map(|dev| DeviceListItem {
+            model: models.remove(&dev.identifier).flatten(),
+            state: dev.state.to_string(),
+            serial: dev.identifier,
+        })
+        .collect();
 
-    Ok(info)
+    Ok(result)
 }
 
 #[command]
-fn list_packages(serial: String, filter: String) -> Result<Vec<String>, String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
+fn get_encryption_state(serial: String) -> Result<device::EncryptionState, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    Ok(device::get_encryption_state(&mut device))
+}
 
-    let filter = match filter.as_str() {
-        "system" => PackageFilter::System,
-        "thirdparty" => PackageFilter::ThirdParty,
-        "enabled" => PackageFilter::Enabled,
-        "disabled" => PackageFilter::Disabled,
-        _ => PackageFilter::All,
-    };
+#[command]
+fn get_device_info(serial: String) -> Result<device::DeviceInfo, String> {
+    ensure_device_ready(&serial)?;
 
-    let mut device = ADBServerDevice::new(serial, None);
+    let mut device = adb_device(serial.clone());
+
+    Ok(device::collect_device_info(&mut device, &serial))
+}
+
+#[command]
+fn list_packages(serial: String, filter: PackageFilter) -> Result<Vec<String>, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
     list_installed_packages(&mut device, filter).map_err(|e| e.to_string())
 }
 
 #[command]
 fn get_package_info(serial: String, package_name: String) -> Result<AppInfo, String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
+    ensure_device_ready(&serial)?;
 
-    let mut device = ADBServerDevice::new(serial, None);
+    let mut device = adb_device(serial);
     get_app_info(&mut device, &package_name).map_err(|e| e.to_string())
 }
 
+/// Uninstalls in the background, the [`UninstallResult`] arrives with the `done` event of
+/// the returned operation id.
+#[command]
+fn uninstall_package(
+    app: AppHandle,
+    serial: String,
+    package_name: String,
+) -> Result<String, String> {
+    ensure_device_ready(&serial)?;
+
+    Ok(start_operation(
+        app,
+        serial,
+        "uninstall",
+        move |device, _| uninstall_app(device, &package_name).map_err(|e| e.to_string()),
+    ))
+}
+
+#[command]
+fn uninstall_package_keep_data(
+    serial: String,
+    package_name: String,
+) -> Result<UninstallResult, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::uninstall_app_with_keep_data(&mut device, &package_name).map_err(|e| e.to_string())
+}
+
+#[command]
+fn clear_package_data(serial: String, package_name: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::clear_app_data(&mut device, &package_name).map_err(|e| e.to_string())
+}
+
+#[command]
+fn force_stop_package(serial: String, package_name: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::force_stop_app(&mut device, &package_name).map_err(|e| e.to_string())
+}
+
+#[command]
+fn disable_package(serial: String, package_name: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::disable_app(&mut device, &package_name).map_err(|e| e.to_string())
+}
+
+#[command]
+fn enable_package(serial: String, package_name: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::enable_app(&mut device, &package_name).map_err(|e| e.to_string())
+}
+
+#[command]
+fn launch_package(serial: String, package_name: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::start_app(&mut device, &package_name).map_err(|e| match e {
+        device::AppManagerError::ActivityNotFound(pkg) => {
+            format!(
+                "{} has no launcher activity and can't be opened directly",
+                pkg
+            )
+        }
+        other => other.to_string(),
+    })
+}
+
+#[command]
+fn launch_activity(serial: String, activity: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::start_app_with_activity(&mut device, &activity).map_err(|e| e.to_string())
+}
+
+#[command]
+fn send_broadcast(
+    serial: String,
+    action: String,
+    extras: Vec<(String, device::IntentExtra)>,
+) -> Result<String, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::send_broadcast(&mut device, &action, &extras).map_err(|e| e.to_string())
+}
+
+#[command]
+fn start_intent(serial: String, intent: device::IntentSpec) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::start_intent(&mut device, &intent).map_err(|e| e.to_string())
+}
+
+#[command]
+fn get_app_components(
+    serial: String,
+    package_name: String,
+) -> Result<device::AppComponents, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::get_app_components(&mut device, &package_name).map_err(|e| e.to_string())
+}
+
+/// Enables or disables one component of an app, returns whether it is enabled now.
+#[command]
+fn set_component_enabled(
+    serial: String,
+    package_name: String,
+    component: String,
+    enabled: bool,
+) -> Result<bool, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::set_component_enabled(&mut device, &package_name, &component, enabled)
+        .map_err(|e| e.to_string())
+}
+
+#[command]
+fn read_app_prefs(
+    serial: String,
+    package_name: String,
+    prefs_name: String,
+) -> Result<String, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::read_app_prefs(&mut device, &package_name, &prefs_name).map_err(|e| e.to_string())
+}
+
+/// Runs `command` as a debuggable app, for looking at its private files without root.
+#[command]
+fn run_as_package(serial: String, package_name: String, command: String) -> Result<String, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::run_as(&mut device, &package_name, &command).map_err(|e| e.to_string())
+}
+
+#[command]
+fn get_app_splits(serial: String, package_name: String) -> Result<Vec<String>, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::get_app_splits(&mut device, &package_name).map_err(|e| e.to_string())
+}
+
+#[command]
+fn list_app_files(serial: String, package_name: String) -> Result<Vec<String>, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::list_app_files(&mut device, &package_name).map_err(|e| e.to_string())
+}
+
+/// Installs in the background and returns the operation id. Its events report `uploading`
+/// with a percentage, then `committing`, and `done` carries the [`InstallResult`].
+#[command]
+fn install_package(
+    app: AppHandle,
+    serial: String,
+    apk_path: String,
+    options: Option<InstallOptions>,
+) -> Result<String, String> {
+    ensure_device_ready(&serial)?;
+
+    // Without options keep granting permissions like the install button always did
+    let options = options.unwrap_or(InstallOptions {
+        grant_permissions: true,
+        ..Default::default()
+    });
+
+    Ok(start_operation(
+        app,
+        serial,
+        "install",
+        move |device, reporter| {
+            let mut last_percent = None;
+            install_app_with_progress(
+                device,
+                &apk_path,
+                &options,
+                |stage| match stage {
+                    InstallStage::Uploading { bytes, total } => {
+                        reporter.percent("uploading", bytes, total, &mut last_percent)
+                    }
+                    InstallStage::Committing => reporter.progress("committing", None),
+                },
+                &reporter.cancel,
+            )
+            .map_err(|e| e.to_string())
+        },
+    ))
+}
+
+#[command]
+async fn install_package_from_url(
+    serial: String,
+    url: String,
+    options: Option<InstallOptions>,
+) -> Result<InstallResult, String> {
+    let options = options.unwrap_or(InstallOptions {
+        grant_permissions: true,
+        ..Default::default()
+    });
+
+    on_device(serial, move |device| {
+        device::install_from_url(device, &url, &options).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Package and version of a local APK next to what the device has installed.
 #[command]
-fn uninstall_package(serial: String, package_name: String) -> Result<UninstallResult, String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
+fn preview_package_install(serial: String, apk_path: String) -> Result<InstallPreview, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::preview_install(&mut device, &apk_path).map_err(|e| e.to_string())
+}
 
-    let mut device = ADBServerDevice::new(serial, None);
-    uninstall_app(&mut device, &package_name).map_err(|e| e.to_string())
+/// Blocks until the user confirms the backup on the device, so it runs off the main thread.
+#[command]
+async fn backup_package(
+    serial: String,
+    package_name: String,
+    dest_path: String,
+) -> Result<u64, String> {
+    on_device(serial, move |device| {
+        device::backup_app(device, &package_name, &dest_path).map_err(|e| e.to_string())
+    })
+    .await
 }
 
 #[command]
-fn install_package(serial: String, apk_path: String) -> Result<InstallResult, String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
+async fn restore_package_backup(serial: String, backup_path: String) -> Result<(), String> {
+    on_device(serial, move |device| {
+        device::restore_backup(device, &backup_path).map_err(|e| e.to_string())
+    })
+    .await
+}
 
-    let mut device = ADBServerDevice::new(serial, None);
-    install_app(&mut device, &apk_path, true).map_err(|e| e.to_string())
+/// Sideloads the OTA at `zip_path` to a device in sideload mode in the background. The
+/// returned operation id's `progress` events carry how much of the package recovery read.
+#[command]
+fn sideload_package(app: AppHandle, serial: String, zip_path: String) -> String {
+    start_operation(app, serial, "sideload", move |device, reporter| {
+        let mut last_percent = None;
+        device::sideload(device, &zip_path, |bytes, total| {
+            reporter.percent("sideloading", bytes, total, &mut last_percent)
+        })
+        .map_err(|e| e.to_string())
+    })
 }
 
 #[command]
 fn check_root(serial: String) -> Result<bool, String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
+    ensure_device_ready(&serial)?;
 
-    let mut device = ADBServerDevice::new(serial, None);
+    let mut device = adb_device(serial);
     Ok(device::is_rooted(&mut device))
 }
 
+#[command]
+fn has_root_access(serial: String) -> Result<device::RootStatus, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    Ok(device::has_root_access(&mut device))
+}
+
+#[command]
+fn get_root_apps(serial: String) -> Result<Vec<device::RootGrant>, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::get_root_apps(&mut device).map_err(|e| e.to_string())
+}
+
 #[command]
 fn list_processes(serial: String, apps_only: bool) -> Result<Vec<device::ProcessInfo>, String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
+    ensure_device_ready(&serial)?;
 
-    let mut device = ADBServerDevice::new(serial, None);
+    let mut device = adb_device(serial);
     device::list_processes(&mut device, apps_only).map_err(|e| e.to_string())
 }
 
+#[command]
+fn get_process_memory(serial: String, pid: u32) -> Result<device::ProcessMemory, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::get_process_memory(&mut device, pid).map_err(|e| e.to_string())
+}
+
+#[command]
+fn list_services(serial: String) -> Result<Vec<String>, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::list_running_services(&mut device).map_err(|e| e.to_string())
+}
+
+#[command]
+fn list_foreground_services(serial: String) -> Result<Vec<device::ForegroundService>, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::list_foreground_services(&mut device).map_err(|e| e.to_string())
+}
+
+#[command]
+fn find_processes_by_package(
+    serial: String,
+    package_name: String,
+) -> Result<Vec<device::ProcessInfo>, String> {
+    ensure_device_ready(&serial)?;
+
+    let mut device = adb_device(serial);
+    device::find_process_by_package(&mut device, &package_name).map_err(|e| e.to_string())
+}
+
 #[command]
 fn kill_process(serial: String, pid: u32) -> Result<(), String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
+    ensure_device_ready(&serial)?;
 
-    let mut device = ADBServerDevice::new(serial, None);
+    let mut device = adb_device(serial);
     device::kill_process(&mut device, pid).map_err(|e| e.to_string())
 }
 
 #[command]
 fn kill_package(serial: String, package_name: String) -> Result<(), String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
+    ensure_device_ready(&serial)?;
 
-    let mut device = ADBServerDevice::new(serial, None);
+    let mut device = adb_device(serial);
     device::kill_package(&mut device, &package_name).map_err(|e| e.to_string())
 }
 
 #[command]
-fn reboot_device(serial: String, mode: String) -> Result<(), String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
+fn kill_background(serial: String, package_name: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
 
-    let reboot_mode = match mode.as_str() {
-        "recovery" => device::RebootMode::Recovery,
-        "bootloader" => device::RebootMode::Bootloader,
-        "fastboot" => device::RebootMode::Fastboot,
-        "sideload" => device::RebootMode::Sideload,
-        _ => device::RebootMode::Normal,
-    };
+    let mut device = adb_device(serial);
+    device::kill_background(&mut device, &package_name).map_err(|e| e.to_string())
+}
+
+#[command]
+fn kill_all_background(serial: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
 
-    let mut device = ADBServerDevice::new(serial, None);
-    device::reboot(&mut device, reboot_mode).map_err(|e| e.to_string())
+    let mut device = adb_device(serial);
+    device::kill_all_background(&mut device).map_err(|e| e.to_string())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct PerformanceProfile {
-    pub memory: Result<MemoryInfo, String>,
-    pub battery: Result<BatteryInfo, String>,
-    pub cpu: Result<Vec<CpuInfo>, String>,
-    pub fps: Result<FpsData, String>,
-    pub uptime: Result<u64, String>,
+#[command]
+fn get_device_mode(serial: String) -> device::DeviceMode {
+    device::detect_device_mode(&serial)
+}
+
+/// Where a reboot can be started from, recovery and sideload included.
+const REBOOTABLE_STATES: [DeviceState; 3] = [
+    DeviceState::Device,
+    DeviceState::Recovery,
+    DeviceState::Sideload,
+];
+
+/// Waits until the device went offline, so it can take a few seconds.
+#[command]
+async fn reboot_device(serial: String, mode: device::RebootMode) -> Result<(), String> {
+    shell::run_blocking(serial.clone(), move |device| {
+        ensure_device_in(&serial, &REBOOTABLE_STATES)?;
+        device::reboot(device, mode).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Reboots with a reason `reboot_device` doesn't know, e.g. `edl`. Unknown reasons are
+/// usually ignored by the bootloader.
+#[command]
+async fn reboot_device_custom(serial: String, reason: String) -> Result<(), String> {
+    shell::run_blocking(serial.clone(), move |device| {
+        ensure_device_in(&serial, &REBOOTABLE_STATES)?;
+        device::reboot_custom(device, &reason).map_err(|e| e.to_string())
+    })
+    .await
 }
 
 #[command]
 fn get_performance_profile(serial: String) -> Result<PerformanceProfile, String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
-    let mut device = ADBServerDevice::new(serial, None);
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    Ok(performance::get_performance_profile(&mut device))
+}
+
+#[command]
+async fn get_system_load(serial: String) -> Result<SystemLoad, String> {
+    on_device(serial, move |device| {
+        performance::get_system_load(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// FPS of the foreground app, takes about a second when the flip counter is the fallback.
+#[command]
+async fn get_frame_rate(serial: String) -> Result<performance::FrameRate, String> {
+    on_device(serial, move |device| {
+        performance::get_frame_rate(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Watches the CPU frequency ceilings and temperatures for `duration_ms`, e.g. while a
+/// benchmark runs.
+#[command]
+async fn detect_throttling(serial: String, duration_ms: u64) -> Result<ThrottleReport, String> {
+    on_device(serial, move |device| {
+        performance::detect_throttling(device, Duration::from_millis(duration_ms))
+            .map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn get_cpu_governor(serial: String, core: u32) -> Result<String, String> {
+    on_device(serial, move |device| {
+        performance::get_cpu_governor(device, core).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn list_available_governors(serial: String, core: u32) -> Result<Vec<String>, String> {
+    on_device(serial, move |device| {
+        performance::list_available_governors(device, core).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn set_cpu_governor(serial: String, core: u32, governor: String) -> Result<(), String> {
+    on_device(serial, move |device| {
+        performance::set_cpu_governor(device, core, &governor).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn get_cpu_topology(serial: String) -> Result<performance::CpuTopology, String> {
+    on_device(serial, move |device| {
+        performance::get_cpu_topology(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn get_online_cores(serial: String) -> Result<Vec<bool>, String> {
+    on_device(serial, move |device| {
+        performance::get_online_cores(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn set_core_online(serial: String, core: u32, online: bool) -> Result<(), String> {
+    on_device(serial, move |device| {
+        performance::set_core_online(device, core, online).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn get_wakelocks(serial: String) -> Result<Vec<Wakelock>, String> {
+    on_device(serial, move |device| {
+        performance::get_wakelocks(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn get_wakelock_history(serial: String) -> Result<Vec<Wakelock>, String> {
+    on_device(serial, move |device| {
+        performance::get_wakelock_history(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn reset_battery_stats(serial: String) -> Result<(), String> {
+    on_device(serial, move |device| {
+        performance::reset_battery_stats(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn set_charge_limit(serial: String, percent: u32) -> Result<(), String> {
+    on_device(serial, move |device| {
+        performance::set_charge_limit(device, percent).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn get_telephony_info(serial: String) -> Result<device::TelephonyInfo, String> {
+    on_device(serial, move |device| {
+        device::get_telephony_info(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Everything the home screen shows, in one call.
+#[command]
+fn get_device_snapshot(serial: String) -> Result<device::DeviceSnapshot, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    Ok(device::get_device_snapshot(&mut device))
+}
+
+/// Saves a device report for attaching to bug reports, `format` is `Json` or `Markdown`.
+#[command]
+fn export_device_report(
+    serial: String,
+    path: String,
+    format: device::ReportFormat,
+) -> Result<device::DeviceReport, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::export_report(&mut device, &path, format).map_err(|e| e.to_string())
+}
+
+/// Compares two reports saved as JSON, e.g. from before and after an update.
+#[command]
+fn diff_device_reports(
+    before_path: String,
+    after_path: String,
+) -> Result<Vec<device::FieldDiff>, String> {
+    let before = device::load_report(&before_path).map_err(|e| e.to_string())?;
+    let after = device::load_report(&after_path).map_err(|e| e.to_string())?;
+    Ok(device::diff_reports(&before, &after))
+}
+
+#[command]
+async fn record_battery_history(
+    serial: String,
+    interval_ms: u64,
+    samples: usize,
+    operation_id: String,
+    operations: State<'_, Operations>,
+) -> Result<Vec<(u64, u32)>, String> {
+    let cancel = operations.start(&operation_id);
+    let result = on_device(serial, move |device| {
+        monitor_battery(device, Duration::from_millis(interval_ms), samples, &cancel)
+            .map_err(|e| e.to_string())
+    })
+    .await;
+    operations.finish(&operation_id);
+    result
+}
+
+/// Saves a bug report to `dest_path` in the background. The returned operation id's
+/// `progress` events follow the device building it, `cancel_operation` stops it.
+#[command]
+fn capture_bugreport(app: AppHandle, serial: String, dest_path: String) -> Result<String, String> {
+    ensure_device_ready(&serial)?;
+
+    Ok(start_operation(
+        app,
+        serial,
+        "bugreport",
+        move |device, reporter| {
+            let mut last_percent = None;
+            device::capture_bugreport(
+                device,
+                &dest_path,
+                |done, total| {
+                    reporter.percent("capturing", done.into(), total.into(), &mut last_percent)
+                },
+                &reporter.cancel,
+            )
+            .map_err(|e| e.to_string())
+        },
+    ))
+}
 
-    let memory = get_memory_info(&mut device).map_err(|e| e.to_string());
-    let battery = get_battery_info(&mut device).map_err(|e| e.to_string());
-    let cpu = get_cpu_info(&mut device).map_err(|e| e.to_string());
-    let fps = get_flips_count(&mut device).map_err(|e| e.to_string());
-    let uptime = device::performance::get_uptime(&mut device).map_err(|e| e.to_string());
+/// Reads the last `lines` entries of `buffer`. `min_level` and an exact `tag` are
+/// filtered on the device, `filter` afterwards. Without either nothing is dropped.
+#[command]
+async fn read_logcat(
+    serial: String,
+    buffer: LogcatBuffer,
+    lines: usize,
+    min_level: Option<LogLevel>,
+    tag: Option<String>,
+    filter: Option<LogcatFilter>,
+) -> Result<Vec<LogcatEntry>, String> {
+    on_device(serial, move |device| {
+        let entries = logcat::read_logcat(device, buffer, lines, min_level, tag.as_deref())
+            .map_err(|e| e.to_string())?;
+        Ok(match filter {
+            Some(filter) => logcat::filter_entries(entries, filter),
+            None => entries,
+        })
+    })
+    .await
+}
+
+#[command]
+async fn clear_logcat(serial: String, buffer: LogcatBuffer) -> Result<(), String> {
+    on_device(serial, move |device| {
+        logcat::clear_logcat(device, buffer).map_err(|e| e.to_string())
+    })
+    .await
+}
 
-    Ok(PerformanceProfile {
-        memory,
-        battery,
-        cpu,
-        fps,
-        uptime,
+/// Color per log level, keyed like `LogcatEntry.level` is serialized.
+#[command]
+fn logcat_level_colors() -> HashMap<String, String> {
+    LogLevel::ALL
+        .iter()
+        .map(|level| (format!("{:?}", level), level.color_hex().to_string()))
+        .collect()
+}
+
+#[command]
+async fn list_logcat_buffers(serial: String) -> Result<Vec<String>, String> {
+    on_device(serial, move |device| {
+        logcat::get_logcat_buffers(device).map_err(|e| e.to_string())
     })
+    .await
+}
+
+/// Event names only allow these characters.
+fn check_stream_id(stream_id: &str) -> Result<(), String> {
+    if stream_id.is_empty()
+        || !stream_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-/:_".contains(c))
+    {
+        return Err(format!("Invalid stream id '{}'", stream_id));
+    }
+    Ok(())
+}
+
+/// Emits lines appended to `path` as `file-line:<stream_id>` events until
+/// `cancel_operation(stream_id)` is called or the device goes away.
+#[command]
+async fn start_file_follow(
+    app: AppHandle,
+    serial: String,
+    path: String,
+    stream_id: String,
+    operations: State<'_, Operations>,
+) -> Result<(), String> {
+    check_stream_id(&stream_id)?;
+
+    let cancel = operations.start(&stream_id);
+    let follow_cancel = cancel.clone();
+    let receiver = on_device(serial, move |device| {
+        device::follow_file(device, &path, follow_cancel).map_err(|e| e.to_string())
+    })
+    .await
+    .inspect_err(|_| operations.finish(&stream_id))?;
+
+    std::thread::spawn(move || {
+        let event = format!("file-line:{}", stream_id);
+        for line in receiver {
+            if app.emit(&event, line).is_err() {
+                cancel.cancel();
+                break;
+            }
+        }
+        app.state::<Operations>().finish(&stream_id);
+    });
+
+    Ok(())
+}
+
+/// Emits new entries as `logcat:<stream_id>` events until `cancel_operation(stream_id)`
+/// is called or the device goes away.
+#[command]
+async fn start_logcat_stream(
+    app: AppHandle,
+    serial: String,
+    buffer: LogcatBuffer,
+    filter: Option<LogcatFilter>,
+    stream_id: String,
+    operations: State<'_, Operations>,
+) -> Result<(), String> {
+    check_stream_id(&stream_id)?;
+
+    let cancel = operations.start(&stream_id);
+    let stream_cancel = cancel.clone();
+    let filter = filter.unwrap_or_else(LogcatFilter::match_all);
+    let receiver = on_device(serial, move |device| {
+        logcat::stream_logcat(device, buffer, filter, stream_cancel).map_err(|e| e.to_string())
+    })
+    .await
+    .inspect_err(|_| operations.finish(&stream_id))?;
+
+    std::thread::spawn(move || {
+        let event = format!("logcat:{}", stream_id);
+        for entry in receiver {
+            if app.emit(&event, entry).is_err() {
+                cancel.cancel();
+                break;
+            }
+        }
+        app.state::<Operations>().finish(&stream_id);
+    });
+
+    Ok(())
 }
 
 #[command]
 fn get_top_package(serial: String) -> Result<TopPackage, String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
-    let mut device = ADBServerDevice::new(serial, None);
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
     device::get_top_package(&mut device).map_err(|e| e.to_string())
 }
 
 #[command]
-fn execute_shell_command(serial: String, command: String) -> Result<CommandOutput, String> {
-    let _addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037);
-    let mut device = ADBServerDevice::new(serial, None);
+fn get_focused_window(serial: String) -> Result<Option<device::FocusedWindow>, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::get_focused_window(&mut device).map_err(|e| e.to_string())
+}
+
+/// How long a command typed into the terminal may run, so `logcat` or `top` can't hang it.
+const SHELL_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs `command`, giving up after `timeout_ms` or 30 seconds with the output so far.
+#[command]
+async fn execute_shell_command(
+    serial: String,
+    command: String,
+    timeout_ms: Option<u64>,
+) -> Result<CommandOutput, String> {
+    let timeout = timeout_ms.map_or(SHELL_COMMAND_TIMEOUT, Duration::from_millis);
+    on_device(serial, move |device| {
+        ShellExecutor::new()
+            .with_timeout(timeout)
+            .execute(device, &command)
+            .map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn get_property(serial: String, key: String) -> Result<String, String> {
+    on_device(serial, move |device| {
+        ShellExecutor::new()
+            .get_prop(device, &key)
+            .map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn list_dumpsys_services(serial: String) -> Result<Vec<String>, String> {
+    on_device(serial, move |device| {
+        ShellExecutor::new()
+            .list_dumpsys_services(device)
+            .map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Raw `dumpsys <service>` output, for a service picked from `list_dumpsys_services`.
+#[command]
+async fn run_dumpsys(serial: String, service: String) -> Result<String, String> {
+    on_device(serial, move |device| {
+        ShellExecutor::new()
+            .run_dumpsys(device, &service)
+            .map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+fn set_property(serial: String, key: String, value: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    let mut executor = ShellExecutor::new();
+    executor
+        .set_prop(&mut device, &key, &value)
+        .map_err(|e| e.to_string())
+}
+
+#[command]
+async fn get_properties(
+    serial: String,
+    keys: Vec<String>,
+) -> Result<HashMap<String, String>, String> {
+    on_device(serial, move |device| {
+        ShellExecutor::new()
+            .get_props(device, &keys)
+            .map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+fn get_mounts(serial: String) -> Result<Vec<MountInfo>, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    let mut executor = ShellExecutor::new();
+    executor.get_mounts(&mut device).map_err(|e| e.to_string())
+}
+
+#[command]
+fn get_network_interfaces(
+    serial: String,
+    include_loopback: bool,
+) -> Result<Vec<NetworkInterface>, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    let mut executor = ShellExecutor::new();
+    executor
+        .get_networks(&mut device, include_loopback)
+        .map_err(|e| e.to_string())
+}
+
+#[command]
+fn get_selinux_status(serial: String) -> Result<String, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    let mut executor = ShellExecutor::new();
+    executor
+        .get_selinux_status(&mut device)
+        .map_err(|e| e.to_string())
+}
+
+#[command]
+fn set_selinux_enforcing(serial: String, enforce: bool) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
     let mut executor = ShellExecutor::new();
     executor
-        .execute(&mut device, &command)
+        .set_selinux_enforcing(&mut device, enforce)
+        .map_err(|e| e.to_string())
+}
+
+#[command]
+fn is_root_available(serial: String) -> Result<bool, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    let mut executor = ShellExecutor::new();
+    Ok(executor.is_root_available(&mut device))
+}
+
+#[command]
+async fn get_clipboard(serial: String) -> Result<String, String> {
+    on_device(serial, move |device| {
+        shell::get_clipboard(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn set_clipboard(serial: String, text: String) -> Result<(), String> {
+    on_device(serial, move |device| {
+        shell::set_clipboard(device, &text).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Needs root on most release builds.
+#[command]
+async fn read_dmesg(serial: String) -> Result<Vec<shell::KernelLogEntry>, String> {
+    on_device(serial, move |device| {
+        shell::read_dmesg(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// The previous boot's kernel log, needs root.
+#[command]
+async fn read_last_kmsg(serial: String) -> Result<String, String> {
+    on_device(serial, move |device| {
+        shell::read_last_kmsg(device).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn get_device_status(serial: String) -> Result<DeviceStatus, String> {
+    on_device(serial, move |device| {
+        ShellExecutor::new()
+            .get_device_status(device)
+            .map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceCommandResult {
+    pub serial: String,
+    pub output: Result<CommandOutput, String>,
+}
+
+#[command]
+fn run_on_all_devices(command: String) -> Result<Vec<DeviceCommandResult>, String> {
+    let mut server = adb_server();
+    let mut executor = ShellExecutor::new();
+
+    let results = device::for_each_device(&mut server, |device| executor.execute(device, &command))
+        .map_err(|e| e.to_string())?;
+
+    Ok(results
+        .into_iter()
+        .map(|(serial, output)| DeviceCommandResult { serial, output })
+        .collect())
+}
+
+#[command]
+fn get_low_storage_mounts(
+    serial: String,
+    threshold_percent: f64,
+) -> Result<Vec<device::StorageWarning>, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::low_storage_mounts(&mut device, threshold_percent).map_err(|e| e.to_string())
+}
+
+#[command]
+fn take_screenshot(serial: String, output_path: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    let png = device::capture_screenshot(&mut device).map_err(|e| e.to_string())?;
+    std::fs::write(&output_path, png).map_err(|e| e.to_string())
+}
+
+#[command]
+fn set_screen_resolution(serial: String, width: u32, height: u32) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::set_screen_resolution(&mut device, width, height).map_err(|e| e.to_string())
+}
+
+#[command]
+fn set_screen_density(serial: String, dpi: u32) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::set_density(&mut device, dpi).map_err(|e| e.to_string())
+}
+
+/// Drops both the resolution and the density override.
+#[command]
+fn reset_screen(serial: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::reset_screen_resolution(&mut device).map_err(|e| e.to_string())?;
+    device::reset_density(&mut device).map_err(|e| e.to_string())
+}
+
+/// Whether the resolution and the density are overridden, for showing the reset button.
+#[command]
+fn get_screen_overrides(serial: String) -> Result<(bool, bool), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    Ok((
+        device::has_resolution_override(&mut device),
+        device::has_density_override(&mut device),
+    ))
+}
+
+#[command]
+fn get_display_health(serial: String) -> Result<device::DisplayHealth, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::get_display_health(&mut device).map_err(|e| e.to_string())
+}
+
+#[command]
+fn list_users(serial: String) -> Result<Vec<device::UserInfo>, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::list_users(&mut device).map_err(|e| e.to_string())
+}
+
+#[command]
+fn create_user(serial: String, name: String) -> Result<u32, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::create_user(&mut device, &name).map_err(|e| e.to_string())
+}
+
+#[command]
+fn remove_user(serial: String, user_id: u32) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::remove_user(&mut device, user_id).map_err(|e| e.to_string())
+}
+
+#[command]
+fn switch_user(serial: String, user_id: u32) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::switch_user(&mut device, user_id).map_err(|e| e.to_string())
+}
+
+#[command]
+fn get_setting(
+    serial: String,
+    namespace: SettingsNamespace,
+    key: String,
+) -> Result<Option<String>, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::get_setting(&mut device, namespace, &key).map_err(|e| e.to_string())
+}
+
+#[command]
+fn put_setting(
+    serial: String,
+    namespace: SettingsNamespace,
+    key: String,
+    value: String,
+) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::put_setting(&mut device, namespace, &key, &value).map_err(|e| e.to_string())
+}
+
+#[command]
+fn delete_setting(
+    serial: String,
+    namespace: SettingsNamespace,
+    key: String,
+) -> Result<bool, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::delete_setting(&mut device, namespace, &key).map_err(|e| e.to_string())
+}
+
+#[command]
+fn list_settings(
+    serial: String,
+    namespace: SettingsNamespace,
+) -> Result<HashMap<String, String>, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::list_settings(&mut device, namespace).map_err(|e| e.to_string())
+}
+
+/// `0` disables animations, `1` is the default speed.
+#[command]
+fn set_animation_scale(serial: String, scale: f32) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::set_animation_scale(&mut device, scale).map_err(|e| e.to_string())
+}
+
+#[command]
+fn set_show_taps(serial: String, enabled: bool) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::set_show_taps(&mut device, enabled).map_err(|e| e.to_string())
+}
+
+#[command]
+fn get_orientation(serial: String) -> Result<device::Orientation, String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::get_orientation(&mut device).map_err(|e| e.to_string())
+}
+
+#[command]
+fn set_orientation(serial: String, orientation: device::Orientation) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::set_orientation(&mut device, orientation).map_err(|e| e.to_string())
+}
+
+#[command]
+fn reset_orientation(serial: String) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::reset_orientation(&mut device).map_err(|e| e.to_string())
+}
+
+#[command]
+fn set_pointer_location(serial: String, enabled: bool) -> Result<(), String> {
+    ensure_device_ready(&serial)?;
+    let mut device = adb_device(serial);
+    device::set_pointer_location(&mut device, enabled).map_err(|e| e.to_string())
+}
+
+#[command]
+async fn set_airplane_mode(serial: String, enabled: bool) -> Result<bool, String> {
+    on_device(serial, move |device| {
+        device::set_airplane_mode(device, enabled).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn set_wifi(serial: String, enabled: bool) -> Result<bool, String> {
+    on_device(serial, move |device| {
+        device::set_wifi(device, enabled).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+#[command]
+async fn set_mobile_data(serial: String, enabled: bool) -> Result<bool, String> {
+    on_device(serial, move |device| {
+        device::set_mobile_data(device, enabled).map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Open fastboot connections by serial, so connect, flash and reboot calls from the UI
+/// go through the same USB handle instead of claiming the interface again.
+#[derive(Default)]
+struct FastbootSessions(Mutex<HashMap<String, Arc<AsyncMutex<FastbootCore>>>>);
+
+impl FastbootSessions {
+    /// The session for `serial`, connected on first use.
+    async fn connect(&self, serial: &str) -> Result<Arc<AsyncMutex<FastbootCore>>, String> {
+        let session = self
+            .0
+            .lock()
+            .unwrap()
+            .entry(serial.to_string())
+            .or_default()
+            .clone();
+
+        let mut core = session.lock().await;
+        if !core.is_connected() {
+            core.connect(Some(serial))
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        drop(core);
+
+        Ok(session)
+    }
+
+    fn remove(&self, serial: &str) {
+        self.0.lock().unwrap().remove(serial);
+    }
+}
+
+#[command]
+async fn fastboot_list_devices() -> Result<Vec<FastbootDeviceInfo>, String> {
+    FastbootCore::list_devices()
+        .await
         .map_err(|e| e.to_string())
 }
 
+#[command]
+async fn fastboot_get_vars(
+    serial: String,
+    sessions: State<'_, FastbootSessions>,
+) -> Result<FastbootDeviceInfo, String> {
+    let session = sessions.connect(&serial).await?;
+    let mut core = session.lock().await;
+    let info = core.get_all_vars().await.map_err(|e| e.to_string())?;
+    // The core dropped its connection, a late reply must not reach the next command
+    if !info.timed_out.is_empty() {
+        sessions.remove(&serial);
+    }
+    Ok(info)
+}
+
+#[command]
+async fn fastboot_flash(
+    serial: String,
+    partition: FlashPartition,
+    image_path: String,
+    sessions: State<'_, FastbootSessions>,
+) -> Result<(), String> {
+    let session = sessions.connect(&serial).await?;
+    let mut core = session.lock().await;
+    core.flash(partition, &image_path)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[command]
+async fn fastboot_erase(
+    serial: String,
+    partition: FlashPartition,
+    sessions: State<'_, FastbootSessions>,
+) -> Result<(), String> {
+    let session = sessions.connect(&serial).await?;
+    let mut core = session.lock().await;
+    core.erase(partition).await.map_err(|e| e.to_string())
+}
+
+/// The device re-enumerates on reboot, so its session is dropped either way and the
+/// next command connects again.
+#[command]
+async fn fastboot_reboot(
+    serial: String,
+    sessions: State<'_, FastbootSessions>,
+) -> Result<(), String> {
+    let session = sessions.connect(&serial).await?;
+    let result = session.lock().await.reboot().await;
+    sessions.remove(&serial);
+    result.map_err(|e| e.to_string())
+}
+
+#[command]
+async fn fastboot_set_active_slot(
+    serial: String,
+    slot: String,
+    sessions: State<'_, FastbootSessions>,
+) -> Result<(), String> {
+    let session = sessions.connect(&serial).await?;
+    let mut core = session.lock().await;
+    core.set_active_slot(&slot).await.map_err(|e| e.to_string())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .invoke_handler(tauri::generate_handler![
+            set_adb_server,
+            adb_server_command,
+            get_device_features,
+            set_adb_executable,
             get_devices,
+            start_device_tracking,
             get_device_info,
+            get_encryption_state,
             list_packages,
             get_package_info,
             uninstall_package,
+            uninstall_package_keep_data,
+            clear_package_data,
+            force_stop_package,
+            disable_package,
+            enable_package,
+            launch_package,
+            launch_activity,
+            start_intent,
+            get_app_components,
+            set_component_enabled,
+            read_app_prefs,
+            get_app_splits,
+            list_app_files,
+            run_as_package,
+            send_broadcast,
             install_package,
+            install_package_from_url,
+            preview_package_install,
+            backup_package,
+            restore_package_backup,
+            sideload_package,
             check_root,
+            has_root_access,
+            get_root_apps,
             list_processes,
+            get_process_memory,
+            list_services,
+            list_foreground_services,
+            find_processes_by_package,
             kill_process,
             kill_package,
+            kill_background,
+            kill_all_background,
             reboot_device,
+            reboot_device_custom,
+            get_device_mode,
             get_performance_profile,
+            get_system_load,
+            get_frame_rate,
+            detect_throttling,
+            get_cpu_governor,
+            list_available_governors,
+            set_cpu_governor,
+            get_cpu_topology,
+            get_online_cores,
+            set_core_online,
+            get_wakelocks,
+            get_wakelock_history,
+            reset_battery_stats,
+            set_charge_limit,
+            get_telephony_info,
+            get_device_snapshot,
+            export_device_report,
+            diff_device_reports,
+            record_battery_history,
+            capture_bugreport,
+            read_logcat,
+            clear_logcat,
+            list_logcat_buffers,
+            logcat_level_colors,
+            start_logcat_stream,
+            start_file_follow,
             get_top_package,
+            get_focused_window,
             execute_shell_command,
+            list_dumpsys_services,
+            run_dumpsys,
+            get_property,
+            set_property,
+            get_properties,
+            get_mounts,
+            get_network_interfaces,
+            get_selinux_status,
+            set_selinux_enforcing,
+            is_root_available,
+            get_device_status,
+            get_clipboard,
+            set_clipboard,
+            read_dmesg,
+            read_last_kmsg,
+            run_on_all_devices,
+            get_low_storage_mounts,
+            take_screenshot,
+            set_screen_resolution,
+            set_screen_density,
+            reset_screen,
+            get_screen_overrides,
+            get_display_health,
+            list_users,
+            create_user,
+            remove_user,
+            switch_user,
+            get_setting,
+            put_setting,
+            delete_setting,
+            list_settings,
+            set_animation_scale,
+            set_show_taps,
+            get_orientation,
+            set_orientation,
+            reset_orientation,
+            set_pointer_location,
+            set_airplane_mode,
+            set_wifi,
+            set_mobile_data,
+            fastboot_list_devices,
+            fastboot_get_vars,
+            fastboot_flash,
+            fastboot_erase,
+            fastboot_reboot,
+            fastboot_set_active_slot,
+            cancel_operation,
         ])
+        .manage(Operations::default())
+        .manage(FastbootSessions::default())
         .setup(|app| {
             if cfg!(debug_assertions) {
                 app.handle().plugin(